@@ -0,0 +1,113 @@
+// MCU1 and MCU2 as embassy tasks, with an embassy `Channel` standing in for the UART between
+// them. Runs on the host (`cargo run --example embassy`), on a board swap the executor/time
+// features for the chip's and the channel for the UART driver. Nothing in the protocol knows
+// which executor it's on, the clock and the delay for timeouts are the two hooks.
+
+use core::time::Duration;
+
+use canopy::framing::{Cobs, FrameDecoder, Framing};
+use canopy::{AsyncProtocol, Clock, CommunicationProtocol, Delay, OverflowPolicy, ProtocolError};
+use embassy_executor::Spawner;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Instant, Timer};
+
+// frames on the "wire"
+static WIRE: Channel<CriticalSectionRawMutex, Vec<u8>, 4> = Channel::new();
+
+// embassy's tick counter as the protocol clock (reassembly timeouts etc.)
+struct EmbassyClock;
+
+impl Clock for EmbassyClock {
+    fn now(&self) -> Duration {
+        Duration::from_micros(Instant::now().as_micros())
+    }
+}
+
+// and its timer for the async timeouts
+struct EmbassyDelay;
+
+impl Delay for EmbassyDelay {
+    async fn delay(&self, duration: Duration) {
+        Timer::after(embassy_time::Duration::from_micros(
+            duration.as_micros() as u64
+        ))
+        .await
+    }
+}
+
+fn protocol() -> AsyncProtocol {
+    AsyncProtocol::new(
+        CommunicationProtocol::new(8)
+            .with_overflow_policy(OverflowPolicy::Block)
+            .with_mtu(16)
+            .with_clock(EmbassyClock),
+    )
+}
+
+#[embassy_executor::task]
+async fn mcu1(outgoing: AsyncProtocol) {
+    for reading in 0..5u8 {
+        let payload = vec![reading; 4 + reading as usize * 8];
+        match outgoing.send(payload).await {
+            Ok(id) => println!("MCU1 sent reading {reading} as ID {id}"),
+            Err(error) => println!("MCU1 send failed: {error}"),
+        }
+        Timer::after_millis(100).await;
+    }
+}
+
+// MCU1's UART: every queued message (fragments one by one) goes out as a COBS frame
+#[embassy_executor::task]
+async fn uart_tx(outgoing: AsyncProtocol) {
+    loop {
+        let message = outgoing.dequeue().await;
+        let Ok(frame) = outgoing.with(|p| Cobs::default().encode_message(p.codec(), &message))
+        else {
+            continue;
+        };
+        WIRE.send(frame).await;
+    }
+}
+
+// MCU2's UART: decode frames and queue them for MCU2
+#[embassy_executor::task]
+async fn uart_rx(incoming: AsyncProtocol) {
+    let mut decoder = Cobs::default().decoder();
+    loop {
+        decoder.feed(&WIRE.receive().await);
+        while let Some(decoded) = incoming.with(|p| decoder.next_message(p.codec())) {
+            if let Ok(message) = decoded {
+                let _ = incoming.enqueue(message).await;
+            }
+        }
+    }
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let outgoing = protocol();
+    let incoming = protocol();
+    spawner.spawn(mcu1(outgoing.clone()).unwrap());
+    spawner.spawn(uart_tx(outgoing).unwrap());
+    spawner.spawn(uart_rx(incoming.clone()).unwrap());
+
+    // MCU2, gives up once the link has been quiet for half a second
+    loop {
+        match incoming
+            .receive_timeout(&EmbassyDelay, Duration::from_millis(500))
+            .await
+        {
+            Ok((message, valid)) => println!(
+                "MCU2 got ID {} ({} bytes, valid: {valid})",
+                message.id,
+                message.payload.len()
+            ),
+            Err(ProtocolError::Timeout) => {
+                println!("MCU2 link idle, done");
+                std::process::exit(0);
+            }
+            Err(error) => println!("MCU2 receive failed: {error}"),
+        }
+    }
+}
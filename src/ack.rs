@@ -0,0 +1,205 @@
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::codec::Codec;
+use crate::error::ProtocolError;
+use crate::message::{Message, Priority, flags};
+
+// Acknowledgments, so the sender finds out what became of a message: the receiver answers
+// every data message it takes in with an ACK, or with a NACK and the reason when it had to
+// throw the message away.
+//
+// An ack is a regular message with `flags::ACK` set and the id of the message it answers.
+// ACKs have no payload, NACKs also set `flags::NACK` and carry the reason:
+//
+//   ACK:  | id | ACK        | (empty)    |
+//   NACK: | id | ACK | NACK | reason: u8 |
+//
+// An `Endpoint` sends them on its control channel and adds the channel id of the message
+// acked after that (`| channel: u8 |`, `| reason | channel |`), see `channel`.
+//
+// Needs `FormatVersion::V2` on the wire, V1 has nowhere to put the flags. A NACK for a
+// corrupted message carries whatever id the receiver read, which may be corrupted too; the
+// sender doesn't know that id and ignores it.
+
+// How many sent ids `AckTracker` remembers by default
+pub const DEFAULT_HISTORY: usize = 64;
+
+// Why a message was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum NackReason {
+    // checksum didn't verify
+    Checksum = 1,
+    // length or header fields make no sense
+    Malformed = 2,
+    // fragment header was garbage
+    InvalidFragment = 3,
+    // receiver had no room for it
+    BufferFull = 4,
+    // anything else, including reasons from a newer peer we don't know
+    Other = 0xFF,
+}
+
+impl NackReason {
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => NackReason::Checksum,
+            2 => NackReason::Malformed,
+            3 => NackReason::InvalidFragment,
+            4 => NackReason::BufferFull,
+            _ => NackReason::Other,
+        }
+    }
+
+    // Reason to give for a message that failed to come in with `error`
+    pub fn from_error(error: &ProtocolError) -> Self {
+        match error {
+            ProtocolError::ChecksumMismatch { .. } => NackReason::Checksum,
+            ProtocolError::InvalidLength { .. }
+            | ProtocolError::InvalidHeader
+            | ProtocolError::MalformedFrame => NackReason::Malformed,
+            ProtocolError::InvalidFragment => NackReason::InvalidFragment,
+            ProtocolError::BufferFull => NackReason::BufferFull,
+            _ => NackReason::Other,
+        }
+    }
+}
+
+// What an ack message says
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reply {
+    Ack(u16),
+    Nack(u16, NackReason),
+}
+
+impl Reply {
+    // Id of the message this answers
+    pub fn id(&self) -> u16 {
+        match *self {
+            Reply::Ack(id) | Reply::Nack(id, _) => id,
+        }
+    }
+}
+
+// Acks overtake queued data, the sender is waiting on them
+pub fn ack(codec: &Codec, id: u16) -> Message {
+    codec.seal_with(id, flags::ACK, Priority::High, Vec::new())
+}
+
+pub fn nack(codec: &Codec, id: u16, reason: NackReason) -> Message {
+    codec.seal_with(
+        id,
+        flags::ACK | flags::NACK,
+        Priority::High,
+        vec![reason as u8],
+    )
+}
+
+pub fn is_ack(message: &Message) -> bool {
+    message.flags & flags::ACK != 0
+}
+
+// What `message` acknowledges, `None` if it's not an ack. Check the checksum first.
+pub fn parse(message: &Message) -> Option<Reply> {
+    if !is_ack(message) {
+        return None;
+    }
+    if message.flags & flags::NACK == 0 {
+        return Some(Reply::Ack(message.id));
+    }
+    let reason = message
+        .payload
+        .first()
+        .map_or(NackReason::Other, |&reason| NackReason::from_u8(reason));
+    Some(Reply::Nack(message.id, reason))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    // sent, nothing heard back yet
+    Pending,
+    // receiver acked it
+    Delivered,
+    // receiver nacked it
+    Rejected(NackReason),
+    // no answer after every retry, see `retransmit`
+    Failed,
+}
+
+// Sender side: delivery status of the last `history` messages sent, by id. Older ones are
+// forgotten, so ids can wrap around without getting mixed up.
+pub struct AckTracker {
+    sent: VecDeque<(u16, DeliveryStatus)>,
+    history: usize,
+}
+
+impl AckTracker {
+    pub fn new(history: usize) -> Self {
+        AckTracker {
+            sent: VecDeque::new(),
+            history: history.max(1),
+        }
+    }
+
+    // `id` just went out
+    pub fn track(&mut self, id: u16) {
+        self.sent.retain(|&(sent, _)| sent != id);
+        if self.sent.len() >= self.history {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((id, DeliveryStatus::Pending));
+    }
+
+    // Apply an ack from the receiver. Returns the new status of the message, `None` if we
+    // don't know the id or it was already answered (a duplicate ack).
+    pub fn handle(&mut self, reply: Reply) -> Option<DeliveryStatus> {
+        let (_, status) = self
+            .sent
+            .iter_mut()
+            .find(|(id, status)| *id == reply.id() && *status == DeliveryStatus::Pending)?;
+        *status = match reply {
+            Reply::Ack(_) => DeliveryStatus::Delivered,
+            Reply::Nack(_, reason) => DeliveryStatus::Rejected(reason),
+        };
+        Some(*status)
+    }
+
+    // Sender gave up on `id` without an answer
+    pub fn fail(&mut self, id: u16) {
+        if let Some((_, status)) = self
+            .sent
+            .iter_mut()
+            .find(|(sent, status)| *sent == id && *status == DeliveryStatus::Pending)
+        {
+            *status = DeliveryStatus::Failed;
+        }
+    }
+
+    // `None` if `id` was never sent or has been forgotten
+    pub fn status(&self, id: u16) -> Option<DeliveryStatus> {
+        self.sent
+            .iter()
+            .find(|(sent, _)| *sent == id)
+            .map(|&(_, status)| status)
+    }
+
+    // Messages still waiting for an answer
+    pub fn pending(&self) -> usize {
+        self.sent
+            .iter()
+            .filter(|(_, status)| *status == DeliveryStatus::Pending)
+            .count()
+    }
+
+    pub fn clear(&mut self) {
+        self.sent.clear();
+    }
+}
+
+impl Default for AckTracker {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY)
+    }
+}
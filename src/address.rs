@@ -0,0 +1,67 @@
+// Node addressing, for more than two MCUs sharing a bus. Every node gets an id and messages
+// carry where they come from and where they're going (needs `FormatVersion::V3`, the older
+// formats have no room for either and everything comes through as unaddressed).
+//
+// `UNADDRESSED` (0) is what a message gets when nobody said otherwise, e.g. from a point to
+// point peer that doesn't know about addresses; every node takes those. `BROADCAST` reaches
+// every node as well, on purpose. The ids just below it are multicast groups: a node takes a
+// message sent to one only if it joined that group. Anything else is a unicast node id and
+// only surfaces on that node.
+//
+//   0x00         unaddressed
+//   0x01..=0xEF  nodes
+//   0xF0..=0xFE  multicast groups
+//   0xFF         broadcast
+
+pub type NodeId = u8;
+
+pub const UNADDRESSED: NodeId = 0;
+pub const BROADCAST: NodeId = 0xFF;
+pub const FIRST_GROUP: NodeId = 0xF0;
+pub const LAST_GROUP: NodeId = 0xFE;
+
+pub fn is_group(id: NodeId) -> bool {
+    (FIRST_GROUP..=LAST_GROUP).contains(&id)
+}
+
+// Sent to more than one node: broadcast, a group, or no one in particular
+pub fn is_multi(id: NodeId) -> bool {
+    id == UNADDRESSED || id == BROADCAST || is_group(id)
+}
+
+// Multicast groups a node has joined
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Groups(u16);
+
+impl Groups {
+    pub const fn new() -> Self {
+        Groups(0)
+    }
+
+    // Ignores ids that aren't groups
+    pub fn join(&mut self, group: NodeId) {
+        if is_group(group) {
+            self.0 |= 1 << (group - FIRST_GROUP);
+        }
+    }
+
+    pub fn leave(&mut self, group: NodeId) {
+        if is_group(group) {
+            self.0 &= !(1 << (group - FIRST_GROUP));
+        }
+    }
+
+    pub fn contains(&self, group: NodeId) -> bool {
+        is_group(group) && self.0 & (1 << (group - FIRST_GROUP)) != 0
+    }
+}
+
+// Whether a node with id `node`, member of `groups`, should take a message sent to
+// `destination`. A node without an id of its own takes everything.
+pub fn accepts(node: NodeId, groups: Groups, destination: NodeId) -> bool {
+    node == UNADDRESSED
+        || destination == UNADDRESSED
+        || destination == BROADCAST
+        || destination == node
+        || groups.contains(destination)
+}
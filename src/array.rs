@@ -0,0 +1,256 @@
+// `CircularBuffer` with the capacity as a const generic and the storage a plain `[_; N]`, so
+// the whole queue can be allocated statically (or on the stack) and needs no heap of its own.
+// With `N` a power of two the wrap-around `% N` compiles down to a mask.
+//
+// It holds anything implementing `QueuedMessage`: heap backed `Message`s for std users that
+// just want a fixed queue depth, or `FixedMessage`s for a build without an allocator. Ordering
+// and overflow handling are the same as the dynamic `CircularBuffer`.
+
+use crate::buffer::OverflowPolicy;
+use crate::error::ProtocolError;
+use crate::fixed::FixedMessage;
+#[cfg(feature = "alloc")]
+use crate::message::Message;
+use crate::message::Priority;
+use crate::stats::Stats;
+
+// What the buffer needs to know about the messages it queues
+pub trait QueuedMessage {
+    fn id(&self) -> u16;
+    fn priority(&self) -> Priority;
+    fn payload_len(&self) -> usize;
+}
+
+#[cfg(feature = "alloc")]
+impl QueuedMessage for Message {
+    fn id(&self) -> u16 {
+        self.id
+    }
+
+    fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    fn payload_len(&self) -> usize {
+        self.payload.len()
+    }
+}
+
+impl<const P: usize> QueuedMessage for FixedMessage<P> {
+    fn id(&self) -> u16 {
+        self.id
+    }
+
+    fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    fn payload_len(&self) -> usize {
+        self.payload().len()
+    }
+}
+
+// Slots `head .. head + len` (wrapping) are occupied, in receive order
+pub struct CircularBuffer<M, const N: usize> {
+    slots: [Option<M>; N],
+    head: usize,
+    len: usize,
+    policy: OverflowPolicy,
+    stats: Stats,
+}
+
+impl<M: QueuedMessage, const N: usize> CircularBuffer<M, N> {
+    // `const` so the buffer can be a `static`
+    pub const fn new(policy: OverflowPolicy) -> Self {
+        CircularBuffer {
+            slots: [const { None }; N],
+            head: 0,
+            len: 0,
+            policy,
+            stats: Stats::new(),
+        }
+    }
+
+    // slot holding the `index`th message in receive order
+    fn slot(&self, index: usize) -> usize {
+        (self.head + index) % N
+    }
+
+    fn get(&self, index: usize) -> Option<&M> {
+        self.slots[self.slot(index)].as_ref()
+    }
+
+    pub fn send_message(&mut self, message: M) -> Result<(), ProtocolError> {
+        self.try_send(message)
+    }
+
+    pub fn receive_message(&mut self) -> Option<M> {
+        self.try_receive()
+    }
+
+    // Same overflow handling as `buffer::CircularBuffer::try_send`
+    pub fn try_send(&mut self, message: M) -> Result<(), ProtocolError> {
+        if self.len == N {
+            match self.policy {
+                OverflowPolicy::RejectNew | OverflowPolicy::Block => {
+                    self.stats.rejected_full += 1;
+                    return Err(ProtocolError::BufferFull);
+                }
+                OverflowPolicy::DropOldest => {
+                    let lowest = self.len.checked_sub(1).and_then(|last| self.get(last));
+                    match lowest {
+                        Some(lowest) if lowest.priority() <= message.priority() => {
+                            self.evict();
+                        }
+                        _ => {
+                            self.stats.rejected_full += 1;
+                            return Err(ProtocolError::BufferFull);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.record_sent(&message);
+        let priority = message.priority();
+        let tail = self.slot(self.len);
+        self.slots[tail] = Some(message);
+        self.len += 1;
+
+        // bubble it forward past everything of lower priority
+        let mut index = self.len - 1;
+        while index > 0 && self.get(index - 1).is_some_and(|m| m.priority() < priority) {
+            let (a, b) = (self.slot(index - 1), self.slot(index));
+            self.slots.swap(a, b);
+            index -= 1;
+        }
+
+        self.stats.high_watermark = self.stats.high_watermark.max(self.len as u64);
+        Ok(())
+    }
+
+    // oldest message of the lowest priority
+    fn evict(&mut self) -> Option<M> {
+        let lowest = self.get(self.len.checked_sub(1)?)?.priority();
+        let index = (0..self.len).find(|&i| self.get(i).is_some_and(|m| m.priority() == lowest))?;
+        let evicted = self.remove_at(index);
+        self.stats.dropped_overflow += 1;
+        evicted
+    }
+
+    // take the `index`th message out and close the gap
+    fn remove_at(&mut self, index: usize) -> Option<M> {
+        if index >= self.len {
+            return None;
+        }
+        let slot = self.slot(index);
+        let removed = self.slots[slot].take();
+        for i in index..self.len - 1 {
+            let (a, b) = (self.slot(i), self.slot(i + 1));
+            self.slots.swap(a, b);
+        }
+        self.len -= 1;
+        removed
+    }
+
+    fn record_sent(&mut self, message: &M) {
+        self.stats.messages_sent += 1;
+        self.stats.bytes_sent += message.payload_len() as u64;
+    }
+
+    pub fn try_receive(&mut self) -> Option<M> {
+        if self.len == 0 {
+            return None;
+        }
+        let message = self.slots[self.head].take()?;
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        self.stats.messages_received += 1;
+        self.stats.bytes_received += message.payload_len() as u64;
+        Some(message)
+    }
+
+    pub fn peek(&self) -> Option<&M> {
+        self.get(0)
+    }
+
+    pub fn peek_mut(&mut self) -> Option<&mut M> {
+        if self.len == 0 {
+            return None;
+        }
+        self.slots[self.head].as_mut()
+    }
+
+    // Pull a queued message back out by id, see `buffer::CircularBuffer::remove`
+    pub fn remove(&mut self, id: u16) -> Option<M> {
+        let index = (0..self.len).find(|&i| self.get(i).is_some_and(|m| m.id() == id))?;
+        self.remove_at(index)
+    }
+
+    // Keep only the messages `keep` says yes to, returns how many were thrown out
+    pub fn retain<F>(&mut self, mut keep: F) -> usize
+    where
+        F: FnMut(&M) -> bool,
+    {
+        let before = self.len;
+        let mut index = 0;
+        while index < self.len {
+            if self.get(index).is_some_and(&mut keep) {
+                index += 1;
+            } else {
+                self.remove_at(index);
+            }
+        }
+        before - self.len
+    }
+
+    // queued messages in receive order
+    pub fn iter(&self) -> impl Iterator<Item = &M> {
+        (0..self.len).filter_map(|i| self.get(i))
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.iter_mut().for_each(|slot| *slot = None);
+        self.head = 0;
+        self.len = 0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub fn length(&self) -> usize {
+        self.len
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.policy
+    }
+
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn queued_bytes(&self) -> usize {
+        self.iter().map(|m| m.payload_len()).sum()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats {
+            high_watermark: self.len as u64,
+            ..Stats::new()
+        };
+    }
+}
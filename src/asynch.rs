@@ -0,0 +1,344 @@
+// Async front end for `CommunicationProtocol`, for async firmware or a host side tokio
+// program. `send` waits for room the way `BlockingBuffer::send` does, `receive` waits for a
+// message, but instead of parking a thread they register the task's waker and get woken when
+// the other side takes a message out / puts one in.
+//
+// Only `core::task` is used, no runtime types, so any executor can poll these: tokio on a
+// host, embassy on the MCU (see `examples/embassy.rs`). The handle is cheap to clone, give one
+// to the sending task and one to the receiving task:
+//
+//   let protocol = AsyncProtocol::new(CommunicationProtocol::new(16).with_overflow_policy(Block));
+//   let tx = protocol.clone();
+//   spawn(async move { tx.send(vec![1, 2, 3]).await });
+//   let (message, valid) = protocol.receive().await;
+//
+// Waiting only happens under `OverflowPolicy::Block`, with the other policies `send` finishes
+// straight away like `mcu1_send`. Dropping a pending `send` drops its payload, nothing is
+// queued. Timeouts take a `Delay`, whatever sleep the executor provides.
+//
+// The state sits behind a `std::sync::Mutex` with `std`, and behind a critical section
+// without it (`critical-section` feature), so a task and an interrupt handler can share it.
+// Cloning is an `Arc`, which needs atomic compare-and-swap, so Cortex-M3 and up.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+#[cfg(not(feature = "std"))]
+use core::cell::RefCell;
+use core::future::poll_fn;
+#[cfg(feature = "futures")]
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+
+#[cfg(feature = "futures")]
+use futures_core::Stream;
+#[cfg(feature = "futures")]
+use futures_sink::Sink;
+
+use crate::buffer::OverflowPolicy;
+use crate::error::ProtocolError;
+use crate::message::{Message, Payload, Priority};
+use crate::protocol::CommunicationProtocol;
+use crate::time::{self, Delay};
+
+#[cfg(feature = "std")]
+type Lock<T> = Mutex<T>;
+#[cfg(not(feature = "std"))]
+type Lock<T> = critical_section::Mutex<RefCell<T>>;
+
+#[derive(Clone)]
+pub struct AsyncProtocol {
+    state: Arc<Lock<State>>,
+}
+
+struct State {
+    protocol: CommunicationProtocol,
+    // tasks waiting for room
+    senders: Vec<Waker>,
+    // tasks waiting for a message
+    receivers: Vec<Waker>,
+}
+
+impl AsyncProtocol {
+    pub fn new(protocol: CommunicationProtocol) -> Self {
+        let state = State {
+            protocol,
+            senders: Vec::new(),
+            receivers: Vec::new(),
+        };
+        #[cfg(feature = "std")]
+        let state = Mutex::new(state);
+        #[cfg(not(feature = "std"))]
+        let state = critical_section::Mutex::new(RefCell::new(state));
+        AsyncProtocol {
+            state: Arc::new(state),
+        }
+    }
+
+    // Run `f` with the state locked. Whatever wakers it hands back are woken once the lock is
+    // released again.
+    fn locked<R>(&self, f: impl FnOnce(&mut State) -> (R, Vec<Waker>)) -> R {
+        #[cfg(feature = "std")]
+        let (result, wakers) = {
+            // a panic on another task while holding the lock doesn't leave the protocol in a
+            // broken state, so just carry on with it
+            let mut state = self
+                .state
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(&mut state)
+        };
+        #[cfg(not(feature = "std"))]
+        let (result, wakers) = critical_section::with(|cs| f(&mut self.state.borrow_ref_mut(cs)));
+
+        wakers.into_iter().for_each(Waker::wake);
+        result
+    }
+
+    // Direct access to the protocol (stats, cancelling, ...). Everyone waiting is woken
+    // afterwards since `f` may have made room or queued something.
+    pub fn with<R>(&self, f: impl FnOnce(&mut CommunicationProtocol) -> R) -> R {
+        self.locked(|state| {
+            let result = f(&mut state.protocol);
+            let mut wakers = core::mem::take(&mut state.senders);
+            wakers.append(&mut state.receivers);
+            (result, wakers)
+        })
+    }
+
+    pub async fn send(&self, payload: impl Into<Payload>) -> Result<u16, ProtocolError> {
+        self.send_with_priority(payload, Priority::default()).await
+    }
+
+    // Resolves with the message id once the payload is queued, see `mcu1_send_with_priority`
+    pub async fn send_with_priority(
+        &self,
+        payload: impl Into<Payload>,
+        priority: Priority,
+    ) -> Result<u16, ProtocolError> {
+        let mut payload = Some(payload.into());
+        poll_fn(|cx| self.poll_send(cx, &mut payload, priority)).await
+    }
+
+    // `send`, giving up with `Timeout` if there's still no room after `timeout`
+    pub async fn send_timeout(
+        &self,
+        payload: impl Into<Payload>,
+        delay: &impl Delay,
+        timeout: Duration,
+    ) -> Result<u16, ProtocolError> {
+        time::timeout(delay, timeout, self.send(payload)).await?
+    }
+
+    // Queue `payload` once there's room for it, taking it out of the option when it goes
+    fn poll_send(
+        &self,
+        cx: &mut Context<'_>,
+        payload: &mut Option<Payload>,
+        priority: Priority,
+    ) -> Poll<Result<u16, ProtocolError>> {
+        self.locked(|state| {
+            let len = payload.as_ref().map_or(0, |payload| payload.len());
+            if state.must_wait(len) {
+                register(&mut state.senders, cx.waker());
+                return (Poll::Pending, Vec::new());
+            }
+
+            let payload = payload.take().expect("send polled after completion");
+            let result = state.protocol.mcu1_send_with_priority(payload, priority);
+            (Poll::Ready(result), core::mem::take(&mut state.receivers))
+        })
+    }
+
+    // Never waits, same as `mcu1_send`
+    pub fn try_send(&self, payload: impl Into<Payload>) -> Result<u16, ProtocolError> {
+        self.with(|protocol| protocol.mcu1_send(payload))
+    }
+
+    // Next message and whether its checksum checked out, see `mcu2_receive`
+    pub async fn receive(&self) -> (Message, bool) {
+        poll_fn(|cx| self.poll_receive(cx)).await
+    }
+
+    // `receive`, giving up with `Timeout` if nothing turned up within `timeout`
+    pub async fn receive_timeout(
+        &self,
+        delay: &impl Delay,
+        timeout: Duration,
+    ) -> Result<(Message, bool), ProtocolError> {
+        time::timeout(delay, timeout, self.receive()).await
+    }
+
+    fn poll_receive(&self, cx: &mut Context<'_>) -> Poll<(Message, bool)> {
+        self.locked(|state| {
+            let received = if state.protocol.has_pending() {
+                state.protocol.mcu2_receive()
+            } else {
+                None
+            };
+
+            match received {
+                Some(received) => (Poll::Ready(received), core::mem::take(&mut state.senders)),
+                None => {
+                    register(&mut state.receivers, cx.waker());
+                    (Poll::Pending, Vec::new())
+                }
+            }
+        })
+    }
+
+    // Never waits, same as `mcu2_receive`
+    pub fn try_receive(&self) -> Option<(Message, bool)> {
+        self.with(|protocol| protocol.mcu2_receive())
+    }
+
+    // `CommunicationProtocol::enqueue`, waiting for room under `Block`
+    pub async fn enqueue(&self, message: Message) -> Result<(), ProtocolError> {
+        let mut message = Some(message);
+        poll_fn(|cx| {
+            self.locked(|state| {
+                if message
+                    .as_ref()
+                    .is_some_and(|message| state.must_wait_for(message))
+                {
+                    register(&mut state.senders, cx.waker());
+                    return (Poll::Pending, Vec::new());
+                }
+
+                let message = message.take().expect("enqueue polled after completion");
+                let result = state.protocol.enqueue(message);
+                (Poll::Ready(result), core::mem::take(&mut state.receivers))
+            })
+        })
+        .await
+    }
+
+    // `CommunicationProtocol::dequeue`, waiting for something to be queued
+    pub async fn dequeue(&self) -> Message {
+        poll_fn(|cx| {
+            self.locked(|state| match state.protocol.dequeue() {
+                Some(message) => (Poll::Ready(message), core::mem::take(&mut state.senders)),
+                None => {
+                    register(&mut state.receivers, cx.waker());
+                    (Poll::Pending, Vec::new())
+                }
+            })
+        })
+        .await
+    }
+
+    // Receiving side as a `futures::Stream`
+    #[cfg(feature = "futures")]
+    pub fn stream(&self) -> MessageStream {
+        MessageStream {
+            protocol: self.clone(),
+        }
+    }
+
+    // Sending side as a `futures::Sink`
+    #[cfg(feature = "futures")]
+    pub fn sink(&self) -> MessageSink {
+        MessageSink {
+            protocol: self.clone(),
+            pending: None,
+        }
+    }
+}
+
+// Every message that passes its checksum, forever. Corrupted ones are skipped (they still
+// show up in `stats().checksum_failures` or `invalid_fragments`), use `receive` to see them.
+#[cfg(feature = "futures")]
+pub struct MessageStream {
+    protocol: AsyncProtocol,
+}
+
+#[cfg(feature = "futures")]
+impl Stream for MessageStream {
+    type Item = Message;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        loop {
+            match self.protocol.poll_receive(cx) {
+                Poll::Ready((message, true)) => return Poll::Ready(Some(message)),
+                Poll::Ready((_, false)) => continue,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+// Sends each message's payload at its priority, under a fresh id and checksum from this
+// protocol, so `stream.forward(sink)` relays between two links. Holds at most one message
+// while waiting for room, `poll_ready` / `poll_flush` push it out.
+#[cfg(feature = "futures")]
+pub struct MessageSink {
+    protocol: AsyncProtocol,
+    pending: Option<(Payload, Priority)>,
+}
+
+#[cfg(feature = "futures")]
+impl MessageSink {
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), ProtocolError>> {
+        let Some((payload, priority)) = self.pending.take() else {
+            return Poll::Ready(Ok(()));
+        };
+
+        let mut payload = Some(payload);
+        let poll = self.protocol.poll_send(cx, &mut payload, priority);
+        if let Some(payload) = payload {
+            self.pending = Some((payload, priority));
+        }
+        poll.map_ok(|_| ())
+    }
+}
+
+#[cfg(feature = "futures")]
+impl Sink<Message> for MessageSink {
+    type Error = ProtocolError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), ProtocolError>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: Message) -> Result<(), ProtocolError> {
+        self.get_mut().pending = Some((message.payload, message.priority));
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), ProtocolError>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), ProtocolError>> {
+        self.get_mut().poll_pending(cx)
+    }
+}
+
+impl State {
+    // Same rule as `BlockingBuffer`: only `Block` waits, and not for a payload that can never
+    // fit, that one goes through to get its error
+    fn must_wait(&self, len: usize) -> bool {
+        self.protocol.overflow_policy() == OverflowPolicy::Block
+            && self.protocol.can_ever_fit(len)
+            && !self.protocol.has_room_for_payload(len)
+    }
+
+    // Same for a message queued as is
+    fn must_wait_for(&self, message: &Message) -> bool {
+        let buffer = self.protocol.buffer();
+        buffer.overflow_policy() == OverflowPolicy::Block
+            && buffer
+                .byte_budget()
+                .is_none_or(|budget| message.payload.len() <= budget)
+            && !buffer.has_room_for(message)
+    }
+}
+
+// a task polled again replaces its old waker rather than piling up copies
+fn register(wakers: &mut Vec<Waker>, waker: &Waker) {
+    if !wakers.iter().any(|w| w.will_wake(waker)) {
+        wakers.push(waker.clone());
+    }
+}
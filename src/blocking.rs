@@ -0,0 +1,477 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::buffer::{CircularBuffer, OverflowPolicy};
+use crate::error::ProtocolError;
+use crate::message::Message;
+use crate::stats::Stats;
+
+// `CircularBuffer` behind a mutex, with condvars so a producer can sleep until the consumer
+// makes room instead of spin-polling `is_full()`. Share it between threads with an `Arc`.
+//
+// Every operation comes in a polling and a waiting flavour:
+//
+// - `try_send` / `try_receive` never block, `BufferFull` / `None` when they can't go ahead
+// - `send` / `receive` block for as long as it takes
+// - `send_message_timeout` / `receive_timeout` block up to a deadline, then `Timeout`
+pub struct BlockingBuffer {
+    buffer: Mutex<CircularBuffer>,
+    // signalled every time a message is taken out. Everyone waiting gets woken, with a byte
+    // budget a small message may fit where a big one still doesn't.
+    space: Condvar,
+    // signalled every time a message is put in
+    data: Condvar,
+}
+
+impl BlockingBuffer {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self::from_buffer(CircularBuffer::new(capacity, policy))
+    }
+
+    pub fn from_buffer(buffer: CircularBuffer) -> Self {
+        BlockingBuffer {
+            buffer: Mutex::new(buffer),
+            space: Condvar::new(),
+            data: Condvar::new(),
+        }
+    }
+
+    // Direct access to the buffer underneath, don't hold on to it across a blocking call
+    pub fn lock(&self) -> MutexGuard<'_, CircularBuffer> {
+        // a panic on another thread while holding the lock doesn't leave the queue in a
+        // broken state, so just carry on with it
+        self.buffer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Non-blocking send, same semantics as `CircularBuffer::try_send`
+    pub fn try_send(&self, message: Message) -> Result<(), ProtocolError> {
+        self.lock().try_send(message)?;
+        self.data.notify_one();
+        Ok(())
+    }
+
+    // Same as `try_send`
+    pub fn send_message(&self, message: Message) -> Result<(), ProtocolError> {
+        self.try_send(message)
+    }
+
+    // Send, waiting for as long as it takes for room to free up (RejectNew/Block policies)
+    pub fn send(&self, message: Message) -> Result<(), ProtocolError> {
+        let mut buffer = self.lock();
+        while must_wait(&buffer, &message) {
+            buffer = self
+                .space
+                .wait(buffer)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+
+        buffer.try_send(message)?;
+        drop(buffer);
+        self.data.notify_one();
+        Ok(())
+    }
+
+    // Send, waiting up to `timeout` for room if the buffer is full. Only makes a difference
+    // under the RejectNew/Block policies, DropOldest makes room or refuses right away. On
+    // timeout the message is dropped and `ProtocolError::Timeout` returned.
+    pub fn send_message_timeout(
+        &self,
+        message: Message,
+        timeout: Duration,
+    ) -> Result<(), ProtocolError> {
+        let deadline = Instant::now() + timeout;
+        let mut buffer = self.lock();
+
+        while must_wait(&buffer, &message) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(ProtocolError::Timeout);
+            }
+            buffer = self
+                .space
+                .wait_timeout(buffer, remaining)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+
+        buffer.try_send(message)?;
+        drop(buffer);
+        self.data.notify_one();
+        Ok(())
+    }
+
+    // Non-blocking receive, `None` if nothing is queued. Wakes up a producer waiting for space.
+    pub fn try_receive(&self) -> Option<Message> {
+        let message = self.lock().try_receive();
+        if message.is_some() {
+            self.space.notify_all();
+        }
+        message
+    }
+
+    // Same as `try_receive`
+    pub fn receive_message(&self) -> Option<Message> {
+        self.try_receive()
+    }
+
+    // Wait until a message shows up
+    pub fn receive(&self) -> Message {
+        let mut buffer = self.lock();
+        loop {
+            if let Some(message) = buffer.try_receive() {
+                drop(buffer);
+                self.space.notify_all();
+                return message;
+            }
+            buffer = self
+                .data
+                .wait(buffer)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    // Wait up to `timeout` for a message, `ProtocolError::Timeout` if none showed up
+    pub fn receive_timeout(&self, timeout: Duration) -> Result<Message, ProtocolError> {
+        let deadline = Instant::now() + timeout;
+        let mut buffer = self.lock();
+        loop {
+            if let Some(message) = buffer.try_receive() {
+                drop(buffer);
+                self.space.notify_all();
+                return Ok(message);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(ProtocolError::Timeout);
+            }
+            buffer = self
+                .data
+                .wait_timeout(buffer, remaining)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+    }
+
+    // Retract a queued message by id
+    pub fn remove(&self, id: u16) -> Option<Message> {
+        let message = self.lock().remove(id);
+        if message.is_some() {
+            self.space.notify_all();
+        }
+        message
+    }
+
+    // Filter the queue in place, see `CircularBuffer::retain`
+    pub fn retain<F>(&self, keep: F) -> usize
+    where
+        F: FnMut(&Message) -> bool,
+    {
+        let removed = self.lock().retain(keep);
+        if removed > 0 {
+            self.space.notify_all();
+        }
+        removed
+    }
+
+    // Change the capacity, see `CircularBuffer::resize`
+    pub fn resize(&self, new_capacity: usize) -> Result<Vec<Message>, ProtocolError> {
+        let evicted = self.lock().resize(new_capacity)?;
+        // growing may have made room for a waiting producer
+        self.space.notify_all();
+        Ok(evicted)
+    }
+
+    // Change the byte budget, see `CircularBuffer::set_byte_budget`
+    pub fn set_byte_budget(&self, budget: Option<usize>) -> Result<Vec<Message>, ProtocolError> {
+        let evicted = self.lock().set_byte_budget(budget)?;
+        self.space.notify_all();
+        Ok(evicted)
+    }
+
+    // Take everything out at once, wakes up every producer waiting for space
+    pub fn drain(&self) -> Vec<Message> {
+        let messages: Vec<Message> = self.lock().drain().collect();
+        if !messages.is_empty() {
+            self.space.notify_all();
+        }
+        messages
+    }
+
+    // Copy of the next message without taking it out
+    pub fn peek(&self) -> Option<Message> {
+        self.lock().peek().cloned()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.lock().stats()
+    }
+
+    pub fn reset_stats(&self) {
+        self.lock().reset_stats();
+    }
+
+    pub fn length(&self) -> usize {
+        self.lock().length()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.lock().is_full()
+    }
+}
+
+// No room for `message` yet, but there will be once the consumer catches up. Payloads over
+// the byte budget never fit, those go straight through to `try_send` and its error.
+fn must_wait(buffer: &CircularBuffer, message: &Message) -> bool {
+    let fits_at_all = buffer
+        .byte_budget()
+        .is_none_or(|budget| message.payload.len() <= budget);
+    buffer.overflow_policy() != OverflowPolicy::DropOldest
+        && fits_at_all
+        && !buffer.has_room_for(message)
+}
+
+// `BlockingBuffer` split into a sending and a receiving handle, for simulating MCU1 and MCU2
+// as two threads without hand-rolling the locking:
+//
+//   let (tx, rx) = SharedBuffer::new(16, OverflowPolicy::Block).split();
+//   thread::spawn(move || tx.send(message));
+//   let message = rx.receive()?;
+//
+// Both handles can be cloned. Once every `Sender` is gone the `Receiver` drains what's left
+// and then gets `Disconnected` instead of waiting forever, same the other way round.
+pub struct SharedBuffer {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    buffer: BlockingBuffer,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+}
+
+impl SharedBuffer {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self::from_buffer(CircularBuffer::new(capacity, policy))
+    }
+
+    pub fn from_buffer(buffer: CircularBuffer) -> Self {
+        SharedBuffer {
+            shared: Arc::new(Shared {
+                buffer: BlockingBuffer::from_buffer(buffer),
+                senders: AtomicUsize::new(0),
+                receivers: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    // The buffer both handles go through, e.g. for stats
+    pub fn buffer(&self) -> &BlockingBuffer {
+        &self.shared.buffer
+    }
+
+    pub fn split(self) -> (Sender, Receiver) {
+        self.shared.senders.store(1, Ordering::SeqCst);
+        self.shared.receivers.store(1, Ordering::SeqCst);
+        (
+            Sender {
+                shared: self.shared.clone(),
+            },
+            Receiver {
+                shared: self.shared,
+            },
+        )
+    }
+}
+
+// MCU1's end
+pub struct Sender {
+    shared: Arc<Shared>,
+}
+
+impl Sender {
+    fn connected(&self) -> bool {
+        self.shared.receivers.load(Ordering::SeqCst) > 0
+    }
+
+    // Non-blocking send, see `BlockingBuffer::try_send`
+    pub fn try_send(&self, message: Message) -> Result<(), ProtocolError> {
+        if !self.connected() {
+            return Err(ProtocolError::Disconnected);
+        }
+        self.shared.buffer.try_send(message)
+    }
+
+    // Send, waiting for room as long as there's a receiver left to make some
+    pub fn send(&self, message: Message) -> Result<(), ProtocolError> {
+        let buffer = &self.shared.buffer;
+        let mut guard = buffer.lock();
+        while must_wait(&guard, &message) && self.connected() {
+            guard = buffer
+                .space
+                .wait(guard)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        if !self.connected() {
+            return Err(ProtocolError::Disconnected);
+        }
+
+        guard.try_send(message)?;
+        drop(guard);
+        buffer.data.notify_one();
+        Ok(())
+    }
+
+    // Send, waiting up to `timeout` for room
+    pub fn send_timeout(&self, message: Message, timeout: Duration) -> Result<(), ProtocolError> {
+        let buffer = &self.shared.buffer;
+        let deadline = Instant::now() + timeout;
+        let mut guard = buffer.lock();
+        while must_wait(&guard, &message) && self.connected() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(ProtocolError::Timeout);
+            }
+            guard = buffer
+                .space
+                .wait_timeout(guard, remaining)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+        if !self.connected() {
+            return Err(ProtocolError::Disconnected);
+        }
+
+        guard.try_send(message)?;
+        drop(guard);
+        buffer.data.notify_one();
+        Ok(())
+    }
+
+    pub fn buffer(&self) -> &BlockingBuffer {
+        &self.shared.buffer
+    }
+}
+
+impl Clone for Sender {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::SeqCst);
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        // under the lock, so a receiver can't check the count and then miss the wakeup
+        let guard = self.shared.buffer.lock();
+        self.shared.senders.fetch_sub(1, Ordering::SeqCst);
+        drop(guard);
+        self.shared.buffer.data.notify_all();
+    }
+}
+
+// MCU2's end
+pub struct Receiver {
+    shared: Arc<Shared>,
+}
+
+impl Receiver {
+    fn connected(&self) -> bool {
+        self.shared.senders.load(Ordering::SeqCst) > 0
+    }
+
+    // Non-blocking receive, `None` if nothing's queued right now
+    pub fn try_receive(&self) -> Option<Message> {
+        self.shared.buffer.try_receive()
+    }
+
+    // Wait for the next message. `Disconnected` once the queue is empty and every sender has
+    // been dropped.
+    pub fn receive(&self) -> Result<Message, ProtocolError> {
+        let buffer = &self.shared.buffer;
+        let mut guard = buffer.lock();
+        loop {
+            if let Some(message) = guard.try_receive() {
+                drop(guard);
+                buffer.space.notify_all();
+                return Ok(message);
+            }
+            if !self.connected() {
+                return Err(ProtocolError::Disconnected);
+            }
+            guard = buffer
+                .data
+                .wait(guard)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    // Same as `receive`, giving up with `Timeout` after `timeout`
+    pub fn receive_timeout(&self, timeout: Duration) -> Result<Message, ProtocolError> {
+        let buffer = &self.shared.buffer;
+        let deadline = Instant::now() + timeout;
+        let mut guard = buffer.lock();
+        loop {
+            if let Some(message) = guard.try_receive() {
+                drop(guard);
+                buffer.space.notify_all();
+                return Ok(message);
+            }
+            if !self.connected() {
+                return Err(ProtocolError::Disconnected);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(ProtocolError::Timeout);
+            }
+            guard = buffer
+                .data
+                .wait_timeout(guard, remaining)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+    }
+
+    pub fn buffer(&self) -> &BlockingBuffer {
+        &self.shared.buffer
+    }
+}
+
+impl Clone for Receiver {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::SeqCst);
+        Receiver {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        let guard = self.shared.buffer.lock();
+        self.shared.receivers.fetch_sub(1, Ordering::SeqCst);
+        drop(guard);
+        self.shared.buffer.space.notify_all();
+    }
+}
+
+// Every message until all senders are gone
+impl Iterator for Receiver {
+    type Item = Message;
+
+    fn next(&mut self) -> Option<Message> {
+        self.receive().ok()
+    }
+}
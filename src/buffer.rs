@@ -0,0 +1,526 @@
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+use alloc::collections::VecDeque;
+#[cfg(feature = "alloc")]
+use alloc::collections::vec_deque::{Drain, Iter, IterMut};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+#[cfg(feature = "alloc")]
+use crate::error::ProtocolError;
+#[cfg(feature = "alloc")]
+use crate::message::{Message, Priority};
+#[cfg(feature = "alloc")]
+use crate::stats::Stats;
+
+// What `send_message` does when the buffer is already at capacity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    // make room by throwing away the oldest message of the lowest priority (the original
+    // behaviour, fine for telemetry where only the latest values matter). A message nothing
+    // queued ranks at or below still gets `BufferFull`
+    #[default]
+    DropOldest,
+    // refuse the new message with `ProtocolError::BufferFull`, nothing queued is lost
+    RejectNew,
+    // producer should wait for space. `send_message` can't wait on its own so it reports
+    // `BufferFull` like RejectNew, the blocking send variants wait and retry instead.
+    Block,
+}
+
+// Fired when the queue depth crosses a configured watermark, so the producer can throttle
+// before messages start getting dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferEvent {
+    // depth rose to the high watermark
+    AlmostFull { depth: usize },
+    // depth fell back to the low watermark after an AlmostFull
+    AlmostEmpty { depth: usize },
+}
+
+// Event thresholds in messages. There's hysteresis between the two: after AlmostFull nothing
+// else fires until the depth is back down to `low`, and the other way round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watermarks {
+    pub high: usize,
+    pub low: usize,
+}
+
+// Called with every watermark event. Runs inside the buffer operation that caused it (and
+// under the lock for `BlockingBuffer`), so it must not call back into the buffer.
+#[cfg(feature = "alloc")]
+pub type EventCallback = Box<dyn FnMut(BufferEvent) + Send>;
+
+// Shared communication between MCU1->MCU2
+//
+// Messages are kept in the order they'll be handed out: highest priority first, oldest first
+// within the same priority.
+#[cfg(feature = "alloc")]
+pub struct CircularBuffer {
+    buffer: VecDeque<Message>,
+    capacity: usize,
+    // optional cap on the total queued payload bytes, on top of the message count
+    byte_budget: Option<usize>,
+    policy: OverflowPolicy,
+    stats: Stats,
+    watermarks: Option<Watermarks>,
+    above_high: bool,
+    on_event: Option<EventCallback>,
+}
+
+#[cfg(feature = "alloc")]
+impl CircularBuffer {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        CircularBuffer {
+            buffer: VecDeque::with_capacity(capacity),
+            capacity,
+            byte_budget: None,
+            policy,
+            stats: Stats::new(),
+            watermarks: None,
+            above_high: false,
+            on_event: None,
+        }
+    }
+
+    // Send message to buffer, same as `try_send`
+    pub fn send_message(&mut self, message: Message) -> Result<(), ProtocolError> {
+        self.try_send(message)
+    }
+
+    // Receive message, same as `try_receive`
+    pub fn receive_message(&mut self) -> Option<Message> {
+        self.try_receive()
+    }
+
+    // Non-blocking send, never waits. When the buffer is full (by count or by bytes) what
+    // happens is up to the overflow policy: DropOldest makes room and succeeds, unless only
+    // higher priority messages are queued, RejectNew/Block return `ProtocolError::BufferFull`
+    // and leave the queue untouched. A payload bigger than
+    // the whole byte budget can never go in and gets `PayloadTooLarge`.
+    pub fn try_send(&mut self, message: Message) -> Result<(), ProtocolError> {
+        if let Some(budget) = self.byte_budget
+            && message.payload.len() > budget
+        {
+            return Err(ProtocolError::PayloadTooLarge {
+                len: message.payload.len(),
+                max: budget,
+            });
+        }
+
+        if !self.has_room_for(&message) {
+            match self.policy {
+                OverflowPolicy::RejectNew | OverflowPolicy::Block => {
+                    self.stats.rejected_full += 1;
+                    return Err(ProtocolError::BufferFull);
+                }
+                OverflowPolicy::DropOldest => {
+                    // Only messages ranking at or below the new one may go to make room. If
+                    // throwing all of those out still isn't enough the new one is refused
+                    // instead, otherwise evict the usual way until it fits.
+                    if !self.can_evict_for(&message) {
+                        self.stats.rejected_full += 1;
+                        return Err(ProtocolError::BufferFull);
+                    }
+                    while !self.has_room_for(&message) {
+                        self.evict();
+                    }
+                }
+            }
+        }
+
+        // behind everything of the same or higher priority
+        let index = self
+            .buffer
+            .iter()
+            .position(|m| m.priority < message.priority)
+            .unwrap_or(self.buffer.len());
+        self.record_sent(&message);
+        self.buffer.insert(index, message);
+        self.stats.high_watermark = self.stats.high_watermark.max(self.buffer.len() as u64);
+        self.check_watermarks(self.buffer.len());
+        Ok(())
+    }
+
+    // Whether `count` more messages holding `bytes` payload bytes fit without evicting
+    pub fn has_room(&self, count: usize, bytes: usize) -> bool {
+        let slots = self.buffer.len() + count <= self.capacity;
+        let budget = self
+            .byte_budget
+            .is_none_or(|budget| self.queued_bytes() + bytes <= budget);
+        slots && budget
+    }
+
+    // Whether `count` messages at `priority` holding `bytes` payload bytes (the fragments of
+    // one payload) can go in together and all stay: without evicting anything under
+    // RejectNew/Block, under DropOldest by evicting only what was there before and ranks
+    // with or below them, never each other
+    pub fn can_take(&self, count: usize, bytes: usize, priority: Priority) -> bool {
+        if self.policy != OverflowPolicy::DropOldest {
+            return self.has_room(count, bytes);
+        }
+        let (kept, kept_bytes) = self
+            .buffer
+            .iter()
+            .filter(|m| m.priority > priority)
+            .fold((0, 0), |(count, bytes), m| {
+                (count + 1, bytes + m.payload.len())
+            });
+        let slots = kept + count <= self.capacity;
+        let budget = self
+            .byte_budget
+            .is_none_or(|budget| kept_bytes + bytes <= budget);
+        slots && budget
+    }
+
+    pub fn has_room_for(&self, message: &Message) -> bool {
+        self.has_room(1, message.payload.len())
+    }
+
+    // would evicting everything of the same or lower priority make room for `message`
+    fn can_evict_for(&self, message: &Message) -> bool {
+        let (count, bytes) = self
+            .buffer
+            .iter()
+            .filter(|m| m.priority > message.priority)
+            .fold((0, 0), |(count, bytes), m| {
+                (count + 1, bytes + m.payload.len())
+            });
+        let slots = count < self.capacity;
+        let budget = self
+            .byte_budget
+            .is_none_or(|budget| bytes + message.payload.len() <= budget);
+        slots && budget
+    }
+
+    // Throw out the oldest message of the lowest priority
+    fn evict(&mut self) -> Option<Message> {
+        let lowest = self.buffer.back()?.priority;
+        let index = self.buffer.iter().position(|m| m.priority == lowest)?;
+        let evicted = self.buffer.remove(index);
+        self.stats.dropped_overflow += 1;
+        self.check_watermarks(self.buffer.len());
+        evicted
+    }
+
+    // Watch the queue depth and report crossings through the event callback
+    pub fn set_watermarks(&mut self, watermarks: Watermarks) {
+        self.watermarks = Some(watermarks);
+        self.above_high = false;
+        self.check_watermarks(self.buffer.len());
+    }
+
+    pub fn clear_watermarks(&mut self) {
+        self.watermarks = None;
+        self.above_high = false;
+    }
+
+    pub fn watermarks(&self) -> Option<Watermarks> {
+        self.watermarks
+    }
+
+    pub fn on_event<F>(&mut self, callback: F)
+    where
+        F: FnMut(BufferEvent) + Send + 'static,
+    {
+        self.on_event = Some(Box::new(callback));
+    }
+
+    // Between an AlmostFull and the AlmostEmpty that follows it
+    pub fn is_above_high_watermark(&self) -> bool {
+        self.above_high
+    }
+
+    fn check_watermarks(&mut self, depth: usize) {
+        let Some(watermarks) = self.watermarks else {
+            return;
+        };
+
+        let event = if !self.above_high && depth >= watermarks.high {
+            self.above_high = true;
+            BufferEvent::AlmostFull { depth }
+        } else if self.above_high && depth <= watermarks.low {
+            self.above_high = false;
+            BufferEvent::AlmostEmpty { depth }
+        } else {
+            return;
+        };
+
+        if let Some(callback) = self.on_event.as_mut() {
+            callback(event);
+        }
+    }
+
+    fn record_sent(&mut self, message: &Message) {
+        self.stats.messages_sent += 1;
+        self.stats.bytes_sent += message.payload.len() as u64;
+    }
+
+    fn record_received(&mut self, message: &Message) {
+        self.stats.messages_received += 1;
+        self.stats.bytes_received += message.payload.len() as u64;
+    }
+
+    // Change the capacity at runtime, e.g. grow the queue when switching into a high
+    // throughput logging mode. Growing always works. Shrinking below the number of queued
+    // messages follows the overflow policy:
+    //
+    // - DropOldest evicts the same messages an overflow would (oldest of the lowest priority
+    //   first) and hands them back
+    // - RejectNew/Block promise never to lose a queued message, so the resize is refused
+    //   with `BufferFull` and nothing changes
+    pub fn resize(&mut self, new_capacity: usize) -> Result<Vec<Message>, ProtocolError> {
+        let excess = self.buffer.len().saturating_sub(new_capacity);
+        if excess > 0 && self.policy != OverflowPolicy::DropOldest {
+            return Err(ProtocolError::BufferFull);
+        }
+
+        let evicted = (0..excess).filter_map(|_| self.evict()).collect();
+        self.capacity = new_capacity;
+        if new_capacity > self.buffer.capacity() {
+            self.buffer.reserve(new_capacity - self.buffer.len());
+        } else {
+            self.buffer.shrink_to(new_capacity);
+        }
+        Ok(evicted)
+    }
+
+    // Limit the total queued payload size as well as the message count, `None` takes the
+    // limit off again. Lowering it below what's queued follows the same rules as shrinking
+    // with `resize`.
+    pub fn set_byte_budget(
+        &mut self,
+        budget: Option<usize>,
+    ) -> Result<Vec<Message>, ProtocolError> {
+        let Some(limit) = budget else {
+            self.byte_budget = None;
+            return Ok(Vec::new());
+        };
+
+        if self.queued_bytes() > limit && self.policy != OverflowPolicy::DropOldest {
+            return Err(ProtocolError::BufferFull);
+        }
+
+        let mut evicted = Vec::new();
+        while self.queued_bytes() > limit {
+            evicted.extend(self.evict());
+        }
+        self.byte_budget = budget;
+        Ok(evicted)
+    }
+
+    pub fn byte_budget(&self) -> Option<usize> {
+        self.byte_budget
+    }
+
+    // Non-blocking receive, `None` means the buffer is empty right now
+    pub fn try_receive(&mut self) -> Option<Message> {
+        if let Some(message) = self.buffer.pop_front() {
+            self.record_received(&message);
+            self.check_watermarks(self.buffer.len());
+            Some(message)
+        } else {
+            None
+        }
+    }
+
+    // The message `try_receive` would hand out next, left in the buffer
+    pub fn peek(&self) -> Option<&Message> {
+        self.buffer.front()
+    }
+
+    // Same as `peek` but mutable. Changing `priority` through it doesn't move the message,
+    // it stays at the front.
+    pub fn peek_mut(&mut self) -> Option<&mut Message> {
+        self.buffer.front_mut()
+    }
+
+    // Pull a queued message back out by id before the receiver gets to it, e.g. a command
+    // that's been superseded. `None` if it's not (or no longer) in the buffer.
+    pub fn remove(&mut self, id: u16) -> Option<Message> {
+        let index = self.buffer.iter().position(|m| m.id == id)?;
+        let message = self.buffer.remove(index);
+        self.check_watermarks(self.buffer.len());
+        message
+    }
+
+    // Keep only the messages `keep` says yes to, in one pass, e.g. purge all stale telemetry
+    // while leaving commands queued. Returns how many were thrown out.
+    pub fn retain<F>(&mut self, keep: F) -> usize
+    where
+        F: FnMut(&Message) -> bool,
+    {
+        let before = self.buffer.len();
+        self.buffer.retain(keep);
+        self.check_watermarks(self.buffer.len());
+        before - self.buffer.len()
+    }
+
+    // Walk the queued messages in receive order without taking them out, e.g. to list the
+    // pending ids and sizes from diagnostic code
+    pub fn iter(&self) -> Iter<'_, Message> {
+        self.buffer.iter()
+    }
+
+    // Mutable walk over the queue. Like `peek_mut`, editing `priority` doesn't reorder anything.
+    pub fn iter_mut(&mut self) -> IterMut<'_, Message> {
+        self.buffer.iter_mut()
+    }
+
+    // Take everything out in receive order, e.g. to flush the queue on shutdown or a mode
+    // switch. The buffer is empty afterwards even if the iterator isn't run to the end.
+    pub fn drain(&mut self) -> Drain<'_, Message> {
+        self.stats.messages_received += self.buffer.len() as u64;
+        self.stats.bytes_received += self.queued_bytes() as u64;
+        // it's all going, the drain iterator doesn't give anything back
+        self.check_watermarks(0);
+        self.buffer.drain(..)
+    }
+
+    // empty check
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    // full check, by message count or by the byte budget if that's run out completely
+    pub fn is_full(&self) -> bool {
+        self.buffer.len() >= self.capacity
+            || self
+                .byte_budget
+                .is_some_and(|budget| self.queued_bytes() >= budget)
+    }
+
+    // length of buffer
+    pub fn length(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.policy
+    }
+
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.policy = policy;
+    }
+
+    // capacity of buffer
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // payload bytes currently queued
+    pub fn queued_bytes(&self) -> usize {
+        self.buffer.iter().map(|m| m.payload.len()).sum()
+    }
+
+    // total messages written / read since the stats were last reset
+    pub fn write_count(&self) -> usize {
+        self.stats.messages_sent as usize
+    }
+
+    pub fn read_count(&self) -> usize {
+        self.stats.messages_received as usize
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    // Zero the counters, the high watermark restarts from the current depth
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats {
+            high_watermark: self.buffer.len() as u64,
+            ..Stats::new()
+        };
+    }
+
+    pub fn high_watermark(&self) -> usize {
+        self.stats.high_watermark as usize
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> IntoIterator for &'a CircularBuffer {
+    type Item = &'a Message;
+    type IntoIter = Iter<'a, Message>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> IntoIterator for &'a mut CircularBuffer {
+    type Item = &'a mut Message;
+    type IntoIter = IterMut<'a, Message>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn message(id: u16, priority: Priority) -> Message {
+        let mut message = Message::new(id, vec![id as u8]);
+        message.priority = priority;
+        message
+    }
+
+    #[test]
+    fn drop_oldest_evicts_the_oldest_of_the_lowest_priority() {
+        let mut buffer = CircularBuffer::new(3, OverflowPolicy::DropOldest);
+        buffer.try_send(message(1, Priority::Low)).unwrap();
+        buffer.try_send(message(2, Priority::High)).unwrap();
+        buffer.try_send(message(3, Priority::Low)).unwrap();
+        buffer.try_send(message(4, Priority::Normal)).unwrap();
+
+        let ids: Vec<u16> = buffer.iter().map(|message| message.id).collect();
+        assert_eq!(ids, [2, 4, 3]);
+        assert_eq!(buffer.stats().dropped_overflow, 1);
+        assert_eq!(buffer.stats().messages_sent, 4);
+    }
+
+    #[test]
+    fn drop_oldest_refuses_what_it_cant_make_room_for() {
+        let mut buffer = CircularBuffer::new(2, OverflowPolicy::DropOldest);
+        buffer.try_send(message(1, Priority::High)).unwrap();
+        buffer.try_send(message(2, Priority::High)).unwrap();
+
+        assert_eq!(
+            buffer.try_send(message(3, Priority::Normal)),
+            Err(ProtocolError::BufferFull)
+        );
+        let stats = buffer.stats();
+        assert_eq!(stats.messages_sent, 2);
+        assert_eq!(stats.rejected_full, 1);
+        assert_eq!(stats.dropped_overflow, 0);
+        assert_eq!(buffer.length(), 2);
+    }
+
+    #[test]
+    fn reject_new_leaves_the_queue_alone() {
+        let mut buffer = CircularBuffer::new(1, OverflowPolicy::RejectNew);
+        buffer.try_send(message(1, Priority::Low)).unwrap();
+        assert_eq!(
+            buffer.try_send(message(2, Priority::Critical)),
+            Err(ProtocolError::BufferFull)
+        );
+        assert_eq!(buffer.peek().map(|message| message.id), Some(1));
+    }
+
+    #[test]
+    fn can_take_matches_what_try_send_would_do() {
+        let mut buffer = CircularBuffer::new(4, OverflowPolicy::DropOldest);
+        for id in 0..3 {
+            buffer.try_send(message(id, Priority::High)).unwrap();
+        }
+        assert!(buffer.can_take(1, 1, Priority::Normal));
+        assert!(!buffer.can_take(2, 2, Priority::Normal));
+        assert!(buffer.can_take(4, 4, Priority::Critical));
+    }
+}
@@ -0,0 +1,268 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::buffer::{CircularBuffer, OverflowPolicy};
+use crate::message::Message;
+use crate::reorder::{DEFAULT_REORDER_TIMEOUT, ReorderBuffer};
+use crate::retransmit::{ArqMode, RetransmitPolicy, SendWindow};
+use crate::sequence::{DEFAULT_DUPLICATE_WINDOW, DuplicateFilter};
+
+// Channels of an `Endpoint`: independent streams between the same two nodes, each with its
+// own queues, message ids and delivery guarantees, like DDS topics or QUIC streams:
+//
+//   let endpoint = Endpoint::new(uart, Cobs::default(), 8)
+//       .with_codec(Codec::new(Crc16Ccitt, FormatVersion::V3))
+//       // commands: acked, resent, in order
+//       .with_channel_config(1, ChannelConfig { ordered: true, ..ChannelConfig::reliable(8) })
+//       // telemetry: only the latest values matter
+//       .with_channel_config(2, ChannelConfig::best_effort(4))
+//       // logs: deep queue, the application waits rather than losing lines
+//       .with_channel_config(3, ChannelConfig {
+//           overflow_policy: OverflowPolicy::RejectNew,
+//           ..ChannelConfig::best_effort(64)
+//       });
+//
+// A reliable channel keeps every frame it sends (fragments one by one) until the other end
+// acks it, and sends it again on timeout as `RetransmitPolicy` says. A channel with a full
+// send window waits without holding up the others. The receiver acks on the same channel and
+// drops copies it already has.
+//
+// The receiver acks every frame on its own, so frames are always resent one by one.
+// `ArqMode::SelectiveRepeat` makes the channel ordered as well: the receiver holds what
+// arrives ahead of a gap and hands it on once the gap is filled, with no more than `depth`
+// ids in flight. `ArqMode::GoBackN` needs cumulative acks, which channels don't have, so a
+// channel set up with it runs selective repeat instead: the same in order delivery within
+// the same window, only a loss resends the one frame rather than everything after it.
+// `Endpoint::channel_config` reports `SelectiveRepeat` for it.
+//
+// An ordered channel puts frames back in id order before they're handed on, see
+// `ReorderBuffer`. Gaps are waited on as long as the sender would keep retrying, or
+// `DEFAULT_REORDER_TIMEOUT` on a best effort channel, then skipped.
+//
+// Both ends have to configure a channel the same way, nothing about it goes on the wire but
+// the channel id.
+//
+// A channel can be paused from either end (`Endpoint::pause`), the rest keep going. Sends
+// still queue up on a paused channel, it just doesn't transmit.
+//
+// The endpoint's own traffic (acks, flow control, heartbeats ...) goes on `CONTROL_CHANNEL`,
+// which can't be opened for data. It has a queue of its own that goes out before anything
+// else, retransmissions included, and it's handled as soon as it's read, never waiting
+// behind data in a queue or a reorder buffer.
+
+// Independent stream of messages between two endpoints
+pub type ChannelId = u8;
+
+// The channel `Endpoint::send` and friends use, open on every endpoint
+pub const DEFAULT_CHANNEL: ChannelId = 0;
+
+// Reserved for protocol messages
+pub const CONTROL_CHANNEL: ChannelId = 0xFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelConfig {
+    // messages the TX queue holds, and the RX queue when there's no receive handler
+    pub depth: usize,
+    // what a full queue does, on either side. A full RX queue on a reliable channel doesn't
+    // ack (unless it drops the oldest), so the sender tries again later.
+    pub overflow_policy: OverflowPolicy,
+    // `Some` = reliable, acked and resent until it gets through. `None` = best effort.
+    pub retransmit: Option<RetransmitPolicy>,
+    // hand messages out in the order they were sent
+    pub ordered: bool,
+    // payloads start with a topic id and go to its subscribers, see `topic`
+    pub topics: bool,
+    // payloads are requests and responses, see `rpc`
+    pub rpc: bool,
+}
+
+impl ChannelConfig {
+    // Fire and forget, unordered
+    pub fn best_effort(depth: usize) -> Self {
+        ChannelConfig {
+            depth,
+            overflow_policy: OverflowPolicy::default(),
+            retransmit: None,
+            ordered: false,
+            topics: false,
+            rpc: false,
+        }
+    }
+
+    // Acked and resent with the default `RetransmitPolicy`, unordered. Full queues refuse
+    // new messages rather than dropping old ones.
+    pub fn reliable(depth: usize) -> Self {
+        ChannelConfig {
+            overflow_policy: OverflowPolicy::RejectNew,
+            retransmit: Some(RetransmitPolicy::default()),
+            ..Self::best_effort(depth)
+        }
+    }
+
+    pub fn is_reliable(&self) -> bool {
+        self.retransmit.is_some()
+    }
+
+    // How long an ordered channel waits on a gap
+    fn reorder_timeout(&self) -> Duration {
+        match self.retransmit {
+            Some(policy) => (0..=policy.max_retries)
+                .map(|retries| policy.timeout_after(retries))
+                .sum(),
+            None => DEFAULT_REORDER_TIMEOUT,
+        }
+    }
+}
+
+pub(crate) struct Channel {
+    pub(crate) id: ChannelId,
+    pub(crate) config: ChannelConfig,
+    pub(crate) tx: CircularBuffer,
+    pub(crate) rx: VecDeque<Message>,
+    // ids count per channel, so an ordered channel sees no gaps for the others' messages
+    pub(crate) next_message: u16,
+    // reliable channels only
+    pub(crate) window: Option<SendWindow>,
+    pub(crate) duplicates: Option<DuplicateFilter>,
+    // ordered channels only
+    pub(crate) reorder: Option<ReorderBuffer>,
+    // held by either end, nothing goes out (retransmissions included) until it's resumed
+    pub(crate) paused: bool,
+}
+
+impl Channel {
+    pub(crate) fn new(id: ChannelId, mut config: ChannelConfig) -> Self {
+        // selective repeat is individual acks and resends on top of the reorder buffer,
+        // Go-Back-N gets the same
+        if let Some(policy) = config.retransmit.as_mut()
+            && policy.mode.is_ordered()
+        {
+            policy.mode = ArqMode::SelectiveRepeat;
+            config.ordered = true;
+        }
+        // no more in flight than the other end has room to hold. The window only keeps and
+        // resends, the ordering is the reorder buffer's job, so it works one by one.
+        let retransmit = config.retransmit.map(|policy| RetransmitPolicy {
+            mode: ArqMode::Individual,
+            window: policy.window.min(config.depth),
+            ..policy
+        });
+        let window = retransmit.map(SendWindow::new);
+        let duplicates = retransmit
+            .map(|policy| DuplicateFilter::new(DEFAULT_DUPLICATE_WINDOW.max(2 * policy.window)));
+        let reorder = config
+            .ordered
+            .then(|| ReorderBuffer::starting_at(config.depth, config.reorder_timeout(), 1));
+        Channel {
+            id,
+            config,
+            tx: CircularBuffer::new(config.depth, config.overflow_policy),
+            rx: VecDeque::new(),
+            next_message: 1,
+            window,
+            duplicates,
+            reorder,
+            paused: false,
+        }
+    }
+
+    pub(crate) fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.config.overflow_policy = policy;
+        self.tx.set_overflow_policy(policy);
+    }
+
+    // Next frame to go out, unless the send window is full. Reliable channels keep a copy.
+    pub(crate) fn next_to_send(&mut self, now: Duration) -> Option<Message> {
+        if self.paused {
+            return None;
+        }
+        if let Some(window) = self.window.as_ref() {
+            if window.is_full() {
+                return None;
+            }
+            // an ordered receiver can't hold more than `depth` ids from the oldest unacked on
+            if self.reorder.is_some()
+                && let (Some(oldest), Some(next)) = (window.oldest(), self.tx.peek())
+                && next.id.wrapping_sub(oldest) as usize >= self.config.depth
+            {
+                return None;
+            }
+        }
+        let message = self.tx.receive_message()?;
+        if let Some(window) = self.window.as_mut() {
+            let _ = window.push(message.id, Vec::from([message.clone()]), now);
+        }
+        Some(message)
+    }
+
+    // Whether another message fits in the RX queue without dropping one
+    pub(crate) fn has_rx_room(&self) -> bool {
+        self.rx.len() < self.config.depth
+    }
+
+    // Same for frame `id` on its way in, counting what the reorder buffer still has to hand
+    // on. The last slot is kept for the frame that fills the gap.
+    pub(crate) fn has_rx_room_for(&self, id: u16) -> bool {
+        let Some(reorder) = self.reorder.as_ref() else {
+            return self.has_rx_room();
+        };
+        let reserved = (reorder.expected() != Some(id)) as usize;
+        self.rx.len() + reorder.waiting() + reorder.ready() + reserved < self.config.depth
+    }
+
+    // New connection, ids start over both ways. Whatever was queued for the old one is
+    // dropped, the other end wouldn't take it.
+    pub(crate) fn start_session(&mut self, local_first: u16, remote_first: u16) {
+        let dropped = self.tx.drain().count();
+        if dropped > 0 {
+            log!(
+                "Endpoint: {} queued on channel {} dropped",
+                dropped,
+                self.id
+            );
+        }
+        self.next_message = local_first;
+        if let Some(window) = self.window.as_mut() {
+            window.clear();
+        }
+        if let Some(duplicates) = self.duplicates.as_mut() {
+            duplicates.clear();
+        }
+        if self.reorder.is_some() {
+            self.reorder = Some(ReorderBuffer::starting_at(
+                self.config.depth,
+                self.config.reorder_timeout(),
+                remote_first,
+            ));
+        }
+    }
+
+    // Back to how it was opened: queues empty, ids from 1 again, not paused
+    pub(crate) fn reset(&mut self) {
+        self.start_session(1, 1);
+        self.rx.clear();
+        self.paused = false;
+    }
+
+    // Everything that hasn't made it to the other end yet, unacked first then queued. Both
+    // are empty afterwards.
+    pub(crate) fn take_undelivered(&mut self) -> Vec<Message> {
+        let mut messages = self
+            .window
+            .as_mut()
+            .map(SendWindow::drain)
+            .unwrap_or_default();
+        messages.extend(self.tx.drain());
+        messages
+    }
+
+    // Anything queued or waiting for an ack
+    pub(crate) fn has_pending_tx(&self) -> bool {
+        !self.tx.is_empty()
+            || self
+                .window
+                .as_ref()
+                .is_some_and(|window| !window.is_empty())
+    }
+}
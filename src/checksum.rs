@@ -0,0 +1,423 @@
+// Checksums used to protect messages on the wire.
+//
+// XOR is what the protocol started with and is still the default, it's cheap but it can't see
+// things like two flipped bits in the same column or reordered bytes. CRC-16-CCITT catches
+// all of those (and every burst up to 16 bits) for a 2 byte field. For payloads of a few KB
+// CRC-32 is the better pick, at 4 bytes on the wire.
+
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+
+use crate::error::ProtocolError;
+
+// Anything that can turn a run of bytes into a checksum. Implement this to match whatever the
+// firmware on the other side already uses.
+pub trait ChecksumAlgorithm {
+    // Run the algorithm over `data`, only the low `width()` bytes of the result are used
+    fn compute(&self, data: &[u8]) -> u32;
+
+    // Number of bytes the checksum takes up on the wire (1..=4), messages refuse to encode or
+    // decode with anything else (`InvalidHeader`)
+    fn width(&self) -> usize;
+
+    // Same as `compute` over several slices back to back (header + payload). The default
+    // glues them into one buffer first, the built-in algorithms stream through them instead.
+    // Without `alloc` there's nothing to glue them into, so implementations have to stream.
+    #[cfg(feature = "alloc")]
+    fn compute_chunks(&self, chunks: &[&[u8]]) -> u32 {
+        self.compute(&chunks.concat())
+    }
+
+    #[cfg(not(feature = "alloc"))]
+    fn compute_chunks(&self, chunks: &[&[u8]]) -> u32;
+
+    // `compute` trimmed down to the bytes that actually go on the wire, this is what gets
+    // stored in and compared against `Message::checksum`
+    fn checksum(&self, data: &[u8]) -> u32 {
+        self.checksum_chunks(&[data])
+    }
+
+    fn checksum_chunks(&self, chunks: &[&[u8]]) -> u32 {
+        let width = self.width();
+        let checksum = match chunks {
+            [data] => self.compute(data),
+            _ => self.compute_chunks(chunks),
+        };
+        if width >= 4 {
+            checksum
+        } else {
+            checksum & ((1u32 << (width * 8)) - 1)
+        }
+    }
+}
+
+impl<C: ChecksumAlgorithm + ?Sized> ChecksumAlgorithm for &C {
+    fn compute(&self, data: &[u8]) -> u32 {
+        (**self).compute(data)
+    }
+
+    fn width(&self) -> usize {
+        (**self).width()
+    }
+
+    fn compute_chunks(&self, chunks: &[&[u8]]) -> u32 {
+        (**self).compute_chunks(chunks)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<C: ChecksumAlgorithm + ?Sized> ChecksumAlgorithm for Box<C> {
+    fn compute(&self, data: &[u8]) -> u32 {
+        (**self).compute(data)
+    }
+
+    fn width(&self) -> usize {
+        (**self).width()
+    }
+
+    fn compute_chunks(&self, chunks: &[&[u8]]) -> u32 {
+        (**self).compute_chunks(chunks)
+    }
+}
+
+// 8-bit XOR of every byte, the default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Xor8;
+
+impl ChecksumAlgorithm for Xor8 {
+    fn compute(&self, data: &[u8]) -> u32 {
+        xor8(data) as u32
+    }
+
+    fn compute_chunks(&self, chunks: &[&[u8]]) -> u32 {
+        chunks.iter().fold(0, |acc, chunk| acc ^ xor8(chunk)) as u32
+    }
+
+    fn width(&self) -> usize {
+        1
+    }
+}
+
+// CRC-16-CCITT (poly 0x1021, init 0xFFFF, no reflection, no final xor)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Crc16Ccitt;
+
+impl ChecksumAlgorithm for Crc16Ccitt {
+    fn compute(&self, data: &[u8]) -> u32 {
+        crc16_ccitt(data) as u32
+    }
+
+    fn compute_chunks(&self, chunks: &[&[u8]]) -> u32 {
+        chunks.iter().fold(CRC16_CCITT_INIT, |crc, chunk| {
+            crc16_ccitt_update(crc, chunk)
+        }) as u32
+    }
+
+    fn width(&self) -> usize {
+        2
+    }
+}
+
+// CRC-32 (IEEE 802.3, the zlib/ethernet one)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Crc32;
+
+impl ChecksumAlgorithm for Crc32 {
+    fn compute(&self, data: &[u8]) -> u32 {
+        crc32(data)
+    }
+
+    fn compute_chunks(&self, chunks: &[&[u8]]) -> u32 {
+        !chunks
+            .iter()
+            .fold(!0u32, |crc, chunk| crc32_update(crc, chunk))
+    }
+
+    fn width(&self) -> usize {
+        4
+    }
+}
+
+// Fletcher-16 (two running sums mod 255), what the existing MCU2 firmware uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Fletcher16;
+
+impl ChecksumAlgorithm for Fletcher16 {
+    fn compute(&self, data: &[u8]) -> u32 {
+        fletcher16(data) as u32
+    }
+
+    fn compute_chunks(&self, chunks: &[&[u8]]) -> u32 {
+        chunks
+            .iter()
+            .fold(0, |sums, chunk| fletcher16_update(sums, chunk)) as u32
+    }
+
+    fn width(&self) -> usize {
+        2
+    }
+}
+
+// A CRC peripheral. PAC register blocks hand out `&self` access so that's all we ask for,
+// the three calls always come in order reset -> feed -> finish for a single computation.
+// If the peripheral is shared with an interrupt handler the integrator has to make sure one
+// computation can't interleave with another.
+pub trait HardwareChecksum {
+    // Load the initial value / clear the data register
+    fn reset(&self);
+
+    // Push bytes through the unit
+    fn feed(&self, data: &[u8]);
+
+    // Read back the result
+    fn finish(&self) -> u32;
+}
+
+// Offloads checksumming to a `HardwareChecksum` peripheral instead of the software fold.
+// `width` has to match what the peer expects, e.g. 2 when the unit is configured for CRC-16,
+// anything outside 1..=4 gets `InvalidHeader`.
+#[derive(Debug, Clone, Copy)]
+pub struct Hardware<H> {
+    unit: H,
+    width: usize,
+}
+
+impl<H: HardwareChecksum> Hardware<H> {
+    pub fn new(unit: H, width: usize) -> Result<Self, ProtocolError> {
+        check_width(width)?;
+        Ok(Hardware { unit, width })
+    }
+
+    pub fn unit(&self) -> &H {
+        &self.unit
+    }
+
+    pub fn into_inner(self) -> H {
+        self.unit
+    }
+}
+
+impl<H: HardwareChecksum> ChecksumAlgorithm for Hardware<H> {
+    fn compute(&self, data: &[u8]) -> u32 {
+        self.compute_chunks(&[data])
+    }
+
+    fn compute_chunks(&self, chunks: &[&[u8]]) -> u32 {
+        self.unit.reset();
+        for chunk in chunks {
+            self.unit.feed(chunk);
+        }
+        self.unit.finish()
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+}
+
+// Closure-backed hook, for when the peripheral driver already exposes a one-shot
+// `fn(&[u8]) -> u32` (or the integrator wants to wrap it in their own critical section).
+// One-shot means header and payload have to be glued together first, so this needs `alloc`,
+// use `Hardware` without it. Same widths as `Hardware`.
+#[cfg(feature = "alloc")]
+#[derive(Clone, Copy)]
+pub struct HardwareFn<F> {
+    compute: F,
+    width: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl<F: Fn(&[u8]) -> u32> HardwareFn<F> {
+    pub fn new(width: usize, compute: F) -> Result<Self, ProtocolError> {
+        check_width(width)?;
+        Ok(HardwareFn { compute, width })
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<F: Fn(&[u8]) -> u32> ChecksumAlgorithm for HardwareFn<F> {
+    fn compute(&self, data: &[u8]) -> u32 {
+        (self.compute)(data)
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+}
+
+// A checksum is a u32, it can't take up more than 4 bytes
+fn check_width(width: usize) -> Result<(), ProtocolError> {
+    if (1..=4).contains(&width) {
+        Ok(())
+    } else {
+        Err(ProtocolError::InvalidHeader)
+    }
+}
+
+// XOR of every byte
+pub fn xor8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |acc, byte| acc ^ byte)
+}
+
+// Fletcher-16 as `sum2 << 8 | sum1`, check value for b"abcde" is 0xC8F0. On the wire it goes
+// out little-endian like every other field, so sum1 first.
+pub fn fletcher16(data: &[u8]) -> u16 {
+    fletcher16_update(0, data)
+}
+
+// Continue a Fletcher-16 from a previous result
+pub fn fletcher16_update(sums: u16, data: &[u8]) -> u16 {
+    let mut sum1 = sums & 0xFF;
+    let mut sum2 = sums >> 8;
+    for &byte in data {
+        sum1 = (sum1 + byte as u16) % 255;
+        sum2 = (sum2 + sum1) % 255;
+    }
+    (sum2 << 8) | sum1
+}
+
+const CRC16_CCITT_POLY: u16 = 0x1021;
+const CRC16_CCITT_INIT: u16 = 0xFFFF;
+
+// Lookup table built at compile time so it lives in flash rather than RAM
+static CRC16_CCITT_TABLE: [u16; 256] = crc16_table(CRC16_CCITT_POLY);
+
+const fn crc16_table(poly: u16) -> [u16; 256] {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ poly
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+// CRC-16-CCITT ("CCITT-FALSE"), check value for b"123456789" is 0x29B1
+pub fn crc16_ccitt(data: &[u8]) -> u16 {
+    crc16_ccitt_update(CRC16_CCITT_INIT, data)
+}
+
+// Continue a CRC-16-CCITT from a previous value
+pub fn crc16_ccitt_update(crc: u16, data: &[u8]) -> u16 {
+    data.iter().fold(crc, |crc, &byte| {
+        let index = ((crc >> 8) as u8 ^ byte) as usize;
+        (crc << 8) ^ CRC16_CCITT_TABLE[index]
+    })
+}
+
+// Reflected form of the IEEE polynomial 0x04C11DB7
+const CRC32_POLY: u32 = 0xEDB8_8320;
+
+static CRC32_TABLE: [u32; 256] = crc32_table(CRC32_POLY);
+
+const fn crc32_table(poly: u32) -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ poly
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+// CRC-32 (init 0xFFFFFFFF, reflected, final xor 0xFFFFFFFF), check value for b"123456789"
+// is 0xCBF43926
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0u32, data)
+}
+
+// Continue a CRC-32 from a previous, not yet inverted, register value
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &byte| {
+        let index = ((crc as u8) ^ byte) as usize;
+        (crc >> 8) ^ CRC32_TABLE[index]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    // Stands in for a peripheral, XORs whatever it's fed
+    #[derive(Default)]
+    struct XorUnit(Cell<u8>);
+
+    impl HardwareChecksum for XorUnit {
+        fn reset(&self) {
+            self.0.set(0);
+        }
+
+        fn feed(&self, data: &[u8]) {
+            self.0.set(self.0.get() ^ xor8(data));
+        }
+
+        fn finish(&self) -> u32 {
+            self.0.get() as u32
+        }
+    }
+
+    #[test]
+    fn check_values() {
+        assert_eq!(crc16_ccitt(b"123456789"), 0x29B1);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(fletcher16(b"abcde"), 0xC8F0);
+        assert_eq!(xor8(&[0x0F, 0xF0, 0x01]), 0xFE);
+    }
+
+    #[test]
+    fn chunks_give_the_same_result_as_one_run() {
+        let algorithms: [&dyn ChecksumAlgorithm; 4] = [&Xor8, &Crc16Ccitt, &Crc32, &Fletcher16];
+        for algorithm in algorithms {
+            assert_eq!(
+                algorithm.compute_chunks(&[b"1234", b"", b"56789"]),
+                algorithm.compute(b"123456789")
+            );
+        }
+    }
+
+    #[test]
+    fn hardware_runs_the_unit() {
+        let hardware = Hardware::new(XorUnit::default(), 1).unwrap();
+        assert_eq!(hardware.compute(b"abc"), Xor8.compute(b"abc"));
+        assert_eq!(
+            hardware.compute_chunks(&[b"a", b"bc"]),
+            Xor8.compute(b"abc")
+        );
+    }
+
+    #[test]
+    fn widths_outside_a_u32_are_refused() {
+        for width in [0, 5, 8] {
+            assert!(matches!(
+                Hardware::new(XorUnit::default(), width),
+                Err(ProtocolError::InvalidHeader)
+            ));
+        }
+        #[cfg(feature = "alloc")]
+        assert!(matches!(
+            HardwareFn::new(5, |data: &[u8]| xor8(data) as u32),
+            Err(ProtocolError::InvalidHeader)
+        ));
+    }
+}
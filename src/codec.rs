@@ -0,0 +1,132 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::checksum::{ChecksumAlgorithm, Xor8};
+use crate::error::ProtocolError;
+use crate::message::{FormatVersion, Message, MessageRef, Payload, Priority};
+
+// Checksum algorithm + format version, everything needed to seal, verify and (de)serialize
+// messages the same way the peer does. Protocol instances and links each own one.
+pub struct Codec {
+    checksum: Box<dyn ChecksumAlgorithm + Send + Sync>,
+    version: FormatVersion,
+}
+
+impl Codec {
+    pub fn new<C>(checksum: C, version: FormatVersion) -> Self
+    where
+        C: ChecksumAlgorithm + Send + Sync + 'static,
+    {
+        Codec {
+            checksum: Box::new(checksum),
+            version,
+        }
+    }
+
+    pub fn checksum(&self) -> &dyn ChecksumAlgorithm {
+        self.checksum.as_ref()
+    }
+
+    pub fn set_checksum<C>(&mut self, checksum: C)
+    where
+        C: ChecksumAlgorithm + Send + Sync + 'static,
+    {
+        self.checksum = Box::new(checksum);
+    }
+
+    pub fn version(&self) -> FormatVersion {
+        self.version
+    }
+
+    pub fn set_version(&mut self, version: FormatVersion) {
+        self.version = version;
+    }
+
+    // Bytes a serialized message takes besides its payload, header and checksum
+    pub fn overhead(&self) -> usize {
+        self.version.header_len() + self.checksum.width()
+    }
+
+    // Build a message with its checksum filled in
+    pub fn seal(&self, id: u16, payload: impl Into<Payload>) -> Message {
+        Message::sealed(id, payload, self.checksum(), self.version)
+    }
+
+    // Same as `seal` with header flags and priority set
+    pub fn seal_with(
+        &self,
+        id: u16,
+        flags: u8,
+        priority: Priority,
+        payload: impl Into<Payload>,
+    ) -> Message {
+        let mut message = Message {
+            id,
+            flags,
+            priority,
+            source: 0,
+            destination: 0,
+            channel: 0,
+            payload: payload.into(),
+            checksum: 0,
+        };
+        self.reseal(&mut message);
+        message
+    }
+
+    // Recompute the checksum of a message, e.g. after editing its payload in place
+    pub fn reseal(&self, message: &mut Message) {
+        message.checksum = message.compute_checksum(self.checksum(), self.version);
+    }
+
+    pub fn verify(&self, message: &Message) -> bool {
+        message.verify(self.checksum(), self.version)
+    }
+
+    pub fn encoded_len(&self, message: &Message) -> usize {
+        message.encoded_len_for(self.checksum(), self.version)
+    }
+
+    pub fn encode(&self, message: &Message) -> Result<Vec<u8>, ProtocolError> {
+        message.encode(self.checksum(), self.version)
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Result<Message, ProtocolError> {
+        Message::decode(bytes, self.checksum(), self.version)
+    }
+
+    // Borrowed payload versions of the above, see `MessageRef`
+    pub fn seal_ref<'a>(&self, id: u16, payload: &'a [u8]) -> MessageRef<'a> {
+        MessageRef::sealed(id, payload, self.checksum(), self.version)
+    }
+
+    pub fn reseal_ref(&self, message: &mut MessageRef<'_>) {
+        message.checksum = message.compute_checksum(self.checksum(), self.version);
+    }
+
+    pub fn verify_ref(&self, message: &MessageRef<'_>) -> bool {
+        message.verify(self.checksum(), self.version)
+    }
+
+    pub fn encode_ref(&self, message: &MessageRef<'_>) -> Result<Vec<u8>, ProtocolError> {
+        message.encode(self.checksum(), self.version)
+    }
+
+    pub fn encode_into(
+        &self,
+        message: &MessageRef<'_>,
+        out: &mut [u8],
+    ) -> Result<usize, ProtocolError> {
+        message.encode_into(self.checksum(), self.version, out)
+    }
+
+    pub fn decode_ref<'a>(&self, bytes: &'a [u8]) -> Result<MessageRef<'a>, ProtocolError> {
+        MessageRef::decode(bytes, self.checksum(), self.version)
+    }
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec::new(Xor8, FormatVersion::default())
+    }
+}
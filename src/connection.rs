@@ -0,0 +1,622 @@
+use core::time::Duration;
+
+use crate::control::Control;
+use crate::error::ProtocolError;
+use crate::rng::Rng;
+
+// Connection between two endpoints, set up before any data flows:
+//
+//   Idle -> Connecting -> Established -> Closing -> Closed
+//
+//   MCU1                         MCU2
+//   SYN (my ids start at a) --->
+//                           <--- SYN_ACK (mine start at b)
+//   ESTABLISHED             --->
+//
+// Both ends start their message ids where they said and expect the other's where it said,
+// so nothing left over from an earlier connection (or a restart) is taken for new data.
+// Whoever calls `connect` sends the SYN; the other end answers any SYN it gets, it doesn't
+// have to be told to listen. If both connect at once each answers the other's SYN and both
+// come up on the SYN_ACKs.
+//
+// SYN and SYN_ACK are sent again until answered, `timeout` apart, and after `max_retries`
+// the attempt is given up (back to Idle). Closing goes in two steps: `close` stops the
+// connection taking anything new, and `fin` sends the FIN once the last data is through.
+// FIN is sent again like SYN, after giving up the connection is closed anyway.
+//
+// A SYN with new ids on an established connection means the other end started over, it gets a new
+// connection (new ids both ways).
+//
+// The first ids come from a seeded generator mixed with the clock, so every connection from
+// the same `Connection` starts somewhere new even on a clock that's still at 0. A restarted
+// MCU starts the generator over, give it something that changes between boots with
+// `with_seed` (a hardware RNG reading, a boot counter ...) or it picks the ids it did last
+// time.
+//
+// The SYN also says which protocol versions the connecting end speaks, and the SYN_ACK the
+// highest one both do, which is what the connection runs at (`version`). An MCU with new
+// firmware keeps speaking the old version to one that hasn't been updated yet, as long as
+// its range still reaches down that far. With no version in common the answer is a REJECT
+// with the versions the other end does speak, and the connecting end ends up Closed with
+// `ProtocolError::IncompatibleVersion` in `error`.
+//
+// Both also carry what the sending end can do (`Capabilities`): the largest payload it takes
+// in one frame, the checksum algorithms it can check and whether it can put fragments back
+// together. `capabilities` is what both ends can do, and `Endpoint` sticks to it: payloads
+// above the other end's maximum are fragmented, or refused when either end can't fragment.
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(200);
+pub const DEFAULT_MAX_RETRIES: u8 = 5;
+
+// Protocol version this build speaks, and the oldest it still does. Version 1 is the
+// handshake from before versions were negotiated, 2 added them and 3 capabilities.
+pub const PROTOCOL_VERSION: u8 = 3;
+pub const MIN_PROTOCOL_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Idle,
+    Connecting,
+    Established,
+    Closing,
+    Closed,
+}
+
+// Checksum algorithms an end can check, bits of `Capabilities::checksums`
+pub mod checksums {
+    pub const XOR8: u8 = 0x01;
+    pub const CRC16_CCITT: u8 = 0x02;
+    pub const CRC32: u8 = 0x04;
+    pub const FLETCHER16: u8 = 0x08;
+    // the built-in ones, every build has them
+    pub const ALL: u8 = XOR8 | CRC16_CCITT | CRC32 | FLETCHER16;
+}
+
+// Bits of `Capabilities::features`
+pub mod features {
+    // puts fragmented payloads back together, see `fragment`
+    pub const FRAGMENTATION: u8 = 0x01;
+    // takes compressed payloads. Nothing in here compresses, it's for applications that do
+    // it on top and want to know whether the other end can.
+    pub const COMPRESSION: u8 = 0x02;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    // longest payload this end takes in one frame
+    pub max_payload: u16,
+    // `checksums` bits
+    pub checksums: u8,
+    // `features` bits
+    pub features: u8,
+}
+
+impl Capabilities {
+    // Bytes they take in a SYN / SYN_ACK
+    pub const LEN: usize = 4;
+
+    pub fn supports(&self, feature: u8) -> bool {
+        self.features & feature == feature
+    }
+
+    // What both this end and `other` can do
+    pub fn common(&self, other: &Capabilities) -> Capabilities {
+        Capabilities {
+            max_payload: self.max_payload.min(other.max_payload),
+            checksums: self.checksums & other.checksums,
+            features: self.features & other.features,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let [low, high] = self.max_payload.to_le_bytes();
+        [low, high, self.checksums, self.features]
+    }
+
+    // `None` if `bytes` is too short
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let &[low, high, checksums, features, ..] = bytes else {
+            return None;
+        };
+        Some(Capabilities {
+            max_payload: u16::from_le_bytes([low, high]),
+            checksums,
+            features,
+        })
+    }
+}
+
+// Anything up to `MAX_PAYLOAD_LEN`, every built-in checksum and fragmentation, which is
+// what every peer from before capabilities could do. An `Endpoint` lowers `max_payload` to
+// what its own framing takes.
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities {
+            max_payload: u16::MAX,
+            checksums: checksums::ALL,
+            features: features::FRAGMENTATION,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Connection {
+    state: ConnectionState,
+    // first id each end sends with, `remote_first` is `None` until the other end said
+    local_first: u16,
+    remote_first: Option<u16>,
+    // what we're waiting on an answer for, sent again at `deadline`
+    pending: Option<Control>,
+    deadline: Duration,
+    retries: u8,
+    timeout: Duration,
+    max_retries: u8,
+    // versions we speak, and the one agreed on for this connection
+    min_version: u8,
+    max_version: u8,
+    version: Option<u8>,
+    // ours, and the other end's once it said
+    capabilities: Capabilities,
+    remote_capabilities: Option<Capabilities>,
+    // why the last attempt failed
+    error: Option<ProtocolError>,
+    // where the first ids come from
+    rng: Rng,
+}
+
+impl Connection {
+    pub fn new(timeout: Duration, max_retries: u8) -> Self {
+        Connection {
+            state: ConnectionState::Idle,
+            local_first: 1,
+            remote_first: None,
+            pending: None,
+            deadline: Duration::ZERO,
+            retries: 0,
+            timeout,
+            max_retries,
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            version: None,
+            capabilities: Capabilities::default(),
+            remote_capabilities: None,
+            error: None,
+            rng: Rng::new(Rng::DEFAULT_SEED),
+        }
+    }
+
+    // Seed for the first ids, see above
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    // Offer no more than `max` bytes of payload per frame, whatever the capabilities say
+    pub fn with_max_payload(mut self, max: usize) -> Self {
+        let max = max.min(u16::MAX as usize) as u16;
+        self.capabilities.max_payload = self.capabilities.max_payload.min(max);
+        self
+    }
+
+    // Speak only versions `min` to `max`
+    pub fn with_versions(mut self, min: u8, max: u8) -> Self {
+        self.min_version = min;
+        self.max_version = max.max(min);
+        self
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    pub fn is_established(&self) -> bool {
+        self.state == ConnectionState::Established
+    }
+
+    pub fn local_first(&self) -> u16 {
+        self.local_first
+    }
+
+    pub fn remote_first(&self) -> Option<u16> {
+        self.remote_first
+    }
+
+    // Protocol version the two ends agreed on, `None` until they have
+    pub fn version(&self) -> Option<u8> {
+        self.version
+    }
+
+    // What both ends can do, `None` until the other end said
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.remote_capabilities
+            .map(|remote| self.capabilities.common(&remote))
+    }
+
+    pub fn error(&self) -> Option<&ProtocolError> {
+        self.error.as_ref()
+    }
+
+    // Start connecting, returns the SYN to send. Does nothing on a connection that's already
+    // being set up or up.
+    pub fn connect(&mut self, now: Duration) -> Option<Control> {
+        if matches!(
+            self.state,
+            ConnectionState::Connecting | ConnectionState::Established
+        ) {
+            return None;
+        }
+        self.local_first = first_id(&mut self.rng, now);
+        self.remote_first = None;
+        self.version = None;
+        self.remote_capabilities = None;
+        self.error = None;
+        self.state = ConnectionState::Connecting;
+        self.expect(
+            Control::Syn {
+                first_id: self.local_first,
+                min_version: self.min_version,
+                max_version: self.max_version,
+                capabilities: self.capabilities,
+            },
+            now,
+        )
+    }
+
+    // Back to Idle, forgetting the other end, e.g. after a RESET
+    pub fn reset(&mut self) {
+        self.state = ConnectionState::Idle;
+        self.remote_first = None;
+        self.version = None;
+        self.remote_capabilities = None;
+        self.pending = None;
+        self.error = None;
+    }
+
+    // Start closing, data still flows until `fin`
+    pub fn close(&mut self) {
+        if matches!(
+            self.state,
+            ConnectionState::Connecting | ConnectionState::Established
+        ) {
+            self.state = ConnectionState::Closing;
+        }
+    }
+
+    // Nothing more to send on a closing connection, returns the FIN
+    pub fn fin(&mut self, now: Duration) -> Option<Control> {
+        if self.state != ConnectionState::Closing || self.pending.is_some() {
+            return None;
+        }
+        self.expect(Control::Fin, now)
+    }
+
+    // A handshake / close message from the other end, returns the answer to send if any.
+    // Other control messages are ignored.
+    pub fn handle(&mut self, control: &Control, now: Duration) -> Option<Control> {
+        match *control {
+            Control::Syn {
+                first_id: remote,
+                min_version,
+                max_version,
+                capabilities,
+            } => {
+                let Some(version) = self.negotiate(min_version, max_version) else {
+                    log!(
+                        "Connection: rejecting peer speaking versions {} to {}",
+                        min_version,
+                        max_version
+                    );
+                    return Some(self.reject());
+                };
+                match self.state {
+                    ConnectionState::Closing => None,
+                    // a late copy of the SYN this connection came from
+                    ConnectionState::Established if self.remote_first == Some(remote) => {
+                        Some(self.syn_ack(version))
+                    }
+                    // ours crossed theirs, or our SYN_ACK got lost
+                    ConnectionState::Connecting => {
+                        self.remote_first = Some(remote);
+                        self.version = Some(version);
+                        self.remote_capabilities = Some(capabilities);
+                        self.expect(self.syn_ack(version), now)
+                    }
+                    _ => {
+                        self.local_first = first_id(&mut self.rng, now);
+                        self.remote_first = Some(remote);
+                        self.version = Some(version);
+                        self.remote_capabilities = Some(capabilities);
+                        self.state = ConnectionState::Connecting;
+                        self.expect(self.syn_ack(version), now)
+                    }
+                }
+            }
+            Control::SynAck {
+                first_id: remote,
+                version,
+                capabilities,
+            } => match self.state {
+                // picked one we never offered
+                ConnectionState::Connecting
+                    if !(self.min_version..=self.max_version).contains(&version) =>
+                {
+                    self.fail(ProtocolError::IncompatibleVersion {
+                        min: version,
+                        max: version,
+                    });
+                    Some(self.reject())
+                }
+                ConnectionState::Connecting => {
+                    self.remote_first = Some(remote);
+                    self.version = Some(version);
+                    self.remote_capabilities = Some(capabilities);
+                    self.set_established();
+                    Some(Control::Established)
+                }
+                // our ESTABLISHED got lost, they're still waiting
+                ConnectionState::Established if self.remote_first == Some(remote) => {
+                    Some(Control::Established)
+                }
+                _ => None,
+            },
+            Control::Established => {
+                if self.state == ConnectionState::Connecting && self.remote_first.is_some() {
+                    self.set_established();
+                }
+                None
+            }
+            Control::Fin => {
+                self.state = ConnectionState::Closed;
+                self.pending = None;
+                Some(Control::FinAck)
+            }
+            Control::FinAck => {
+                if self.state == ConnectionState::Closing {
+                    self.state = ConnectionState::Closed;
+                    self.pending = None;
+                }
+                None
+            }
+            Control::Reject {
+                min_version,
+                max_version,
+            } => {
+                if self.state == ConnectionState::Connecting {
+                    self.fail(ProtocolError::IncompatibleVersion {
+                        min: min_version,
+                        max: max_version,
+                    });
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    // What has to go out again because it wasn't answered in time
+    pub fn poll(&mut self, now: Duration) -> Option<Control> {
+        let pending = self.pending?;
+        if now < self.deadline {
+            return None;
+        }
+        if self.retries >= self.max_retries {
+            log!("Connection: no answer to {:?}, giving up", pending);
+            self.pending = None;
+            self.state = match self.state {
+                ConnectionState::Closing => ConnectionState::Closed,
+                _ => ConnectionState::Idle,
+            };
+            return None;
+        }
+        self.retries += 1;
+        self.deadline = now + self.timeout;
+        Some(pending)
+    }
+
+    fn expect(&mut self, control: Control, now: Duration) -> Option<Control> {
+        self.pending = Some(control);
+        self.deadline = now + self.timeout;
+        self.retries = 0;
+        Some(control)
+    }
+
+    // Highest version both ends speak
+    fn negotiate(&self, min_version: u8, max_version: u8) -> Option<u8> {
+        let version = self.max_version.min(max_version);
+        (version >= self.min_version.max(min_version)).then_some(version)
+    }
+
+    fn syn_ack(&self, version: u8) -> Control {
+        Control::SynAck {
+            first_id: self.local_first,
+            version,
+            capabilities: self.capabilities,
+        }
+    }
+
+    fn reject(&self) -> Control {
+        Control::Reject {
+            min_version: self.min_version,
+            max_version: self.max_version,
+        }
+    }
+
+    fn fail(&mut self, error: ProtocolError) {
+        log!("Connection: {}", error);
+        self.state = ConnectionState::Closed;
+        self.pending = None;
+        self.error = Some(error);
+    }
+
+    fn set_established(&mut self) {
+        log!(
+            "Connection: established at version {:?}, ids from {} out, {:?} in",
+            self.version,
+            self.local_first,
+            self.remote_first
+        );
+        self.state = ConnectionState::Established;
+        self.pending = None;
+        self.error = None;
+    }
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIMEOUT, DEFAULT_MAX_RETRIES)
+    }
+}
+
+// Different every connection (near enough), never 0, that's for protocol messages
+fn first_id(rng: &mut Rng, now: Duration) -> u16 {
+    let bits = rng.next_u64() ^ now.as_micros() as u64;
+    let id = (bits ^ (bits >> 16) ^ (bits >> 32) ^ (bits >> 48)) as u16;
+    id.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    const NOW: Duration = Duration::ZERO;
+
+    // Runs the three way handshake from `a` to `b`
+    fn handshake(a: &mut Connection, b: &mut Connection) {
+        let syn = a.connect(NOW).unwrap();
+        let syn_ack = b.handle(&syn, NOW).unwrap();
+        let established = a.handle(&syn_ack, NOW).unwrap();
+        assert_eq!(established, Control::Established);
+        assert_eq!(b.handle(&established, NOW), None);
+    }
+
+    #[test]
+    fn three_way_handshake() {
+        let (mut a, mut b) = (Connection::default(), Connection::default().with_seed(2));
+        handshake(&mut a, &mut b);
+
+        assert!(a.is_established() && b.is_established());
+        assert_eq!(a.remote_first(), Some(b.local_first()));
+        assert_eq!(b.remote_first(), Some(a.local_first()));
+        assert_eq!(a.version(), Some(PROTOCOL_VERSION));
+        assert_eq!(b.version(), Some(PROTOCOL_VERSION));
+        assert_eq!(a.capabilities(), Some(Capabilities::default()));
+    }
+
+    #[test]
+    fn reconnect_starts_at_new_ids_on_a_stopped_clock() {
+        let mut a = Connection::default();
+        let mut firsts = Vec::new();
+        for _ in 0..4 {
+            a.connect(NOW);
+            firsts.push(a.local_first());
+            a.reset();
+        }
+        firsts.sort_unstable();
+        firsts.dedup();
+        assert_eq!(firsts.len(), 4);
+        assert!(!firsts.contains(&0));
+    }
+
+    #[test]
+    fn seed_picks_the_ids() {
+        let first = |seed| {
+            let mut connection = Connection::default().with_seed(seed);
+            connection.connect(NOW);
+            connection.local_first()
+        };
+        assert_eq!(first(7), first(7));
+        assert_ne!(first(7), first(8));
+    }
+
+    #[test]
+    fn crossed_syns_both_come_up() {
+        let (mut a, mut b) = (Connection::default(), Connection::default().with_seed(2));
+        let syn_a = a.connect(NOW).unwrap();
+        let syn_b = b.connect(NOW).unwrap();
+        let syn_ack_a = a.handle(&syn_b, NOW).unwrap();
+        let syn_ack_b = b.handle(&syn_a, NOW).unwrap();
+        a.handle(&syn_ack_b, NOW);
+        b.handle(&syn_ack_a, NOW);
+
+        assert!(a.is_established() && b.is_established());
+        assert_eq!(a.remote_first(), Some(b.local_first()));
+        assert_eq!(b.remote_first(), Some(a.local_first()));
+    }
+
+    #[test]
+    fn unanswered_syn_is_retried_then_given_up() {
+        let mut a = Connection::new(Duration::from_millis(10), 2);
+        let syn = a.connect(NOW).unwrap();
+        assert_eq!(a.poll(Duration::from_millis(9)), None);
+        assert_eq!(a.poll(Duration::from_millis(10)), Some(syn));
+        assert_eq!(a.poll(Duration::from_millis(20)), Some(syn));
+        assert_eq!(a.poll(Duration::from_millis(30)), None);
+        assert_eq!(a.state(), ConnectionState::Idle);
+    }
+
+    #[test]
+    fn restarted_peer_gets_a_new_connection() {
+        let (mut a, mut b) = (Connection::default(), Connection::default().with_seed(2));
+        handshake(&mut a, &mut b);
+        let old = b.local_first();
+
+        // `a` rebooted and connects again
+        let mut a = Connection::default().with_seed(3);
+        let syn = a.connect(NOW).unwrap();
+        let syn_ack = b.handle(&syn, NOW).unwrap();
+        assert_eq!(b.state(), ConnectionState::Connecting);
+        assert_ne!(b.local_first(), old);
+        a.handle(&syn_ack, NOW);
+        assert!(a.is_established());
+    }
+
+    #[test]
+    fn highest_common_version_wins() {
+        let mut a = Connection::default();
+        let mut b = Connection::default().with_versions(1, 2);
+        handshake(&mut a, &mut b);
+        assert_eq!(a.version(), Some(2));
+        assert_eq!(b.version(), Some(2));
+    }
+
+    #[test]
+    fn no_common_version_is_rejected() {
+        let mut a = Connection::default().with_versions(1, 1);
+        let mut b = Connection::default().with_versions(2, 3);
+        let syn = a.connect(NOW).unwrap();
+        let reject = b.handle(&syn, NOW).unwrap();
+        assert_eq!(
+            reject,
+            Control::Reject {
+                min_version: 2,
+                max_version: 3
+            }
+        );
+        a.handle(&reject, NOW);
+        assert_eq!(a.state(), ConnectionState::Closed);
+        assert_eq!(
+            a.error(),
+            Some(&ProtocolError::IncompatibleVersion { min: 2, max: 3 })
+        );
+    }
+
+    #[test]
+    fn close_waits_for_fin_ack() {
+        let (mut a, mut b) = (Connection::default(), Connection::default().with_seed(2));
+        handshake(&mut a, &mut b);
+
+        a.close();
+        assert_eq!(a.state(), ConnectionState::Closing);
+        let fin = a.fin(NOW).unwrap();
+        assert_eq!(b.handle(&fin, NOW), Some(Control::FinAck));
+        assert_eq!(b.state(), ConnectionState::Closed);
+        a.handle(&Control::FinAck, NOW);
+        assert_eq!(a.state(), ConnectionState::Closed);
+    }
+}
@@ -0,0 +1,314 @@
+use alloc::vec::Vec;
+
+use crate::codec::Codec;
+use crate::connection::Capabilities;
+use crate::error::ProtocolError;
+use crate::message::{Message, Priority, flags};
+
+// Messages the protocol sends to itself, e.g. MCU2 granting MCU1 more credit. A control
+// message has `flags::CONTROL` set, id 0 (ids belong to application data) and an opcode in
+// front of its arguments:
+//
+//   | opcode: u8 | arguments |
+//
+//   CREDIT  0x01  | limit: u16 |   may send until the message counter reaches `limit`
+//   XOFF    0x02                   stop sending, the receive buffer is nearly full
+//   XON     0x03                   go ahead again
+//   SYN     0x04  | first_id: u16 | min_version: u8 | max_version: u8 | capabilities |
+//                                  open a connection, my ids start at `first_id`
+//   SYN_ACK 0x05  | first_id: u16 | version: u8 | capabilities |
+//                                  accepted, mine start at `first_id`, we speak `version`
+//   ESTABLISHED 0x06               got yours, data can flow
+//   FIN     0x07                   closing the connection
+//   FIN_ACK 0x08                   closed
+//   REJECT  0x09  | min_version: u8 | max_version: u8 |
+//                                  no version in common, I only speak these
+//   PAUSE   0x0A  | channel: u8 |  stop sending on `channel`, I can't take it for now
+//   RESUME  0x0B  | channel: u8 |  go ahead on `channel` again
+//   RESET   0x0C                   I started over, forget everything from before
+//
+// with the sender's capabilities being
+//
+//   | max_payload: u16 | checksums: u8 | features: u8 |
+//
+// see `connection::Capabilities`. Peers from before version negotiation send SYN and SYN_ACK
+// without versions, that's version 1, and version 2 peers send no capabilities, they get
+// the defaults. Anything after the last known argument of any opcode is skipped, for later revisions.
+//
+// Unknown opcodes come out as `InvalidHeader` so a newer peer's extras can be skipped.
+
+pub const CREDIT: u8 = 0x01;
+pub const XOFF: u8 = 0x02;
+pub const XON: u8 = 0x03;
+pub const SYN: u8 = 0x04;
+pub const SYN_ACK: u8 = 0x05;
+pub const ESTABLISHED: u8 = 0x06;
+pub const FIN: u8 = 0x07;
+pub const FIN_ACK: u8 = 0x08;
+pub const REJECT: u8 = 0x09;
+pub const PAUSE: u8 = 0x0A;
+pub const RESUME: u8 = 0x0B;
+pub const RESET: u8 = 0x0C;
+
+// What a peer that doesn't say speaks
+const FIRST_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    // see `credit`
+    Credit {
+        limit: u16,
+    },
+    // XOFF/XON, see `CommunicationProtocol::with_xon_xoff`
+    Pause,
+    Resume,
+    // handshake and close, see `connection`
+    Syn {
+        first_id: u16,
+        min_version: u8,
+        max_version: u8,
+        capabilities: Capabilities,
+    },
+    SynAck {
+        first_id: u16,
+        version: u8,
+        capabilities: Capabilities,
+    },
+    Established,
+    Fin,
+    FinAck,
+    Reject {
+        min_version: u8,
+        max_version: u8,
+    },
+    // one channel of an `Endpoint`, see `Endpoint::pause`
+    PauseChannel {
+        channel: u8,
+    },
+    ResumeChannel {
+        channel: u8,
+    },
+    // see `Endpoint::reset`
+    Reset,
+}
+
+impl Control {
+    // Sealed and ready to go, ahead of queued data
+    pub fn to_message(&self, codec: &Codec) -> Message {
+        let mut payload = Vec::with_capacity(9);
+        match *self {
+            Control::Credit { limit } => {
+                payload.push(CREDIT);
+                payload.extend_from_slice(&limit.to_le_bytes());
+            }
+            Control::Pause => payload.push(XOFF),
+            Control::Resume => payload.push(XON),
+            Control::Syn {
+                first_id,
+                min_version,
+                max_version,
+                capabilities,
+            } => {
+                payload.push(SYN);
+                payload.extend_from_slice(&first_id.to_le_bytes());
+                payload.extend_from_slice(&[min_version, max_version]);
+                payload.extend_from_slice(&capabilities.to_bytes());
+            }
+            Control::SynAck {
+                first_id,
+                version,
+                capabilities,
+            } => {
+                payload.push(SYN_ACK);
+                payload.extend_from_slice(&first_id.to_le_bytes());
+                payload.push(version);
+                payload.extend_from_slice(&capabilities.to_bytes());
+            }
+            Control::Established => payload.push(ESTABLISHED),
+            Control::Fin => payload.push(FIN),
+            Control::FinAck => payload.push(FIN_ACK),
+            Control::Reject {
+                min_version,
+                max_version,
+            } => payload.extend_from_slice(&[REJECT, min_version, max_version]),
+            Control::PauseChannel { channel } => payload.extend_from_slice(&[PAUSE, channel]),
+            Control::ResumeChannel { channel } => payload.extend_from_slice(&[RESUME, channel]),
+            Control::Reset => payload.push(RESET),
+        }
+        codec.seal_with(0, flags::CONTROL, Priority::Critical, payload)
+    }
+
+    // What a (checksum verified) message with `flags::CONTROL` says
+    pub fn parse(message: &Message) -> Result<Self, ProtocolError> {
+        let payload = &message.payload;
+        match payload.first() {
+            Some(&CREDIT) if payload.len() >= 3 => Ok(Control::Credit {
+                limit: u16::from_le_bytes([payload[1], payload[2]]),
+            }),
+            Some(&XOFF) => Ok(Control::Pause),
+            Some(&XON) => Ok(Control::Resume),
+            Some(&SYN) if payload.len() >= 3 => {
+                let (min_version, max_version) = match payload.get(3..5) {
+                    Some(&[min, max]) => (min, max),
+                    _ => (FIRST_VERSION, FIRST_VERSION),
+                };
+                Ok(Control::Syn {
+                    first_id: u16::from_le_bytes([payload[1], payload[2]]),
+                    min_version,
+                    max_version,
+                    capabilities: capabilities_at(payload, 5),
+                })
+            }
+            Some(&SYN_ACK) if payload.len() >= 3 => Ok(Control::SynAck {
+                first_id: u16::from_le_bytes([payload[1], payload[2]]),
+                version: payload.get(3).copied().unwrap_or(FIRST_VERSION),
+                capabilities: capabilities_at(payload, 4),
+            }),
+            Some(&REJECT) if payload.len() >= 3 => Ok(Control::Reject {
+                min_version: payload[1],
+                max_version: payload[2],
+            }),
+            Some(&ESTABLISHED) => Ok(Control::Established),
+            Some(&FIN) => Ok(Control::Fin),
+            Some(&FIN_ACK) => Ok(Control::FinAck),
+            Some(&RESET) => Ok(Control::Reset),
+            Some(&PAUSE) if payload.len() >= 2 => Ok(Control::PauseChannel {
+                channel: payload[1],
+            }),
+            Some(&RESUME) if payload.len() >= 2 => Ok(Control::ResumeChannel {
+                channel: payload[1],
+            }),
+            Some(&(PAUSE | RESUME)) => Err(ProtocolError::InvalidLength {
+                expected: 2,
+                actual: payload.len(),
+            }),
+            Some(&(CREDIT | SYN | SYN_ACK | REJECT)) => Err(ProtocolError::InvalidLength {
+                expected: 3,
+                actual: payload.len(),
+            }),
+            _ => Err(ProtocolError::InvalidHeader),
+        }
+    }
+}
+
+// Capabilities starting at `at`, the defaults when the peer sent none
+fn capabilities_at(payload: &[u8], at: usize) -> Capabilities {
+    payload
+        .get(at..)
+        .and_then(Capabilities::from_bytes)
+        .unwrap_or_default()
+}
+
+pub fn is_control(message: &Message) -> bool {
+    message.flags & flags::CONTROL != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn raw(payload: Vec<u8>) -> Message {
+        Codec::default().seal_with(0, flags::CONTROL, Priority::Critical, payload)
+    }
+
+    #[test]
+    fn every_control_round_trips() {
+        let codec = Codec::default();
+        let capabilities = Capabilities {
+            max_payload: 512,
+            ..Capabilities::default()
+        };
+        let controls = [
+            Control::Credit { limit: 300 },
+            Control::Pause,
+            Control::Resume,
+            Control::Syn {
+                first_id: 0x1234,
+                min_version: 1,
+                max_version: 3,
+                capabilities,
+            },
+            Control::SynAck {
+                first_id: 0x4321,
+                version: 3,
+                capabilities,
+            },
+            Control::Established,
+            Control::Fin,
+            Control::FinAck,
+            Control::Reject {
+                min_version: 2,
+                max_version: 4,
+            },
+            Control::PauseChannel { channel: 7 },
+            Control::ResumeChannel { channel: 7 },
+            Control::Reset,
+        ];
+        for control in controls {
+            let message = control.to_message(&codec);
+            assert!(is_control(&message));
+            assert_eq!(Control::parse(&message), Ok(control));
+        }
+    }
+
+    #[test]
+    fn older_peers_get_version_one_and_default_capabilities() {
+        assert_eq!(
+            Control::parse(&raw(vec![SYN, 5, 0])),
+            Ok(Control::Syn {
+                first_id: 5,
+                min_version: FIRST_VERSION,
+                max_version: FIRST_VERSION,
+                capabilities: Capabilities::default(),
+            })
+        );
+        assert_eq!(
+            Control::parse(&raw(vec![SYN_ACK, 5, 0, 2])),
+            Ok(Control::SynAck {
+                first_id: 5,
+                version: 2,
+                capabilities: Capabilities::default(),
+            })
+        );
+    }
+
+    #[test]
+    fn bytes_past_the_last_known_argument_are_skipped() {
+        assert_eq!(
+            Control::parse(&raw(vec![CREDIT, 5, 0, 0xEE, 0xEE])),
+            Ok(Control::Credit { limit: 5 })
+        );
+        assert_eq!(Control::parse(&raw(vec![FIN, 0xEE])), Ok(Control::Fin));
+        assert_eq!(
+            Control::parse(&raw(vec![PAUSE, 3, 0xEE])),
+            Ok(Control::PauseChannel { channel: 3 })
+        );
+    }
+
+    #[test]
+    fn short_and_unknown_ones_are_refused() {
+        assert_eq!(
+            Control::parse(&raw(vec![CREDIT, 5])),
+            Err(ProtocolError::InvalidLength {
+                expected: 3,
+                actual: 2
+            })
+        );
+        assert_eq!(
+            Control::parse(&raw(vec![RESUME])),
+            Err(ProtocolError::InvalidLength {
+                expected: 2,
+                actual: 1
+            })
+        );
+        assert_eq!(
+            Control::parse(&raw(vec![0xEE])),
+            Err(ProtocolError::InvalidHeader)
+        );
+        assert_eq!(
+            Control::parse(&raw(vec![])),
+            Err(ProtocolError::InvalidHeader)
+        );
+    }
+}
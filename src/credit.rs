@@ -0,0 +1,159 @@
+// Credit based flow control: MCU2 tells MCU1 how many messages it has room for and MCU1
+// never sends more than that, so nothing is ever dropped or overwritten for lack of space on
+// the receive side.
+//
+// Both ends count messages (fragments one by one) as a wrapping u16. The receiver grants a
+// limit, "send until your counter reaches N", rather than a number of extra credits, so a
+// grant that gets lost or arrives twice doesn't throw the count off, the next one fixes it.
+// It announces a new limit once at least `refresh` credits have been freed since the last
+// grant, instead of after every single message.
+
+use crate::sequence::is_ahead;
+
+// Sender side
+#[derive(Debug, Default)]
+pub struct CreditSender {
+    sent: u16,
+    limit: u16,
+}
+
+impl CreditSender {
+    // No credit until the first grant
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Messages that can go out right now
+    pub fn available(&self) -> u16 {
+        if is_ahead(self.limit, self.sent) {
+            self.limit.wrapping_sub(self.sent)
+        } else {
+            0
+        }
+    }
+
+    // Use up credit for `count` messages, false (and nothing used) if there isn't that much
+    pub fn consume(&mut self, count: u16) -> bool {
+        if self.available() < count {
+            return false;
+        }
+        self.sent = self.sent.wrapping_add(count);
+        true
+    }
+
+    // Grant from the receiver, stale ones are ignored
+    pub fn grant(&mut self, limit: u16) {
+        if is_ahead(limit, self.limit) {
+            self.limit = limit;
+        }
+    }
+}
+
+// Receiver side
+#[derive(Debug)]
+pub struct CreditReceiver {
+    window: u16,
+    refresh: u16,
+    consumed: u16,
+    granted: u16,
+    // a grant is owed even if less than `refresh` changed, e.g. the first one
+    forced: bool,
+}
+
+impl CreditReceiver {
+    // Room for `window` messages (the receive buffer), new grants every `window / 2`
+    pub fn new(window: u16) -> Self {
+        let window = window.clamp(1, 0x7FFF);
+        CreditReceiver {
+            window,
+            refresh: (window / 2).max(1),
+            consumed: 0,
+            granted: 0,
+            forced: true,
+        }
+    }
+
+    pub fn with_refresh(mut self, refresh: u16) -> Self {
+        self.refresh = refresh.clamp(1, self.window);
+        self
+    }
+
+    pub fn window(&self) -> u16 {
+        self.window
+    }
+
+    // `count` messages were taken out of the receive buffer, their room can be granted again
+    pub fn consumed(&mut self, count: u16) {
+        self.consumed = self.consumed.wrapping_add(count);
+    }
+
+    // Announce the current limit again on the next `grant`, e.g. after the peer restarted
+    pub fn regrant(&mut self) {
+        self.forced = true;
+    }
+
+    // New limit to send the sender, if it's time for one
+    pub fn grant(&mut self) -> Option<u16> {
+        let limit = self.consumed.wrapping_add(self.window);
+        if !self.forced && limit.wrapping_sub(self.granted) < self.refresh {
+            return None;
+        }
+        self.forced = false;
+        self.granted = limit;
+        Some(limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sender_stops_at_the_limit() {
+        let mut sender = CreditSender::new();
+        assert_eq!(sender.available(), 0);
+        assert!(!sender.consume(1));
+
+        sender.grant(3);
+        assert!(sender.consume(2));
+        assert!(!sender.consume(2));
+        assert!(sender.consume(1));
+        assert_eq!(sender.available(), 0);
+    }
+
+    #[test]
+    fn stale_and_repeated_grants_change_nothing() {
+        let mut sender = CreditSender::new();
+        sender.grant(10);
+        sender.grant(10);
+        sender.grant(4);
+        assert_eq!(sender.available(), 10);
+    }
+
+    #[test]
+    fn limits_keep_working_across_the_wrap() {
+        let mut sender = CreditSender::new();
+        let mut receiver = CreditReceiver::new(8).with_refresh(1);
+        for _ in 0..10_000 {
+            sender.grant(receiver.grant().unwrap_or(0));
+            assert!(sender.consume(8));
+            assert_eq!(sender.available(), 0);
+            receiver.consumed(8);
+        }
+    }
+
+    #[test]
+    fn receiver_grants_once_refresh_is_freed() {
+        let mut receiver = CreditReceiver::new(8);
+        assert_eq!(receiver.grant(), Some(8));
+        assert_eq!(receiver.grant(), None);
+
+        receiver.consumed(3);
+        assert_eq!(receiver.grant(), None);
+        receiver.consumed(1);
+        assert_eq!(receiver.grant(), Some(12));
+
+        receiver.regrant();
+        assert_eq!(receiver.grant(), Some(12));
+    }
+}
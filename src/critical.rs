@@ -0,0 +1,121 @@
+// Interrupt-safe buffer access on bare metal, for a main loop consumer and an ISR producer
+// sharing one buffer. Every access runs inside `critical_section::with`, so an interrupt can't
+// land in the middle of an insert or a receive. Which critical section that is comes from the
+// integrator's `critical-section` implementation (e.g. `cortex-m`'s `critical-section-single-core`,
+// or `critical-section/std` on a host).
+//
+//   static QUEUE: CriticalBuffer<array::CircularBuffer<FixedMessage<32>, 8>> =
+//       CriticalBuffer::new(array::CircularBuffer::new(OverflowPolicy::DropOldest));
+//
+//   #[interrupt]
+//   fn USART1() {
+//       let _ = QUEUE.try_send(FixedMessage::new(1, &[byte]).unwrap());
+//   }
+//
+// The section is held for the whole call, so keep what goes into `with` short, and don't call
+// back into the same `CriticalBuffer` from inside it (the `RefCell` would panic). For a
+// producer that can't afford a critical section at all there's `SpscRing`.
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+
+use crate::array::{self, QueuedMessage};
+#[cfg(feature = "alloc")]
+use crate::buffer::CircularBuffer;
+use crate::error::ProtocolError;
+#[cfg(feature = "alloc")]
+use crate::message::Message;
+use crate::stats::Stats;
+
+pub struct CriticalBuffer<B> {
+    inner: Mutex<RefCell<B>>,
+}
+
+impl<B> CriticalBuffer<B> {
+    // `const` so it can be a `static`
+    pub const fn new(buffer: B) -> Self {
+        CriticalBuffer {
+            inner: Mutex::new(RefCell::new(buffer)),
+        }
+    }
+
+    // Run `f` on the buffer with interrupts held off
+    pub fn with<R>(&self, f: impl FnOnce(&mut B) -> R) -> R {
+        critical_section::with(|cs| f(&mut self.inner.borrow_ref_mut(cs)))
+    }
+
+    // No section needed when we've got it exclusively
+    pub fn get_mut(&mut self) -> &mut B {
+        self.inner.get_mut().get_mut()
+    }
+
+    pub fn into_inner(self) -> B {
+        self.inner.into_inner().into_inner()
+    }
+}
+
+impl<M: QueuedMessage, const N: usize> CriticalBuffer<array::CircularBuffer<M, N>> {
+    pub fn try_send(&self, message: M) -> Result<(), ProtocolError> {
+        self.with(|buffer| buffer.try_send(message))
+    }
+
+    pub fn try_receive(&self) -> Option<M> {
+        self.with(|buffer| buffer.try_receive())
+    }
+
+    pub fn remove(&self, id: u16) -> Option<M> {
+        self.with(|buffer| buffer.remove(id))
+    }
+
+    pub fn clear(&self) {
+        self.with(|buffer| buffer.clear())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.with(|buffer| buffer.is_empty())
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.with(|buffer| buffer.is_full())
+    }
+
+    pub fn length(&self) -> usize {
+        self.with(|buffer| buffer.length())
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.with(|buffer| buffer.stats())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl CriticalBuffer<CircularBuffer> {
+    pub fn try_send(&self, message: Message) -> Result<(), ProtocolError> {
+        self.with(|buffer| buffer.try_send(message))
+    }
+
+    pub fn try_receive(&self) -> Option<Message> {
+        self.with(|buffer| buffer.try_receive())
+    }
+
+    pub fn remove(&self, id: u16) -> Option<Message> {
+        self.with(|buffer| buffer.remove(id))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.with(|buffer| buffer.is_empty())
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.with(|buffer| buffer.is_full())
+    }
+
+    pub fn length(&self) -> usize {
+        self.with(|buffer| buffer.length())
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.with(|buffer| buffer.stats())
+    }
+}
@@ -0,0 +1,137 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::message::Message;
+use crate::protocol::CommunicationProtocol;
+
+// Routes received messages to handlers by message type, instead of a `while let` loop with a
+// `match` on the payload in every application:
+//
+//   let mut dispatcher = Dispatcher::new()
+//       .on(SET_LED, |message| set_led(&message.payload[1..]))
+//       .on(READ_SENSOR, |message| read_sensor(&message.payload[1..]))
+//       .otherwise(|message| println!("unknown message {}", message.id))
+//       .on_corrupted(|message| println!("message {} corrupted", message.id));
+//
+//   // in MCU2's main loop
+//   dispatcher.poll(&mut protocol);
+//
+// The type is the first payload byte, the application decides what the numbers mean. Only
+// messages that passed their checksum get routed by type; a message without a handler for
+// its type (or with an empty payload) goes to `otherwise`, corrupted ones to `on_corrupted`,
+// and either is dropped when there's no such handler.
+//
+// `dispatch` routes a single message, e.g. from `Endpoint::on_receive`.
+
+// First payload byte of an application message
+pub type MessageType = u8;
+
+// Gets every message routed to it
+pub type Handler = Box<dyn FnMut(&Message) + Send>;
+
+// Type of `message`, `None` for an empty payload
+pub fn message_type(message: &Message) -> Option<MessageType> {
+    message.payload.first().copied()
+}
+
+pub struct Dispatcher {
+    handlers: Vec<(MessageType, Handler)>,
+    otherwise: Option<Handler>,
+    corrupted: Option<Handler>,
+}
+
+impl Dispatcher {
+    pub fn new() -> Self {
+        Dispatcher {
+            handlers: Vec::new(),
+            otherwise: None,
+            corrupted: None,
+        }
+    }
+
+    // Messages of type `kind` go to `handler`, replacing the one it had
+    pub fn on<H>(mut self, kind: MessageType, handler: H) -> Self
+    where
+        H: FnMut(&Message) + Send + 'static,
+    {
+        self.register(kind, handler);
+        self
+    }
+
+    // Same as `on`, for a dispatcher that's already built
+    pub fn register<H>(&mut self, kind: MessageType, handler: H)
+    where
+        H: FnMut(&Message) + Send + 'static,
+    {
+        self.handlers.retain(|(registered, _)| *registered != kind);
+        self.handlers.push((kind, Box::new(handler)));
+    }
+
+    // Drop the handler for `kind`. Returns whether there was one.
+    pub fn unregister(&mut self, kind: MessageType) -> bool {
+        let before = self.handlers.len();
+        self.handlers.retain(|(registered, _)| *registered != kind);
+        self.handlers.len() != before
+    }
+
+    // Valid messages no handler is registered for
+    pub fn otherwise<H>(mut self, handler: H) -> Self
+    where
+        H: FnMut(&Message) + Send + 'static,
+    {
+        self.otherwise = Some(Box::new(handler));
+        self
+    }
+
+    // Messages that failed their checksum
+    pub fn on_corrupted<H>(mut self, handler: H) -> Self
+    where
+        H: FnMut(&Message) + Send + 'static,
+    {
+        self.corrupted = Some(Box::new(handler));
+        self
+    }
+
+    // Route one verified message. Returns whether a handler for its type took it.
+    pub fn dispatch(&mut self, message: &Message) -> bool {
+        let handler = message_type(message).and_then(|kind| {
+            self.handlers
+                .iter_mut()
+                .find(|(registered, _)| *registered == kind)
+        });
+        match handler {
+            Some((_, handler)) => {
+                handler(message);
+                true
+            }
+            None => {
+                if let Some(otherwise) = self.otherwise.as_mut() {
+                    otherwise(message);
+                }
+                false
+            }
+        }
+    }
+
+    // Everything MCU2 has waiting in `protocol`, checked and routed. Returns how many
+    // messages it went through, corrupted ones included.
+    pub fn poll(&mut self, protocol: &mut CommunicationProtocol) -> usize {
+        let mut count = 0;
+        while let Some((message, valid_checksum)) = protocol.mcu2_receive() {
+            count += 1;
+            if valid_checksum {
+                self.dispatch(&message);
+            } else if let Some(corrupted) = self.corrupted.as_mut() {
+                corrupted(&message);
+            }
+            protocol.recycle(message);
+        }
+        count
+    }
+}
+
+impl Default for Dispatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,1391 @@
+// One MCU's end of the conversation, the way the firmware on each side is structured: a TX
+// queue the application sends into, an RX handler that gets what arrives, and a `Link`
+// (transport + framing + checksums) to the other MCU. Each side builds its own `Endpoint`
+// and calls `poll` from its main loop.
+//
+//   let (uart1, uart2) = Loopback::pair();
+//   let mut mcu1 = Endpoint::new(uart1, Cobs::default(), 16);
+//   let mut mcu2 = Endpoint::new(uart2, Cobs::default(), 16).on_receive(|message| {
+//       println!("got {:?}", message.payload);
+//   });
+//   mcu1.send(vec![1, 2, 3])?;
+//   mcu1.poll()?;
+//   mcu2.poll()?;
+//
+// `CommunicationProtocol` models both MCUs and the buffer between them in one process, which
+// is handy on the desk; on hardware each MCU only ever has its own `Endpoint`.
+//
+// With more than two MCUs on the bus give each endpoint an address (`with_address`) and
+// use `FormatVersion::V3`: `send_to` addresses a message to one node (or a multicast group,
+// or everyone with `broadcast`) and the others drop it on receive, see `address`.
+//
+// Commands, telemetry and log output between the same two MCUs can go on separate channels
+// (needs `FormatVersion::V3`, which carries the channel id). Each channel opened with
+// `with_channel` has its own TX and RX queue, and `poll` takes one message from each channel
+// with something queued in turn, so a backed up log channel can't starve the commands.
+// `with_channel_config` also picks reliable or best effort, ordered or not, see `channel`.
+// Channel 0 is always there, it's what `send` uses. Both ends have to open the same channels,
+// messages on one that isn't open are dropped and counted in `Stats::unknown_channel`.
+//
+// `with_heartbeat` sends a heartbeat on the control channel every interval and watches for
+// the other end going quiet, see `heartbeat`. `link_state` says whether it's up,
+// `on_link_state` gets told when that changes.
+//
+// `with_handshake` makes both ends agree on a connection before any data flows, see
+// `connection`: one end calls `connect`, and until the handshake is through sends fail with
+// `ProtocolError::NotConnected` and data that arrives is dropped. `on_connection_state` gets
+// told about every change of `connection_state`. The handshake also settles the protocol
+// version (`protocol_version`), `with_protocol_versions` limits the ones offered. When the
+// other end has none in common the connection ends up Closed and sends fail with
+// `ProtocolError::IncompatibleVersion`. Each end says what it can do as well
+// (`with_capabilities`), and sends stick to what both can (`capabilities`): payloads above
+// the other end's `max_payload` are fragmented to fit, or refused with
+// `ProtocolError::PayloadTooLarge` when either end can't fragment.
+//
+// `close` shuts an endpoint down gracefully, with or without a handshake: new sends are
+// refused, what's queued keeps going out (and being resent) until every reliable channel
+// has its acks or the deadline passes, then a FIN goes out. What never got through is
+// handed back by `take_undelivered`.
+//
+// `reset` starts the session over on both ends, e.g. when MCU2 has just rebooted and MCU1
+// still has queues, ids and unacked messages from before: a RESET goes to the other end,
+// both drop everything queued, in flight or half received, ids start from scratch and the
+// handshake (if there is one) runs again.
+//
+// A channel set up for RPC carries requests and responses, see `rpc`: `request` sends one
+// and returns its handle, `response` hands over the answer once it's in, and the other end's
+// `on_request` handler gives the answers. `request_with_timeout` gives up after a while and
+// `cancel` drops a request that's no longer wanted.
+//
+// `with_receive_filter` picks what the application gets, e.g. when MCU2 only wants some of
+// the broadcast traffic on the bus: messages it turns down are still acked (the sender
+// shouldn't keep trying) but go no further, and are counted in `Stats::filtered`.
+//
+// `with_middleware` adds a layer application data goes through on the way out and back in,
+// see `middleware`.
+//
+// `with_rate_limit` caps how fast data goes out (messages and / or bytes per second), so a
+// burst on one side can't take the whole link, see `rate`. Control messages aren't held back.
+//
+// `stats` also has how many messages and bytes a second go each way, over a sliding window
+// (`with_throughput_window`, a second by default), see `throughput`, and the round trip time
+// reliable channels measure on their acks, which their retransmit timeout follows, see `round_trip`.
+//
+// Each TX queue is a `CircularBuffer`, so priorities and the overflow policy work as they do
+// there. `poll` hands queued messages to the link only once the link has written out the
+// previous ones, so a slow transport backs up in the queues (where priorities and channels
+// still count) rather than in framed bytes.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use alloc::collections::VecDeque;
+
+use crate::ack::{self, Reply};
+use crate::address::{self, Groups, NodeId};
+use crate::buffer::{CircularBuffer, OverflowPolicy};
+use crate::channel::{CONTROL_CHANNEL, Channel, ChannelConfig, ChannelId, DEFAULT_CHANNEL};
+use crate::codec::Codec;
+use crate::connection::{Capabilities, Connection, ConnectionState, features};
+use crate::control::Control;
+use crate::error::ProtocolError;
+use crate::fragment::{self, Reassembler};
+use crate::framing::{Cobs, Framing};
+use crate::heartbeat::{self, HeartbeatMonitor, LinkState};
+use crate::link::Link;
+use crate::message::{FormatVersion, Message, MessageKind, Payload, Priority};
+use crate::middleware::{Chain, Middleware};
+use crate::rate::RateLimit;
+use crate::reorder::ReorderBuffer;
+use crate::rpc::{self, CancelHandler, PendingRequest, RequestHandler, RequestId, Requests};
+use crate::stats::Stats;
+use crate::throughput::Throughput;
+use crate::time::{self, Clock};
+use crate::topic::{self, Subscriptions, TopicFilter, TopicId};
+use crate::transport::Transport;
+
+// Gets every message that arrives, put together and verified. Runs inside `poll`.
+pub type ReceiveHandler = Box<dyn FnMut(Message) + Send>;
+
+// Whether the application wants a received message, see `with_receive_filter`
+pub type ReceiveFilter = Box<dyn FnMut(&Message) -> bool + Send>;
+
+// Gets told when the link goes up or down, runs inside `poll`
+pub type LinkStateHandler = Box<dyn FnMut(LinkState) + Send>;
+
+// Gets every change of the connection state, runs inside `poll`
+pub type ConnectionStateHandler = Box<dyn FnMut(ConnectionState) + Send>;
+
+pub struct Endpoint<T, F: Framing = Cobs> {
+    link: Link<T, F>,
+    // `DEFAULT_CHANNEL` first
+    channels: Vec<Channel>,
+    // sealed protocol messages for `CONTROL_CHANNEL`, they go out before anything else
+    control: VecDeque<Message>,
+    // channel `poll_transmit` looks at first next time round
+    next_channel: usize,
+    overflow_policy: OverflowPolicy,
+    address: NodeId,
+    groups: Groups,
+    mtu: Option<usize>,
+    reassembler: Reassembler,
+    clock: Box<dyn Clock + Send + Sync>,
+    // `None` = received messages wait in their channel for `receive`
+    handler: Option<ReceiveHandler>,
+    middleware: Chain,
+    filter: Option<ReceiveFilter>,
+    // `None` = send as fast as the link takes it
+    rate_limit: Option<RateLimit>,
+    // for messages on topic channels
+    subscriptions: Subscriptions,
+    // RPC, our requests waiting on a response and the handler for the other end's
+    requests: Requests,
+    on_request: Option<RequestHandler>,
+    on_cancel: Option<CancelHandler>,
+    // `None` = no heartbeats
+    heartbeat: Option<HeartbeatMonitor>,
+    on_link_state: Option<LinkStateHandler>,
+    // `None` = no handshake, data flows straight away
+    connection: Option<Connection>,
+    on_connection_state: Option<ConnectionStateHandler>,
+    // `Some` while closing, when to stop waiting for the last acks
+    closing: Option<Duration>,
+    closed: bool,
+    // what never made it to the other end before closing
+    undelivered: Vec<Message>,
+    stats: Stats,
+    // frames onto / off the link, for the rates in `stats`
+    sent: Throughput,
+    received: Throughput,
+}
+
+impl<T: Transport, F: Framing> Endpoint<T, F> {
+    pub fn new(transport: T, framing: F, tx_capacity: usize) -> Self {
+        Endpoint {
+            link: Link::new(transport, framing),
+            channels: Vec::from([Channel::new(
+                DEFAULT_CHANNEL,
+                ChannelConfig::best_effort(tx_capacity),
+            )]),
+            control: VecDeque::new(),
+            next_channel: 0,
+            overflow_policy: OverflowPolicy::default(),
+            address: address::UNADDRESSED,
+            groups: Groups::new(),
+            mtu: None,
+            reassembler: Reassembler::default(),
+            clock: time::default_clock(),
+            handler: None,
+            middleware: Chain::new(),
+            filter: None,
+            rate_limit: None,
+            subscriptions: Subscriptions::new(),
+            requests: Requests::new(),
+            on_request: None,
+            on_cancel: None,
+            heartbeat: None,
+            on_link_state: None,
+            connection: None,
+            on_connection_state: None,
+            closing: None,
+            closed: false,
+            undelivered: Vec::new(),
+            stats: Stats::new(),
+            sent: Throughput::default(),
+            received: Throughput::default(),
+        }
+    }
+
+    // Checksum and wire format, both ends have to agree on it
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.link = self.link.with_codec(codec);
+        self.limit_connection()
+    }
+
+    // This node's id on the bus, messages for other nodes get dropped on receive
+    pub fn with_address(mut self, address: NodeId) -> Self {
+        self.address = address;
+        self
+    }
+
+    pub fn address(&self) -> NodeId {
+        self.address
+    }
+
+    // Take messages sent to multicast group `group` as well
+    pub fn join_group(&mut self, group: NodeId) {
+        self.groups.join(group);
+    }
+
+    pub fn leave_group(&mut self, group: NodeId) {
+        self.groups.leave(group);
+    }
+
+    pub fn groups(&self) -> Groups {
+        self.groups
+    }
+
+    // what happens when the application sends into a full TX queue, for every channel
+    pub fn with_overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = policy;
+        for channel in &mut self.channels {
+            channel.set_overflow_policy(policy);
+        }
+        self
+    }
+
+    // Open channel `id`, best effort and unordered with queues `depth` messages deep
+    pub fn with_channel(self, id: ChannelId, depth: usize) -> Self {
+        let config = ChannelConfig {
+            overflow_policy: self.overflow_policy,
+            ..ChannelConfig::best_effort(depth)
+        };
+        self.with_channel_config(id, config)
+    }
+
+    // Open channel `id` set up as `config` says. Opening one that's already open (the default
+    // channel too) starts it over with the new settings. `CONTROL_CHANNEL` is reserved,
+    // opening it does nothing. `ArqMode::GoBackN` runs as selective repeat, see `channel`.
+    pub fn with_channel_config(mut self, id: ChannelId, config: ChannelConfig) -> Self {
+        if id == CONTROL_CHANNEL {
+            return self;
+        }
+        let channel = Channel::new(id, config);
+        match self.channels.iter_mut().find(|c| c.id == id) {
+            Some(existing) => *existing = channel,
+            None => self.channels.push(channel),
+        }
+        self
+    }
+
+    pub fn channel_config(&self, channel: ChannelId) -> Option<ChannelConfig> {
+        self.channel(channel).map(|channel| channel.config)
+    }
+
+    // Open channels, `DEFAULT_CHANNEL` first
+    pub fn channels(&self) -> impl Iterator<Item = ChannelId> + '_ {
+        self.channels.iter().map(|channel| channel.id)
+    }
+
+    // split payloads bigger than `mtu` bytes into fragments (needs FormatVersion::V2). Without
+    // it they're split at what one frame of the framing carries.
+    pub fn with_mtu(mut self, mtu: usize) -> Self {
+        self.mtu = Some(mtu);
+        self
+    }
+
+    pub fn with_reassembly_timeout(mut self, timeout: Duration) -> Self {
+        self.reassembler.set_timeout(timeout);
+        self
+    }
+
+    pub fn with_clock<C>(mut self, clock: C) -> Self
+    where
+        C: Clock + Send + Sync + 'static,
+    {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    // Hand every received message to `handler` instead of keeping it for `receive`
+    pub fn on_receive<H>(mut self, handler: H) -> Self
+    where
+        H: FnMut(Message) + Send + 'static,
+    {
+        self.handler = Some(Box::new(handler));
+        self
+    }
+
+    // Call `handler` for everything published under `filter` (a topic, or a `TopicFilter`
+    // for many), on any topic channel
+    pub fn subscribe<H>(&mut self, filter: impl Into<TopicFilter>, handler: H)
+    where
+        H: FnMut(TopicId, &[u8]) + Send + 'static,
+    {
+        self.subscriptions.subscribe(filter, handler);
+    }
+
+    pub fn unsubscribe(&mut self, filter: impl Into<TopicFilter>) -> bool {
+        self.subscriptions.unsubscribe(filter)
+    }
+
+    // Send a heartbeat every `interval`, the link is down after `max_missed` of them without
+    // hearing anything from the other end
+    pub fn with_heartbeat(mut self, interval: Duration, max_missed: u32) -> Self {
+        self.heartbeat = Some(HeartbeatMonitor::new(interval, max_missed));
+        self
+    }
+
+    pub fn on_link_state<H>(mut self, handler: H) -> Self
+    where
+        H: FnMut(LinkState) + Send + 'static,
+    {
+        self.on_link_state = Some(Box::new(handler));
+        self
+    }
+
+    // `None` without heartbeats, there's no telling then
+    pub fn link_state(&self) -> Option<LinkState> {
+        self.heartbeat.as_ref().map(HeartbeatMonitor::state)
+    }
+
+    // Don't send or take data before a handshake, both ends need this
+    pub fn with_handshake(mut self) -> Self {
+        self.connection = Some(Connection::default());
+        self.limit_connection()
+    }
+
+    // Offer only protocol versions `min` to `max` in the handshake, turns it on
+    pub fn with_protocol_versions(mut self, min: u8, max: u8) -> Self {
+        let connection = self.connection.take().unwrap_or_default();
+        self.connection = Some(connection.with_versions(min, max));
+        self.limit_connection()
+    }
+
+    // Seed for the ids the handshake starts connections at, something that changes between
+    // boots so a restart isn't taken for the old session, see `connection`. Turns it on
+    pub fn with_handshake_seed(mut self, seed: u64) -> Self {
+        let connection = self.connection.take().unwrap_or_default();
+        self.connection = Some(connection.with_seed(seed));
+        self.limit_connection()
+    }
+
+    // What this end tells the other it can do in the handshake, turns it on
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        let connection = self.connection.take().unwrap_or_default();
+        self.connection = Some(connection.with_capabilities(capabilities));
+        self.limit_connection()
+    }
+
+    // What both ends can do, agreed on in the handshake
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.connection.as_ref().and_then(Connection::capabilities)
+    }
+
+    pub fn on_connection_state<H>(mut self, handler: H) -> Self
+    where
+        H: FnMut(ConnectionState) + Send + 'static,
+    {
+        self.on_connection_state = Some(Box::new(handler));
+        self
+    }
+
+    // `None` without `with_handshake`
+    pub fn connection_state(&self) -> Option<ConnectionState> {
+        self.connection.as_ref().map(Connection::state)
+    }
+
+    // Agreed on in the handshake, `None` until then (or without one)
+    pub fn protocol_version(&self) -> Option<u8> {
+        self.connection.as_ref().and_then(Connection::version)
+    }
+
+    // Why the last handshake failed, if it did
+    pub fn connection_error(&self) -> Option<&ProtocolError> {
+        self.connection.as_ref().and_then(Connection::error)
+    }
+
+    // Start the handshake, the SYN goes out on the next poll. Does nothing without
+    // `with_handshake` or while already connecting / connected.
+    pub fn connect(&mut self) {
+        self.closed = false;
+        let now = self.clock.now();
+        self.step_connection(address::UNADDRESSED, |connection| connection.connect(now));
+    }
+
+    // Stop taking new sends and close once everything queued has gone out (and been acked on
+    // reliable channels) or `timeout` from now, whichever comes first. Then a FIN tells the
+    // other end, and whatever didn't make it is in `take_undelivered`. Keep polling until
+    // `is_closed`.
+    pub fn close(&mut self, timeout: Duration) {
+        if self.closing.is_some() || self.closed {
+            return;
+        }
+        self.closing = Some(self.clock.now() + timeout);
+        self.step_connection(address::UNADDRESSED, |connection| {
+            connection.close();
+            None
+        });
+    }
+
+    // Start over on both ends: everything queued, unacked, half received or waiting in a
+    // reorder buffer is dropped here and a RESET tells the other end to do the same. With a
+    // handshake a new one starts right behind the RESET.
+    pub fn reset(&mut self) {
+        let now = self.clock.now();
+        self.clear_session();
+        let message = Control::Reset.to_message(self.link.codec());
+        self.queue_control(message, address::UNADDRESSED);
+        self.step_connection(address::UNADDRESSED, |connection| {
+            connection.reset();
+            connection.connect(now)
+        });
+    }
+
+    // Closed by `close` or the other end
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    // Hold `channel` both ways, e.g. before MCU2 goes to sleep: nothing goes out on it from
+    // here (sends still queue up) and the other end is told to do the same. PAUSE and RESUME
+    // aren't acked, on a lossy link call them again until the other end goes quiet / starts
+    // sending.
+    pub fn pause(&mut self, channel: ChannelId) -> Result<(), ProtocolError> {
+        self.set_paused(channel, true)
+    }
+
+    pub fn resume(&mut self, channel: ChannelId) -> Result<(), ProtocolError> {
+        self.set_paused(channel, false)
+    }
+
+    // Paused by either end
+    pub fn is_paused(&self, channel: ChannelId) -> bool {
+        self.channel(channel).is_some_and(|channel| channel.paused)
+    }
+
+    // Frames (fragments one by one) that were still queued or unacked when the endpoint
+    // closed
+    pub fn take_undelivered(&mut self) -> Vec<Message> {
+        core::mem::take(&mut self.undelivered)
+    }
+
+    pub fn link(&self) -> &Link<T, F> {
+        &self.link
+    }
+
+    pub fn link_mut(&mut self) -> &mut Link<T, F> {
+        &mut self.link
+    }
+
+    // Read-only view of the default channel's TX queue
+    pub fn tx_queue(&self) -> &CircularBuffer {
+        &self.channels[0].tx
+    }
+
+    pub fn channel_queue(&self, channel: ChannelId) -> Option<&CircularBuffer> {
+        self.channel(channel).map(|channel| &channel.tx)
+    }
+
+    pub fn codec(&self) -> &Codec {
+        self.link.codec()
+    }
+
+    // Queue a payload for the other MCU, it goes out on the next `poll`. Returns the id the
+    // other end will see it under (ids count per channel); payloads above the MTU use up one
+    // id per fragment.
+    pub fn send(&mut self, payload: impl Into<Payload>) -> Result<u16, ProtocolError> {
+        self.send_with_priority(payload, Priority::default())
+    }
+
+    pub fn send_with_priority(
+        &mut self,
+        payload: impl Into<Payload>,
+        priority: Priority,
+    ) -> Result<u16, ProtocolError> {
+        self.send_to_with_priority(address::UNADDRESSED, payload, priority)
+    }
+
+    // Same as `send`, only node `destination` takes it
+    pub fn send_to(
+        &mut self,
+        destination: NodeId,
+        payload: impl Into<Payload>,
+    ) -> Result<u16, ProtocolError> {
+        self.send_to_with_priority(destination, payload, Priority::default())
+    }
+
+    // To every node listening, one send for all of them
+    pub fn broadcast(&mut self, payload: impl Into<Payload>) -> Result<u16, ProtocolError> {
+        self.send_to(address::BROADCAST, payload)
+    }
+
+    pub fn send_to_with_priority(
+        &mut self,
+        destination: NodeId,
+        payload: impl Into<Payload>,
+        priority: Priority,
+    ) -> Result<u16, ProtocolError> {
+        self.send_on_to(DEFAULT_CHANNEL, destination, payload, priority)
+    }
+
+    // Publish `data` under `topic` on `channel`, which has to be set up for topics both ends
+    pub fn publish(
+        &mut self,
+        channel: ChannelId,
+        topic: TopicId,
+        data: &[u8],
+    ) -> Result<u16, ProtocolError> {
+        if !self.channel(channel).is_some_and(|c| c.config.topics) {
+            return Err(ProtocolError::UnknownChannel { channel });
+        }
+        self.send_on(channel, topic::encode(topic, data))
+    }
+
+    // Only hand on received messages `filter` says yes to (by id, source, channel, payload
+    // ...). Runs on whole messages, after reassembly and the middleware.
+    pub fn with_receive_filter<P>(mut self, filter: P) -> Self
+    where
+        P: FnMut(&Message) -> bool + Send + 'static,
+    {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    // Hold data frames back to stay within `limit`, see `rate`
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    // How far back the messages / bytes per second in `stats` look
+    pub fn with_throughput_window(mut self, window: Duration) -> Self {
+        self.sent = Throughput::new(window);
+        self.received = Throughput::new(window);
+        self
+    }
+
+    // Run data through `layer`, after the layers added before it on the way out and before
+    // them on the way in
+    pub fn with_middleware<M: Middleware + 'static>(mut self, layer: M) -> Self {
+        self.middleware.push(layer);
+        self
+    }
+
+    // Answers requests that come in on RPC channels, see `rpc`
+    pub fn on_request<H>(mut self, handler: H) -> Self
+    where
+        H: FnMut(RequestId, &[u8]) -> Option<Vec<u8>> + Send + 'static,
+    {
+        self.on_request = Some(Box::new(handler));
+        self
+    }
+
+    // Gets told which requests the other end cancelled
+    pub fn on_cancel<H>(mut self, handler: H) -> Self
+    where
+        H: FnMut(RequestId) + Send + 'static,
+    {
+        self.on_cancel = Some(Box::new(handler));
+        self
+    }
+
+    // Send a request on `channel`, which has to be set up for RPC both ends. The handle
+    // gets its response from `response`. Waits for it as long as it takes.
+    pub fn request(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+    ) -> Result<PendingRequest, ProtocolError> {
+        self.start_request(channel, data, None)
+    }
+
+    // Same as `request`, giving up with `ProtocolError::Timeout` after `timeout`
+    pub fn request_with_timeout(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        timeout: Duration,
+    ) -> Result<PendingRequest, ProtocolError> {
+        let deadline = self.clock.now() + timeout;
+        self.start_request(channel, data, Some(deadline))
+    }
+
+    // Stop waiting for `request`'s response, with a CANCEL to the other end if `notify`.
+    // Returns whether it was still outstanding.
+    pub fn cancel(&mut self, request: &PendingRequest, notify: bool) -> bool {
+        if !self.requests.remove(request) {
+            return false;
+        }
+        if notify {
+            let payload = rpc::encode(rpc::CANCEL, request.id(), &[]);
+            if let Err(error) = self.send_on(request.channel(), payload) {
+                log!(
+                    "Endpoint: cancel of request {} failed: {}",
+                    request.id(),
+                    error
+                );
+            }
+        }
+        true
+    }
+
+    // The response to `request` once it's in, `Timeout` if its deadline passed first
+    pub fn response(&mut self, request: &PendingRequest) -> Option<Result<Payload, ProtocolError>> {
+        self.requests.take(request)
+    }
+
+    // Whether `request` is still waiting for its response
+    pub fn is_pending(&self, request: &PendingRequest) -> bool {
+        self.requests.is_pending(request)
+    }
+
+    // Answer request `id` on `channel`, for requests the handler didn't answer straight away
+    pub fn respond(
+        &mut self,
+        channel: ChannelId,
+        id: RequestId,
+        data: &[u8],
+    ) -> Result<u16, ProtocolError> {
+        if !self.channel(channel).is_some_and(|c| c.config.rpc) {
+            return Err(ProtocolError::UnknownChannel { channel });
+        }
+        self.send_on(channel, rpc::encode(rpc::RESPONSE, id, data))
+    }
+
+    // Same as `send`, on channel `channel`
+    pub fn send_on(
+        &mut self,
+        channel: ChannelId,
+        payload: impl Into<Payload>,
+    ) -> Result<u16, ProtocolError> {
+        self.send_on_to(channel, address::UNADDRESSED, payload, Priority::default())
+    }
+
+    pub fn send_on_to(
+        &mut self,
+        channel: ChannelId,
+        destination: NodeId,
+        payload: impl Into<Payload>,
+        priority: Priority,
+    ) -> Result<u16, ProtocolError> {
+        let Some(index) = self.channels.iter().position(|c| c.id == channel) else {
+            return Err(ProtocolError::UnknownChannel { channel });
+        };
+        if self.closing.is_some() || self.closed {
+            return Err(ProtocolError::NotConnected);
+        }
+        if let Some(connection) = self.connection.as_ref()
+            && !connection.is_established()
+        {
+            return Err(connection
+                .error()
+                .cloned()
+                .unwrap_or(ProtocolError::NotConnected));
+        }
+        let payload = self.middleware.on_send(channel, payload.into())?;
+        let id = self.channels[index].next_message;
+        let len = payload.len();
+        let codec = self.link.codec();
+
+        let (mtu, can_fragment) = self.frame_limits();
+        let mut messages = if len > mtu {
+            if codec.version() == FormatVersion::V1 || !can_fragment {
+                return Err(ProtocolError::PayloadTooLarge { len, max: mtu });
+            }
+            fragment::fragment(codec, id, priority, &payload, mtu)?
+        } else {
+            Vec::from([codec.seal_with(id, 0, priority, payload)])
+        };
+
+        if codec.version().has_addresses() {
+            for message in &mut messages {
+                message.source = self.address;
+                message.destination = destination;
+                message.channel = channel;
+                codec.reseal(message);
+            }
+        }
+
+        // don't leave half a payload behind in the queue when it can't take all of it, a
+        // payload with more fragments than the queue holds can never go through whole
+        let count = messages.len();
+        let channel = &mut self.channels[index];
+        let capacity = channel.tx.capacity();
+        if count > capacity {
+            return Err(ProtocolError::PayloadTooLarge {
+                len,
+                max: capacity * mtu.saturating_sub(fragment::INDEX_LEN),
+            });
+        }
+        let bytes = messages.iter().map(|m| m.payload.len()).sum();
+        let fits = if count > 1 {
+            channel.tx.can_take(count, bytes, priority)
+        } else {
+            // a single message is up to the overflow policy as usual
+            channel.tx.overflow_policy() == OverflowPolicy::DropOldest
+                || channel.tx.has_room(count, bytes)
+        };
+        if !fits {
+            return Err(ProtocolError::BufferFull);
+        }
+        for message in messages {
+            channel.tx.send_message(message)?;
+        }
+        channel.next_message = id.wrapping_add(count as u16);
+        self.stats.messages_sent += 1;
+        self.stats.bytes_sent += len as u64;
+        Ok(id)
+    }
+
+    // Move queued messages onto the link as far as it writes them, then take in whatever
+    // arrived. Returns how many messages came in (whole payloads). Corrupted frames are
+    // counted and skipped, transport errors come out as they are.
+    pub fn poll(&mut self) -> Result<usize, ProtocolError> {
+        self.poll_transmit()?;
+        let received = self.poll_receive()?;
+        // acks for what just came in don't wait for the next round
+        self.flush_control()?;
+        Ok(received)
+    }
+
+    // Just the sending half of `poll`. Control messages go first, then reliable channels
+    // resend what timed out, then the channels take turns, one message each.
+    pub fn poll_transmit(&mut self) -> Result<(), ProtocolError> {
+        let now = self.clock.now();
+        if let Some(monitor) = self.heartbeat.as_mut()
+            && monitor.is_due(now)
+        {
+            monitor.sent(now);
+            let message = heartbeat::heartbeat(self.link.codec());
+            self.queue_control(message, address::UNADDRESSED);
+        }
+        self.step_connection(address::UNADDRESSED, |connection| connection.poll(now));
+        if let Some(deadline) = self.closing
+            && (now >= deadline || !self.has_pending_tx() || !self.carries_data())
+        {
+            self.finish_close(now);
+        }
+        self.flush_control()?;
+        if !self.carries_data() {
+            return self.link.poll_write();
+        }
+        for channel in &mut self.channels {
+            let Some(window) = channel.window.as_mut() else {
+                continue;
+            };
+            if channel.paused {
+                continue;
+            }
+            for message in window.poll(now) {
+                self.stats.retransmissions += 1;
+                let len = self.link.codec().encoded_len(&message);
+                if let Some(limit) = self.rate_limit.as_mut() {
+                    limit.charge(len, now);
+                }
+                self.sent.record(len, now);
+                self.link.send_message(&message)?;
+            }
+            while let Some(id) = window.take_failed() {
+                log!("Endpoint: ID {} on channel {} never acked", id, channel.id);
+                self.stats.delivery_failures += 1;
+            }
+        }
+
+        self.link.poll_write()?;
+        while self.link.pending_bytes() == 0 {
+            let Some(message) = self.next_to_send(now) else {
+                break;
+            };
+            self.transmit(&message, now)?;
+        }
+        Ok(())
+    }
+
+    // Just the receiving half of `poll`
+    pub fn poll_receive(&mut self) -> Result<usize, ProtocolError> {
+        let now = self.clock.now();
+        self.reassembler.expire(now);
+        self.requests.expire(now);
+
+        // gaps on ordered channels that have been waited on long enough
+        let mut received = 0;
+        for index in 0..self.channels.len() {
+            let Some(reorder) = self.channels[index].reorder.as_mut() else {
+                continue;
+            };
+            reorder.poll(now);
+            for message in drain_ready(reorder) {
+                received += self.deliver(index, message, now) as usize;
+            }
+        }
+
+        while let Some(result) = self.link.receive() {
+            let message = match result {
+                Ok(message) => {
+                    self.received
+                        .record(self.link.codec().encoded_len(&message), now);
+                    message
+                }
+                Err(error @ (ProtocolError::Transport(_) | ProtocolError::Disconnected)) => {
+                    return Err(error);
+                }
+                Err(error) => {
+                    self.stats.count_decode_error(&error);
+                    continue;
+                }
+            };
+
+            if !address::accepts(self.address, self.groups, message.destination) {
+                self.stats.addressed_elsewhere += 1;
+                continue;
+            }
+            if let Some(state) = self.heartbeat.as_mut().and_then(|m| m.heard(now)) {
+                self.link_state_changed(state);
+            }
+            if message.kind().is_internal() {
+                self.handle_internal(&message, now);
+                continue;
+            }
+            let Some(index) = self.channels.iter().position(|c| c.id == message.channel) else {
+                log!(
+                    "Endpoint: ID {} on unknown channel {} dropped",
+                    message.id,
+                    message.channel
+                );
+                self.stats.unknown_channel += 1;
+                continue;
+            };
+            if !self.carries_data() {
+                log!("Endpoint: ID {} dropped, not connected", message.id);
+                continue;
+            }
+
+            let channel = &mut self.channels[index];
+            if channel.config.is_reliable() {
+                // copies are acked again whatever, the first ack may have been lost
+                let duplicate = channel
+                    .duplicates
+                    .as_ref()
+                    .is_some_and(|duplicates| duplicates.contains(message.id));
+                if !duplicate
+                    && self.handler.is_none()
+                    && !channel.has_rx_room_for(message.id)
+                    && channel.config.overflow_policy != OverflowPolicy::DropOldest
+                {
+                    // no ack, the sender tries again once there's room
+                    self.stats.rejected_full += 1;
+                    continue;
+                }
+                let mut reply = ack::ack(self.link.codec(), message.id);
+                reply.payload = Payload::from(Vec::from([message.channel]));
+                self.queue_control(reply, message.source);
+                if let Some(duplicates) = self.channels[index].duplicates.as_mut()
+                    && !duplicates.check(message.id)
+                {
+                    self.stats.duplicates += 1;
+                    continue;
+                }
+            }
+
+            match self.channels[index].reorder.as_mut() {
+                Some(reorder) => {
+                    reorder.push(message, now);
+                    for message in drain_ready(reorder) {
+                        received += self.deliver(index, message, now) as usize;
+                    }
+                }
+                None => received += self.deliver(index, message, now) as usize,
+            }
+        }
+
+        if let Some(state) = self.heartbeat.as_mut().and_then(|m| m.check(now)) {
+            self.link_state_changed(state);
+        }
+        Ok(received)
+    }
+
+    // Next received message on any channel (lowest channel first), when there's no
+    // `on_receive` handler
+    pub fn receive(&mut self) -> Option<Message> {
+        self.channels
+            .iter_mut()
+            .find_map(|channel| channel.rx.pop_front())
+    }
+
+    // Next received message on channel `channel`
+    pub fn receive_on(&mut self, channel: ChannelId) -> Option<Message> {
+        self.channels
+            .iter_mut()
+            .find(|c| c.id == channel)
+            .and_then(|channel| channel.rx.pop_front())
+    }
+
+    // Anything still queued, on its way out or waiting for an ack
+    pub fn has_pending_tx(&self) -> bool {
+        self.channels.iter().any(Channel::has_pending_tx) || self.link.pending_bytes() > 0
+    }
+
+    // Protocol level counters (whole payloads), the link's own count frames. Queue counters
+    // are summed over the channels, the high watermark is the deepest any one got. The rates
+    // are up to now, the round trip time is the longest any reliable channel measured.
+    pub fn stats(&self) -> Stats {
+        let now = self.clock.now();
+        let mut stats = self.stats;
+        (stats.send_messages_per_sec, stats.send_bytes_per_sec) = self.sent.rates(now);
+        (stats.receive_messages_per_sec, stats.receive_bytes_per_sec) = self.received.rates(now);
+        // the slowest channel's round trip
+        if let Some(window) = self
+            .channels
+            .iter()
+            .filter_map(|channel| channel.window.as_ref())
+            .max_by_key(|window| window.rtt().srtt())
+        {
+            stats.set_rtt(window.rtt(), window.rto());
+        }
+        for channel in &self.channels {
+            let tx = channel.tx.stats();
+            stats.dropped_overflow += tx.dropped_overflow;
+            stats.rejected_full += tx.rejected_full;
+            stats.high_watermark = stats.high_watermark.max(tx.high_watermark);
+        }
+        stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::new();
+        self.sent.clear();
+        self.received.clear();
+        for channel in &mut self.channels {
+            channel.tx.reset_stats();
+        }
+        self.link.reset_stats();
+    }
+
+    fn channel(&self, id: ChannelId) -> Option<&Channel> {
+        self.channels.iter().find(|channel| channel.id == id)
+    }
+
+    // Round robin over the channels with something queued (and room in the send window and
+    // the rate limit)
+    fn next_to_send(&mut self, now: Duration) -> Option<Message> {
+        let count = self.channels.len();
+        for offset in 0..count {
+            let index = (self.next_channel + offset) % count;
+            let channel = &mut self.channels[index];
+            if let Some(limit) = self.rate_limit.as_mut() {
+                let Some(next) = channel.tx.peek() else {
+                    continue;
+                };
+                if !limit.allows(self.link.codec().encoded_len(next), now) {
+                    continue;
+                }
+            }
+            if let Some(message) = channel.next_to_send(now) {
+                if let Some(limit) = self.rate_limit.as_mut() {
+                    limit.charge(self.link.codec().encoded_len(&message), now);
+                }
+                self.next_channel = (index + 1) % count;
+                return Some(message);
+            }
+        }
+        None
+    }
+
+    // Seal `message` for `CONTROL_CHANNEL` and queue it, it goes out on the next poll
+    fn queue_control(&mut self, mut message: Message, destination: NodeId) {
+        let codec = self.link.codec();
+        if codec.version().has_addresses() {
+            message.source = self.address;
+            message.destination = destination;
+            message.channel = CONTROL_CHANNEL;
+        }
+        codec.reseal(&mut message);
+        self.control.push_back(message);
+    }
+
+    fn flush_control(&mut self) -> Result<(), ProtocolError> {
+        let now = self.clock.now();
+        while let Some(message) = self.control.pop_front() {
+            self.transmit(&message, now)?;
+        }
+        Ok(())
+    }
+
+    // Onto the link, counted in the send rates
+    fn transmit(&mut self, message: &Message, now: Duration) -> Result<(), ProtocolError> {
+        self.sent
+            .record(self.link.codec().encoded_len(message), now);
+        self.link.send_message(message)
+    }
+
+    // The handshake offers no longer payloads than a frame of ours carries
+    fn limit_connection(mut self) -> Self {
+        let max = self.link.max_payload_len();
+        self.connection = self
+            .connection
+            .take()
+            .map(|connection| connection.with_max_payload(max));
+        self
+    }
+
+    // Longest payload that goes in one frame and whether longer ones may be fragmented: the
+    // MTU if there is one, no more than a frame carries (`Link::max_payload_len`) and no more
+    // than the handshake agreed on
+    fn frame_limits(&self) -> (usize, bool) {
+        let mtu = self
+            .mtu
+            .unwrap_or(usize::MAX)
+            .min(self.link.max_payload_len());
+        match self.capabilities() {
+            Some(agreed) => (
+                mtu.min(agreed.max_payload as usize),
+                agreed.supports(features::FRAGMENTATION),
+            ),
+            None => (mtu, true),
+        }
+    }
+
+    fn set_paused(&mut self, channel: ChannelId, paused: bool) -> Result<(), ProtocolError> {
+        let Some(open) = self.channels.iter_mut().find(|c| c.id == channel) else {
+            return Err(ProtocolError::UnknownChannel { channel });
+        };
+        open.paused = paused;
+        let control = match paused {
+            true => Control::PauseChannel { channel },
+            false => Control::ResumeChannel { channel },
+        };
+        let message = control.to_message(self.link.codec());
+        self.queue_control(message, address::UNADDRESSED);
+        Ok(())
+    }
+
+    // Everything this end knows about the session, gone
+    fn clear_session(&mut self) {
+        log!("Endpoint: session reset");
+        for channel in &mut self.channels {
+            channel.reset();
+        }
+        self.reassembler.clear();
+        self.control.clear();
+        self.closing = None;
+        self.closed = false;
+    }
+
+    // Done draining (or out of time), the FIN goes out
+    fn finish_close(&mut self, now: Duration) {
+        self.closing = None;
+        self.closed = true;
+        self.take_undelivered_frames();
+        if self.connection.is_some() {
+            self.step_connection(address::UNADDRESSED, |connection| connection.fin(now));
+        } else {
+            let message = Control::Fin.to_message(self.link.codec());
+            self.queue_control(message, address::UNADDRESSED);
+        }
+    }
+
+    fn take_undelivered_frames(&mut self) {
+        for channel in &mut self.channels {
+            let undelivered = channel.take_undelivered();
+            if !undelivered.is_empty() {
+                log!(
+                    "Endpoint: {} undelivered on channel {}",
+                    undelivered.len(),
+                    channel.id
+                );
+                self.stats.delivery_failures += undelivered.len() as u64;
+            }
+            self.undelivered.extend(undelivered);
+        }
+    }
+
+    // Whether data can go out / come in on the connection (always without a handshake)
+    fn carries_data(&self) -> bool {
+        self.connection.as_ref().is_none_or(|connection| {
+            matches!(
+                connection.state(),
+                ConnectionState::Established | ConnectionState::Closing
+            )
+        })
+    }
+
+    // Run `step` on the connection, queue what it has to send to `destination` and act on
+    // the state it ends up in
+    fn step_connection(
+        &mut self,
+        destination: NodeId,
+        step: impl FnOnce(&mut Connection) -> Option<Control>,
+    ) {
+        let Some(connection) = self.connection.as_mut() else {
+            return;
+        };
+        let before = connection.state();
+        let reply = step(connection);
+        let state = connection.state();
+        let (local_first, remote_first) = (connection.local_first(), connection.remote_first());
+
+        if let Some(reply) = reply {
+            let message = reply.to_message(self.link.codec());
+            self.queue_control(message, destination);
+        }
+        if state == before {
+            return;
+        }
+        match state {
+            ConnectionState::Established => {
+                for channel in &mut self.channels {
+                    channel.start_session(local_first, remote_first.unwrap_or(1));
+                }
+            }
+            // closed by the other end, nothing queued here can go anywhere now
+            ConnectionState::Closed => {
+                self.closing = None;
+                self.closed = true;
+                self.take_undelivered_frames();
+            }
+            _ => {}
+        }
+        if let Some(handler) = self.on_connection_state.as_mut() {
+            handler(state);
+        }
+    }
+
+    fn link_state_changed(&mut self, state: LinkState) {
+        if let Some(handler) = self.on_link_state.as_mut() {
+            handler(state);
+        }
+    }
+
+    // Protocol messages, handled as soon as they're read
+    fn handle_internal(&mut self, message: &Message, now: Duration) {
+        match message.kind() {
+            MessageKind::Ack | MessageKind::Nack => {
+                let Some(reply) = ack::parse(message) else {
+                    return;
+                };
+                // the channel comes after the NACK reason
+                let at = (message.kind() == MessageKind::Nack) as usize;
+                let channel = message.payload.get(at).copied().unwrap_or(DEFAULT_CHANNEL);
+                self.handle_reply(channel, reply, now);
+            }
+            MessageKind::Control => match Control::parse(message) {
+                Ok(
+                    control @ (Control::PauseChannel { channel }
+                    | Control::ResumeChannel { channel }),
+                ) => {
+                    let paused = matches!(control, Control::PauseChannel { .. });
+                    if let Some(channel) = self.channels.iter_mut().find(|c| c.id == channel) {
+                        let change = if paused { "paused" } else { "resumed" };
+                        log!("Endpoint: other end {} channel {}", change, channel.id);
+                        channel.paused = paused;
+                    }
+                }
+                // the other end started over, it sends a SYN itself if there's a handshake
+                Ok(Control::Reset) => {
+                    self.clear_session();
+                    self.step_connection(message.source, |connection| {
+                        connection.reset();
+                        None
+                    });
+                }
+                Ok(control) => {
+                    self.step_connection(message.source, |connection| {
+                        connection.handle(&control, now)
+                    });
+                }
+                Err(_) => {}
+            },
+            // heard from the other end, that's all a heartbeat says
+            MessageKind::Heartbeat => {}
+            // nothing else is acted on yet
+            _ => {}
+        }
+    }
+
+    fn handle_reply(&mut self, channel: ChannelId, reply: Reply, now: Duration) {
+        let Some(window) = self
+            .channels
+            .iter_mut()
+            .find(|c| c.id == channel)
+            .and_then(|channel| channel.window.as_mut())
+        else {
+            return;
+        };
+        match reply {
+            Reply::Ack(id) => self.stats.messages_acked += window.acked(id, now).len() as u64,
+            Reply::Nack(id, _) => {
+                if window.nacked(id, now) {
+                    self.stats.messages_nacked += 1;
+                }
+            }
+        }
+    }
+
+    fn start_request(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        deadline: Option<Duration>,
+    ) -> Result<PendingRequest, ProtocolError> {
+        if !self.channel(channel).is_some_and(|c| c.config.rpc) {
+            return Err(ProtocolError::UnknownChannel { channel });
+        }
+        let request = self.requests.start(channel, deadline);
+        if let Err(error) = self.send_on(channel, rpc::encode(rpc::REQUEST, request.id(), data)) {
+            self.requests.remove(&request);
+            return Err(error);
+        }
+        Ok(request)
+    }
+
+    // A request or response on an RPC channel. Returns whether it was one.
+    fn handle_rpc(&mut self, index: usize, message: &Message) -> bool {
+        let channel = self.channels[index].id;
+        match rpc::decode(&message.payload) {
+            Some((rpc::REQUEST, id, data)) => {
+                let Some(mut handler) = self.on_request.take() else {
+                    log!("Endpoint: request {} on channel {} unanswered", id, channel);
+                    return true;
+                };
+                let response = handler(id, data);
+                self.on_request = Some(handler);
+                if let Some(response) = response
+                    && let Err(error) = self.respond(channel, id, &response)
+                {
+                    log!("Endpoint: response to request {} failed: {}", id, error);
+                }
+                true
+            }
+            Some((rpc::RESPONSE, id, data)) => {
+                if !self
+                    .requests
+                    .complete(channel, id, Payload::from(data.to_vec()))
+                {
+                    log!("Endpoint: response to unknown request {} dropped", id);
+                }
+                true
+            }
+            Some((rpc::CANCEL, id, _)) => {
+                if let Some(handler) = self.on_cancel.as_mut() {
+                    handler(id);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Put fragments back together and hand the message on. Returns whether a whole message
+    // came out of it.
+    fn deliver(&mut self, index: usize, message: Message, now: Duration) -> bool {
+        let message = if message.is_fragment() {
+            match self.reassembler.push(self.link.codec(), &message, now) {
+                Ok(Some(message)) => message,
+                Ok(None) => return false,
+                Err(_) => {
+                    self.stats.invalid_fragments += 1;
+                    return false;
+                }
+            }
+        } else {
+            message
+        };
+        let Some(message) = self.middleware.on_receive(message) else {
+            return false;
+        };
+        if let Some(filter) = self.filter.as_mut()
+            && !filter(&message)
+        {
+            self.stats.filtered += 1;
+            return false;
+        }
+
+        let len = message.payload.len() as u64;
+        if self.channels[index].config.topics && self.subscriptions.dispatch(&message.payload) {
+            self.stats.messages_received += 1;
+            self.stats.bytes_received += len;
+            return true;
+        }
+        if self.channels[index].config.rpc && self.handle_rpc(index, &message) {
+            self.stats.messages_received += 1;
+            self.stats.bytes_received += len;
+            return true;
+        }
+        match self.handler.as_mut() {
+            Some(handler) => handler(message),
+            None => {
+                let channel = &mut self.channels[index];
+                if !channel.has_rx_room() {
+                    if channel.config.overflow_policy != OverflowPolicy::DropOldest {
+                        self.stats.rejected_full += 1;
+                        return false;
+                    }
+                    channel.rx.pop_front();
+                    self.stats.dropped_overflow += 1;
+                }
+                channel.rx.push_back(message);
+            }
+        }
+        self.stats.messages_received += 1;
+        self.stats.bytes_received += len;
+        true
+    }
+}
+
+fn drain_ready(reorder: &mut ReorderBuffer) -> Vec<Message> {
+    core::iter::from_fn(|| reorder.pop()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::Crc16Ccitt;
+    use crate::framing::{Cobs, cobs};
+    use crate::message::FormatVersion;
+    use crate::retransmit::{ArqMode, RetransmitPolicy};
+    use crate::transport::{Fault, FaultyTransport, Loopback};
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    #[derive(Clone, Default)]
+    struct TestClock(Arc<AtomicU64>);
+
+    impl TestClock {
+        fn advance(&self, millis: u64) {
+            self.0.fetch_add(millis, Ordering::SeqCst);
+        }
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> Duration {
+            Duration::from_millis(self.0.load(Ordering::SeqCst))
+        }
+    }
+
+    fn endpoint<T: Transport>(transport: T, clock: &TestClock) -> Endpoint<T, Cobs> {
+        Endpoint::new(transport, Cobs::default(), 8)
+            .with_codec(Codec::new(Crc16Ccitt, FormatVersion::V3))
+            .with_clock(clock.clone())
+    }
+
+    #[test]
+    fn go_back_n_channel_runs_as_selective_repeat() {
+        let clock = TestClock::default();
+        let (a, b) = Loopback::pair();
+        let a = FaultyTransport::new(a, cobs::DELIMITER)
+            .with_seed(3)
+            .with_probability(Fault::Drop, 0.2);
+        let config = ChannelConfig {
+            retransmit: Some(RetransmitPolicy::go_back_n(8)),
+            ..ChannelConfig::reliable(8)
+        };
+        let mut mcu1 = endpoint(a, &clock).with_channel_config(1, config);
+        let mut mcu2 = endpoint(b, &clock).with_channel_config(1, config);
+
+        let reported = mcu1.channel_config(1).unwrap();
+        assert_eq!(reported.retransmit.unwrap().mode, ArqMode::SelectiveRepeat);
+        assert!(reported.ordered);
+
+        let mut sent = 0u8;
+        let mut received = Vec::new();
+        for _ in 0..10_000 {
+            if sent < 40 && mcu1.send_on(1, vec![sent]).is_ok() {
+                sent += 1;
+            }
+            mcu1.poll().unwrap();
+            mcu2.poll().unwrap();
+            while let Some(message) = mcu2.receive_on(1) {
+                received.push(message.payload[0]);
+            }
+            if received.len() == 40 {
+                break;
+            }
+            clock.advance(5);
+        }
+        assert_eq!(received, (0..40).collect::<Vec<u8>>());
+        assert!(mcu1.stats().retransmissions > 0);
+    }
+}
@@ -0,0 +1,144 @@
+use core::fmt;
+
+// Everything that can go wrong while moving messages between MCU1 and MCU2
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    // shared buffer has no room for another message
+    BufferFull,
+    // nothing queued to receive
+    BufferEmpty,
+    // payload doesn't fit in a single message
+    PayloadTooLarge { len: usize, max: usize },
+    // recalculated checksum doesn't match the one carried by the message
+    ChecksumMismatch { expected: u32, actual: u32 },
+    // serialized message is shorter/longer than its header says
+    InvalidLength { expected: usize, actual: usize },
+    // header field holds a value we don't know about
+    InvalidHeader,
+    // framing layer got bytes that can't be a valid frame
+    MalformedFrame,
+    // fragment header doesn't make sense (missing index, index past the last fragment ...)
+    InvalidFragment,
+    // operation didn't complete in time
+    Timeout,
+    // message was retransmitted as often as allowed and never acked
+    DeliveryFailed { id: u16 },
+    // no route to that node, see `router`
+    Unroutable { destination: u8 },
+    // channel that hasn't been opened on this endpoint, see `endpoint`
+    UnknownChannel { channel: u8 },
+    // handshake hasn't finished (or the connection is closed), see `connection`
+    NotConnected,
+    // the other end only speaks protocol versions `min` to `max`, none of them ours
+    IncompatibleVersion { min: u8, max: u8 },
+    // the other end is gone, nothing will ever arrive / be picked up again
+    Disconnected,
+    // the transport underneath failed to move the bytes
+    Transport(TransportError),
+}
+
+// What went wrong below the framing layer, kept coarse so every transport can map its own
+// errors onto it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportError {
+    // OS level I/O error (serial port, socket ...)
+    Io,
+    // UART receiver overrun, bytes were lost before anyone read them
+    Overrun,
+    // UART framing error, e.g. a missing stop bit or a baud rate mismatch
+    FrameFormat,
+    // UART parity error
+    Parity,
+    // UART saw noise on the line
+    Noise,
+    // SPI / I2C bus fault (mode fault, arbitration lost, ...)
+    Bus,
+    // I2C slave stopped acknowledging in the middle of a transfer
+    Nack,
+    // anything the transport has no better name for
+    Other,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::BufferFull => write!(f, "buffer is full"),
+            ProtocolError::BufferEmpty => write!(f, "buffer is empty"),
+            ProtocolError::PayloadTooLarge { len, max } => {
+                write!(
+                    f,
+                    "payload of {} bytes exceeds maximum of {} bytes",
+                    len, max
+                )
+            }
+            ProtocolError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "checksum mismatch: expected {:#x}, got {:#x}",
+                expected, actual
+            ),
+            ProtocolError::InvalidLength { expected, actual } => write!(
+                f,
+                "invalid message length: expected {} bytes, got {}",
+                expected, actual
+            ),
+            ProtocolError::InvalidHeader => write!(f, "invalid message header"),
+            ProtocolError::MalformedFrame => write!(f, "malformed frame"),
+            ProtocolError::InvalidFragment => write!(f, "invalid fragment"),
+            ProtocolError::Timeout => write!(f, "operation timed out"),
+            ProtocolError::DeliveryFailed { id } => {
+                write!(f, "message {} was never acknowledged", id)
+            }
+            ProtocolError::Unroutable { destination } => {
+                write!(f, "no route to node {}", destination)
+            }
+            ProtocolError::UnknownChannel { channel } => write!(f, "unknown channel {}", channel),
+            ProtocolError::NotConnected => write!(f, "not connected"),
+            ProtocolError::IncompatibleVersion { min, max } => write!(
+                f,
+                "no protocol version in common, the other end speaks {} to {}",
+                min, max
+            ),
+            ProtocolError::Disconnected => write!(f, "other end disconnected"),
+            ProtocolError::Transport(error) => write!(f, "transport error: {}", error),
+        }
+    }
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Io => write!(f, "I/O error"),
+            TransportError::Overrun => write!(f, "receiver overrun"),
+            TransportError::FrameFormat => write!(f, "UART framing error"),
+            TransportError::Parity => write!(f, "parity error"),
+            TransportError::Noise => write!(f, "line noise"),
+            TransportError::Bus => write!(f, "bus error"),
+            TransportError::Nack => write!(f, "not acknowledged"),
+            TransportError::Other => write!(f, "transport failure"),
+        }
+    }
+}
+
+impl From<TransportError> for ProtocolError {
+    fn from(error: TransportError) -> Self {
+        ProtocolError::Transport(error)
+    }
+}
+
+impl core::error::Error for ProtocolError {}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for ProtocolError {
+    fn from(error: std::io::Error) -> Self {
+        use std::io::ErrorKind;
+        match error.kind() {
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => ProtocolError::Timeout,
+            ErrorKind::BrokenPipe
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::UnexpectedEof => ProtocolError::Disconnected,
+            _ => ProtocolError::Transport(TransportError::Io),
+        }
+    }
+}
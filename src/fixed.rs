@@ -0,0 +1,468 @@
+// Messages and buffers with all their storage inline, for MCUs without an allocator. Nothing
+// in here touches the heap, so it builds without the `alloc` feature.
+//
+// The payload capacity `P` is a const generic, pick it to fit the largest payload on the
+// link. Wire format and checksums are the same as `Message`, both go through `MessageRef`.
+
+use crate::checksum::{ChecksumAlgorithm, Xor8};
+use crate::error::ProtocolError;
+use crate::message::{FormatVersion, MessageRef, Priority, flags};
+
+use crate::array::CircularBuffer;
+use crate::buffer::OverflowPolicy;
+use crate::stats::Stats;
+
+// `Message` with the payload in a `[u8; P]` instead of a `Vec`
+#[derive(Debug, Clone, Copy)]
+pub struct FixedMessage<const P: usize> {
+    pub id: u16,
+    pub flags: u8,
+    pub priority: Priority,
+    pub source: u8,
+    pub destination: u8,
+    pub channel: u8,
+    pub checksum: u32,
+    len: u16,
+    payload: [u8; P],
+}
+
+impl<const P: usize> FixedMessage<P> {
+    // Empty message, `const` so it can sit in a `static`
+    pub const fn empty(id: u16) -> Self {
+        FixedMessage {
+            id,
+            flags: 0,
+            priority: Priority::Normal,
+            source: 0,
+            destination: 0,
+            channel: 0,
+            checksum: 0,
+            len: 0,
+            payload: [0; P],
+        }
+    }
+
+    // Same as `Message::new`, XOR checksum under the default format version.
+    // `PayloadTooLarge` if `payload` doesn't fit in `P` bytes.
+    pub fn new(id: u16, payload: &[u8]) -> Result<Self, ProtocolError> {
+        Self::sealed(id, payload, &Xor8, FormatVersion::default())
+    }
+
+    pub fn sealed(
+        id: u16,
+        payload: &[u8],
+        algorithm: &dyn ChecksumAlgorithm,
+        version: FormatVersion,
+    ) -> Result<Self, ProtocolError> {
+        let mut message = Self::empty(id);
+        message.set_payload(payload)?;
+        message.reseal(algorithm, version);
+        Ok(message)
+    }
+
+    // Copy a borrowed message in, checksum and all
+    pub fn from_ref(message: &MessageRef<'_>) -> Result<Self, ProtocolError> {
+        let mut fixed = Self::empty(message.id);
+        fixed.flags = message.flags;
+        fixed.priority = message.priority;
+        fixed.source = message.source;
+        fixed.destination = message.destination;
+        fixed.channel = message.channel;
+        fixed.checksum = message.checksum;
+        fixed.set_payload(message.payload)?;
+        Ok(fixed)
+    }
+
+    pub fn borrowed(&self) -> MessageRef<'_> {
+        MessageRef {
+            id: self.id,
+            flags: self.flags,
+            priority: self.priority,
+            source: self.source,
+            destination: self.destination,
+            channel: self.channel,
+            payload: self.payload(),
+            checksum: self.checksum,
+        }
+    }
+
+    pub const fn capacity() -> usize {
+        P
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len as usize]
+    }
+
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        &mut self.payload[..self.len as usize]
+    }
+
+    // Replace the payload, the checksum isn't touched so `reseal` afterwards
+    pub fn set_payload(&mut self, payload: &[u8]) -> Result<(), ProtocolError> {
+        if payload.len() > P || payload.len() > u16::MAX as usize {
+            return Err(ProtocolError::PayloadTooLarge {
+                len: payload.len(),
+                max: P.min(u16::MAX as usize),
+            });
+        }
+        self.payload[..payload.len()].copy_from_slice(payload);
+        // keep the unused tail zeroed, copies of the message shouldn't leak old payloads
+        self.payload[payload.len()..].fill(0);
+        self.len = payload.len() as u16;
+        Ok(())
+    }
+
+    pub fn is_fragment(&self) -> bool {
+        self.flags & flags::FRAGMENT != 0
+    }
+
+    pub fn reseal(&mut self, algorithm: &dyn ChecksumAlgorithm, version: FormatVersion) {
+        self.checksum = self.borrowed().compute_checksum(algorithm, version);
+    }
+
+    pub fn verify(&self, algorithm: &dyn ChecksumAlgorithm, version: FormatVersion) -> bool {
+        self.borrowed().verify(algorithm, version)
+    }
+
+    pub fn encoded_len_for(
+        &self,
+        algorithm: &dyn ChecksumAlgorithm,
+        version: FormatVersion,
+    ) -> usize {
+        self.borrowed().encoded_len_for(algorithm, version)
+    }
+
+    // Serialize into `out`, see `MessageRef::encode_into`
+    pub fn encode_into(
+        &self,
+        algorithm: &dyn ChecksumAlgorithm,
+        version: FormatVersion,
+        out: &mut [u8],
+    ) -> Result<usize, ProtocolError> {
+        self.borrowed().encode_into(algorithm, version, out)
+    }
+
+    // Parse and verify a message off the wire straight into fixed storage
+    pub fn decode(
+        bytes: &[u8],
+        algorithm: &dyn ChecksumAlgorithm,
+        version: FormatVersion,
+    ) -> Result<Self, ProtocolError> {
+        Self::from_ref(&MessageRef::decode(bytes, algorithm, version)?)
+    }
+}
+
+impl<const P: usize> PartialEq for FixedMessage<P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.borrowed() == other.borrowed()
+    }
+}
+
+impl<const P: usize> Eq for FixedMessage<P> {}
+
+// `CircularBuffer` for targets without a heap: at most `N` messages of at most `P` payload
+// bytes, all stored in a `heapless::Deque`. Same ordering (highest priority first, FIFO
+// within a priority) and the same overflow policies, Block acts like RejectNew since there's
+// nothing to wait on.
+#[cfg(feature = "heapless")]
+pub struct HeaplessBuffer<const P: usize, const N: usize> {
+    buffer: heapless::Deque<FixedMessage<P>, N>,
+    policy: OverflowPolicy,
+    stats: Stats,
+}
+
+#[cfg(feature = "heapless")]
+impl<const P: usize, const N: usize> HeaplessBuffer<P, N> {
+    pub const fn new(policy: OverflowPolicy) -> Self {
+        HeaplessBuffer {
+            buffer: heapless::Deque::new(),
+            policy,
+            stats: Stats::new(),
+        }
+    }
+
+    pub fn send_message(&mut self, message: FixedMessage<P>) -> Result<(), ProtocolError> {
+        self.try_send(message)
+    }
+
+    pub fn receive_message(&mut self) -> Option<FixedMessage<P>> {
+        self.try_receive()
+    }
+
+    // Overflow handling mirrors `CircularBuffer::try_send`
+    pub fn try_send(&mut self, message: FixedMessage<P>) -> Result<(), ProtocolError> {
+        if self.buffer.is_full() {
+            match self.policy {
+                OverflowPolicy::RejectNew | OverflowPolicy::Block => {
+                    self.stats.rejected_full += 1;
+                    return Err(ProtocolError::BufferFull);
+                }
+                OverflowPolicy::DropOldest => match self.buffer.back() {
+                    Some(lowest) if lowest.priority <= message.priority => {
+                        self.evict();
+                    }
+                    _ => {
+                        self.stats.rejected_full += 1;
+                        return Err(ProtocolError::BufferFull);
+                    }
+                },
+            }
+        }
+
+        self.record_sent(&message);
+        let priority = message.priority;
+        // can't fail, a full buffer (N == 0 included) made room or returned above
+        let _ = self.buffer.push_back(message);
+
+        // bubble it forward past everything of lower priority
+        let mut index = self.buffer.len() - 1;
+        while index > 0
+            && self
+                .buffer
+                .get(index - 1)
+                .is_some_and(|m| m.priority < priority)
+        {
+            self.buffer.swap(index - 1, index);
+            index -= 1;
+        }
+
+        self.stats.high_watermark = self.stats.high_watermark.max(self.buffer.len() as u64);
+        Ok(())
+    }
+
+    // oldest message of the lowest priority
+    fn evict(&mut self) -> Option<FixedMessage<P>> {
+        let lowest = self.buffer.back()?.priority;
+        let index = self.buffer.iter().position(|m| m.priority == lowest)?;
+        let evicted = self.remove_at(index);
+        self.stats.dropped_overflow += 1;
+        evicted
+    }
+
+    // The deque can't take things out of the middle, so rotate once round and leave the one
+    // at `index` out. Order of the rest is unchanged.
+    fn remove_at(&mut self, index: usize) -> Option<FixedMessage<P>> {
+        let len = self.buffer.len();
+        let mut removed = None;
+        for i in 0..len {
+            let message = self.buffer.pop_front()?;
+            if i == index {
+                removed = Some(message);
+            } else {
+                // there's always room, one just came out
+                let _ = self.buffer.push_back(message);
+            }
+        }
+        removed
+    }
+
+    fn record_sent(&mut self, message: &FixedMessage<P>) {
+        self.stats.messages_sent += 1;
+        self.stats.bytes_sent += message.len as u64;
+    }
+
+    pub fn try_receive(&mut self) -> Option<FixedMessage<P>> {
+        let message = self.buffer.pop_front()?;
+        self.stats.messages_received += 1;
+        self.stats.bytes_received += message.len as u64;
+        Some(message)
+    }
+
+    pub fn peek(&self) -> Option<&FixedMessage<P>> {
+        self.buffer.front()
+    }
+
+    // Pull a queued message back out by id, see `CircularBuffer::remove`
+    pub fn remove(&mut self, id: u16) -> Option<FixedMessage<P>> {
+        let index = self.buffer.iter().position(|m| m.id == id)?;
+        self.remove_at(index)
+    }
+
+    pub fn retain<F>(&mut self, keep: F) -> usize
+    where
+        F: FnMut(&FixedMessage<P>) -> bool,
+    {
+        let before = self.buffer.len();
+        self.buffer.retain(keep);
+        before - self.buffer.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &FixedMessage<P>> {
+        self.buffer.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.buffer.is_full()
+    }
+
+    pub fn length(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.policy
+    }
+
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn queued_bytes(&self) -> usize {
+        self.buffer.iter().map(|m| m.len as usize).sum()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats {
+            high_watermark: self.buffer.len() as u64,
+            ..Stats::new()
+        };
+    }
+}
+
+// `CommunicationProtocol` with every size fixed at compile time: up to `N` queued messages of
+// up to `P` payload bytes, checksummed with `C`. The queue lives inside the struct and `new`
+// is `const`, so the whole thing can be a `static` and ends up in `.bss`, e.g. on a
+// Cortex-M0 with 8KB of RAM:
+//
+//   static PROTOCOL: Mutex<RefCell<StaticProtocol<8, 32>>> =
+//       Mutex::new(RefCell::new(StaticProtocol::new(Xor8, FormatVersion::V2)));
+//
+// Roughly `N * (P + 16)` bytes of RAM plus 128 for the counters. No fragmentation (there's nowhere to reassemble into)
+// and no timeouts, payloads above `P` are refused with `PayloadTooLarge`.
+pub struct StaticProtocol<const N: usize, const P: usize, C = Xor8> {
+    buffer: CircularBuffer<FixedMessage<P>, N>,
+    next_message: u16,
+    checksum: C,
+    version: FormatVersion,
+    stats: Stats,
+}
+
+impl<const N: usize, const P: usize, C: ChecksumAlgorithm> StaticProtocol<N, P, C> {
+    pub const fn new(checksum: C, version: FormatVersion) -> Self {
+        StaticProtocol {
+            buffer: CircularBuffer::new(OverflowPolicy::DropOldest),
+            next_message: 1,
+            checksum,
+            version,
+            stats: Stats::new(),
+        }
+    }
+
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.buffer.set_overflow_policy(policy);
+    }
+
+    pub fn checksum(&self) -> &C {
+        &self.checksum
+    }
+
+    pub fn version(&self) -> FormatVersion {
+        self.version
+    }
+
+    // Returns the id the receiver will see the message under
+    pub fn mcu1_send(&mut self, payload: &[u8]) -> Result<u16, ProtocolError> {
+        self.mcu1_send_with_priority(payload, Priority::Normal)
+    }
+
+    pub fn mcu1_send_with_priority(
+        &mut self,
+        payload: &[u8],
+        priority: Priority,
+    ) -> Result<u16, ProtocolError> {
+        let mut message = FixedMessage::empty(self.next_message);
+        message.priority = priority;
+        message.set_payload(payload)?;
+        message.reseal(&self.checksum, self.version);
+
+        let message_id = self.next_message;
+        self.buffer.send_message(message)?;
+        self.next_message = self.next_message.wrapping_add(1);
+        self.stats.messages_sent += 1;
+        self.stats.bytes_sent += payload.len() as u64;
+
+        log!("MCU1 message sent- ID {}", message_id);
+        Ok(message_id)
+    }
+
+    // Retract a message MCU2 hasn't read yet
+    pub fn mcu1_cancel(&mut self, id: u16) -> Option<FixedMessage<P>> {
+        self.buffer.remove(id)
+    }
+
+    // Next message and whether its checksum holds up, see `CommunicationProtocol::mcu2_receive`
+    pub fn mcu2_receive(&mut self) -> Option<(FixedMessage<P>, bool)> {
+        let message = self.buffer.receive_message()?;
+
+        let valid_checksum = message.verify(&self.checksum, self.version);
+        if valid_checksum {
+            log!("MCU2 message received with valid ID {}", message.id);
+            self.stats.messages_received += 1;
+            self.stats.bytes_received += message.payload().len() as u64;
+        } else {
+            log!("MCU2 corrupted ID found {}", message.id);
+            self.stats.checksum_failures += 1;
+        }
+        Some((message, valid_checksum))
+    }
+
+    // Serialize a message for the wire into `out`, returns the bytes written
+    pub fn encode_into(
+        &self,
+        message: &FixedMessage<P>,
+        out: &mut [u8],
+    ) -> Result<usize, ProtocolError> {
+        message.encode_into(&self.checksum, self.version, out)
+    }
+
+    // Parse and verify a message that came in over the wire
+    pub fn decode(&self, bytes: &[u8]) -> Result<FixedMessage<P>, ProtocolError> {
+        FixedMessage::decode(bytes, &self.checksum, self.version)
+    }
+
+    pub fn buffer(&self) -> &CircularBuffer<FixedMessage<P>, N> {
+        &self.buffer
+    }
+
+    // Protocol counters, overflow drops and rejected sends come from the queue
+    pub fn stats(&self) -> Stats {
+        let buffer = self.buffer.stats();
+        Stats {
+            dropped_overflow: buffer.dropped_overflow,
+            rejected_full: buffer.rejected_full,
+            high_watermark: buffer.high_watermark,
+            ..self.stats
+        }
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::new();
+        self.buffer.reset_stats();
+    }
+
+    // (length, empty, full)
+    pub fn get_buffer_status(&self) -> (usize, bool, bool) {
+        (
+            self.buffer.length(),
+            self.buffer.is_empty(),
+            self.buffer.is_full(),
+        )
+    }
+}
@@ -0,0 +1,369 @@
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::codec::Codec;
+use crate::error::ProtocolError;
+use crate::message::{Message, Priority, flags};
+
+// Fragmentation for payloads bigger than the link MTU.
+//
+// Each fragment is a regular message with `flags::FRAGMENT` set (plus `flags::LAST_FRAGMENT`
+// on the final one) and a fragment number in front of its payload:
+//
+//   | index: u16 | data |
+//
+// Fragments take consecutive message ids, so the id of the whole payload (the id of fragment 0)
+// is `id - index`. Needs `FormatVersion::V2`, V1 has nowhere to put the flags.
+pub const INDEX_LEN: usize = 2;
+
+// Default time a partially received payload is kept around waiting for the rest of it
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Default number of payloads that can be half-received at the same time
+pub const DEFAULT_MAX_IN_PROGRESS: usize = 4;
+
+// Number of fragments `payload_len` bytes turn into at the given MTU (payload bytes per
+// message, fragment number included)
+pub fn fragment_count(payload_len: usize, mtu: usize) -> usize {
+    let per_fragment = mtu.saturating_sub(INDEX_LEN).max(1);
+    payload_len.div_ceil(per_fragment).max(1)
+}
+
+// Id of the payload a fragment belongs to (the id of fragment 0)
+pub fn payload_id(fragment: &Message) -> Option<u16> {
+    if !fragment.is_fragment() || fragment.payload.len() < INDEX_LEN {
+        return None;
+    }
+    let index = u16::from_le_bytes([fragment.payload[0], fragment.payload[1]]);
+    Some(fragment.id.wrapping_sub(index))
+}
+
+// Split `payload` into sealed fragments with ids starting at `first_id`
+pub fn fragment(
+    codec: &Codec,
+    first_id: u16,
+    priority: Priority,
+    payload: &[u8],
+    mtu: usize,
+) -> Result<Vec<Message>, ProtocolError> {
+    if mtu <= INDEX_LEN {
+        return Err(ProtocolError::PayloadTooLarge {
+            len: payload.len(),
+            max: 0,
+        });
+    }
+
+    let per_fragment = mtu - INDEX_LEN;
+    let count = fragment_count(payload.len(), mtu);
+    if count > u16::MAX as usize + 1 {
+        return Err(ProtocolError::PayloadTooLarge {
+            len: payload.len(),
+            max: per_fragment * (u16::MAX as usize + 1),
+        });
+    }
+
+    let mut fragments = Vec::with_capacity(count);
+    for index in 0..count {
+        let start = index * per_fragment;
+        let end = (start + per_fragment).min(payload.len());
+
+        let mut data = Vec::with_capacity(INDEX_LEN + end - start);
+        data.extend_from_slice(&(index as u16).to_le_bytes());
+        data.extend_from_slice(&payload[start..end]);
+
+        let mut fragment_flags = flags::FRAGMENT;
+        if index == count - 1 {
+            fragment_flags |= flags::LAST_FRAGMENT;
+        }
+        let id = first_id.wrapping_add(index as u16);
+        fragments.push(codec.seal_with(id, fragment_flags, priority, data));
+    }
+    Ok(fragments)
+}
+
+// Payload we've seen some of the fragments for
+struct Partial {
+    // ids are only unique per sender and channel
+    source: u8,
+    id: u16,
+    priority: Priority,
+    destination: u8,
+    channel: u8,
+    chunks: Vec<(u16, Vec<u8>)>,
+    last_index: Option<u16>,
+    started: Duration,
+}
+
+impl Partial {
+    fn is_complete(&self) -> bool {
+        match self.last_index {
+            Some(last) => self.chunks.len() == last as usize + 1,
+            None => false,
+        }
+    }
+
+    fn into_payload(mut self) -> Vec<u8> {
+        self.chunks.sort_by_key(|(index, _)| *index);
+        self.chunks.into_iter().flat_map(|(_, data)| data).collect()
+    }
+}
+
+// Receive side: collects fragments until a payload is complete. Payloads that don't complete
+// within the timeout are dropped.
+pub struct Reassembler {
+    in_progress: Vec<Partial>,
+    timeout: Duration,
+    max_in_progress: usize,
+    expired: usize,
+}
+
+impl Reassembler {
+    pub fn new(timeout: Duration) -> Self {
+        Reassembler {
+            in_progress: Vec::new(),
+            timeout,
+            max_in_progress: DEFAULT_MAX_IN_PROGRESS,
+            expired: 0,
+        }
+    }
+
+    pub fn with_max_in_progress(mut self, max_in_progress: usize) -> Self {
+        self.max_in_progress = max_in_progress.max(1);
+        self
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    // Add a (checksum verified) fragment. Returns the rebuilt message once the last missing
+    // fragment of its payload arrives, sealed with `codec` like any other message.
+    pub fn push(
+        &mut self,
+        codec: &Codec,
+        fragment: &Message,
+        now: Duration,
+    ) -> Result<Option<Message>, ProtocolError> {
+        let id = payload_id(fragment).ok_or(ProtocolError::InvalidFragment)?;
+        let index = fragment.id.wrapping_sub(id);
+        let is_last = fragment.flags & flags::LAST_FRAGMENT != 0;
+
+        let position = match self.in_progress.iter().position(|p| {
+            p.id == id && p.source == fragment.source && p.channel == fragment.channel
+        }) {
+            Some(position) => position,
+            None => {
+                if self.in_progress.len() >= self.max_in_progress {
+                    // out of slots, give up on the oldest one
+                    self.in_progress.remove(0);
+                    self.expired += 1;
+                }
+                self.in_progress.push(Partial {
+                    source: fragment.source,
+                    id,
+                    priority: fragment.priority,
+                    destination: fragment.destination,
+                    channel: fragment.channel,
+                    chunks: Vec::new(),
+                    last_index: None,
+                    started: now,
+                });
+                self.in_progress.len() - 1
+            }
+        };
+
+        let partial = &mut self.in_progress[position];
+        if partial.chunks.iter().any(|(i, _)| *i == index) {
+            // duplicate, already have it
+            return Ok(None);
+        }
+        if let Some(last) = partial.last_index
+            && (index > last || is_last)
+        {
+            return Err(ProtocolError::InvalidFragment);
+        }
+        if is_last {
+            if partial.chunks.iter().any(|(i, _)| *i > index) {
+                return Err(ProtocolError::InvalidFragment);
+            }
+            partial.last_index = Some(index);
+        }
+        partial
+            .chunks
+            .push((index, fragment.payload[INDEX_LEN..].to_vec()));
+
+        if !partial.is_complete() {
+            return Ok(None);
+        }
+
+        let partial = self.in_progress.remove(position);
+        let (id, priority) = (partial.id, partial.priority);
+        let (source, destination, channel) = (partial.source, partial.destination, partial.channel);
+        let mut message = codec.seal_with(id, 0, priority, partial.into_payload());
+        if codec.version().has_addresses() {
+            message.source = source;
+            message.destination = destination;
+            message.channel = channel;
+            codec.reseal(&mut message);
+        }
+        Ok(Some(message))
+    }
+
+    // Drop payloads that have been waiting longer than the timeout, returns how many
+    pub fn expire(&mut self, now: Duration) -> usize {
+        let timeout = self.timeout;
+        let before = self.in_progress.len();
+        self.in_progress
+            .retain(|partial| now.saturating_sub(partial.started) < timeout);
+        let dropped = before - self.in_progress.len();
+        self.expired += dropped;
+        dropped
+    }
+
+    // Drop every half received payload, e.g. when the sender started over
+    pub fn clear(&mut self) {
+        self.in_progress.clear();
+    }
+
+    // Payloads currently half received
+    pub fn in_progress(&self) -> usize {
+        self.in_progress.len()
+    }
+
+    // Payloads given up on (timed out or pushed out by newer ones)
+    pub fn expired(&self) -> usize {
+        self.expired
+    }
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(DEFAULT_REASSEMBLY_TIMEOUT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const MTU: usize = 8;
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn split_into_consecutive_ids() {
+        let codec = Codec::default();
+        let fragments = fragment(&codec, 10, Priority::High, &payload(20), MTU).unwrap();
+        assert_eq!(fragments.len(), fragment_count(20, MTU));
+        assert_eq!(fragments.len(), 4);
+        for (index, message) in fragments.iter().enumerate() {
+            assert_eq!(message.id, 10 + index as u16);
+            assert!(message.payload.len() <= MTU);
+            assert_eq!(payload_id(message), Some(10));
+            assert_eq!(
+                message.flags & flags::LAST_FRAGMENT != 0,
+                index == fragments.len() - 1
+            );
+            assert!(codec.verify(message));
+        }
+    }
+
+    #[test]
+    fn reassembles_in_any_order() {
+        let codec = Codec::default();
+        let mut fragments = fragment(&codec, 1, Priority::Normal, &payload(20), MTU).unwrap();
+        fragments.swap(0, 3);
+        fragments.swap(1, 2);
+
+        let mut reassembler = Reassembler::default();
+        let (last, rest) = fragments.split_last().unwrap();
+        for message in rest {
+            assert_eq!(reassembler.push(&codec, message, Duration::ZERO), Ok(None));
+        }
+        // a copy of one we already have changes nothing
+        assert_eq!(reassembler.push(&codec, &rest[0], Duration::ZERO), Ok(None));
+
+        let message = reassembler
+            .push(&codec, last, Duration::ZERO)
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.id, 1);
+        assert_eq!(&message.payload[..], &payload(20)[..]);
+        assert!(!message.is_fragment());
+        assert!(codec.verify(&message));
+        assert_eq!(reassembler.in_progress(), 0);
+    }
+
+    #[test]
+    fn fragments_that_dont_fit_together_are_refused() {
+        let codec = Codec::default();
+        let fragments = fragment(&codec, 1, Priority::Normal, &payload(20), MTU).unwrap();
+        let mut reassembler = Reassembler::default();
+
+        let plain = codec.seal_with(7, 0, Priority::Normal, vec![1, 2, 3]);
+        assert_eq!(
+            reassembler.push(&codec, &plain, Duration::ZERO),
+            Err(ProtocolError::InvalidFragment)
+        );
+
+        // a fragment past the one marked last
+        let last = fragments.last().unwrap();
+        assert_eq!(reassembler.push(&codec, last, Duration::ZERO), Ok(None));
+        let past = codec.seal_with(
+            last.id + 1,
+            flags::FRAGMENT,
+            Priority::Normal,
+            [&4u16.to_le_bytes()[..], &[0xEE]].concat(),
+        );
+        assert_eq!(
+            reassembler.push(&codec, &past, Duration::ZERO),
+            Err(ProtocolError::InvalidFragment)
+        );
+    }
+
+    #[test]
+    fn incomplete_payloads_expire() {
+        let codec = Codec::default();
+        let fragments = fragment(&codec, 1, Priority::Normal, &payload(20), MTU).unwrap();
+        let mut reassembler = Reassembler::new(Duration::from_millis(100));
+        reassembler
+            .push(&codec, &fragments[0], Duration::ZERO)
+            .unwrap();
+
+        assert_eq!(reassembler.expire(Duration::from_millis(99)), 0);
+        assert_eq!(reassembler.expire(Duration::from_millis(100)), 1);
+        assert_eq!(reassembler.in_progress(), 0);
+        assert_eq!(reassembler.expired(), 1);
+    }
+
+    #[test]
+    fn oldest_partial_makes_way_when_out_of_slots() {
+        let codec = Codec::default();
+        let mut reassembler = Reassembler::default().with_max_in_progress(2);
+        for first_id in [1, 100, 200] {
+            let fragments =
+                fragment(&codec, first_id, Priority::Normal, &payload(20), MTU).unwrap();
+            reassembler
+                .push(&codec, &fragments[0], Duration::ZERO)
+                .unwrap();
+        }
+        assert_eq!(reassembler.in_progress(), 2);
+        assert_eq!(reassembler.expired(), 1);
+    }
+
+    #[test]
+    fn mtu_without_room_for_data_is_refused() {
+        let codec = Codec::default();
+        assert!(matches!(
+            fragment(&codec, 1, Priority::Normal, &payload(4), INDEX_LEN),
+            Err(ProtocolError::PayloadTooLarge { .. })
+        ));
+    }
+}
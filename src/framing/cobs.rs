@@ -0,0 +1,230 @@
+use alloc::vec::Vec;
+
+use super::{FrameDecoder, Framing};
+use crate::codec::Codec;
+use crate::error::ProtocolError;
+use crate::message::Message;
+
+// Consistent Overhead Byte Stuffing. The encoded form never contains 0x00, so a single zero
+// byte can delimit frames and a receiver that joins mid-stream resyncs at the next one.
+// Overhead is at most one byte per 254 plus the delimiter.
+pub const DELIMITER: u8 = 0x00;
+
+// Worst case size of `encode(data)` (without the delimiter)
+pub fn max_encoded_len(len: usize) -> usize {
+    len + len / 254 + 1
+}
+
+// COBS encode `data`, no trailing delimiter
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(max_encoded_len(data.len()));
+    let mut code_index = 0;
+    let mut code: u8 = 1;
+    out.push(0);
+
+    for &byte in data {
+        if byte == 0 {
+            out[code_index] = code;
+            code_index = out.len();
+            out.push(0);
+            code = 1;
+        } else {
+            out.push(byte);
+            code += 1;
+            if code == 0xFF {
+                out[code_index] = code;
+                code_index = out.len();
+                out.push(0);
+                code = 1;
+            }
+        }
+    }
+
+    out[code_index] = code;
+    out
+}
+
+// COBS encode `data` and terminate it with the delimiter, ready to go on the wire
+pub fn encode_frame(data: &[u8]) -> Vec<u8> {
+    let mut frame = encode(data);
+    frame.push(DELIMITER);
+    frame
+}
+
+// Serialize `message` with `codec` and COBS-frame it in one call, within the default
+// `max_frame_len`
+pub fn encode_message(codec: &Codec, message: &Message) -> Result<Vec<u8>, ProtocolError> {
+    super::Cobs::default().encode_message(codec, message)
+}
+
+// Undo `encode`. Accepts the data with or without the trailing delimiter.
+pub fn decode(encoded: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+    let encoded = encoded.strip_suffix(&[DELIMITER]).unwrap_or(encoded);
+    let mut out = Vec::with_capacity(encoded.len());
+    let mut index = 0;
+
+    while index < encoded.len() {
+        let code = encoded[index] as usize;
+        if code == 0 || index + code > encoded.len() {
+            return Err(ProtocolError::MalformedFrame);
+        }
+
+        let block = &encoded[index + 1..index + code];
+        if block.contains(&0) {
+            return Err(ProtocolError::MalformedFrame);
+        }
+        out.extend_from_slice(block);
+        index += code;
+
+        // a full 254 byte block doesn't imply a zero, nor does the last block
+        if code < 0xFF && index < encoded.len() {
+            out.push(0);
+        }
+    }
+
+    Ok(out)
+}
+
+// Splits a byte stream on zero delimiters and COBS-decodes each frame. A bad frame costs
+// exactly that frame, decoding picks back up at the next delimiter.
+pub struct CobsDecoder {
+    buffer: Vec<u8>,
+    max_frame_len: usize,
+    // ran past the maximum frame size, ignore everything up to the next delimiter
+    discarding: bool,
+    resyncs: usize,
+    discarded_bytes: usize,
+}
+
+impl CobsDecoder {
+    pub fn new() -> Self {
+        Self::with_max_frame_len(super::sync::DEFAULT_MAX_FRAME_LEN)
+    }
+
+    pub fn with_max_frame_len(max_frame_len: usize) -> Self {
+        CobsDecoder {
+            buffer: Vec::new(),
+            max_frame_len,
+            discarding: false,
+            resyncs: 0,
+            discarded_bytes: 0,
+        }
+    }
+}
+
+impl FrameDecoder for CobsDecoder {
+    fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn next_frame(&mut self) -> Option<Result<Vec<u8>, ProtocolError>> {
+        loop {
+            let Some(end) = self.buffer.iter().position(|&b| b == DELIMITER) else {
+                if self.discarding {
+                    self.discarded_bytes += self.buffer.len();
+                    self.buffer.clear();
+                } else if self.buffer.len() > max_encoded_len(self.max_frame_len) {
+                    // no delimiter in sight and already too long, this can't turn into a frame
+                    self.discarded_bytes += self.buffer.len();
+                    self.buffer.clear();
+                    self.discarding = true;
+                    self.resyncs += 1;
+                    return Some(Err(ProtocolError::MalformedFrame));
+                }
+                return None;
+            };
+
+            let frame: Vec<u8> = self.buffer.drain(..=end).collect();
+            if self.discarding {
+                self.discarding = false;
+                self.discarded_bytes += frame.len();
+                continue;
+            }
+            // back to back delimiters are just idle line, skip them
+            if frame.len() == 1 {
+                continue;
+            }
+
+            let decoded = decode(&frame);
+            if decoded.is_err() {
+                self.resyncs += 1;
+                self.discarded_bytes += frame.len();
+            }
+            return Some(decoded);
+        }
+    }
+
+    fn resyncs(&self) -> usize {
+        self.resyncs
+    }
+
+    fn discarded_bytes(&self) -> usize {
+        self.discarded_bytes
+    }
+}
+
+impl Default for CobsDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(decoder: &mut CobsDecoder) -> Vec<Result<Vec<u8>, ProtocolError>> {
+        core::iter::from_fn(|| decoder.next_frame()).collect()
+    }
+
+    #[test]
+    fn round_trip_with_zeros() {
+        let data = [0x00, 0x11, 0x00, 0x00, 0x22];
+        let frame = encode_frame(&data);
+        assert_eq!(frame.iter().filter(|&&b| b == DELIMITER).count(), 1);
+        assert_eq!(decode(&frame), Ok(data.to_vec()));
+    }
+
+    #[test]
+    fn bad_frame_costs_only_itself() {
+        let mut decoder = CobsDecoder::new();
+        // code byte claims more bytes than the frame has
+        decoder.feed(&[0x05, 0x01, DELIMITER]);
+        decoder.feed(&encode_frame(b"good"));
+
+        let out = frames(&mut decoder);
+        assert!(out[0].is_err());
+        assert_eq!(out.last(), Some(&Ok(b"good".to_vec())));
+        assert_eq!(decoder.resyncs(), 1);
+        assert_eq!(decoder.discarded_bytes(), 3);
+    }
+
+    #[test]
+    fn joining_mid_frame_picks_up_at_the_next_delimiter() {
+        let first = encode_frame(b"first frame");
+        let mut decoder = CobsDecoder::new();
+        decoder.feed(&first[4..]);
+        decoder.feed(&encode_frame(b"second"));
+        assert_eq!(frames(&mut decoder).last(), Some(&Ok(b"second".to_vec())));
+    }
+
+    #[test]
+    fn overlong_frame_is_dropped_up_to_its_delimiter() {
+        let mut decoder = CobsDecoder::with_max_frame_len(8);
+        decoder.feed(&[0x01; 32]);
+        assert_eq!(
+            decoder.next_frame(),
+            Some(Err(ProtocolError::MalformedFrame))
+        );
+        decoder.feed(&[0x01; 8]);
+        assert!(decoder.next_frame().is_none());
+        decoder.feed(&[DELIMITER]);
+        decoder.feed(&encode_frame(b"next"));
+        assert_eq!(decoder.next_frame(), Some(Ok(b"next".to_vec())));
+        assert_eq!(decoder.resyncs(), 1);
+    }
+}
@@ -0,0 +1,83 @@
+use super::{FrameDecoder, Framing};
+use crate::codec::Codec;
+use crate::error::ProtocolError;
+use crate::message::Message;
+
+// Push-style parser that turns raw chunks from a UART ISR or DMA buffer into validated
+// messages. Partial frames are kept between calls, so chunks can be split anywhere.
+//
+//     let mut decoder = Decoder::new(Cobs::default(), Codec::default());
+//     for message in decoder.push(&dma_chunk) {
+//         handle(message);
+//     }
+//
+pub struct Decoder<F: Framing> {
+    frames: F::Decoder,
+    codec: Codec,
+    rejected: usize,
+    last_error: Option<ProtocolError>,
+}
+
+impl<F: Framing> Decoder<F> {
+    pub fn new(framing: F, codec: Codec) -> Self {
+        Decoder {
+            frames: framing.decoder(),
+            codec,
+            rejected: 0,
+            last_error: None,
+        }
+    }
+
+    pub fn codec(&self) -> &Codec {
+        &self.codec
+    }
+
+    // Feed a chunk of received bytes and iterate over every message it completes. Frames that
+    // fail framing, length or checksum checks are skipped and counted in `rejected`.
+    //
+    // Bytes are buffered as soon as `push` is called, messages still unread when the iterator
+    // is dropped come out of the next `push` (or `poll`).
+    pub fn push<'a>(&'a mut self, bytes: &[u8]) -> impl Iterator<Item = Message> + 'a {
+        self.frames.feed(bytes);
+        self.poll()
+    }
+
+    // Iterate over messages already sitting in the buffer
+    pub fn poll(&mut self) -> impl Iterator<Item = Message> + '_ {
+        core::iter::from_fn(move || {
+            loop {
+                match self.frames.next_message(&self.codec)? {
+                    Ok(message) => return Some(message),
+                    Err(error) => {
+                        self.rejected += 1;
+                        self.last_error = Some(error);
+                    }
+                }
+            }
+        })
+    }
+
+    // Frames thrown away so far
+    pub fn rejected(&self) -> usize {
+        self.rejected
+    }
+
+    // Why the most recent frame was thrown away
+    pub fn last_error(&self) -> Option<&ProtocolError> {
+        self.last_error.as_ref()
+    }
+
+    // Resync events of the underlying frame decoder
+    pub fn resyncs(&self) -> usize {
+        self.frames.resyncs()
+    }
+
+    pub fn discarded_bytes(&self) -> usize {
+        self.frames.discarded_bytes()
+    }
+
+    // Bytes buffered for a frame that hasn't completed yet
+    pub fn pending(&self) -> usize {
+        self.frames.pending()
+    }
+}
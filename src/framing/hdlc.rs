@@ -0,0 +1,175 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use super::FrameDecoder;
+use crate::error::ProtocolError;
+
+// HDLC-like framing: frames are delimited by 0x7E flags, any flag/escape byte inside the frame
+// is sent as 0x7D followed by the byte XOR 0x20, and a 16-bit frame check sequence
+// (CRC-16/X.25, same as PPP) goes out little-endian before the closing flag.
+//
+//   | 0x7E | escaped(body + fcs) | 0x7E |
+//
+pub const FLAG: u8 = 0x7E;
+pub const ESCAPE: u8 = 0x7D;
+pub const ESCAPE_XOR: u8 = 0x20;
+pub const FCS_LEN: usize = 2;
+
+// FCS-16 from RFC 1662 (reflected 0x1021, init 0xFFFF, final xor 0xFFFF), check value for
+// b"123456789" is 0x906E
+pub fn fcs16(data: &[u8]) -> u16 {
+    let mut fcs: u16 = 0xFFFF;
+    for &byte in data {
+        fcs ^= byte as u16;
+        for _ in 0..8 {
+            fcs = if fcs & 1 != 0 {
+                (fcs >> 1) ^ 0x8408
+            } else {
+                fcs >> 1
+            };
+        }
+    }
+    !fcs
+}
+
+fn push_escaped(frame: &mut Vec<u8>, byte: u8) {
+    if byte == FLAG || byte == ESCAPE {
+        frame.push(ESCAPE);
+        frame.push(byte ^ ESCAPE_XOR);
+    } else {
+        frame.push(byte);
+    }
+}
+
+// Escape `body`, append its FCS and wrap it in flags
+pub fn encode_frame(body: &[u8]) -> Vec<u8> {
+    let fcs = fcs16(body).to_le_bytes();
+    let mut frame = Vec::with_capacity(body.len() + FCS_LEN + 2);
+    frame.push(FLAG);
+    for &byte in body.iter().chain(fcs.iter()) {
+        push_escaped(&mut frame, byte);
+    }
+    frame.push(FLAG);
+    frame
+}
+
+// Byte-at-a-time decoder, escape state survives across `feed` calls
+pub struct HdlcDecoder {
+    current: Vec<u8>,
+    frames: VecDeque<Result<Vec<u8>, ProtocolError>>,
+    escaped: bool,
+    discarding: bool,
+    max_frame_len: usize,
+    resyncs: usize,
+    discarded_bytes: usize,
+}
+
+impl HdlcDecoder {
+    pub fn new() -> Self {
+        Self::with_max_frame_len(super::sync::DEFAULT_MAX_FRAME_LEN)
+    }
+
+    pub fn with_max_frame_len(max_frame_len: usize) -> Self {
+        HdlcDecoder {
+            current: Vec::new(),
+            frames: VecDeque::new(),
+            escaped: false,
+            discarding: false,
+            max_frame_len,
+            resyncs: 0,
+            discarded_bytes: 0,
+        }
+    }
+
+    fn feed_byte(&mut self, byte: u8) {
+        if byte == FLAG {
+            if !self.discarding && !self.current.is_empty() {
+                let frame = core::mem::take(&mut self.current);
+                let len = frame.len();
+                let checked = check_fcs(frame);
+                if checked.is_err() {
+                    self.resyncs += 1;
+                    self.discarded_bytes += len;
+                }
+                self.frames.push_back(checked);
+            }
+            self.current.clear();
+            self.escaped = false;
+            self.discarding = false;
+            return;
+        }
+
+        if self.discarding {
+            self.discarded_bytes += 1;
+            return;
+        }
+
+        let decoded = if self.escaped {
+            self.escaped = false;
+            byte ^ ESCAPE_XOR
+        } else if byte == ESCAPE {
+            self.escaped = true;
+            return;
+        } else {
+            byte
+        };
+
+        if self.current.len() >= self.max_frame_len + FCS_LEN {
+            // too long to be a frame, drop it and realign on the next flag
+            self.discarded_bytes += self.current.len() + 1;
+            self.resyncs += 1;
+            self.current.clear();
+            self.discarding = true;
+            self.frames.push_back(Err(ProtocolError::MalformedFrame));
+            return;
+        }
+        self.current.push(decoded);
+    }
+}
+
+fn check_fcs(mut frame: Vec<u8>) -> Result<Vec<u8>, ProtocolError> {
+    if frame.len() < FCS_LEN {
+        return Err(ProtocolError::MalformedFrame);
+    }
+    let split = frame.len() - FCS_LEN;
+    let received = u16::from_le_bytes([frame[split], frame[split + 1]]);
+    frame.truncate(split);
+    let actual = fcs16(&frame);
+    if received != actual {
+        return Err(ProtocolError::ChecksumMismatch {
+            expected: received as u32,
+            actual: actual as u32,
+        });
+    }
+    Ok(frame)
+}
+
+impl FrameDecoder for HdlcDecoder {
+    fn feed(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.feed_byte(byte);
+        }
+    }
+
+    fn next_frame(&mut self) -> Option<Result<Vec<u8>, ProtocolError>> {
+        self.frames.pop_front()
+    }
+
+    fn pending(&self) -> usize {
+        self.current.len()
+    }
+
+    fn resyncs(&self) -> usize {
+        self.resyncs
+    }
+
+    fn discarded_bytes(&self) -> usize {
+        self.discarded_bytes
+    }
+}
+
+impl Default for HdlcDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
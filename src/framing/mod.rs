@@ -0,0 +1,217 @@
+// Framing layers that carry serialized messages over a raw byte stream (UART and friends),
+// where there is nothing telling the receiver where one message ends and the next begins.
+//
+// All of them implement `Framing` so links can be generic over which one is on the wire:
+//
+// - `SyncWord` - 0xAA55 sync word, length prefix and CRC-16
+// - `Cobs`     - consistent overhead byte stuffing, zero delimited
+// - `Slip`     - RFC 1055, what most host tooling speaks
+// - `Hdlc`     - 0x7E flags with escape stuffing and a 16-bit FCS
+
+use alloc::vec::Vec;
+
+use crate::codec::Codec;
+use crate::error::ProtocolError;
+use crate::message::{Message, MessageRef};
+
+pub mod cobs;
+pub mod decoder;
+pub mod hdlc;
+pub mod slip;
+pub mod sync;
+
+pub use cobs::CobsDecoder;
+pub use decoder::Decoder;
+pub use hdlc::HdlcDecoder;
+pub use slip::SlipDecoder;
+pub use sync::{SyncDecoder, encode_frame};
+
+// Receive half of a framing layer. Bytes go in as they arrive, frame bodies come out once
+// they're complete.
+pub trait FrameDecoder {
+    // Append received bytes
+    fn feed(&mut self, bytes: &[u8]);
+
+    // Next complete frame body, `None` if more bytes are needed. Bad frames come out as
+    // errors and decoding carries on with whatever follows them.
+    fn next_frame(&mut self) -> Option<Result<Vec<u8>, ProtocolError>>;
+
+    // Bytes held on to for a frame that isn't complete yet
+    fn pending(&self) -> usize;
+
+    // Times a bad frame (length, CRC, escaping) was thrown away and the decoder had to
+    // realign on the next frame boundary
+    fn resyncs(&self) -> usize;
+
+    // Bytes skipped that never made it into a valid frame, line noise included
+    fn discarded_bytes(&self) -> usize;
+
+    // Next frame decoded all the way to a `Message`
+    fn next_message(&mut self, codec: &Codec) -> Option<Result<Message, ProtocolError>> {
+        self.next_frame()
+            .map(|frame| frame.and_then(|body| codec.decode(&body)))
+    }
+}
+
+// A way of delimiting frames on a byte stream
+pub trait Framing {
+    type Decoder: FrameDecoder;
+
+    // Wrap a serialized message into a frame. `PayloadTooLarge` when the body is longer than
+    // `max_frame_len`, a decoder with the same settings would throw the frame away.
+    fn encode(&self, body: &[u8]) -> Result<Vec<u8>, ProtocolError>;
+
+    // Fresh decoder for the receive side
+    fn decoder(&self) -> Self::Decoder;
+
+    // Longest frame body (serialized message) either side takes
+    fn max_frame_len(&self) -> usize;
+
+    // Serialize and frame a message in one go
+    fn encode_message(&self, codec: &Codec, message: &Message) -> Result<Vec<u8>, ProtocolError> {
+        self.encode(&codec.encode(message)?)
+    }
+
+    // Same for a borrowed payload
+    fn encode_message_ref(
+        &self,
+        codec: &Codec,
+        message: &MessageRef<'_>,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        self.encode(&codec.encode_ref(message)?)
+    }
+}
+
+// Sync word + length + CRC framing, see `sync`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncWord {
+    pub max_frame_len: usize,
+}
+
+// COBS framing, see `cobs`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cobs {
+    pub max_frame_len: usize,
+}
+
+// SLIP framing, see `slip`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slip {
+    pub max_frame_len: usize,
+}
+
+// HDLC-style framing, see `hdlc`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hdlc {
+    pub max_frame_len: usize,
+}
+
+impl Default for SyncWord {
+    fn default() -> Self {
+        SyncWord {
+            max_frame_len: sync::DEFAULT_MAX_FRAME_LEN,
+        }
+    }
+}
+
+impl Default for Cobs {
+    fn default() -> Self {
+        Cobs {
+            max_frame_len: sync::DEFAULT_MAX_FRAME_LEN,
+        }
+    }
+}
+
+impl Default for Slip {
+    fn default() -> Self {
+        Slip {
+            max_frame_len: sync::DEFAULT_MAX_FRAME_LEN,
+        }
+    }
+}
+
+impl Default for Hdlc {
+    fn default() -> Self {
+        Hdlc {
+            max_frame_len: sync::DEFAULT_MAX_FRAME_LEN,
+        }
+    }
+}
+
+impl Framing for SyncWord {
+    type Decoder = SyncDecoder;
+
+    fn encode(&self, body: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+        check_frame_len(body, self.max_frame_len)?;
+        sync::encode_frame(body)
+    }
+
+    fn decoder(&self) -> SyncDecoder {
+        SyncDecoder::with_max_frame_len(self.max_frame_len)
+    }
+
+    fn max_frame_len(&self) -> usize {
+        self.max_frame_len.min(sync::MAX_FRAME_LEN)
+    }
+}
+
+impl Framing for Cobs {
+    type Decoder = CobsDecoder;
+
+    fn encode(&self, body: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+        check_frame_len(body, self.max_frame_len)?;
+        Ok(cobs::encode_frame(body))
+    }
+
+    fn decoder(&self) -> CobsDecoder {
+        CobsDecoder::with_max_frame_len(self.max_frame_len)
+    }
+
+    fn max_frame_len(&self) -> usize {
+        self.max_frame_len
+    }
+}
+
+impl Framing for Slip {
+    type Decoder = SlipDecoder;
+
+    fn encode(&self, body: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+        check_frame_len(body, self.max_frame_len)?;
+        Ok(slip::encode_frame(body))
+    }
+
+    fn decoder(&self) -> SlipDecoder {
+        SlipDecoder::with_max_frame_len(self.max_frame_len)
+    }
+
+    fn max_frame_len(&self) -> usize {
+        self.max_frame_len
+    }
+}
+
+impl Framing for Hdlc {
+    type Decoder = HdlcDecoder;
+
+    fn encode(&self, body: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+        check_frame_len(body, self.max_frame_len)?;
+        Ok(hdlc::encode_frame(body))
+    }
+
+    fn decoder(&self) -> HdlcDecoder {
+        HdlcDecoder::with_max_frame_len(self.max_frame_len)
+    }
+
+    fn max_frame_len(&self) -> usize {
+        self.max_frame_len
+    }
+}
+
+fn check_frame_len(body: &[u8], max: usize) -> Result<(), ProtocolError> {
+    if body.len() > max {
+        return Err(ProtocolError::PayloadTooLarge {
+            len: body.len(),
+            max,
+        });
+    }
+    Ok(())
+}
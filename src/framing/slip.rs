@@ -0,0 +1,152 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use super::{FrameDecoder, Framing};
+use crate::codec::Codec;
+use crate::error::ProtocolError;
+use crate::message::Message;
+
+// SLIP (RFC 1055) framing, for talking to host tooling that already speaks it
+pub const END: u8 = 0xC0;
+pub const ESC: u8 = 0xDB;
+pub const ESC_END: u8 = 0xDC;
+pub const ESC_ESC: u8 = 0xDD;
+
+// SLIP encode `data` with an END on both sides, the leading one flushes any line noise the
+// receiver picked up before the frame started
+pub fn encode_frame(data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(data.len() + 2);
+    frame.push(END);
+    for &byte in data {
+        match byte {
+            END => frame.extend_from_slice(&[ESC, ESC_END]),
+            ESC => frame.extend_from_slice(&[ESC, ESC_ESC]),
+            _ => frame.push(byte),
+        }
+    }
+    frame.push(END);
+    frame
+}
+
+// Serialize `message` with `codec` and SLIP-frame it in one call, within the default
+// `max_frame_len`
+pub fn encode_message(codec: &Codec, message: &Message) -> Result<Vec<u8>, ProtocolError> {
+    super::Slip::default().encode_message(codec, message)
+}
+
+// Undo the escaping of a single frame, END bytes at either edge are ignored
+pub fn decode(frame: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+    let mut decoder = SlipDecoder::new();
+    decoder.feed(frame);
+    decoder.feed(&[END]);
+    decoder.next_frame().unwrap_or(Ok(Vec::new()))
+}
+
+// Byte-at-a-time SLIP decoder, keeps the escape state between `feed` calls so frames can be
+// split anywhere
+pub struct SlipDecoder {
+    current: Vec<u8>,
+    frames: VecDeque<Result<Vec<u8>, ProtocolError>>,
+    escaped: bool,
+    // set once the current frame went bad, the rest of it is ignored up to the next END
+    discarding: bool,
+    max_frame_len: usize,
+    resyncs: usize,
+    discarded_bytes: usize,
+}
+
+impl SlipDecoder {
+    pub fn new() -> Self {
+        Self::with_max_frame_len(super::sync::DEFAULT_MAX_FRAME_LEN)
+    }
+
+    pub fn with_max_frame_len(max_frame_len: usize) -> Self {
+        SlipDecoder {
+            current: Vec::new(),
+            frames: VecDeque::new(),
+            escaped: false,
+            discarding: false,
+            max_frame_len,
+            resyncs: 0,
+            discarded_bytes: 0,
+        }
+    }
+
+    fn feed_byte(&mut self, byte: u8) {
+        if byte == END {
+            if !self.discarding && !self.current.is_empty() {
+                self.frames
+                    .push_back(Ok(core::mem::take(&mut self.current)));
+            }
+            self.current.clear();
+            self.escaped = false;
+            self.discarding = false;
+            return;
+        }
+
+        if self.discarding {
+            self.discarded_bytes += 1;
+            return;
+        }
+
+        let decoded = if self.escaped {
+            self.escaped = false;
+            match byte {
+                ESC_END => END,
+                ESC_ESC => ESC,
+                _ => return self.fail(),
+            }
+        } else if byte == ESC {
+            self.escaped = true;
+            return;
+        } else {
+            byte
+        };
+
+        if self.current.len() >= self.max_frame_len {
+            return self.fail();
+        }
+        self.current.push(decoded);
+    }
+
+    // frame went bad, throw it away and pick back up after the next END
+    fn fail(&mut self) {
+        self.discarded_bytes += self.current.len() + 1;
+        self.resyncs += 1;
+        self.current.clear();
+        self.escaped = false;
+        self.discarding = true;
+        self.frames.push_back(Err(ProtocolError::MalformedFrame));
+    }
+}
+
+impl FrameDecoder for SlipDecoder {
+    fn feed(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.feed_byte(byte);
+        }
+    }
+
+    // Next complete frame body, `None` until an END closes one
+    fn next_frame(&mut self) -> Option<Result<Vec<u8>, ProtocolError>> {
+        self.frames.pop_front()
+    }
+
+    fn pending(&self) -> usize {
+        self.current.len()
+    }
+
+    fn resyncs(&self) -> usize {
+        self.resyncs
+    }
+
+    fn discarded_bytes(&self) -> usize {
+        self.discarded_bytes
+    }
+}
+
+impl Default for SlipDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,267 @@
+use alloc::vec::Vec;
+
+use super::{FrameDecoder, Framing};
+use crate::checksum::crc16_ccitt;
+use crate::codec::Codec;
+use crate::error::ProtocolError;
+use crate::message::Message;
+
+// Frame layout:
+//
+//   | sync: 0xAA 0x55 | length: u16 LE | hcs: u8 | body: [u8; length] | crc: u16 LE |
+//
+// The body is a serialized `Message`. `hcs` is a CRC-8 over the length field alone, so a
+// corrupted length is spotted as soon as the header is in instead of the decoder sitting
+// there waiting for bytes that will never come. The CRC-16-CCITT covers length + body.
+pub const SYNC_WORD: [u8; 2] = [0xAA, 0x55];
+pub const LENGTH_LEN: usize = 2;
+pub const HCS_LEN: usize = 1;
+pub const HEADER_LEN: usize = SYNC_WORD.len() + LENGTH_LEN + HCS_LEN;
+pub const CRC_LEN: usize = 2;
+pub const FRAME_OVERHEAD: usize = HEADER_LEN + CRC_LEN;
+
+// Default upper bound on a frame body, anything claiming to be longer is treated as garbage
+pub const DEFAULT_MAX_FRAME_LEN: usize = 1024;
+
+// Longest body the length field can say
+pub const MAX_FRAME_LEN: usize = u16::MAX as usize;
+
+// Wrap an already serialized message into a frame. `PayloadTooLarge` above `MAX_FRAME_LEN`.
+pub fn encode_frame(body: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+    if body.len() > MAX_FRAME_LEN {
+        return Err(ProtocolError::PayloadTooLarge {
+            len: body.len(),
+            max: MAX_FRAME_LEN,
+        });
+    }
+    let length = (body.len() as u16).to_le_bytes();
+    let crc = frame_crc(&length, body);
+
+    let mut frame = Vec::with_capacity(body.len() + FRAME_OVERHEAD);
+    frame.extend_from_slice(&SYNC_WORD);
+    frame.extend_from_slice(&length);
+    frame.push(header_check(&length));
+    frame.extend_from_slice(body);
+    frame.extend_from_slice(&crc.to_le_bytes());
+    Ok(frame)
+}
+
+// Serialize `message` with `codec` and frame it in one go, within the default
+// `max_frame_len`
+pub fn encode_message(codec: &Codec, message: &Message) -> Result<Vec<u8>, ProtocolError> {
+    super::SyncWord::default().encode_message(codec, message)
+}
+
+// CRC-8 (poly 0x07) of the length field
+fn header_check(length: &[u8]) -> u8 {
+    let mut crc: u8 = 0;
+    for &byte in length {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn frame_crc(length: &[u8], body: &[u8]) -> u16 {
+    crate::checksum::crc16_ccitt_update(crc16_ccitt(length), body)
+}
+
+// Finds frame boundaries in an arbitrary byte stream. Feed it whatever the UART hands over
+// and pull complete frame bodies out with `next_frame`.
+//
+// A frame only gets consumed once its CRC checks out. If the header check, length or CRC is
+// bad the decoder skips just the sync word it locked onto and scans forward for the next one,
+// so a corrupted frame can't swallow the good frames that follow it.
+pub struct SyncDecoder {
+    buffer: Vec<u8>,
+    max_frame_len: usize,
+    resyncs: usize,
+    discarded_bytes: usize,
+}
+
+impl SyncDecoder {
+    pub fn new() -> Self {
+        Self::with_max_frame_len(DEFAULT_MAX_FRAME_LEN)
+    }
+
+    pub fn with_max_frame_len(max_frame_len: usize) -> Self {
+        SyncDecoder {
+            buffer: Vec::new(),
+            max_frame_len,
+            resyncs: 0,
+            discarded_bytes: 0,
+        }
+    }
+
+    fn discard(&mut self, count: usize) {
+        self.buffer.drain(..count);
+        self.discarded_bytes += count;
+    }
+
+    // drop the sync word we locked onto and go hunting for the next one
+    fn resync(&mut self) {
+        self.discard(1);
+        self.resyncs += 1;
+    }
+}
+
+impl FrameDecoder for SyncDecoder {
+    fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn next_frame(&mut self) -> Option<Result<Vec<u8>, ProtocolError>> {
+        // everything before the sync word is line noise
+        match find_sync(&self.buffer) {
+            Some(start) => self.discard(start),
+            None => {
+                // keep a trailing 0xAA around, it could be the first half of the sync word
+                let keep = usize::from(self.buffer.last() == Some(&SYNC_WORD[0]));
+                self.discard(self.buffer.len() - keep);
+                return None;
+            }
+        }
+
+        let header = HEADER_LEN;
+        if self.buffer.len() < header {
+            return None;
+        }
+
+        let length = u16::from_le_bytes([self.buffer[2], self.buffer[3]]) as usize;
+        if header_check(&self.buffer[2..4]) != self.buffer[4] || length > self.max_frame_len {
+            self.resync();
+            return Some(Err(ProtocolError::MalformedFrame));
+        }
+
+        let total = header + length + CRC_LEN;
+        if self.buffer.len() < total {
+            return None;
+        }
+
+        let body = &self.buffer[header..header + length];
+        let received = u16::from_le_bytes([self.buffer[total - 2], self.buffer[total - 1]]);
+        let actual = frame_crc(&self.buffer[2..4], body);
+        if received != actual {
+            self.resync();
+            return Some(Err(ProtocolError::ChecksumMismatch {
+                expected: received as u32,
+                actual: actual as u32,
+            }));
+        }
+
+        let body = body.to_vec();
+        self.buffer.drain(..total);
+        Some(Ok(body))
+    }
+
+    fn resyncs(&self) -> usize {
+        self.resyncs
+    }
+
+    fn discarded_bytes(&self) -> usize {
+        self.discarded_bytes
+    }
+}
+
+impl Default for SyncDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn find_sync(bytes: &[u8]) -> Option<usize> {
+    bytes.windows(SYNC_WORD.len()).position(|w| w == SYNC_WORD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(decoder: &mut SyncDecoder) -> Vec<Result<Vec<u8>, ProtocolError>> {
+        core::iter::from_fn(|| decoder.next_frame()).collect()
+    }
+
+    #[test]
+    fn round_trip_byte_by_byte() {
+        let frame = encode_frame(b"hello").unwrap();
+        let mut decoder = SyncDecoder::new();
+        for byte in &frame[..frame.len() - 1] {
+            decoder.feed(&[*byte]);
+            assert!(decoder.next_frame().is_none());
+        }
+        decoder.feed(&frame[frame.len() - 1..]);
+        assert_eq!(decoder.next_frame(), Some(Ok(b"hello".to_vec())));
+        assert_eq!(decoder.pending(), 0);
+    }
+
+    #[test]
+    fn noise_before_the_sync_word_is_skipped() {
+        let mut decoder = SyncDecoder::new();
+        decoder.feed(&[0x01, 0x02, 0x03]);
+        decoder.feed(&encode_frame(b"data").unwrap());
+        assert_eq!(frames(&mut decoder), [Ok(b"data".to_vec())]);
+        assert_eq!(decoder.discarded_bytes(), 3);
+        assert_eq!(decoder.resyncs(), 0);
+    }
+
+    #[test]
+    fn bad_crc_resyncs_onto_the_next_frame() {
+        let mut bad = encode_frame(b"first").unwrap();
+        bad[HEADER_LEN] ^= 0x01;
+        let mut decoder = SyncDecoder::new();
+        decoder.feed(&bad);
+        decoder.feed(&encode_frame(b"second").unwrap());
+
+        let out = frames(&mut decoder);
+        assert!(matches!(
+            out[0],
+            Err(ProtocolError::ChecksumMismatch { .. })
+        ));
+        assert_eq!(out.last(), Some(&Ok(b"second".to_vec())));
+        assert_eq!(decoder.resyncs(), 1);
+    }
+
+    #[test]
+    fn corrupted_length_doesnt_swallow_what_follows() {
+        // a length that passes for huge would have the decoder wait forever without the hcs
+        let mut bad = encode_frame(b"first").unwrap();
+        bad[3] = 0x7F;
+        let mut decoder = SyncDecoder::new();
+        decoder.feed(&bad);
+        decoder.feed(&encode_frame(b"second").unwrap());
+
+        let out = frames(&mut decoder);
+        assert_eq!(out[0], Err(ProtocolError::MalformedFrame));
+        assert_eq!(out.last(), Some(&Ok(b"second".to_vec())));
+    }
+
+    #[test]
+    fn frame_over_max_len_is_refused_both_ways() {
+        let framing = crate::framing::SyncWord::default();
+        let body = [0u8; DEFAULT_MAX_FRAME_LEN + 1];
+        assert_eq!(
+            framing.encode(&body),
+            Err(ProtocolError::PayloadTooLarge {
+                len: DEFAULT_MAX_FRAME_LEN + 1,
+                max: DEFAULT_MAX_FRAME_LEN
+            })
+        );
+
+        let mut decoder = SyncDecoder::with_max_frame_len(4);
+        decoder.feed(&encode_frame(b"toolong").unwrap());
+        decoder.feed(&encode_frame(b"ok").unwrap());
+        let out = frames(&mut decoder);
+        assert_eq!(out[0], Err(ProtocolError::MalformedFrame));
+        assert_eq!(out.last(), Some(&Ok(b"ok".to_vec())));
+    }
+}
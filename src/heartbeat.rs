@@ -0,0 +1,98 @@
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::codec::Codec;
+use crate::message::{Message, Priority, flags};
+
+// Keepalive between two endpoints: each side sends a heartbeat every `interval`, and takes
+// the link as down once it hasn't heard anything for `max_missed` intervals. Any message
+// from the other end counts, heartbeats just make sure there's something to hear while the
+// application has nothing to say. When it hears from the other end again the link is up.
+//
+// A heartbeat has `flags::HEARTBEAT` set, id 0 and no payload.
+//
+// The link starts out down, the first message heard brings it up.
+
+pub const DEFAULT_INTERVAL: Duration = Duration::from_millis(500);
+pub const DEFAULT_MAX_MISSED: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    Up,
+    Down,
+}
+
+// Sealed and ready to go
+pub fn heartbeat(codec: &Codec) -> Message {
+    codec.seal_with(0, flags::HEARTBEAT, Priority::High, Vec::new())
+}
+
+#[derive(Debug, Clone)]
+pub struct HeartbeatMonitor {
+    interval: Duration,
+    max_missed: u32,
+    // `None` until the first heartbeat goes out / first message comes in
+    last_sent: Option<Duration>,
+    last_heard: Option<Duration>,
+    state: LinkState,
+}
+
+impl HeartbeatMonitor {
+    pub fn new(interval: Duration, max_missed: u32) -> Self {
+        HeartbeatMonitor {
+            interval,
+            max_missed: max_missed.max(1),
+            last_sent: None,
+            last_heard: None,
+            state: LinkState::Down,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn state(&self) -> LinkState {
+        self.state
+    }
+
+    // Whether the next heartbeat is due
+    pub fn is_due(&self, now: Duration) -> bool {
+        self.last_sent
+            .is_none_or(|sent| now.saturating_sub(sent) >= self.interval)
+    }
+
+    pub fn sent(&mut self, now: Duration) {
+        self.last_sent = Some(now);
+    }
+
+    // Something came in from the other end. Returns the new state if the link just came up.
+    pub fn heard(&mut self, now: Duration) -> Option<LinkState> {
+        self.last_heard = Some(now);
+        self.set_state(LinkState::Up)
+    }
+
+    // Returns the new state if the link just went down
+    pub fn check(&mut self, now: Duration) -> Option<LinkState> {
+        let silence = now.saturating_sub(self.last_heard?);
+        if silence > self.interval * self.max_missed {
+            return self.set_state(LinkState::Down);
+        }
+        None
+    }
+
+    fn set_state(&mut self, state: LinkState) -> Option<LinkState> {
+        if self.state == state {
+            return None;
+        }
+        log!("Heartbeat: link {:?}", state);
+        self.state = state;
+        Some(state)
+    }
+}
+
+impl Default for HeartbeatMonitor {
+    fn default() -> Self {
+        Self::new(DEFAULT_INTERVAL, DEFAULT_MAX_MISSED)
+    }
+}
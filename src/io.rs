@@ -0,0 +1,248 @@
+// `embedded_io::Read` / `Write` over a message channel, for byte oriented code (a shell, a
+// log sink, a file transfer) that wants a plain reader/writer and doesn't care about message
+// boundaries:
+//
+// - `LinkIo` over a `Link`: reading blocks until a message arrives (spinning on the
+//   transport, like any other blocking `embedded_io::Read`)
+// - `ProtocolIo` over a `CommunicationProtocol`: reads hit end of file once the buffer is empty
+//
+// Each `write` goes out as one message of up to `MAX_PAYLOAD_LEN` bytes, so the chunk size on
+// the wire is whatever the caller writes in one go. Reads hand out the payloads of messages
+// that passed their checksum back to back, corrupted ones are skipped (and counted in the
+// stats).
+
+use embedded_io::{BufRead, ErrorKind, ErrorType, Read, ReadReady, Write, WriteReady};
+
+use crate::error::ProtocolError;
+use crate::framing::Framing;
+use crate::link::Link;
+use crate::message::{MAX_PAYLOAD_LEN, Payload, copy_payload};
+use crate::protocol::CommunicationProtocol;
+use crate::transport::Transport;
+
+impl embedded_io::Error for ProtocolError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            ProtocolError::Timeout | ProtocolError::DeliveryFailed { .. } => ErrorKind::TimedOut,
+            ProtocolError::Disconnected => ErrorKind::BrokenPipe,
+            ProtocolError::NotConnected => ErrorKind::NotConnected,
+            ProtocolError::IncompatibleVersion { .. } => ErrorKind::ConnectionRefused,
+            ProtocolError::Unroutable { .. } => ErrorKind::AddrNotAvailable,
+            ProtocolError::UnknownChannel { .. } => ErrorKind::InvalidInput,
+            ProtocolError::BufferFull => ErrorKind::OutOfMemory,
+            ProtocolError::PayloadTooLarge { .. } => ErrorKind::InvalidInput,
+            ProtocolError::ChecksumMismatch { .. }
+            | ProtocolError::InvalidLength { .. }
+            | ProtocolError::InvalidHeader
+            | ProtocolError::MalformedFrame
+            | ProtocolError::InvalidFragment => ErrorKind::InvalidData,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+// the payload being read out and how far into it we are
+#[derive(Default)]
+struct Cursor {
+    payload: Option<Payload>,
+    offset: usize,
+}
+
+impl Cursor {
+    fn remaining(&self) -> &[u8] {
+        self.payload
+            .as_ref()
+            .map_or(&[], |payload| &payload[self.offset..])
+    }
+
+    fn set(&mut self, payload: Payload) {
+        self.payload = Some(payload);
+        self.offset = 0;
+    }
+
+    fn consume(&mut self, amount: usize) {
+        let len = self.payload.as_ref().map_or(0, |payload| payload.len());
+        self.offset = (self.offset + amount).min(len);
+        if self.offset == len {
+            self.payload = None;
+            self.offset = 0;
+        }
+    }
+
+    fn copy_to(&mut self, buf: &mut [u8]) -> usize {
+        let remaining = self.remaining();
+        let len = remaining.len().min(buf.len());
+        buf[..len].copy_from_slice(&remaining[..len]);
+        self.consume(len);
+        len
+    }
+}
+
+pub struct LinkIo<T, F: Framing> {
+    link: Link<T, F>,
+    cursor: Cursor,
+}
+
+impl<T: Transport, F: Framing> LinkIo<T, F> {
+    pub fn new(link: Link<T, F>) -> Self {
+        LinkIo {
+            link,
+            cursor: Cursor::default(),
+        }
+    }
+
+    pub fn link(&self) -> &Link<T, F> {
+        &self.link
+    }
+
+    pub fn link_mut(&mut self) -> &mut Link<T, F> {
+        &mut self.link
+    }
+
+    pub fn into_link(self) -> Link<T, F> {
+        self.link
+    }
+
+    // Pull the next valid payload into the cursor if there's one on the wire right now.
+    // Transport errors come out, corrupted frames are dropped.
+    fn poll_next(&mut self) -> Result<bool, ProtocolError> {
+        while self.cursor.remaining().is_empty() {
+            match self.link.receive() {
+                None => return Ok(false),
+                Some(Ok(message)) => self.cursor.set(message.payload),
+                Some(Err(error @ (ProtocolError::Transport(_) | ProtocolError::Disconnected))) => {
+                    return Err(error);
+                }
+                Some(Err(_)) => continue,
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<T: Transport, F: Framing> ErrorType for LinkIo<T, F> {
+    type Error = ProtocolError;
+}
+
+impl<T: Transport, F: Framing> Read for LinkIo<T, F> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while !self.poll_next()? {}
+        Ok(self.cursor.copy_to(buf))
+    }
+}
+
+impl<T: Transport, F: Framing> BufRead for LinkIo<T, F> {
+    fn fill_buf(&mut self) -> Result<&[u8], ProtocolError> {
+        while !self.poll_next()? {}
+        Ok(self.cursor.remaining())
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.cursor.consume(amount);
+    }
+}
+
+impl<T: Transport, F: Framing> ReadReady for LinkIo<T, F> {
+    fn read_ready(&mut self) -> Result<bool, ProtocolError> {
+        self.poll_next()
+    }
+}
+
+impl<T: Transport, F: Framing> Write for LinkIo<T, F> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ProtocolError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(MAX_PAYLOAD_LEN);
+        self.link.send(copy_payload(&buf[..len]))?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        self.link.flush()
+    }
+}
+
+impl<T: Transport, F: Framing> WriteReady for LinkIo<T, F> {
+    fn write_ready(&mut self) -> Result<bool, ProtocolError> {
+        self.link.poll_write()?;
+        Ok(self.link.pending_bytes() == 0)
+    }
+}
+
+pub struct ProtocolIo<'a> {
+    protocol: &'a mut CommunicationProtocol,
+    cursor: Cursor,
+}
+
+impl<'a> ProtocolIo<'a> {
+    pub fn new(protocol: &'a mut CommunicationProtocol) -> Self {
+        ProtocolIo {
+            protocol,
+            cursor: Cursor::default(),
+        }
+    }
+
+    pub fn protocol(&mut self) -> &mut CommunicationProtocol {
+        self.protocol
+    }
+
+    // next valid payload into the cursor, false once the buffer is drained
+    fn poll_next(&mut self) -> bool {
+        while self.cursor.remaining().is_empty() {
+            match self.protocol.mcu2_receive() {
+                None => return false,
+                Some((message, true)) => self.cursor.set(message.payload),
+                Some((_, false)) => continue,
+            }
+        }
+        true
+    }
+}
+
+impl ErrorType for ProtocolIo<'_> {
+    type Error = ProtocolError;
+}
+
+impl Read for ProtocolIo<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        // nobody else can queue anything while we hold the protocol, empty is end of file
+        self.poll_next();
+        Ok(self.cursor.copy_to(buf))
+    }
+}
+
+impl BufRead for ProtocolIo<'_> {
+    fn fill_buf(&mut self) -> Result<&[u8], ProtocolError> {
+        self.poll_next();
+        Ok(self.cursor.remaining())
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.cursor.consume(amount);
+    }
+}
+
+impl ReadReady for ProtocolIo<'_> {
+    fn read_ready(&mut self) -> Result<bool, ProtocolError> {
+        Ok(self.poll_next())
+    }
+}
+
+impl Write for ProtocolIo<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ProtocolError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(MAX_PAYLOAD_LEN);
+        self.protocol.mcu1_send_slice(&buf[..len])?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+//
+// Communication protocol to manage messages between MCU1 and MCU2
+// - Shared Buffer
+// - Data Structures
+//
+// Req:
+//    - Structure for messages should include a message id, payload and a checksum
+//    - MCU1 -> MCU2 uses a circular Buffer
+//    - Functions to send and receive messages including calculating and verifying checksums
+//
+// The core (messages, buffer, checksums, framing, protocol) only needs `alloc`. Everything
+// that needs an OS (`BlockingBuffer`, `StdClock`, the socket transports) sits behind the `std`
+// feature, which is on by default. Build with `default-features = false, features = ["alloc"]`
+// for bare metal with a heap, or without `alloc` at all for the fixed size types in `fixed`.
+
+#![no_std]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+// Diagnostics, handed to the `log` facade at debug level with the `log` feature and compiled
+// out without it. The library never prints on its own.
+macro_rules! log {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        ::log::debug!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        let _ = core::format_args!($($arg)*);
+    };
+}
+
+#[cfg(feature = "alloc")]
+pub mod ack;
+pub mod address;
+pub mod array;
+#[cfg(all(
+    feature = "alloc",
+    target_has_atomic = "ptr",
+    any(feature = "std", feature = "critical-section")
+))]
+pub mod asynch;
+#[cfg(feature = "std")]
+pub mod blocking;
+pub mod buffer;
+#[cfg(feature = "alloc")]
+pub mod channel;
+pub mod checksum;
+#[cfg(feature = "alloc")]
+pub mod codec;
+#[cfg(feature = "alloc")]
+pub mod connection;
+#[cfg(feature = "alloc")]
+pub mod control;
+#[cfg(feature = "alloc")]
+pub mod credit;
+#[cfg(feature = "critical-section")]
+pub mod critical;
+#[cfg(feature = "alloc")]
+pub mod dispatch;
+#[cfg(feature = "alloc")]
+pub mod endpoint;
+pub mod error;
+pub mod fixed;
+#[cfg(feature = "alloc")]
+pub mod fragment;
+#[cfg(feature = "alloc")]
+pub mod framing;
+#[cfg(feature = "alloc")]
+pub mod heartbeat;
+#[cfg(feature = "embedded-io")]
+pub mod io;
+#[cfg(feature = "alloc")]
+pub mod link;
+pub mod message;
+#[cfg(feature = "alloc")]
+pub mod middleware;
+#[cfg(feature = "alloc")]
+pub mod payload;
+#[cfg(feature = "alloc")]
+pub mod pool;
+#[cfg(feature = "alloc")]
+pub mod protocol;
+#[cfg(feature = "tokio")]
+pub mod pump;
+pub mod rate;
+#[cfg(feature = "alloc")]
+pub mod reorder;
+#[cfg(feature = "alloc")]
+pub mod retransmit;
+#[cfg(feature = "alloc")]
+mod rng;
+pub mod round_trip;
+#[cfg(feature = "alloc")]
+pub mod router;
+#[cfg(feature = "alloc")]
+pub mod rpc;
+#[cfg(feature = "alloc")]
+pub mod sequence;
+#[cfg(feature = "alloc")]
+pub mod sim;
+pub mod spsc;
+pub mod stats;
+pub mod throughput;
+pub mod time;
+#[cfg(feature = "alloc")]
+pub mod topic;
+pub mod transport;
+
+#[cfg(feature = "alloc")]
+pub use ack::DeliveryStatus;
+#[cfg(all(
+    feature = "alloc",
+    target_has_atomic = "ptr",
+    any(feature = "std", feature = "critical-section")
+))]
+pub use asynch::AsyncProtocol;
+#[cfg(feature = "futures")]
+pub use asynch::{MessageSink, MessageStream};
+#[cfg(feature = "std")]
+pub use blocking::{BlockingBuffer, Receiver, Sender, SharedBuffer};
+#[cfg(feature = "alloc")]
+pub use buffer::CircularBuffer;
+pub use buffer::{BufferEvent, OverflowPolicy, Watermarks};
+#[cfg(feature = "alloc")]
+pub use channel::ChannelConfig;
+#[cfg(feature = "alloc")]
+pub use checksum::HardwareFn;
+pub use checksum::{
+    ChecksumAlgorithm, Crc16Ccitt, Crc32, Fletcher16, Hardware, HardwareChecksum, Xor8,
+};
+#[cfg(feature = "alloc")]
+pub use codec::Codec;
+#[cfg(feature = "alloc")]
+pub use connection::{Capabilities, ConnectionState};
+#[cfg(feature = "critical-section")]
+pub use critical::CriticalBuffer;
+#[cfg(feature = "alloc")]
+pub use dispatch::Dispatcher;
+#[cfg(feature = "alloc")]
+pub use endpoint::Endpoint;
+pub use error::{ProtocolError, TransportError};
+#[cfg(feature = "heapless")]
+pub use fixed::HeaplessBuffer;
+pub use fixed::{FixedMessage, StaticProtocol};
+#[cfg(feature = "alloc")]
+pub use heartbeat::LinkState;
+#[cfg(feature = "alloc")]
+pub use link::Link;
+pub use message::{FormatVersion, MessageKind, MessageRef, Priority};
+#[cfg(feature = "alloc")]
+pub use message::{Message, Payload};
+#[cfg(feature = "alloc")]
+pub use middleware::Middleware;
+#[cfg(feature = "alloc")]
+pub use payload::SmallPayload;
+#[cfg(feature = "alloc")]
+pub use pool::PayloadPool;
+#[cfg(feature = "alloc")]
+pub use protocol::{CommunicationProtocol, ReceivedHeader};
+pub use rate::RateLimit;
+#[cfg(feature = "alloc")]
+pub use retransmit::{ArqMode, RetransmitPolicy};
+#[cfg(feature = "alloc")]
+pub use router::Router;
+#[cfg(feature = "alloc")]
+pub use rpc::{PendingRequest, RequestId};
+#[cfg(feature = "alloc")]
+pub use sequence::Gap;
+pub use spsc::SpscRing;
+pub use stats::Stats;
+#[cfg(feature = "std")]
+pub use time::StdClock;
+#[cfg(feature = "alloc")]
+pub use time::VirtualClock;
+pub use time::{Clock, Delay, NoClock};
+#[cfg(feature = "alloc")]
+pub use topic::{TopicFilter, TopicId};
+pub use transport::Transport;
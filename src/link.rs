@@ -0,0 +1,185 @@
+// One end of a point to point connection: a `Transport` with framing and checksums on top.
+// `send` seals a payload, frames it and hands the bytes to the transport, `receive` feeds
+// whatever the transport has into the frame decoder and hands back verified messages.
+//
+//   let mut link = Link::new(uart, Cobs::default());
+//   link.send(vec![1, 2, 3])?;
+//   while let Some(received) = link.receive() { ... }
+//
+// Nothing here blocks. Bytes the transport couldn't take yet are kept and retried on the next
+// `send`, `receive` or `poll_write`, `flush` keeps going until they're all out.
+
+use alloc::vec::Vec;
+
+use crate::codec::Codec;
+use crate::error::ProtocolError;
+use crate::framing::{Cobs, FrameDecoder, Framing};
+use crate::message::{MAX_PAYLOAD_LEN, Message, Payload, Priority};
+use crate::stats::Stats;
+use crate::transport::Transport;
+
+// bytes pulled from the transport per `read_bytes` call
+const READ_CHUNK: usize = 64;
+
+pub struct Link<T, F: Framing = Cobs> {
+    transport: T,
+    framing: F,
+    decoder: F::Decoder,
+    codec: Codec,
+    next_message: u16,
+    // framed bytes the transport hasn't taken yet
+    outgoing: Vec<u8>,
+    stats: Stats,
+}
+
+impl<T: Transport, F: Framing> Link<T, F> {
+    pub fn new(transport: T, framing: F) -> Self {
+        Link {
+            transport,
+            decoder: framing.decoder(),
+            framing,
+            codec: Codec::default(),
+            next_message: 1,
+            outgoing: Vec::new(),
+            stats: Stats::new(),
+        }
+    }
+
+    // Checksum and wire format, both ends have to agree on it
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    pub fn codec(&self) -> &Codec {
+        &self.codec
+    }
+
+    pub fn framing(&self) -> &F {
+        &self.framing
+    }
+
+    pub fn decoder(&self) -> &F::Decoder {
+        &self.decoder
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    // Returns the id the other end will see the message under, `PayloadTooLarge` if it
+    // doesn't fit in a frame (see `max_payload_len`)
+    pub fn send(&mut self, payload: impl Into<Payload>) -> Result<u16, ProtocolError> {
+        self.send_with_priority(payload, Priority::default())
+    }
+
+    pub fn send_with_priority(
+        &mut self,
+        payload: impl Into<Payload>,
+        priority: Priority,
+    ) -> Result<u16, ProtocolError> {
+        let message = self
+            .codec
+            .seal_with(self.next_message, 0, priority, payload);
+        self.send_message(&message)?;
+        self.next_message = self.next_message.wrapping_add(1);
+        Ok(message.id)
+    }
+
+    // Longest payload that fits in one frame, after the framing's `max_frame_len` and the
+    // codec's header and checksum
+    pub fn max_payload_len(&self) -> usize {
+        self.framing
+            .max_frame_len()
+            .saturating_sub(self.codec.overhead())
+            .min(MAX_PAYLOAD_LEN)
+    }
+
+    // Frame and send a message that's already sealed, as is. `PayloadTooLarge` above
+    // `max_payload_len`, the other end's decoder would throw the frame away.
+    pub fn send_message(&mut self, message: &Message) -> Result<(), ProtocolError> {
+        let max = self.max_payload_len();
+        if message.payload.len() > max {
+            return Err(ProtocolError::PayloadTooLarge {
+                len: message.payload.len(),
+                max,
+            });
+        }
+        let frame = self.framing.encode_message(&self.codec, message)?;
+        self.outgoing.extend_from_slice(&frame);
+        self.stats.messages_sent += 1;
+        self.stats.bytes_sent += message.payload.len() as u64;
+        self.poll_write()
+    }
+
+    // Hand as much of the backlog to the transport as it takes right now
+    pub fn poll_write(&mut self) -> Result<(), ProtocolError> {
+        while !self.outgoing.is_empty() {
+            let written = self.transport.write_bytes(&self.outgoing)?;
+            if written == 0 {
+                break;
+            }
+            self.outgoing.drain(..written);
+        }
+        Ok(())
+    }
+
+    // Keep writing until the backlog is gone, then flush the transport. Spins if the
+    // transport stays full, only call it where waiting is fine.
+    pub fn flush(&mut self) -> Result<(), ProtocolError> {
+        while !self.outgoing.is_empty() {
+            self.poll_write()?;
+        }
+        self.transport.flush()
+    }
+
+    // Framed bytes still waiting for the transport
+    pub fn pending_bytes(&self) -> usize {
+        self.outgoing.len()
+    }
+
+    // Next message off the wire, `None` until a whole frame has arrived. Frames that fail to
+    // decode or verify come out as errors (and count as checksum failures or malformed frames), receiving carries
+    // on with the next one.
+    pub fn receive(&mut self) -> Option<Result<Message, ProtocolError>> {
+        if let Err(error) = self.poll_write() {
+            return Some(Err(error));
+        }
+
+        loop {
+            if let Some(decoded) = self.decoder.next_message(&self.codec) {
+                match &decoded {
+                    Ok(message) => {
+                        self.stats.messages_received += 1;
+                        self.stats.bytes_received += message.payload.len() as u64;
+                    }
+                    Err(error) => self.stats.count_decode_error(error),
+                }
+                return Some(decoded);
+            }
+
+            let mut chunk = [0u8; READ_CHUNK];
+            match self.transport.read_bytes(&mut chunk) {
+                Ok(0) => return None,
+                Ok(read) => self.decoder.feed(&chunk[..read]),
+                Err(error) => return Some(Err(error)),
+            }
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::new();
+    }
+}
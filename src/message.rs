@@ -0,0 +1,595 @@
+#[cfg(feature = "alloc")]
+use alloc::vec;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+use crate::checksum::ChecksumAlgorithm;
+#[cfg(feature = "alloc")]
+use crate::checksum::Xor8;
+use crate::error::ProtocolError;
+
+// Largest payload a single message can carry
+pub const MAX_PAYLOAD_LEN: usize = u16::MAX as usize;
+
+// Wire layout (all multi-byte fields little-endian):
+//
+//   V1: | id: u16 | length: u16 | payload: [u8; length] | checksum: 1..=4 bytes |
+//   V2: | id: u16 | flags: u8 | priority: u8 | length: u16 | payload | checksum |
+//   V3: | id: u16 | flags: u8 | priority: u8 | source: u8 | destination: u8 | channel: u8 |
+//       | length: u16 | payload | checksum |
+//
+// The checksum width depends on the algorithm, 1 byte for XOR, 2 for CRC-16 and 4 for CRC-32.
+// What the checksum covers depends on the `FormatVersion`.
+pub const V1_HEADER_LEN: usize = 4;
+pub const V2_HEADER_LEN: usize = 6;
+pub const V3_HEADER_LEN: usize = 9;
+pub const MAX_HEADER_LEN: usize = V3_HEADER_LEN;
+
+// What a `Message` keeps its payload in. Normally a `SmallPayload`, small payloads inline and
+// big ones on the heap. With the `bytes` feature it's `bytes::Bytes`, so one payload can be
+// handed to several channels or kept around for retransmission with a refcount bump instead
+// of a copy.
+#[cfg(all(feature = "alloc", not(feature = "bytes")))]
+pub type Payload = crate::payload::SmallPayload;
+#[cfg(feature = "bytes")]
+pub type Payload = bytes::Bytes;
+
+#[cfg(all(feature = "alloc", not(feature = "bytes")))]
+pub(crate) fn copy_payload(bytes: &[u8]) -> Payload {
+    Payload::from_slice(bytes)
+}
+
+#[cfg(feature = "bytes")]
+pub(crate) fn copy_payload(bytes: &[u8]) -> Payload {
+    bytes::Bytes::copy_from_slice(bytes)
+}
+
+// Bits of `Message::flags`, only carried on the wire from V2 on. See `MessageKind` for what
+// they add up to.
+pub mod flags {
+    // message is one fragment of a larger payload, see `fragment`
+    pub const FRAGMENT: u8 = 0x01;
+    // last fragment of its payload
+    pub const LAST_FRAGMENT: u8 = 0x02;
+    // acknowledgment of the message with the same id, see `ack`
+    pub const ACK: u8 = 0x04;
+    // negative acknowledgment, set together with `ACK`
+    pub const NACK: u8 = 0x08;
+    // in order receivers take this id as the next one in sequence, see `retransmit`
+    pub const SYNC: u8 = 0x10;
+    // the protocol's own message (flow control ...) rather than application data, see
+    // `control`
+    pub const CONTROL: u8 = 0x20;
+    // keepalive, says the sender is still there and nothing else, see `heartbeat`
+    pub const HEARTBEAT: u8 = 0x40;
+}
+
+// What a message is for, read off its flags. Everything but `Data` and `Fragment` is the
+// protocol talking to itself and never reaches the application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    Data,
+    Ack,
+    Nack,
+    Control,
+    Heartbeat,
+    // piece of a larger `Data` payload
+    Fragment,
+}
+
+impl MessageKind {
+    pub fn from_flags(bits: u8) -> Self {
+        if bits & flags::NACK != 0 {
+            MessageKind::Nack
+        } else if bits & flags::ACK != 0 {
+            MessageKind::Ack
+        } else if bits & flags::CONTROL != 0 {
+            MessageKind::Control
+        } else if bits & flags::HEARTBEAT != 0 {
+            MessageKind::Heartbeat
+        } else if bits & flags::FRAGMENT != 0 {
+            MessageKind::Fragment
+        } else {
+            MessageKind::Data
+        }
+    }
+
+    // Flag bits that make a message this kind
+    pub fn flags(self) -> u8 {
+        match self {
+            MessageKind::Data => 0,
+            MessageKind::Ack => flags::ACK,
+            MessageKind::Nack => flags::ACK | flags::NACK,
+            MessageKind::Control => flags::CONTROL,
+            MessageKind::Heartbeat => flags::HEARTBEAT,
+            MessageKind::Fragment => flags::FRAGMENT,
+        }
+    }
+
+    // The protocol's own, not application data
+    pub fn is_internal(self) -> bool {
+        !matches!(self, MessageKind::Data | MessageKind::Fragment)
+    }
+}
+
+// How urgent a message is. The shared buffer hands out higher priorities first so
+// safety-critical commands don't sit behind bulk telemetry, FIFO within the same priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[repr(u8)]
+pub enum Priority {
+    Low = 0,
+    #[default]
+    Normal = 1,
+    High = 2,
+    Critical = 3,
+}
+
+impl Priority {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Priority::Low),
+            1 => Some(Priority::Normal),
+            2 => Some(Priority::High),
+            3 => Some(Priority::Critical),
+            _ => None,
+        }
+    }
+}
+
+// Which revision of the wire format a peer speaks. V1 is the original layout, so an old peer
+// can still be talked to by switching back to it (at the cost of the newer header fields).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FormatVersion {
+    // checksum covers the payload only (original behaviour), a corrupted id goes unnoticed
+    V1,
+    // flags and priority bytes in the header, checksum covers the whole header as well as the payload
+    #[default]
+    V2,
+    // V2 plus source and destination node ids, for more than two MCUs on a bus (see
+    // `address`), and the channel
+    V3,
+}
+
+impl FormatVersion {
+    pub const fn header_len(self) -> usize {
+        match self {
+            FormatVersion::V1 => V1_HEADER_LEN,
+            FormatVersion::V2 => V2_HEADER_LEN,
+            FormatVersion::V3 => V3_HEADER_LEN,
+        }
+    }
+
+    // Whether source, destination and channel make it onto the wire
+    pub const fn has_addresses(self) -> bool {
+        matches!(self, FormatVersion::V3)
+    }
+}
+
+// Payload, message id and checksum
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub id: u16,
+    pub flags: u8,
+    pub priority: Priority,
+    // node ids, only carried on the wire from V3 on. 0 = unaddressed.
+    pub source: u8,
+    pub destination: u8,
+    // independent stream between the same two nodes (V3 on), see `endpoint`
+    pub channel: u8,
+    pub payload: Payload,
+    pub checksum: u32,
+}
+
+#[cfg(feature = "alloc")]
+impl Message {
+    // create our new message and calculate checksum automatically (XOR)
+    pub fn new(id: u16, payload: impl Into<Payload>) -> Self {
+        Self::with_checksum(id, payload, &Xor8)
+    }
+
+    // same as `new` but with an explicit checksum algorithm
+    pub fn with_checksum(
+        id: u16,
+        payload: impl Into<Payload>,
+        algorithm: &dyn ChecksumAlgorithm,
+    ) -> Self {
+        Self::sealed(id, payload, algorithm, FormatVersion::default())
+    }
+
+    // build a message and compute its checksum for a specific format version
+    pub fn sealed(
+        id: u16,
+        payload: impl Into<Payload>,
+        algorithm: &dyn ChecksumAlgorithm,
+        version: FormatVersion,
+    ) -> Self {
+        let mut message = Message {
+            id,
+            flags: 0,
+            priority: Priority::default(),
+            source: 0,
+            destination: 0,
+            channel: 0,
+            payload: payload.into(),
+            checksum: 0,
+        };
+        message.checksum = message.compute_checksum(algorithm, version);
+        message
+    }
+
+    pub fn is_fragment(&self) -> bool {
+        self.flags & flags::FRAGMENT != 0
+    }
+
+    pub fn kind(&self) -> MessageKind {
+        MessageKind::from_flags(self.flags)
+    }
+
+    // Same message with the payload borrowed instead of owned
+    pub fn borrowed(&self) -> MessageRef<'_> {
+        MessageRef {
+            id: self.id,
+            flags: self.flags,
+            priority: self.priority,
+            source: self.source,
+            destination: self.destination,
+            channel: self.channel,
+            payload: &self.payload,
+            checksum: self.checksum,
+        }
+    }
+
+    // XOR Checksum of payload bytes
+    // Ideally we'd do this byte-by-byte to minimize memory usage and processing overhead
+    pub fn calculate_checksum(payload: &[u8]) -> u8 {
+        crate::checksum::xor8(payload)
+    }
+
+    // Checksum this message should carry under `version`
+    pub fn compute_checksum(
+        &self,
+        algorithm: &dyn ChecksumAlgorithm,
+        version: FormatVersion,
+    ) -> u32 {
+        self.borrowed().compute_checksum(algorithm, version)
+    }
+
+    // Simply verifies the messages integrity by recalculating the checksum
+    pub fn verify_checksum(&self) -> bool {
+        self.verify_checksum_with(&Xor8)
+    }
+
+    pub fn verify_checksum_with(&self, algorithm: &dyn ChecksumAlgorithm) -> bool {
+        self.verify(algorithm, FormatVersion::default())
+    }
+
+    pub fn verify(&self, algorithm: &dyn ChecksumAlgorithm, version: FormatVersion) -> bool {
+        self.checksum == self.compute_checksum(algorithm, version)
+    }
+
+    // Header fields as they appear on the wire, only the first `version.header_len()` bytes
+    // are used
+    pub fn header(&self, version: FormatVersion) -> [u8; MAX_HEADER_LEN] {
+        self.borrowed().header(version)
+    }
+
+    // Size of this message once serialized with the default checksum
+    pub fn encoded_len(&self) -> usize {
+        self.encoded_len_with(&Xor8)
+    }
+
+    pub fn encoded_len_with(&self, algorithm: &dyn ChecksumAlgorithm) -> usize {
+        self.encoded_len_for(algorithm, FormatVersion::default())
+    }
+
+    pub fn encoded_len_for(
+        &self,
+        algorithm: &dyn ChecksumAlgorithm,
+        version: FormatVersion,
+    ) -> usize {
+        version.header_len() + self.payload.len() + algorithm.width()
+    }
+
+    // Serialize into the wire layout above so it can go out over a physical link.
+    // `PayloadTooLarge` above `MAX_PAYLOAD_LEN`, the length field can't say more.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ProtocolError> {
+        self.to_bytes_with(&Xor8)
+    }
+
+    pub fn to_bytes_with(
+        &self,
+        algorithm: &dyn ChecksumAlgorithm,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        self.encode(algorithm, FormatVersion::default())
+    }
+
+    pub fn encode(
+        &self,
+        algorithm: &dyn ChecksumAlgorithm,
+        version: FormatVersion,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        self.borrowed().encode(algorithm, version)
+    }
+
+    // Serialize and COBS-frame in one call, zero-delimited and ready for a serial link
+    pub fn to_cobs_frame(&self) -> Result<Vec<u8>, ProtocolError> {
+        Ok(crate::framing::cobs::encode_frame(&self.to_bytes()?))
+    }
+
+    // Inverse of `to_cobs_frame`
+    pub fn from_cobs_frame(frame: &[u8]) -> Result<Self, ProtocolError> {
+        Self::from_bytes(&crate::framing::cobs::decode(frame)?)
+    }
+
+    // Parse a message off the wire, checking the length field against what we actually
+    // got and the checksum against the payload (and header under V2)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        Self::from_bytes_with(bytes, &Xor8)
+    }
+
+    pub fn from_bytes_with(
+        bytes: &[u8],
+        algorithm: &dyn ChecksumAlgorithm,
+    ) -> Result<Self, ProtocolError> {
+        Self::decode(bytes, algorithm, FormatVersion::default())
+    }
+
+    pub fn decode(
+        bytes: &[u8],
+        algorithm: &dyn ChecksumAlgorithm,
+        version: FormatVersion,
+    ) -> Result<Self, ProtocolError> {
+        MessageRef::decode(bytes, algorithm, version).map(|message| message.to_message())
+    }
+}
+
+// `Message` with a borrowed payload, for hot paths that frame and transmit straight out of
+// the caller's memory (or parse straight out of a receive buffer) without copying the payload
+// into a `Vec` first. Wire format and checksums are identical to `Message`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageRef<'a> {
+    pub id: u16,
+    pub flags: u8,
+    pub priority: Priority,
+    pub source: u8,
+    pub destination: u8,
+    pub channel: u8,
+    pub payload: &'a [u8],
+    pub checksum: u32,
+}
+
+impl<'a> MessageRef<'a> {
+    pub fn sealed(
+        id: u16,
+        payload: &'a [u8],
+        algorithm: &dyn ChecksumAlgorithm,
+        version: FormatVersion,
+    ) -> Self {
+        let mut message = MessageRef {
+            id,
+            flags: 0,
+            priority: Priority::default(),
+            source: 0,
+            destination: 0,
+            channel: 0,
+            payload,
+            checksum: 0,
+        };
+        message.checksum = message.compute_checksum(algorithm, version);
+        message
+    }
+
+    pub fn is_fragment(&self) -> bool {
+        self.flags & flags::FRAGMENT != 0
+    }
+
+    pub fn kind(&self) -> MessageKind {
+        MessageKind::from_flags(self.flags)
+    }
+
+    pub fn compute_checksum(
+        &self,
+        algorithm: &dyn ChecksumAlgorithm,
+        version: FormatVersion,
+    ) -> u32 {
+        match version {
+            FormatVersion::V1 => algorithm.checksum(self.payload),
+            FormatVersion::V2 | FormatVersion::V3 => {
+                let header = self.header(version);
+                algorithm.checksum_chunks(&[&header[..version.header_len()], self.payload])
+            }
+        }
+    }
+
+    pub fn verify(&self, algorithm: &dyn ChecksumAlgorithm, version: FormatVersion) -> bool {
+        self.checksum == self.compute_checksum(algorithm, version)
+    }
+
+    // Longer payloads than `MAX_PAYLOAD_LEN` don't fit the length field, `encode_into` refuses
+    // them
+    pub fn header(&self, version: FormatVersion) -> [u8; MAX_HEADER_LEN] {
+        let id = self.id.to_le_bytes();
+        let length = (self.payload.len() as u16).to_le_bytes();
+        match version {
+            FormatVersion::V1 => [id[0], id[1], length[0], length[1], 0, 0, 0, 0, 0],
+            FormatVersion::V2 => [
+                id[0],
+                id[1],
+                self.flags,
+                self.priority as u8,
+                length[0],
+                length[1],
+                0,
+                0,
+                0,
+            ],
+            FormatVersion::V3 => [
+                id[0],
+                id[1],
+                self.flags,
+                self.priority as u8,
+                self.source,
+                self.destination,
+                self.channel,
+                length[0],
+                length[1],
+            ],
+        }
+    }
+
+    pub fn encoded_len_for(
+        &self,
+        algorithm: &dyn ChecksumAlgorithm,
+        version: FormatVersion,
+    ) -> usize {
+        version.header_len() + self.payload.len() + algorithm.width()
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn encode(
+        &self,
+        algorithm: &dyn ChecksumAlgorithm,
+        version: FormatVersion,
+    ) -> Result<Vec<u8>, ProtocolError> {
+        let mut bytes = vec![0; self.encoded_len_for(algorithm, version)];
+        self.encode_into(algorithm, version, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    // Serialize into `out` (e.g. a static TX buffer) without allocating, returns the number
+    // of bytes written. `InvalidLength` if `out` is too short, `PayloadTooLarge` if the
+    // payload is longer than the length field can say.
+    pub fn encode_into(
+        &self,
+        algorithm: &dyn ChecksumAlgorithm,
+        version: FormatVersion,
+        out: &mut [u8],
+    ) -> Result<usize, ProtocolError> {
+        if self.payload.len() > MAX_PAYLOAD_LEN {
+            return Err(ProtocolError::PayloadTooLarge {
+                len: self.payload.len(),
+                max: MAX_PAYLOAD_LEN,
+            });
+        }
+        let width = checksum_width(algorithm)?;
+        let len = self.encoded_len_for(algorithm, version);
+        if out.len() < len {
+            return Err(ProtocolError::InvalidLength {
+                expected: len,
+                actual: out.len(),
+            });
+        }
+
+        let header_len = version.header_len();
+        let end = header_len + self.payload.len();
+        out[..header_len].copy_from_slice(&self.header(version)[..header_len]);
+        out[header_len..end].copy_from_slice(self.payload);
+        out[end..len].copy_from_slice(&self.checksum.to_le_bytes()[..width]);
+        Ok(len)
+    }
+
+    // Parse a message off the wire like `Message::decode`, the payload points into `bytes`
+    pub fn decode(
+        bytes: &'a [u8],
+        algorithm: &dyn ChecksumAlgorithm,
+        version: FormatVersion,
+    ) -> Result<Self, ProtocolError> {
+        let width = checksum_width(algorithm)?;
+        let header_len = version.header_len();
+        if bytes.len() < header_len + width {
+            return Err(ProtocolError::InvalidLength {
+                expected: header_len + width,
+                actual: bytes.len(),
+            });
+        }
+
+        let id = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let (flags, priority, length) = match version {
+            FormatVersion::V1 => (
+                0,
+                Priority::default(),
+                u16::from_le_bytes([bytes[2], bytes[3]]),
+            ),
+            FormatVersion::V2 => (
+                bytes[2],
+                Priority::from_u8(bytes[3]).ok_or(ProtocolError::InvalidHeader)?,
+                u16::from_le_bytes([bytes[4], bytes[5]]),
+            ),
+            FormatVersion::V3 => (
+                bytes[2],
+                Priority::from_u8(bytes[3]).ok_or(ProtocolError::InvalidHeader)?,
+                u16::from_le_bytes([bytes[7], bytes[8]]),
+            ),
+        };
+        let (source, destination, channel) = if version.has_addresses() {
+            (bytes[4], bytes[5], bytes[6])
+        } else {
+            (0, 0, 0)
+        };
+        let length = length as usize;
+
+        let expected = header_len + length + width;
+        if bytes.len() != expected {
+            return Err(ProtocolError::InvalidLength {
+                expected,
+                actual: bytes.len(),
+            });
+        }
+
+        let mut raw = [0u8; 4];
+        raw[..width].copy_from_slice(&bytes[header_len + length..]);
+        let checksum = u32::from_le_bytes(raw);
+
+        let message = MessageRef {
+            id,
+            flags,
+            priority,
+            source,
+            destination,
+            channel,
+            payload: &bytes[header_len..header_len + length],
+            checksum,
+        };
+
+        let actual = message.compute_checksum(algorithm, version);
+        if checksum != actual {
+            return Err(ProtocolError::ChecksumMismatch {
+                expected: checksum,
+                actual,
+            });
+        }
+
+        Ok(message)
+    }
+
+    // Copy the payload out into an owned `Message`
+    #[cfg(feature = "alloc")]
+    pub fn to_message(&self) -> Message {
+        Message {
+            id: self.id,
+            flags: self.flags,
+            priority: self.priority,
+            source: self.source,
+            destination: self.destination,
+            channel: self.channel,
+            payload: copy_payload(self.payload),
+            checksum: self.checksum,
+        }
+    }
+}
+
+// Bytes `algorithm` puts on the wire, `InvalidHeader` outside 1..=4 (the checksum is a u32)
+fn checksum_width(algorithm: &dyn ChecksumAlgorithm) -> Result<usize, ProtocolError> {
+    match algorithm.width() {
+        width @ 1..=4 => Ok(width),
+        _ => Err(ProtocolError::InvalidHeader),
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> From<&'a Message> for MessageRef<'a> {
+    fn from(message: &'a Message) -> Self {
+        message.borrowed()
+    }
+}
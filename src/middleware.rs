@@ -0,0 +1,83 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::channel::ChannelId;
+use crate::error::ProtocolError;
+use crate::message::{Message, Payload};
+
+// Layers an `Endpoint` runs application data through on the way out and in, for logging,
+// metrics, encryption, compression ... without touching the protocol itself:
+//
+//   let endpoint = Endpoint::new(uart, Cobs::default(), 8)
+//       .with_middleware(Compress)
+//       .with_middleware(Encrypt::new(key));
+//
+// Sending goes through the layers in the order they were added (compress, then encrypt) and
+// receiving the other way round (decrypt, then decompress), so each layer gets back what it
+// handed on. Both ends need the same layers in the same order.
+//
+// A layer sees payloads before they're framed and fragmented, and messages after they've
+// been verified and put back together, so the limits that count are the ones on what it
+// hands on. Protocol traffic (acks, heartbeats, the handshake ...) doesn't go through the
+// layers.
+
+pub trait Middleware: Send {
+    // Payload on its way out on `channel`. An error stops the send and comes out of it.
+    fn on_send(&mut self, channel: ChannelId, payload: Payload) -> Result<Payload, ProtocolError> {
+        let _ = channel;
+        Ok(payload)
+    }
+
+    // Message on its way in, `None` drops it. The checksum was checked before and isn't
+    // redone for a changed payload.
+    fn on_receive(&mut self, message: Message) -> Option<Message> {
+        Some(message)
+    }
+}
+
+// Layers in the order they were added
+pub struct Chain {
+    layers: Vec<Box<dyn Middleware>>,
+}
+
+impl Chain {
+    pub fn new() -> Self {
+        Chain { layers: Vec::new() }
+    }
+
+    // Goes after (sending) / before (receiving) the ones already there
+    pub fn push<M: Middleware + 'static>(&mut self, layer: M) {
+        self.layers.push(Box::new(layer));
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    pub fn on_send(
+        &mut self,
+        channel: ChannelId,
+        payload: Payload,
+    ) -> Result<Payload, ProtocolError> {
+        self.layers
+            .iter_mut()
+            .try_fold(payload, |payload, layer| layer.on_send(channel, payload))
+    }
+
+    pub fn on_receive(&mut self, message: Message) -> Option<Message> {
+        self.layers
+            .iter_mut()
+            .rev()
+            .try_fold(message, |message, layer| layer.on_receive(message))
+    }
+}
+
+impl Default for Chain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
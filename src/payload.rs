@@ -0,0 +1,218 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+// Payloads up to this many bytes live inside the `Message` itself, most of our traffic
+// (commands, short telemetry) is well under it
+pub const INLINE_CAPACITY: usize = 16;
+
+// Payload storage that keeps small payloads inline and only goes to the heap for big ones,
+// SmallVec style. Derefs to `[u8]` so it reads like a slice/`Vec` everywhere.
+//
+// A `Vec` handed in is kept as it is rather than copied inline, so memory from the payload
+// pool goes round the pool instead of being thrown away. Payloads built from a slice
+// (`from_slice`, decoding off the wire, `mcu1_send_slice`) go inline when they fit.
+#[derive(Clone)]
+pub struct SmallPayload {
+    repr: Repr,
+}
+
+#[derive(Clone)]
+enum Repr {
+    Inline {
+        len: u8,
+        data: [u8; INLINE_CAPACITY],
+    },
+    Heap(Vec<u8>),
+}
+
+impl SmallPayload {
+    pub const fn new() -> Self {
+        SmallPayload {
+            repr: Repr::Inline {
+                len: 0,
+                data: [0; INLINE_CAPACITY],
+            },
+        }
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Self {
+        if bytes.len() > INLINE_CAPACITY {
+            return SmallPayload {
+                repr: Repr::Heap(bytes.to_vec()),
+            };
+        }
+
+        let mut data = [0; INLINE_CAPACITY];
+        data[..bytes.len()].copy_from_slice(bytes);
+        SmallPayload {
+            repr: Repr::Inline {
+                len: bytes.len() as u8,
+                data,
+            },
+        }
+    }
+
+    pub fn is_inline(&self) -> bool {
+        matches!(self.repr, Repr::Inline { .. })
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        match &self.repr {
+            Repr::Inline { len, data } => &data[..*len as usize],
+            Repr::Heap(vec) => vec,
+        }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        match &mut self.repr {
+            Repr::Inline { len, data } => &mut data[..*len as usize],
+            Repr::Heap(vec) => vec,
+        }
+    }
+
+    // Append, spilling to the heap once the inline space runs out
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        match &mut self.repr {
+            Repr::Inline { len, data } if *len as usize + bytes.len() <= INLINE_CAPACITY => {
+                let start = *len as usize;
+                data[start..start + bytes.len()].copy_from_slice(bytes);
+                *len += bytes.len() as u8;
+            }
+            Repr::Inline { len, data } => {
+                let mut vec = Vec::with_capacity(*len as usize + bytes.len());
+                vec.extend_from_slice(&data[..*len as usize]);
+                vec.extend_from_slice(bytes);
+                self.repr = Repr::Heap(vec);
+            }
+            Repr::Heap(vec) => vec.extend_from_slice(bytes),
+        }
+    }
+
+    pub fn truncate(&mut self, new_len: usize) {
+        match &mut self.repr {
+            Repr::Inline { len, .. } => *len = (*len).min(new_len as u8),
+            Repr::Heap(vec) => vec.truncate(new_len),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    // Heap payloads hand back their `Vec` as is, inline ones get copied into a new one
+    pub fn into_vec(self) -> Vec<u8> {
+        match self.repr {
+            Repr::Inline { len, data } => data[..len as usize].to_vec(),
+            Repr::Heap(vec) => vec,
+        }
+    }
+
+    // The heap buffer behind this payload, `None` when it's stored inline
+    pub fn into_heap(self) -> Option<Vec<u8>> {
+        match self.repr {
+            Repr::Inline { .. } => None,
+            Repr::Heap(vec) => Some(vec),
+        }
+    }
+}
+
+impl Default for SmallPayload {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for SmallPayload {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl DerefMut for SmallPayload {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.as_mut_slice()
+    }
+}
+
+impl AsRef<[u8]> for SmallPayload {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl fmt::Debug for SmallPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
+
+impl PartialEq for SmallPayload {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for SmallPayload {}
+
+impl PartialEq<[u8]> for SmallPayload {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl PartialEq<&[u8]> for SmallPayload {
+    fn eq(&self, other: &&[u8]) -> bool {
+        self.as_slice() == *other
+    }
+}
+
+impl PartialEq<Vec<u8>> for SmallPayload {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<const N: usize> PartialEq<[u8; N]> for SmallPayload {
+    fn eq(&self, other: &[u8; N]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl From<Vec<u8>> for SmallPayload {
+    fn from(vec: Vec<u8>) -> Self {
+        SmallPayload {
+            repr: Repr::Heap(vec),
+        }
+    }
+}
+
+impl From<&[u8]> for SmallPayload {
+    fn from(bytes: &[u8]) -> Self {
+        Self::from_slice(bytes)
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for SmallPayload {
+    fn from(bytes: [u8; N]) -> Self {
+        Self::from_slice(&bytes)
+    }
+}
+
+impl From<SmallPayload> for Vec<u8> {
+    fn from(payload: SmallPayload) -> Self {
+        payload.into_vec()
+    }
+}
+
+impl FromIterator<u8> for SmallPayload {
+    fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Self {
+        let mut payload = SmallPayload::new();
+        for byte in iter {
+            payload.extend_from_slice(&[byte]);
+        }
+        payload
+    }
+}
@@ -0,0 +1,84 @@
+use alloc::vec::Vec;
+
+use crate::message::Payload;
+
+// Fixed set of reusable payload buffers. Buffers are allocated once up front; `take` checks one
+// out for a send and `put` hands it back once the receiver is done with the payload. With the
+// pool sized for the traffic, steady state operation never touches the heap.
+//
+// An empty pool doesn't fail a send. `take` falls back to a fresh allocation and counts a
+// miss, so `misses()` staying at 0 tells you the pool is big enough.
+pub struct PayloadPool {
+    free: Vec<Vec<u8>>,
+    buffers: usize,
+    buffer_len: usize,
+    misses: usize,
+}
+
+impl PayloadPool {
+    // `buffers` buffers of `buffer_len` bytes capacity each
+    pub fn new(buffers: usize, buffer_len: usize) -> Self {
+        PayloadPool {
+            free: (0..buffers)
+                .map(|_| Vec::with_capacity(buffer_len))
+                .collect(),
+            buffers,
+            buffer_len,
+            misses: 0,
+        }
+    }
+
+    // An empty buffer with at least `buffer_len` bytes of capacity
+    pub fn take(&mut self) -> Vec<u8> {
+        match self.free.pop() {
+            Some(buffer) => buffer,
+            None => {
+                self.misses += 1;
+                Vec::with_capacity(self.buffer_len)
+            }
+        }
+    }
+
+    // Give a buffer back. It's cleared and kept if the pool has a free slot and the buffer is
+    // still big enough, anything else (extra buffers from misses, shrunk ones) is dropped.
+    pub fn put(&mut self, mut buffer: Vec<u8>) {
+        if self.free.len() < self.buffers && buffer.capacity() >= self.buffer_len {
+            buffer.clear();
+            self.free.push(buffer);
+        }
+    }
+
+    // Give back the payload of a received message, inline ones have nothing to give back
+    #[cfg(not(feature = "bytes"))]
+    pub fn put_payload(&mut self, payload: Payload) {
+        if let Some(buffer) = payload.into_heap() {
+            self.put(buffer);
+        }
+    }
+
+    // Only payloads nobody else holds a reference to can be reused
+    #[cfg(feature = "bytes")]
+    pub fn put_payload(&mut self, payload: Payload) {
+        if let Ok(buffer) = payload.try_into_mut() {
+            self.put(buffer.into());
+        }
+    }
+
+    // buffers ready to be checked out
+    pub fn available(&self) -> usize {
+        self.free.len()
+    }
+
+    pub fn buffers(&self) -> usize {
+        self.buffers
+    }
+
+    pub fn buffer_len(&self) -> usize {
+        self.buffer_len
+    }
+
+    // times `take` found the pool empty and had to allocate
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+}
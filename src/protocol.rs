@@ -0,0 +1,1242 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::ack::{self, AckTracker, DeliveryStatus, NackReason, Reply};
+use crate::buffer::{BufferEvent, CircularBuffer, OverflowPolicy, Watermarks};
+use crate::checksum::{ChecksumAlgorithm, Xor8};
+use crate::codec::Codec;
+use crate::control::{self, Control};
+use crate::credit::{CreditReceiver, CreditSender};
+use crate::error::ProtocolError;
+use crate::fragment::{self, Reassembler};
+use crate::message::{
+    FormatVersion, MAX_PAYLOAD_LEN, Message, MessageKind, Payload, Priority, flags,
+};
+use crate::pool::PayloadPool;
+use crate::reorder::ReorderBuffer;
+use crate::retransmit::{OrderedReceiver, RetransmitPolicy, SendWindow, Verdict};
+use crate::sequence::{self, DuplicateFilter, Gap, SequenceTracker};
+use crate::stats::Stats;
+use crate::time::{self, Clock};
+
+// What `receive_into` tells about the message it copied out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceivedHeader {
+    pub id: u16,
+    pub flags: u8,
+    pub kind: MessageKind,
+    pub priority: Priority,
+    // payload bytes written to the start of the caller's buffer
+    pub len: usize,
+    pub valid: bool,
+}
+
+pub struct CommunicationProtocol {
+    shared_buffer: CircularBuffer,
+    next_message: u16,
+    codec: Codec,
+    // payload bytes per message before we start fragmenting, `None` = never fragment
+    mtu: Option<usize>,
+    reassembler: Reassembler,
+    clock: Box<dyn Clock + Send + Sync>,
+    pool: Option<PayloadPool>,
+    // received but didn't fit the buffer passed to `receive_into`, handed out next time
+    held: Option<(Message, bool)>,
+    // delivery status of what MCU1 sent, `None` = acks are off
+    acks: Option<AckTracker>,
+    // copies of what MCU2 hasn't acked yet, `None` = no retransmission
+    window: Option<SendWindow>,
+    // MCU2's place in the sequence under Go-Back-N / selective repeat
+    in_order: Option<OrderedReceiver>,
+    // puts messages back in id order without retransmission, `None` = hand them on as read
+    reorder: Option<ReorderBuffer>,
+    // credit flow control, what MCU1 may send and what MCU2 has granted
+    credit_sender: Option<CreditSender>,
+    credit_receiver: Option<CreditReceiver>,
+    // MCU2's check for ids that never arrived, `None` = gap detection is off
+    sequence: Option<SequenceTracker>,
+    // ids MCU2 delivered lately, `None` = duplicates aren't looked for
+    duplicates: Option<DuplicateFilter>,
+    // MCU2 side of XON/XOFF, whether it last sent an XOFF. `None` = XON/XOFF is off
+    xoff_sent: Option<bool>,
+    // MCU1 side, an XOFF came in and no XON since
+    paused: bool,
+    // sealed messages MCU1 holds back while paused, in order
+    outbox: VecDeque<Message>,
+    // acks on their way back from MCU2 to MCU1
+    replies: VecDeque<Message>,
+    // MCU2 -> MCU1 data, the way back for status and responses
+    return_buffer: CircularBuffer,
+    next_return: u16,
+    return_reassembler: Reassembler,
+    stats: Stats,
+}
+
+impl CommunicationProtocol {
+    pub fn new(buffer_capacity: usize) -> Self {
+        Self::with_checksum(buffer_capacity, Xor8)
+    }
+
+    // protocol instance that seals and verifies every message with `checksum`
+    pub fn with_checksum<C>(buffer_capacity: usize, checksum: C) -> Self
+    where
+        C: ChecksumAlgorithm + Send + Sync + 'static,
+    {
+        CommunicationProtocol {
+            shared_buffer: CircularBuffer::new(buffer_capacity, OverflowPolicy::default()),
+            next_message: 1,
+            codec: Codec::new(checksum, FormatVersion::default()),
+            mtu: None,
+            reassembler: Reassembler::default(),
+            clock: time::default_clock(),
+            pool: None,
+            held: None,
+            acks: None,
+            window: None,
+            in_order: None,
+            reorder: None,
+            credit_sender: None,
+            credit_receiver: None,
+            sequence: None,
+            duplicates: None,
+            xoff_sent: None,
+            paused: false,
+            outbox: VecDeque::new(),
+            replies: VecDeque::new(),
+            return_buffer: CircularBuffer::new(buffer_capacity, OverflowPolicy::default()),
+            next_return: 1,
+            return_reassembler: Reassembler::default(),
+            stats: Stats::new(),
+        }
+    }
+
+    // pick the wire format revision, V1 for peers that only checksum the payload
+    pub fn with_format(mut self, version: FormatVersion) -> Self {
+        self.codec.set_version(version);
+        self
+    }
+
+    // what happens when either MCU sends into a full buffer, defaults to dropping the oldest
+    // message
+    pub fn with_overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.shared_buffer.set_overflow_policy(policy);
+        self.return_buffer.set_overflow_policy(policy);
+        self
+    }
+
+    // also cap both buffers by total queued payload bytes, not just message count
+    pub fn with_byte_budget(mut self, budget: usize) -> Self {
+        // nothing queued yet, so this can't evict or fail
+        let _ = self.shared_buffer.set_byte_budget(Some(budget));
+        let _ = self.return_buffer.set_byte_budget(Some(budget));
+        self
+    }
+
+    // get told when the shared buffer is close to full (and when it's recovered), e.g. to
+    // throttle the producer on MCU1
+    pub fn with_watermarks<F>(mut self, watermarks: Watermarks, callback: F) -> Self
+    where
+        F: FnMut(BufferEvent) + Send + 'static,
+    {
+        self.shared_buffer.on_event(callback);
+        self.shared_buffer.set_watermarks(watermarks);
+        self
+    }
+
+    // split payloads bigger than `mtu` bytes into fragments, they get put back together on
+    // the receive side before `mcu2_receive` hands them out (needs FormatVersion::V2)
+    pub fn with_mtu(mut self, mtu: usize) -> Self {
+        self.mtu = Some(mtu);
+        self
+    }
+
+    // how long a half received fragmented payload is kept around
+    pub fn with_reassembly_timeout(mut self, timeout: Duration) -> Self {
+        self.reassembler.set_timeout(timeout);
+        self.return_reassembler.set_timeout(timeout);
+        self
+    }
+
+    // time source for timeouts, defaults to the system monotonic clock (`NoClock` without std)
+    pub fn with_clock<C>(mut self, clock: C) -> Self
+    where
+        C: Clock + Send + Sync + 'static,
+    {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    // Preallocate `buffers` payload buffers of `buffer_len` bytes. Check one out with
+    // `payload_buffer`, fill it and send it, then hand received messages back with `recycle`
+    // and the payload memory goes round in circles instead of through the allocator.
+    pub fn with_payload_pool(mut self, buffers: usize, buffer_len: usize) -> Self {
+        self.pool = Some(PayloadPool::new(buffers, buffer_len));
+        self
+    }
+
+    // MCU2 answers every message with an ACK or a NACK (see `ack`), MCU1 picks them up with
+    // `mcu1_poll` and `delivery_status` says what happened to each id
+    pub fn with_acks(mut self) -> Self {
+        self.acks = Some(AckTracker::default());
+        self
+    }
+
+    // Keep what MCU1 sends until MCU2 acks it and send it again when the ack doesn't come
+    // in time, see `retransmit`. Turns acks on. Sends fail with `BufferFull` while the send
+    // window is full, and `mcu1_poll` has to be called regularly to drive the timers.
+    // Under Go-Back-N send everything at one priority, the buffer hands higher priorities out
+    // first and MCU2 would throw away whatever overtook the rest. Keep the window within the
+    // buffer capacity too, or going back pushes the oldest messages out of the buffer again.
+    pub fn with_retransmission(mut self, policy: RetransmitPolicy) -> Self {
+        self.window = Some(SendWindow::new(policy));
+        self.in_order = policy
+            .mode
+            .is_ordered()
+            .then(|| OrderedReceiver::new(policy.mode, policy.window));
+        // remember at least every id that can be in the window at once
+        self.acks = Some(AckTracker::new(ack::DEFAULT_HISTORY.max(policy.window)));
+        // a retry whose original made it after all is a duplicate
+        let window = sequence::DEFAULT_DUPLICATE_WINDOW.max(policy.window * 2);
+        self.duplicates = Some(DuplicateFilter::new(window));
+        self
+    }
+
+    // Have MCU2 drop copies of messages among the last `window` it delivered, so each one
+    // comes out of `mcu2_receive` once (counted in `Stats::duplicates`). Copies still get
+    // acked, the sender is waiting on that. On by default with retransmission.
+    pub fn with_duplicate_suppression(mut self, window: usize) -> Self {
+        self.duplicates = Some(DuplicateFilter::new(window));
+        self
+    }
+
+    // MCU1 only sends what MCU2 has granted credit for (see `credit`), one credit per message
+    // in the buffer, so the buffer never overflows and the overflow policy never kicks in.
+    // Sends out of credit fail with `BufferFull`, MCU2 grants more as it reads and MCU1 picks
+    // the grants up in `mcu1_poll`.
+    pub fn with_credits(mut self) -> Self {
+        let capacity = self.shared_buffer.capacity().min(u16::MAX as usize) as u16;
+        let mut receiver = CreditReceiver::new(capacity);
+        let mut sender = CreditSender::new();
+        // both ends live here, the first grant doesn't have to go through the reply queue
+        if let Some(limit) = receiver.grant() {
+            sender.grant(limit);
+        }
+        self.credit_sender = Some(sender);
+        self.credit_receiver = Some(receiver);
+        self
+    }
+
+    // Software flow control: MCU2 sends an XOFF when the shared buffer fills up to
+    // `watermarks.high` and an XON once it's drained back down to `watermarks.low`. While
+    // paused MCU1 keeps what it sends in an outbox (up to the buffer capacity, then
+    // `BufferFull`) and `mcu1_poll` hands it on after the XON. MCU1 only hears about the XOFF
+    // in `mcu1_poll`, leave room above `high` for what it sends until then. Replaces the
+    // watermarks of `with_watermarks`, its callback still fires.
+    pub fn with_xon_xoff(mut self, watermarks: Watermarks) -> Self {
+        self.shared_buffer.set_watermarks(watermarks);
+        self.xoff_sent = Some(false);
+        self
+    }
+
+    // Have MCU2 hand messages out in id order even when they come in out of order, e.g. over
+    // several paths. Up to `window` messages wait behind a gap, for at most `timeout`, then
+    // the gap is given up on. That also undoes priorities, a message that overtook others in
+    // the buffer waits for them. Go-Back-N and selective repeat deliver in order already,
+    // this does nothing with those.
+    pub fn with_in_order_delivery(mut self, window: usize, timeout: Duration) -> Self {
+        self.reorder = Some(ReorderBuffer::starting_at(
+            window,
+            timeout,
+            self.next_message,
+        ));
+        self
+    }
+
+    // Have MCU2 check the ids it reads for continuity and keep track of the ones skipped, e.g.
+    // overwritten by DropOldest. See `take_gaps`. Ids overtaken by a higher priority message
+    // only count once they're no longer queued, retracted ones (`mcu1_cancel`) count as lost.
+    // With retransmission on a gap may still get filled by a retry later.
+    pub fn with_gap_detection(mut self) -> Self {
+        self.sequence = Some(SequenceTracker::starting_at(self.next_message));
+        self
+    }
+
+    pub fn pool(&self) -> Option<&PayloadPool> {
+        self.pool.as_ref()
+    }
+
+    // Empty buffer to build the next payload in, from the pool if there is one
+    pub fn payload_buffer(&mut self) -> Vec<u8> {
+        match self.pool.as_mut() {
+            Some(pool) => pool.take(),
+            None => Vec::new(),
+        }
+    }
+
+    // Done with a received message, its payload memory goes back to the pool. Without a pool
+    // this just drops it.
+    pub fn recycle(&mut self, message: Message) {
+        if let Some(pool) = self.pool.as_mut() {
+            pool.put_payload(message.payload);
+        }
+    }
+
+    pub fn checksum(&self) -> &dyn ChecksumAlgorithm {
+        self.codec.checksum()
+    }
+
+    pub fn codec(&self) -> &Codec {
+        &self.codec
+    }
+
+    pub fn mtu(&self) -> Option<usize> {
+        self.mtu
+    }
+
+    // Returns the id the receiver will see the message under. Payloads above the MTU go out
+    // as several fragments and use up one id each.
+    pub fn mcu1_send(&mut self, payload: impl Into<Payload>) -> Result<u16, ProtocolError> {
+        self.mcu1_send_with_priority(payload, Priority::default())
+    }
+
+    // Same as `mcu1_send`, higher priorities overtake whatever is already queued
+    pub fn mcu1_send_with_priority(
+        &mut self,
+        payload: impl Into<Payload>,
+        priority: Priority,
+    ) -> Result<u16, ProtocolError> {
+        let payload = payload.into();
+        if let Some(mtu) = self.mtu
+            && payload.len() > mtu
+        {
+            return self.send_fragmented(&payload, priority, mtu);
+        }
+
+        if payload.len() > MAX_PAYLOAD_LEN {
+            return Err(ProtocolError::PayloadTooLarge {
+                len: payload.len(),
+                max: MAX_PAYLOAD_LEN,
+            });
+        }
+
+        self.check_window()?;
+        self.check_credit(1)?;
+        let message = self
+            .codec
+            .seal_with(self.next_message, self.sync_flag(), priority, payload);
+        let message_id = self.next_message;
+        let len = message.payload.len();
+
+        let copy = self.window.is_some().then(|| message.clone());
+        self.transmit(message)?;
+        self.next_message = self.next_message.wrapping_add(1);
+        self.record_sent(message_id, len);
+        self.keep_for_retransmit(message_id, copy.into_iter().collect());
+
+        log!("MCU1 message sent- ID {}", message_id);
+        Ok(message_id)
+    }
+
+    // Send a payload the caller doesn't own as a `Vec`, e.g. a slice of a static buffer. It's
+    // copied into a buffer from the payload pool (see `with_payload_pool`), so with a pool
+    // sized for the traffic there's no allocation per send.
+    pub fn mcu1_send_slice(&mut self, payload: &[u8]) -> Result<u16, ProtocolError> {
+        self.mcu1_send_slice_with_priority(payload, Priority::default())
+    }
+
+    pub fn mcu1_send_slice_with_priority(
+        &mut self,
+        payload: &[u8],
+        priority: Priority,
+    ) -> Result<u16, ProtocolError> {
+        // fragments get their own storage anyway, no point copying the whole thing first
+        if let Some(mtu) = self.mtu
+            && payload.len() > mtu
+        {
+            return self.send_fragmented(payload, priority, mtu);
+        }
+
+        // small enough to sit inline in the message, no buffer needed at all
+        #[cfg(not(feature = "bytes"))]
+        if payload.len() <= crate::payload::INLINE_CAPACITY {
+            return self.mcu1_send_with_priority(crate::message::copy_payload(payload), priority);
+        }
+
+        let mut buffer = self.payload_buffer();
+        buffer.extend_from_slice(payload);
+        self.mcu1_send_with_priority(buffer, priority)
+    }
+
+    fn send_fragmented(
+        &mut self,
+        payload: &[u8],
+        priority: Priority,
+        mtu: usize,
+    ) -> Result<u16, ProtocolError> {
+        if self.codec.version() == FormatVersion::V1 {
+            return Err(ProtocolError::PayloadTooLarge {
+                len: payload.len(),
+                max: mtu,
+            });
+        }
+
+        self.check_window()?;
+        let message_id = self.next_message;
+        let mut fragments = fragment::fragment(&self.codec, message_id, priority, payload, mtu)?;
+        let count = fragments.len();
+        self.check_credit(count)?;
+        let sync = self.sync_flag();
+        if sync != 0 {
+            fragments[0].flags |= sync;
+            self.codec.reseal(&mut fragments[0]);
+        }
+
+        // don't leave half a payload behind in the buffer when it can't take all of it, a
+        // payload with more fragments than the buffer holds can never go through whole
+        let capacity = self.shared_buffer.capacity();
+        if count > capacity {
+            return Err(ProtocolError::PayloadTooLarge {
+                len: payload.len(),
+                max: capacity * mtu.saturating_sub(fragment::INDEX_LEN),
+            });
+        }
+        let bytes = fragments.iter().map(|m| m.payload.len()).sum();
+        if self.is_holding() {
+            if self.outbox.len() + count > capacity {
+                return Err(ProtocolError::BufferFull);
+            }
+        } else if !self.shared_buffer.can_take(count, bytes, priority) {
+            return Err(ProtocolError::BufferFull);
+        }
+
+        let copies = if self.window.is_some() {
+            fragments.clone()
+        } else {
+            Vec::new()
+        };
+        for message in fragments {
+            self.transmit(message)?;
+            self.next_message = self.next_message.wrapping_add(1);
+        }
+        self.record_sent(message_id, payload.len());
+        self.keep_for_retransmit(message_id, copies);
+
+        log!("MCU1 message sent- ID {} ({} fragments)", message_id, count);
+        Ok(message_id)
+    }
+
+    // Retract a message MCU1 queued earlier that MCU2 hasn't read yet. For a fragmented
+    // payload every fragment still queued is pulled, the first one is returned.
+    pub fn mcu1_cancel(&mut self, id: u16) -> Option<Message> {
+        let fragments: Vec<u16> = self
+            .shared_buffer
+            .iter()
+            .filter(|m| m.is_fragment() && fragment::payload_id(m) == Some(id))
+            .map(|m| m.id)
+            .collect();
+
+        if fragments.is_empty() {
+            return self.shared_buffer.remove(id);
+        }
+
+        let mut first = None;
+        for fragment_id in fragments {
+            let removed = self.shared_buffer.remove(fragment_id);
+            if first.is_none() {
+                first = removed;
+            }
+        }
+        first
+    }
+
+    // Valid flag covers the whole message under V2, so a corrupted id is caught too.
+    // Fragments are collected internally, the rebuilt payload comes out once it's complete.
+    // With acks on, whole payloads are acked and anything thrown away is nacked.
+    pub fn mcu2_receive(&mut self) -> Option<(Message, bool)> {
+        if let Some(held) = self.held.take() {
+            return Some(held);
+        }
+
+        let now = self.clock.now();
+        self.reassembler.expire(now);
+
+        while let Some((message, valid_checksum)) = self.next_incoming() {
+            if valid_checksum && message.is_fragment() {
+                match self.reassembler.push(&self.codec, &message, now) {
+                    Ok(Some(message)) => {
+                        if self.is_duplicate(&message) {
+                            self.recycle(message);
+                            continue;
+                        }
+                        log!("MCU2 message received with valid ID {}", message.id);
+                        self.record_received(&message);
+                        self.ack(message.id);
+                        return Some((message, true));
+                    }
+                    Ok(None) => {
+                        // the reassembler copied what it needed
+                        self.recycle(message);
+                        continue;
+                    }
+                    // fragment header is garbage, report it like any other corrupted message
+                    Err(_) => {
+                        log!("MCU2 corrupted ID found {}", message.id);
+                        let id = fragment::payload_id(&message).unwrap_or(message.id);
+                        self.reply(ack::nack(&self.codec, id, NackReason::InvalidFragment));
+                        self.stats.invalid_fragments += 1;
+                        return Some((message, false));
+                    }
+                }
+            }
+
+            if valid_checksum {
+                if self.is_duplicate(&message) {
+                    self.recycle(message);
+                    continue;
+                }
+                log!("MCU2 message received with valid ID {}", message.id);
+                self.record_received(&message);
+                self.ack(message.id);
+            } else {
+                log!("MCU2 corrupted ID found {}", message.id);
+                self.stats.checksum_failures += 1;
+                // a corrupted fragment still names its payload if the index survived
+                let id = fragment::payload_id(&message).unwrap_or(message.id);
+                self.reply(ack::nack(&self.codec, id, NackReason::Checksum));
+            }
+
+            return Some((message, valid_checksum));
+        }
+
+        None
+    }
+
+    // MCU2 -> MCU1, e.g. status or the response to a request. Same format, checksum and MTU
+    // as the other way, with its own ids. The way back is plain for now: no acks,
+    // retransmission or flow control.
+    pub fn mcu2_send(&mut self, payload: impl Into<Payload>) -> Result<u16, ProtocolError> {
+        self.mcu2_send_with_priority(payload, Priority::default())
+    }
+
+    pub fn mcu2_send_with_priority(
+        &mut self,
+        payload: impl Into<Payload>,
+        priority: Priority,
+    ) -> Result<u16, ProtocolError> {
+        let payload = payload.into();
+        let id = self.next_return;
+        let len = payload.len();
+
+        if let Some(mtu) = self.mtu
+            && len > mtu
+        {
+            if self.codec.version() == FormatVersion::V1 {
+                return Err(ProtocolError::PayloadTooLarge { len, max: mtu });
+            }
+            let fragments = fragment::fragment(&self.codec, id, priority, &payload, mtu)?;
+            let count = fragments.len();
+            // same as `send_fragmented`, all of the fragments go in or none
+            let capacity = self.return_buffer.capacity();
+            if count > capacity {
+                return Err(ProtocolError::PayloadTooLarge {
+                    len,
+                    max: capacity * mtu.saturating_sub(fragment::INDEX_LEN),
+                });
+            }
+            let bytes = fragments.iter().map(|m| m.payload.len()).sum();
+            if !self.return_buffer.can_take(count, bytes, priority) {
+                return Err(ProtocolError::BufferFull);
+            }
+            for message in fragments {
+                self.return_buffer.send_message(message)?;
+            }
+            self.next_return = id.wrapping_add(count as u16);
+            log!("MCU2 message sent- ID {} ({} fragments)", id, count);
+        } else {
+            if len > MAX_PAYLOAD_LEN {
+                return Err(ProtocolError::PayloadTooLarge {
+                    len,
+                    max: MAX_PAYLOAD_LEN,
+                });
+            }
+            let message = self.codec.seal_with(id, 0, priority, payload);
+            self.return_buffer.send_message(message)?;
+            self.next_return = id.wrapping_add(1);
+            log!("MCU2 message sent- ID {}", id);
+        }
+
+        self.stats.messages_sent += 1;
+        self.stats.bytes_sent += len as u64;
+        Ok(id)
+    }
+
+    // What MCU2 sent back, put together and checked like on `mcu2_receive`. Acks and control
+    // messages don't come out here, `mcu1_poll` takes care of those.
+    pub fn mcu1_receive(&mut self) -> Option<(Message, bool)> {
+        let now = self.clock.now();
+        self.return_reassembler.expire(now);
+
+        while let Some(message) = self.return_buffer.receive_message() {
+            let valid_checksum = self.codec.verify(&message);
+            if valid_checksum && message.is_fragment() {
+                match self.return_reassembler.push(&self.codec, &message, now) {
+                    Ok(Some(message)) => {
+                        log!("MCU1 message received with valid ID {}", message.id);
+                        self.record_received(&message);
+                        return Some((message, true));
+                    }
+                    Ok(None) => {
+                        self.recycle(message);
+                        continue;
+                    }
+                    Err(_) => {
+                        log!("MCU1 corrupted ID found {}", message.id);
+                        self.stats.invalid_fragments += 1;
+                        return Some((message, false));
+                    }
+                }
+            }
+
+            if valid_checksum {
+                log!("MCU1 message received with valid ID {}", message.id);
+                self.record_received(&message);
+            } else {
+                log!("MCU1 corrupted ID found {}", message.id);
+                self.stats.checksum_failures += 1;
+            }
+            return Some((message, valid_checksum));
+        }
+
+        None
+    }
+
+    // Next message for `mcu2_receive` to look at with its checksum checked, put in sequence
+    // first under the ordered retransmission modes
+    fn next_incoming(&mut self) -> Option<(Message, bool)> {
+        loop {
+            if let Some(message) = self.in_order.as_mut().and_then(OrderedReceiver::pop_ready) {
+                return Some((message, true));
+            }
+            let reorder = self.reorder.as_mut().filter(|_| self.in_order.is_none());
+            if let Some(reorder) = reorder {
+                reorder.poll(self.clock.now());
+                if let Some(message) = reorder.pop() {
+                    return Some((message, true));
+                }
+            }
+            let message = self.shared_buffer.receive_message()?;
+            self.free_credit();
+            self.update_xon_xoff();
+            let valid_checksum = self.codec.verify(&message);
+            if valid_checksum && message.kind().is_internal() {
+                self.handle_internal(message);
+                continue;
+            }
+            // a corrupted id says nothing about what's missing
+            if valid_checksum {
+                self.check_sequence(&message);
+            }
+            if valid_checksum
+                && self.in_order.is_none()
+                && let Some(reorder) = self.reorder.as_mut()
+            {
+                reorder.push(message, self.clock.now());
+                continue;
+            }
+            let Some(in_order) = self.in_order.as_mut().filter(|_| valid_checksum) else {
+                return Some((message, valid_checksum));
+            };
+
+            match in_order.accept(message) {
+                Verdict::Deliver(message) => return Some((message, true)),
+                Verdict::Held { ack } => {
+                    if let Some(id) = ack {
+                        self.reply(ack::ack(&self.codec, id));
+                    }
+                }
+                Verdict::Dropped { message, ack } => {
+                    log!("MCU2 out of order ID {} dropped", message.id);
+                    if let Some(id) = ack {
+                        self.reply(ack::ack(&self.codec, id));
+                    }
+                    self.recycle(message);
+                }
+            }
+        }
+    }
+
+    // A copy of something already delivered, acked again and counted
+    fn is_duplicate(&mut self, message: &Message) -> bool {
+        let Some(duplicates) = self.duplicates.as_mut() else {
+            return false;
+        };
+        if duplicates.check(message.id) {
+            return false;
+        }
+        log!("MCU2 duplicate ID {} dropped", message.id);
+        self.stats.duplicates += 1;
+        self.ack(message.id);
+        true
+    }
+
+    fn check_sequence(&mut self, message: &Message) {
+        let Some(sequence) = self.sequence.as_mut() else {
+            return;
+        };
+        // MCU1 started the sequence over
+        if message.flags & flags::SYNC != 0 {
+            sequence.reset();
+        }
+        let buffer = &self.shared_buffer;
+        let lost = sequence.observe(message.id, |id| buffer.iter().any(|m| m.id == id));
+        if lost > 0 {
+            log!("MCU2 {} message(s) missing before ID {}", lost, message.id);
+        }
+        self.stats.messages_lost += lost as u64;
+    }
+
+    // Runs of ids MCU2 found missing since the last call, oldest first. Empty without gap
+    // detection.
+    pub fn take_gaps(&mut self) -> Vec<Gap> {
+        self.sequence
+            .as_mut()
+            .map_or_else(Vec::new, SequenceTracker::take_gaps)
+    }
+
+    // MCU2 took a message out of the buffer, that's room for one more
+    fn free_credit(&mut self) {
+        let Some(credit) = self.credit_receiver.as_mut() else {
+            return;
+        };
+        credit.consumed(1);
+        // an empty buffer grants right away, or a payload with more fragments than the credit
+        // left would wait for a batch that never comes
+        if self.shared_buffer.is_empty() {
+            credit.regrant();
+        }
+        if let Some(limit) = credit.grant() {
+            self.send_control(Control::Credit { limit });
+        }
+    }
+
+    // Like `mcu2_receive` but the payload is copied straight into `out` (e.g. a DMA-safe
+    // static buffer) and the message memory goes back to the pool, nothing gets allocated.
+    // If the payload is longer than `out` you get `PayloadTooLarge` and the message stays
+    // put, so retry with a bigger buffer or pick it up via `mcu2_receive`.
+    pub fn receive_into(&mut self, out: &mut [u8]) -> Result<ReceivedHeader, ProtocolError> {
+        let (message, valid) = self.mcu2_receive().ok_or(ProtocolError::BufferEmpty)?;
+
+        let len = message.payload.len();
+        if len > out.len() {
+            self.held = Some((message, valid));
+            return Err(ProtocolError::PayloadTooLarge {
+                len,
+                max: out.len(),
+            });
+        }
+
+        out[..len].copy_from_slice(&message.payload);
+        let header = ReceivedHeader {
+            id: message.id,
+            flags: message.flags,
+            kind: message.kind(),
+            priority: message.priority,
+            len,
+            valid,
+        };
+        self.recycle(message);
+        Ok(header)
+    }
+
+    // Queue a message that's already sealed, e.g. one just decoded off the wire, as is. It
+    // gets verified and reassembled on the way out through `mcu2_receive` like anything else.
+    pub fn enqueue(&mut self, message: Message) -> Result<(), ProtocolError> {
+        self.shared_buffer.send_message(message)
+    }
+
+    // Next queued message exactly as it sits in the buffer, fragments one by one and nothing
+    // verified. For handing MCU1's traffic to a transport instead of a local MCU2.
+    pub fn dequeue(&mut self) -> Option<Message> {
+        self.shared_buffer.receive_message()
+    }
+
+    // MCU1 side: take in the acks and control messages MCU2 sent back and, with
+    // retransmission on, queue again whatever is due. Returns how many acks answered a
+    // message we were still waiting on. A message that ran out of retries comes out as
+    // `DeliveryFailed`, one per call, so keep calling until it's `Ok`.
+    pub fn mcu1_poll(&mut self) -> Result<usize, ProtocolError> {
+        let now = self.clock.now();
+        let mut handled = 0;
+        while let Some(message) = self.replies.pop_front() {
+            // a corrupted reply can't be trusted to name the right message, drop it
+            if !self.codec.verify(&message) {
+                self.stats.checksum_failures += 1;
+                continue;
+            }
+            if control::is_control(&message) {
+                self.handle_control(&message);
+                continue;
+            }
+            let Some(tracker) = self.acks.as_mut() else {
+                continue;
+            };
+            let Some(reply) = ack::parse(&message) else {
+                continue;
+            };
+            let answered = match (reply, self.window.as_mut()) {
+                // with a window a NACK only means "send it again", it's not final
+                (Reply::Nack(id, _), Some(window)) => window.nacked(id, now) as usize,
+                // a Go-Back-N ack covers everything sent before it as well
+                (Reply::Ack(id), Some(window)) => {
+                    let acked = window.acked(id, now);
+                    for &id in &acked {
+                        tracker.handle(Reply::Ack(id));
+                    }
+                    acked.len()
+                }
+                (reply, None) => tracker.handle(reply).is_some() as usize,
+            };
+            match reply {
+                Reply::Ack(_) => self.stats.messages_acked += answered as u64,
+                Reply::Nack(..) => self.stats.messages_nacked += answered as u64,
+            }
+            handled += answered;
+        }
+
+        self.release_outbox();
+
+        // paused, retransmits wait as well
+        let mut failed = None;
+        if let Some(window) = self.window.as_mut().filter(|_| !self.paused) {
+            for message in window.poll(now) {
+                // out of credit or a full buffer just costs the retry, the next timeout tries
+                // again
+                if let Some(credit) = self.credit_sender.as_mut()
+                    && !credit.consume(1)
+                {
+                    continue;
+                }
+                log!("MCU1 retransmitting ID {}", message.id);
+                let _ = self.shared_buffer.send_message(message);
+                self.stats.retransmissions += 1;
+            }
+            failed = window.take_failed();
+        }
+        self.update_xon_xoff();
+
+        if let Some(id) = failed {
+            if let Some(tracker) = self.acks.as_mut() {
+                tracker.fail(id);
+            }
+            self.stats.delivery_failures += 1;
+            return Err(ProtocolError::DeliveryFailed { id });
+        }
+        Ok(handled)
+    }
+
+    // What MCU2 said about message `id`, as of the last `mcu1_poll`. `None` with acks
+    // off, or if `id` is older than the history the tracker keeps.
+    pub fn delivery_status(&self, id: u16) -> Option<DeliveryStatus> {
+        self.acks.as_ref()?.status(id)
+    }
+
+    // Sent messages MCU2 hasn't answered yet
+    pub fn unacked(&self) -> usize {
+        self.acks.as_ref().map_or(0, AckTracker::pending)
+    }
+
+    // Messages MCU1 may send before it runs out of credit, `None` without credits
+    pub fn credit(&self) -> Option<u16> {
+        self.credit_sender.as_ref().map(CreditSender::available)
+    }
+
+    // MCU2 told MCU1 to stop sending (XOFF) and hasn't said go ahead yet
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // Messages MCU1 is holding back until the XON
+    pub fn held_back(&self) -> usize {
+        self.outbox.len()
+    }
+
+    // Hand MCU1 an ack or control message that came in over a transport, the counterpart of
+    // `dequeue_reply`
+    pub fn enqueue_reply(&mut self, message: Message) {
+        self.replies.push_back(message);
+    }
+
+    // Next ack or control message MCU2 produced, for sending it back over a transport instead
+    // of to a local MCU1
+    pub fn dequeue_reply(&mut self) -> Option<Message> {
+        self.replies.pop_front()
+    }
+
+    fn handle_control(&mut self, message: &Message) {
+        match Control::parse(message) {
+            Ok(Control::Credit { limit }) => {
+                if let Some(credit) = self.credit_sender.as_mut() {
+                    credit.grant(limit);
+                }
+            }
+            Ok(Control::Pause) => self.paused = true,
+            Ok(Control::Resume) => self.paused = false,
+            // connections are an `Endpoint` thing
+            Ok(_) => {}
+            // not for us, or from a newer peer
+            Err(_) => {}
+        }
+    }
+
+    // Protocol messages that came in with the data, kept away from the application
+    fn handle_internal(&mut self, message: Message) {
+        match message.kind() {
+            // only says MCU1 is still there
+            MessageKind::Heartbeat => {}
+            // acks and control messages go MCU2 -> MCU1, nothing to do with them this way round
+            kind => {
+                log!("MCU2: ignoring {:?} with ID {}", kind, message.id);
+            }
+        }
+        self.recycle(message);
+    }
+
+    fn send_control(&mut self, control: Control) {
+        let message = control.to_message(&self.codec);
+        self.replies.push_back(message);
+    }
+
+    // Into the buffer, or into the outbox while paused. Once something is held back the rest
+    // queues up behind it to keep the order.
+    fn transmit(&mut self, message: Message) -> Result<(), ProtocolError> {
+        if self.is_holding() {
+            if self.outbox.len() >= self.shared_buffer.capacity() {
+                return Err(ProtocolError::BufferFull);
+            }
+            self.outbox.push_back(message);
+            return Ok(());
+        }
+        self.shared_buffer.send_message(message)?;
+        self.use_credit(1);
+        self.update_xon_xoff();
+        Ok(())
+    }
+
+    fn is_holding(&self) -> bool {
+        self.paused || !self.outbox.is_empty()
+    }
+
+    // Hand on what was held back, as far as credit and buffer allow
+    fn release_outbox(&mut self) {
+        while !self.paused
+            && let Some(next) = self.outbox.front()
+        {
+            let out_of_credit = self
+                .credit_sender
+                .as_ref()
+                .is_some_and(|credit| credit.available() == 0);
+            let no_room = !self
+                .shared_buffer
+                .can_take(1, next.payload.len(), next.priority);
+            if out_of_credit || no_room {
+                break;
+            }
+            if let Some(message) = self.outbox.pop_front() {
+                // room was checked
+                let _ = self.shared_buffer.send_message(message);
+                self.use_credit(1);
+                self.update_xon_xoff();
+            }
+        }
+    }
+
+    // MCU2 side, XOFF once the buffer is past the high watermark, XON once it's back down
+    fn update_xon_xoff(&mut self) {
+        let Some(xoff_sent) = self.xoff_sent else {
+            return;
+        };
+        let above = self.shared_buffer.is_above_high_watermark();
+        if above != xoff_sent {
+            self.xoff_sent = Some(above);
+            self.send_control(if above {
+                Control::Pause
+            } else {
+                Control::Resume
+            });
+        }
+    }
+
+    // Sends need credit for every message they put in the buffer, and for what's held back
+    // ahead of them
+    fn check_credit(&self, count: usize) -> Result<(), ProtocolError> {
+        match &self.credit_sender {
+            Some(credit) if (credit.available() as usize) < self.outbox.len() + count => {
+                Err(ProtocolError::BufferFull)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn use_credit(&mut self, count: usize) {
+        if let Some(credit) = self.credit_sender.as_mut() {
+            credit.consume(count as u16);
+        }
+    }
+
+    // Sends wait for room in the send window, not just in the buffer
+    fn check_window(&self) -> Result<(), ProtocolError> {
+        match &self.window {
+            Some(window) if window.is_full() => Err(ProtocolError::BufferFull),
+            _ => Ok(()),
+        }
+    }
+
+    fn keep_for_retransmit(&mut self, id: u16, messages: Vec<Message>) {
+        let now = self.clock.now();
+        if let Some(window) = self.window.as_mut() {
+            // room was checked before sending
+            let _ = window.push(id, messages, now);
+        }
+    }
+
+    fn ack(&mut self, id: u16) {
+        if let Some(in_order) = self.in_order.as_mut() {
+            in_order.acked(id);
+        }
+        self.reply(ack::ack(&self.codec, id));
+    }
+
+    // `flags::SYNC` for the next message if the in order receiver needs to pick up the
+    // sequence from it
+    fn sync_flag(&self) -> u8 {
+        match &self.window {
+            Some(window) if window.needs_sync() => flags::SYNC,
+            _ => 0,
+        }
+    }
+
+    fn reply(&mut self, message: Message) {
+        if self.acks.is_some() {
+            self.replies.push_back(message);
+        }
+    }
+
+    fn record_sent(&mut self, id: u16, len: usize) {
+        self.stats.messages_sent += 1;
+        self.stats.bytes_sent += len as u64;
+        if let Some(tracker) = self.acks.as_mut() {
+            tracker.track(id);
+        }
+    }
+
+    fn record_received(&mut self, message: &Message) {
+        self.stats.messages_received += 1;
+        self.stats.bytes_received += message.payload.len() as u64;
+    }
+
+    // Protocol level counters (whole payloads, not fragments), both directions together.
+    // Overflow drops and rejected sends come from the two buffers, the high watermark from
+    // the MCU1 -> MCU2 one. The round trip time is MCU1's, with retransmission.
+    pub fn stats(&self) -> Stats {
+        let buffer = self.shared_buffer.stats();
+        let back = self.return_buffer.stats();
+        let mut stats = Stats {
+            dropped_overflow: buffer.dropped_overflow + back.dropped_overflow,
+            rejected_full: buffer.rejected_full + back.rejected_full,
+            high_watermark: buffer.high_watermark,
+            ..self.stats
+        };
+        if let Some(window) = self.window.as_ref() {
+            stats.set_rtt(window.rtt(), window.rto());
+        }
+        stats
+    }
+
+    // Messages waiting for MCU2 to put them in order, `None` without in-order delivery
+    pub fn reorder_buffer(&self) -> Option<&ReorderBuffer> {
+        self.reorder.as_ref()
+    }
+
+    // Read-only view of the queue underneath
+    pub fn buffer(&self) -> &CircularBuffer {
+        &self.shared_buffer
+    }
+
+    // Same for the MCU2 -> MCU1 way back
+    pub fn return_buffer(&self) -> &CircularBuffer {
+        &self.return_buffer
+    }
+
+    // Counters of the shared buffer itself, fragments counted one by one
+    pub fn buffer_stats(&self) -> Stats {
+        self.shared_buffer.stats()
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::new();
+        self.shared_buffer.reset_stats();
+        self.return_buffer.reset_stats();
+    }
+
+    // Whether a `len` byte payload fits in the buffer right now without evicting anything,
+    // counting the fragments it turns into above the MTU
+    pub fn has_room_for_payload(&self, len: usize) -> bool {
+        let (count, bytes) = self.queued_size(len);
+        self.shared_buffer.has_room(count, bytes)
+    }
+
+    // Whether `has_room_for_payload` can ever say yes, even with the buffer empty
+    pub fn can_ever_fit(&self, len: usize) -> bool {
+        let (count, bytes) = self.queued_size(len);
+        count <= self.shared_buffer.capacity()
+            && self
+                .shared_buffer
+                .byte_budget()
+                .is_none_or(|budget| bytes <= budget)
+    }
+
+    // (messages, payload bytes) a `len` byte payload takes up once queued
+    fn queued_size(&self, len: usize) -> (usize, usize) {
+        match self.mtu {
+            Some(mtu) if len > mtu => {
+                let count = fragment::fragment_count(len, mtu);
+                (count, len + count * fragment::INDEX_LEN)
+            }
+            _ => (1, len),
+        }
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.shared_buffer.overflow_policy()
+    }
+
+    // Something for `mcu2_receive` to look at, a held message or anything in the buffer
+    pub fn has_pending(&self) -> bool {
+        self.held.is_some()
+            || !self.shared_buffer.is_empty()
+            || self
+                .in_order
+                .as_ref()
+                .is_some_and(|in_order| in_order.ready() > 0)
+            || self
+                .reorder
+                .as_ref()
+                .is_some_and(|reorder| reorder.ready() > 0)
+    }
+
+    // (length, empty, full)
+    pub fn get_buffer_status(&self) -> (usize, bool, bool) {
+        (
+            self.shared_buffer.length(),
+            self.shared_buffer.is_empty(),
+            self.shared_buffer.is_full(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn fragmented_payload_arrives_whole() {
+        let mut protocol = CommunicationProtocol::new(8).with_mtu(16);
+        let id = protocol.mcu1_send(payload(60)).unwrap();
+        assert!(protocol.buffer().length() > 1);
+
+        let (message, valid) = protocol.mcu2_receive().unwrap();
+        assert!(valid);
+        assert_eq!(message.id, id);
+        assert_eq!(&message.payload[..], &payload(60)[..]);
+        assert!(protocol.mcu2_receive().is_none());
+    }
+
+    #[test]
+    fn more_fragments_than_the_buffer_holds_are_refused() {
+        let mut protocol = CommunicationProtocol::new(4).with_mtu(16);
+        assert_eq!(
+            protocol.mcu1_send(payload(200)),
+            Err(ProtocolError::PayloadTooLarge { len: 200, max: 56 })
+        );
+        assert!(protocol.buffer().is_empty());
+    }
+
+    #[test]
+    fn fragments_dont_evict_each_other_under_drop_oldest() {
+        let mut protocol = CommunicationProtocol::new(4).with_mtu(16);
+        for _ in 0..3 {
+            protocol
+                .mcu1_send_with_priority(vec![0xAA], Priority::High)
+                .unwrap();
+        }
+        // three fragments, only one slot they may take
+        assert_eq!(
+            protocol.mcu1_send(payload(40)),
+            Err(ProtocolError::BufferFull)
+        );
+        assert_eq!(protocol.buffer().length(), 3);
+        assert!(
+            protocol
+                .buffer()
+                .iter()
+                .all(|message| message.priority == Priority::High)
+        );
+    }
+
+    #[test]
+    fn credits_hold_mcu1_back_until_mcu2_reads() {
+        let mut protocol = CommunicationProtocol::new(4).with_credits();
+        for _ in 0..4 {
+            protocol.mcu1_send(vec![1]).unwrap();
+        }
+        assert_eq!(protocol.credit(), Some(0));
+        assert_eq!(protocol.mcu1_send(vec![1]), Err(ProtocolError::BufferFull));
+        assert_eq!(protocol.buffer_stats().dropped_overflow, 0);
+
+        // half the window read frees a grant, MCU1 sees it on its next poll
+        protocol.mcu2_receive().unwrap();
+        protocol.mcu2_receive().unwrap();
+        protocol.mcu1_poll().unwrap();
+        assert_eq!(protocol.credit(), Some(2));
+        protocol.mcu1_send(vec![1]).unwrap();
+    }
+
+    #[test]
+    fn mcu2_fragmented_reply_arrives_whole() {
+        let mut protocol = CommunicationProtocol::new(8).with_mtu(16);
+        let id = protocol.mcu2_send(payload(60)).unwrap();
+
+        let (message, valid) = protocol.mcu1_receive().unwrap();
+        assert!(valid);
+        assert_eq!(message.id, id);
+        assert_eq!(&message.payload[..], &payload(60)[..]);
+    }
+
+    #[test]
+    fn mcu2_refuses_what_the_return_buffer_cant_hold_whole() {
+        let mut protocol = CommunicationProtocol::new(4).with_mtu(16);
+        assert_eq!(
+            protocol.mcu2_send(payload(200)),
+            Err(ProtocolError::PayloadTooLarge { len: 200, max: 56 })
+        );
+        assert!(protocol.return_buffer().is_empty());
+
+        for _ in 0..3 {
+            protocol
+                .mcu2_send_with_priority(vec![0xAA], Priority::High)
+                .unwrap();
+        }
+        assert_eq!(
+            protocol.mcu2_send(payload(40)),
+            Err(ProtocolError::BufferFull)
+        );
+        assert_eq!(protocol.return_buffer().length(), 3);
+    }
+}
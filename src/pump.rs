@@ -0,0 +1,105 @@
+// Tokio tasks that move messages between `AsyncProtocol`s and a byte stream, e.g. a serial
+// port or the TCP end of a serial-to-TCP bridge. Framing is done here, the protocols only
+// ever see whole messages:
+//
+//   let outgoing = AsyncProtocol::new(CommunicationProtocol::new(16));
+//   let incoming = AsyncProtocol::new(CommunicationProtocol::new(16));
+//   let stream = TcpStream::connect("192.168.1.20:4000").await?;
+//   let pump = pump::spawn(stream, Cobs::default(), outgoing.clone(), incoming.clone());
+//
+//   outgoing.send(b"ping".to_vec()).await?;
+//   let (reply, valid) = incoming.receive().await;
+//
+// Whatever MCU1 queues on `outgoing` is written out as is (fragments included), every frame
+// read off the stream is queued on `incoming`, where `receive` verifies and reassembles it.
+// Both protocols need the same codec settings as the MCU on the other end.
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::task::JoinHandle;
+
+use crate::asynch::AsyncProtocol;
+use crate::framing::{FrameDecoder, Framing};
+
+// bytes read from the transport per `read` call
+const READ_CHUNK: usize = 512;
+
+// Write every message queued on `protocol` to `writer`, one frame each. Messages that can't
+// be framed (too long) are dropped. Runs until writing fails.
+pub async fn write_loop<W, F>(protocol: AsyncProtocol, framing: F, mut writer: W) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+    F: Framing,
+{
+    loop {
+        let message = protocol.dequeue().await;
+        let Ok(frame) = protocol.with(|p| framing.encode_message(p.codec(), &message)) else {
+            continue;
+        };
+        writer.write_all(&frame).await?;
+        writer.flush().await?;
+    }
+}
+
+// Decode frames from `reader` and queue them on `protocol`. Frames that don't decode are
+// dropped (the decoder counts them as resyncs). Returns `Ok` once the reader hits EOF.
+pub async fn read_loop<R, F>(protocol: AsyncProtocol, framing: F, mut reader: R) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    F: Framing,
+{
+    let mut decoder = framing.decoder();
+    let mut chunk = [0u8; READ_CHUNK];
+    loop {
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        decoder.feed(&chunk[..read]);
+
+        while let Some(decoded) = protocol.with(|p| decoder.next_message(p.codec())) {
+            let Ok(message) = decoded else {
+                continue;
+            };
+            // a full queue under `Block` stops us reading, so the backpressure reaches the
+            // transport instead of piling up here. Refused under `RejectNew` it's dropped,
+            // same as a local send would be.
+            let _ = protocol.enqueue(message).await;
+        }
+    }
+}
+
+// Handles to the two tasks started by `spawn`
+pub struct Pump {
+    pub reader: JoinHandle<io::Result<()>>,
+    pub writer: JoinHandle<io::Result<()>>,
+}
+
+impl Pump {
+    // Stop both tasks
+    pub fn abort(&self) {
+        self.reader.abort();
+        self.writer.abort();
+    }
+}
+
+// Split `transport` and spawn `write_loop` for `outgoing` and `read_loop` for `incoming` on the
+// current tokio runtime.
+pub fn spawn<T, F>(
+    transport: T,
+    framing: F,
+    outgoing: AsyncProtocol,
+    incoming: AsyncProtocol,
+) -> Pump
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+    F: Framing + Clone + Send + 'static,
+    F::Decoder: Send,
+{
+    let (reader, writer) = tokio::io::split(transport);
+    Pump {
+        reader: tokio::spawn(read_loop(incoming, framing.clone(), reader)),
+        writer: tokio::spawn(write_loop(outgoing, framing, writer)),
+    }
+}
@@ -0,0 +1,141 @@
+use core::time::Duration;
+
+// Send rate limiting, so a producer that bursts can't take all of a slow link: a token
+// bucket for messages per second and / or one for bytes per second. Each fills up at its
+// rate to at most `burst`, and a frame only goes out when both have enough for it:
+//
+//   // 115200 baud is ~11 KB/s, leave a quarter of it for everything else
+//   let endpoint = Endpoint::new(uart, Cobs::default(), 16)
+//       .with_rate_limit(RateLimit::new().bytes_per_second(8_000, 512));
+//
+// An `Endpoint` only holds back data frames (they wait in their queue), control messages
+// always go straight out. Retransmissions aren't held back either, they'd only be late, but
+// they use up the budget like anything else.
+//
+// A frame larger than the whole burst goes out when the bucket is full and empties it,
+// rather than never going out at all.
+
+// Bucket contents are kept in millionths so slow rates still fill up on a fast poll loop
+const SCALE: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenBucket {
+    // tokens per second
+    rate: u32,
+    burst: u32,
+    // scaled by `SCALE`
+    tokens: u64,
+    // `None` until the first refill
+    last_refill: Option<Duration>,
+}
+
+impl TokenBucket {
+    // Starts out full
+    pub fn new(rate: u32, burst: u32) -> Self {
+        let burst = burst.max(1);
+        TokenBucket {
+            rate,
+            burst,
+            tokens: burst as u64 * SCALE,
+            last_refill: None,
+        }
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn burst(&self) -> u32 {
+        self.burst
+    }
+
+    // Whole tokens in the bucket right now
+    pub fn available(&self) -> u32 {
+        (self.tokens / SCALE) as u32
+    }
+
+    // Add what came in since the last refill
+    pub fn refill(&mut self, now: Duration) {
+        let elapsed = self
+            .last_refill
+            .map_or(Duration::ZERO, |last| now.saturating_sub(last));
+        self.last_refill = Some(now);
+        let added = (self.rate as u64).saturating_mul(elapsed.as_micros() as u64);
+        self.tokens = self
+            .tokens
+            .saturating_add(added)
+            .min(self.burst as u64 * SCALE);
+    }
+
+    // Whether `amount` can be taken, a full bucket always has room
+    pub fn has(&self, amount: usize) -> bool {
+        let full = self.burst as u64 * SCALE;
+        self.tokens == full || self.tokens >= (amount as u64).saturating_mul(SCALE)
+    }
+
+    // Take `amount`, down to empty if there isn't that much
+    pub fn take(&mut self, amount: usize) {
+        self.tokens = self
+            .tokens
+            .saturating_sub((amount as u64).saturating_mul(SCALE));
+    }
+}
+
+// Budgets for sending, unlimited until one is set
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    messages: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+}
+
+impl RateLimit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // At most `rate` frames a second, `burst` back to back
+    pub fn messages_per_second(mut self, rate: u32, burst: u32) -> Self {
+        self.messages = Some(TokenBucket::new(rate, burst));
+        self
+    }
+
+    // At most `rate` bytes a second on the wire, `burst` back to back
+    pub fn bytes_per_second(mut self, rate: u32, burst: u32) -> Self {
+        self.bytes = Some(TokenBucket::new(rate, burst));
+        self
+    }
+
+    pub fn messages(&self) -> Option<&TokenBucket> {
+        self.messages.as_ref()
+    }
+
+    pub fn bytes(&self) -> Option<&TokenBucket> {
+        self.bytes.as_ref()
+    }
+
+    // Whether a frame of `len` bytes may go out now. Doesn't use anything up, see `charge`.
+    pub fn allows(&mut self, len: usize, now: Duration) -> bool {
+        let mut allowed = true;
+        if let Some(messages) = self.messages.as_mut() {
+            messages.refill(now);
+            allowed &= messages.has(1);
+        }
+        if let Some(bytes) = self.bytes.as_mut() {
+            bytes.refill(now);
+            allowed &= bytes.has(len);
+        }
+        allowed
+    }
+
+    // A frame of `len` bytes went out
+    pub fn charge(&mut self, len: usize, now: Duration) {
+        if let Some(messages) = self.messages.as_mut() {
+            messages.refill(now);
+            messages.take(1);
+        }
+        if let Some(bytes) = self.bytes.as_mut() {
+            bytes.refill(now);
+            bytes.take(len);
+        }
+    }
+}
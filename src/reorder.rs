@@ -0,0 +1,171 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::message::{Message, flags};
+use crate::sequence::is_ahead;
+
+// Puts messages that arrive out of order (several paths, a reordering line, a transport
+// that retries on its own) back into id order before they're handed on. Unlike the ordered
+// retransmission modes nothing gets resent here, so a message that's really gone can't hold
+// everything up for good: once `window` messages are waiting behind a gap, or the oldest has
+// waited `timeout`, the gap is skipped.
+//
+// Ids are per message, fragments included, so this runs before reassembly. Messages that
+// show up behind the sequence after their gap was skipped are dropped as late.
+
+// How many messages can wait behind a gap by default
+pub const DEFAULT_REORDER_WINDOW: usize = 16;
+// How long they wait by default
+pub const DEFAULT_REORDER_TIMEOUT: Duration = Duration::from_millis(50);
+
+#[derive(Debug)]
+struct Waiting {
+    message: Message,
+    since: Duration,
+}
+
+#[derive(Debug)]
+pub struct ReorderBuffer {
+    window: usize,
+    timeout: Duration,
+    // next id to hand on, `None` until the first message
+    expected: Option<u16>,
+    // ahead of a gap
+    waiting: Vec<Waiting>,
+    // next to hand on, in order
+    ready: VecDeque<Message>,
+    skipped: u64,
+    late: u64,
+}
+
+impl ReorderBuffer {
+    pub fn new(window: usize, timeout: Duration) -> Self {
+        ReorderBuffer {
+            window: window.max(1),
+            timeout,
+            expected: None,
+            waiting: Vec::new(),
+            ready: VecDeque::new(),
+            skipped: 0,
+            late: 0,
+        }
+    }
+
+    // When the first id is known up front, so the very first messages get reordered too
+    pub fn starting_at(window: usize, timeout: Duration, expected: u16) -> Self {
+        ReorderBuffer {
+            expected: Some(expected),
+            ..Self::new(window, timeout)
+        }
+    }
+
+    // Sort a (checksum verified) message in, whatever is in order now comes out of `pop`
+    pub fn push(&mut self, message: Message, now: Duration) {
+        // the sender started over, what's waiting goes out as it is
+        if message.flags & flags::SYNC != 0 {
+            self.flush();
+            self.expected = Some(message.id);
+        }
+        let expected = *self.expected.get_or_insert(message.id);
+
+        if message.id != expected && !is_ahead(message.id, expected) {
+            log!("Reorder: late ID {} dropped", message.id);
+            self.late += 1;
+            return;
+        }
+        if !self.waiting.iter().any(|w| w.message.id == message.id) {
+            self.waiting.push(Waiting {
+                message,
+                since: now,
+            });
+        }
+        self.release();
+        while self.waiting.len() > self.window {
+            self.skip();
+        }
+    }
+
+    // Skip gaps that have been waited on for `timeout`
+    pub fn poll(&mut self, now: Duration) {
+        while self
+            .waiting
+            .iter()
+            .any(|w| now.saturating_sub(w.since) >= self.timeout)
+        {
+            self.skip();
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Message> {
+        self.ready.pop_front()
+    }
+
+    // Messages waiting behind a gap
+    pub fn waiting(&self) -> usize {
+        self.waiting.len()
+    }
+
+    // In order and waiting for `pop`
+    pub fn ready(&self) -> usize {
+        self.ready.len()
+    }
+
+    // Ids given up on
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    // Messages dropped because they came after their gap was skipped
+    pub fn late(&self) -> u64 {
+        self.late
+    }
+
+    pub fn expected(&self) -> Option<u16> {
+        self.expected
+    }
+
+    // Everything waiting goes out, gaps and all
+    pub fn flush(&mut self) {
+        while !self.waiting.is_empty() {
+            self.skip();
+        }
+    }
+
+    // Jump the gap in front of the oldest waiting id
+    fn skip(&mut self) {
+        let Some(expected) = self.expected else {
+            return;
+        };
+        let Some(next) = self
+            .waiting
+            .iter()
+            .map(|w| w.message.id)
+            .min_by_key(|id| id.wrapping_sub(expected))
+        else {
+            return;
+        };
+        let gap = next.wrapping_sub(expected);
+        log!("Reorder: skipping {} missing before ID {}", gap, next);
+        self.skipped += gap as u64;
+        self.expected = Some(next);
+        self.release();
+    }
+
+    // Move waiting messages that are next in line over to `ready`
+    fn release(&mut self) {
+        while let Some(expected) = self.expected
+            && let Some(position) = self.waiting.iter().position(|w| w.message.id == expected)
+        {
+            self.ready
+                .push_back(self.waiting.swap_remove(position).message);
+            self.expected = Some(expected.wrapping_add(1));
+        }
+    }
+}
+
+impl Default for ReorderBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_REORDER_WINDOW, DEFAULT_REORDER_TIMEOUT)
+    }
+}
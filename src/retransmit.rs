@@ -0,0 +1,720 @@
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::error::ProtocolError;
+use crate::message::{Message, flags};
+use crate::round_trip::RttEstimator;
+
+// Retransmission on top of `ack`: every message sent is kept in the send window until the
+// receiver acks it. If no ack comes within the timeout it goes out again, up to
+// `max_retries` times, then it's dropped from the window and reported as a delivery failure.
+// A NACK doesn't wait for the timeout, the message is resent on the next poll (and that
+// counts as a retry too).
+//
+// Fragmented payloads sit in the window as one entry under the payload id, all fragments go
+// out again together.
+//
+// With a backoff factor above 1 the wait grows after every retry, `timeout`, then
+// `timeout * factor`, `timeout * factor^2` ... up to `max_timeout`, so a receiver that's
+// stuck for a while isn't buried in copies of the same message:
+//
+//   RetransmitPolicy::exponential(Duration::from_millis(50), 2, Duration::from_secs(1))
+//
+// With `adaptive` set (the default) the wait for an ack follows the round trip time measured
+// on the acks that come back, see `round_trip`: `timeout` is only the first guess, after that it's
+// the estimated RTO, kept between `min_timeout` and `max_timeout`, and backoff multiplies
+// that.
+//
+// `ArqMode::GoBackN` is the classic sliding window: up to `window` messages in flight, the
+// receiver only takes them in id order (`OrderedReceiver`) and acks cumulatively, an ack for
+// an id covers everything sent before it. Anything out of order is thrown away and answered
+// with the last ack again. When the oldest message times out (or gets nacked) it and
+// everything sent after it goes out again.
+//
+// `ArqMode::SelectiveRepeat` is for lossy links where going back wastes too much: messages
+// are acked and resent one by one like `Individual`, and the receiver keeps whatever arrives
+// ahead of a gap (up to `window` ids ahead) and hands it out in order once the gap is filled.
+//
+// In both ordered modes giving up on one message gives up on the whole window, the receiver
+// can't hand out anything past it. The next message sent carries `flags::SYNC` and the
+// receiver picks up the sequence from there, releasing whatever it was still holding; the
+// first message sent carries it too.
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+pub const DEFAULT_MAX_RETRIES: u8 = 3;
+// messages that can be waiting for an ack at the same time
+pub const DEFAULT_WINDOW: usize = 16;
+pub const DEFAULT_MAX_TIMEOUT: Duration = Duration::from_secs(2);
+pub const DEFAULT_MIN_TIMEOUT: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArqMode {
+    // every message is acked, timed and resent on its own
+    #[default]
+    Individual,
+    // cumulative acks, in order delivery, a loss resends the whole window from there
+    GoBackN,
+    // acked one by one, in order delivery, the receiver holds what arrives ahead of a gap
+    SelectiveRepeat,
+}
+
+impl ArqMode {
+    // Whether the receiver only hands messages out in sequence
+    pub fn is_ordered(self) -> bool {
+        matches!(self, ArqMode::GoBackN | ArqMode::SelectiveRepeat)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetransmitPolicy {
+    // wait before the first retry, and the base of the backoff
+    pub timeout: Duration,
+    pub max_retries: u8,
+    pub window: usize,
+    // the wait is multiplied by this after every retry, 1 = fixed interval
+    pub backoff_factor: u32,
+    // the wait never grows past this
+    pub max_timeout: Duration,
+    pub mode: ArqMode,
+    // wait as long as the measured round trip time says, `timeout` is the first guess
+    pub adaptive: bool,
+    // an adaptive wait never drops below this
+    pub min_timeout: Duration,
+}
+
+impl RetransmitPolicy {
+    // Exponential backoff starting at `base`, growing by `factor` up to `cap`
+    pub fn exponential(base: Duration, factor: u32, cap: Duration) -> Self {
+        RetransmitPolicy {
+            timeout: base,
+            backoff_factor: factor,
+            max_timeout: cap,
+            ..Self::default()
+        }
+    }
+
+    // Go-Back-N with up to `window` messages in flight
+    pub fn go_back_n(window: usize) -> Self {
+        RetransmitPolicy {
+            window,
+            mode: ArqMode::GoBackN,
+            ..Self::default()
+        }
+    }
+
+    // Selective repeat with up to `window` messages in flight
+    pub fn selective_repeat(window: usize) -> Self {
+        RetransmitPolicy {
+            window,
+            mode: ArqMode::SelectiveRepeat,
+            ..Self::default()
+        }
+    }
+
+    // How long to wait for an ack after the `retries`th retry (0 = the first send), without
+    // a measured round trip time
+    pub fn timeout_after(&self, retries: u8) -> Duration {
+        self.backoff(self.timeout, retries)
+    }
+
+    // Same, starting from `base` instead of `timeout`
+    pub fn backoff(&self, base: Duration, retries: u8) -> Duration {
+        let factor = self
+            .backoff_factor
+            .max(1)
+            .checked_pow(retries as u32)
+            .unwrap_or(u32::MAX);
+        let cap = self.max_timeout.max(base);
+        base.checked_mul(factor)
+            .map_or(cap, |timeout| timeout.min(cap))
+    }
+}
+
+impl Default for RetransmitPolicy {
+    fn default() -> Self {
+        RetransmitPolicy {
+            timeout: DEFAULT_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            window: DEFAULT_WINDOW,
+            backoff_factor: 1,
+            max_timeout: DEFAULT_MAX_TIMEOUT,
+            mode: ArqMode::Individual,
+            adaptive: true,
+            min_timeout: DEFAULT_MIN_TIMEOUT,
+        }
+    }
+}
+
+struct Unacked {
+    id: u16,
+    // the message, or all fragments of the payload
+    messages: Vec<Message>,
+    deadline: Duration,
+    retries: u8,
+    // when it first went out, `None` once it's gone out again and its ack can't be timed
+    sent: Option<Duration>,
+}
+
+pub struct SendWindow {
+    unacked: VecDeque<Unacked>,
+    policy: RetransmitPolicy,
+    // ids that ran out of retries, not reported yet
+    failed: VecDeque<u16>,
+    // the next message has to carry `flags::SYNC`
+    needs_sync: bool,
+    // id after the last one pushed
+    next_id: u16,
+    rtt: RttEstimator,
+}
+
+impl SendWindow {
+    pub fn new(policy: RetransmitPolicy) -> Self {
+        SendWindow {
+            unacked: VecDeque::new(),
+            policy: RetransmitPolicy {
+                window: policy.window.max(1),
+                ..policy
+            },
+            failed: VecDeque::new(),
+            needs_sync: policy.mode.is_ordered(),
+            next_id: 0,
+            rtt: RttEstimator::new(),
+        }
+    }
+
+    pub fn policy(&self) -> RetransmitPolicy {
+        self.policy
+    }
+
+    // Round trip time measured so far
+    pub fn rtt(&self) -> RttEstimator {
+        self.rtt
+    }
+
+    // How long a message sent now waits for its ack before the first retry
+    pub fn rto(&self) -> Duration {
+        self.timeout_after(0)
+    }
+
+    // Messages waiting for an ack
+    pub fn len(&self) -> usize {
+        self.unacked.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unacked.is_empty()
+    }
+
+    // No room for another message until something gets acked (or gives up). Under selective
+    // repeat the window is a range of ids from the oldest unacked one, fragments counted one
+    // by one, the receiver doesn't hold anything further ahead than that.
+    pub fn is_full(&self) -> bool {
+        match (self.policy.mode, self.unacked.front()) {
+            (ArqMode::SelectiveRepeat, Some(oldest)) => {
+                self.next_id.wrapping_sub(oldest.id) as usize >= self.policy.window
+            }
+            _ => self.unacked.len() >= self.policy.window,
+        }
+    }
+
+    // Id of the oldest message still waiting for an ack
+    pub fn oldest(&self) -> Option<u16> {
+        self.unacked.front().map(|entry| entry.id)
+    }
+
+    // Whether the next message sent has to carry `flags::SYNC`, until it's pushed
+    pub fn needs_sync(&self) -> bool {
+        self.needs_sync
+    }
+
+    // `messages` just went out under `id`, keep a copy until it's acked. `BufferFull` if
+    // the window is full, send it later.
+    pub fn push(
+        &mut self,
+        id: u16,
+        messages: Vec<Message>,
+        now: Duration,
+    ) -> Result<(), ProtocolError> {
+        if self.is_full() {
+            return Err(ProtocolError::BufferFull);
+        }
+        // a fragmented payload uses up one id per fragment
+        self.next_id = id.wrapping_add(messages.len().max(1) as u16);
+        self.unacked.push_back(Unacked {
+            id,
+            messages,
+            deadline: now + self.timeout_after(0),
+            retries: 0,
+            sent: Some(now),
+        });
+        self.needs_sync = false;
+        Ok(())
+    }
+
+    // Receiver acked `id` at `now`, it's done (under Go-Back-N so is everything before it).
+    // Returns the ids that just left the window, empty if `id` wasn't in it.
+    pub fn acked(&mut self, id: u16, now: Duration) -> Vec<u16> {
+        let Some(position) = self.unacked.iter().position(|entry| entry.id == id) else {
+            return Vec::new();
+        };
+        if let Some(sent) = self.unacked[position].sent {
+            self.rtt.sample(now.saturating_sub(sent));
+        }
+        match self.policy.mode {
+            ArqMode::Individual | ArqMode::SelectiveRepeat => {
+                self.unacked.remove(position);
+                vec![id]
+            }
+            ArqMode::GoBackN => self
+                .unacked
+                .drain(..=position)
+                .map(|entry| entry.id)
+                .collect(),
+        }
+    }
+
+    // Receiver nacked `id`, send it again right away. Under Go-Back-N the receiver is still
+    // waiting on the oldest message, so that's where it goes back to.
+    pub fn nacked(&mut self, id: u16, now: Duration) -> bool {
+        if !self.unacked.iter().any(|entry| entry.id == id) {
+            return false;
+        }
+        let entry = match self.policy.mode {
+            ArqMode::Individual | ArqMode::SelectiveRepeat => {
+                self.unacked.iter_mut().find(|entry| entry.id == id)
+            }
+            ArqMode::GoBackN => self.unacked.front_mut(),
+        };
+        if let Some(entry) = entry {
+            entry.deadline = now;
+        }
+        true
+    }
+
+    // Messages to send again now. Entries out of retries leave the window and show up in
+    // `take_failed`.
+    pub fn poll(&mut self, now: Duration) -> Vec<Message> {
+        if self.policy.mode == ArqMode::GoBackN {
+            return self.go_back(now);
+        }
+
+        let mut resend = Vec::new();
+        let mut gave_up = false;
+        let policy = self.policy;
+        let base = self.base_timeout();
+        let failed = &mut self.failed;
+        self.unacked.retain_mut(|entry| {
+            if entry.deadline > now {
+                return true;
+            }
+            if entry.retries >= policy.max_retries {
+                failed.push_back(entry.id);
+                gave_up = true;
+                return false;
+            }
+            entry.retries += 1;
+            entry.deadline = now + policy.backoff(base, entry.retries);
+            entry.sent = None;
+            resend.extend(entry.messages.iter().cloned());
+            true
+        });
+
+        if gave_up && policy.mode.is_ordered() {
+            // the receiver holds everything after the lost one, none of it gets through now
+            self.failed
+                .extend(self.unacked.drain(..).map(|entry| entry.id));
+            self.needs_sync = true;
+            resend.clear();
+        }
+        resend
+    }
+
+    // Oldest message timed out: everything in the window goes out again, in order
+    fn go_back(&mut self, now: Duration) -> Vec<Message> {
+        let Some(oldest) = self.unacked.front_mut() else {
+            return Vec::new();
+        };
+        if oldest.deadline > now {
+            return Vec::new();
+        }
+        if oldest.retries >= self.policy.max_retries {
+            // the receiver won't take anything past the one it's missing
+            self.failed
+                .extend(self.unacked.drain(..).map(|entry| entry.id));
+            self.needs_sync = true;
+            return Vec::new();
+        }
+
+        oldest.retries += 1;
+        let retries = oldest.retries;
+        let deadline = now + self.timeout_after(retries);
+        let mut resend = Vec::new();
+        for entry in &mut self.unacked {
+            entry.deadline = deadline;
+            entry.sent = None;
+            resend.extend(entry.messages.iter().cloned());
+        }
+        resend
+    }
+
+    // Next id that ran out of retries
+    pub fn take_failed(&mut self) -> Option<u16> {
+        self.failed.pop_front()
+    }
+
+    // When the next retransmit (or failure) is due
+    pub fn next_deadline(&self) -> Option<Duration> {
+        self.unacked.iter().map(|entry| entry.deadline).min()
+    }
+
+    // Give up on everything still waiting for an ack, oldest first, e.g. on shutdown
+    pub fn drain(&mut self) -> Vec<Message> {
+        let messages = self
+            .unacked
+            .drain(..)
+            .flat_map(|entry| entry.messages)
+            .collect();
+        self.clear();
+        messages
+    }
+
+    // Everything goes, but the round trip time measured so far, the link is still the same
+    pub fn clear(&mut self) {
+        self.unacked.clear();
+        self.failed.clear();
+        self.needs_sync = self.policy.mode.is_ordered();
+    }
+
+    // Wait before the first retry, the estimated RTO when adaptive
+    fn base_timeout(&self) -> Duration {
+        if !self.policy.adaptive {
+            return self.policy.timeout;
+        }
+        let cap = self.policy.max_timeout.max(self.policy.min_timeout);
+        self.rtt
+            .rto(self.policy.timeout)
+            .max(self.policy.min_timeout)
+            .min(cap)
+    }
+
+    fn timeout_after(&self, retries: u8) -> Duration {
+        self.policy.backoff(self.base_timeout(), retries)
+    }
+}
+
+impl Default for SendWindow {
+    fn default() -> Self {
+        Self::new(RetransmitPolicy::default())
+    }
+}
+
+// What `OrderedReceiver::accept` made of a message
+#[derive(Debug)]
+pub enum Verdict {
+    // next in sequence, hand it on
+    Deliver(Message),
+    // ahead of a gap, kept until the gap is filled. Ack `ack` now so the sender doesn't
+    // resend it (fragments are acked once their payload is complete instead).
+    Held { ack: Option<u16> },
+    // not taken. Ack `ack` again if set, the sender must have missed it.
+    Dropped { message: Message, ack: Option<u16> },
+}
+
+// Receive side of the ordered modes: hands messages out strictly in id order, starting from
+// the first one with `flags::SYNC`. Go-Back-N takes only the next id, selective repeat also
+// holds up to `window` ids ahead of it.
+#[derive(Debug)]
+pub struct OrderedReceiver {
+    mode: ArqMode,
+    window: usize,
+    expected: Option<u16>,
+    // last payload acked in order, what Go-Back-N acks again
+    last_acked: Option<u16>,
+    // selective repeat: arrived ahead of a gap
+    held: Vec<Message>,
+    // released from `held`, next to hand out
+    ready: VecDeque<Message>,
+}
+
+impl OrderedReceiver {
+    pub fn new(mode: ArqMode, window: usize) -> Self {
+        OrderedReceiver {
+            mode,
+            window: window.max(1),
+            expected: None,
+            last_acked: None,
+            held: Vec::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    // Sort a (checksum verified) message into the sequence
+    pub fn accept(&mut self, message: Message) -> Verdict {
+        if message.flags & flags::SYNC != 0 {
+            self.resync(message.id);
+        }
+        let Some(expected) = self.expected else {
+            return Verdict::Dropped { message, ack: None };
+        };
+
+        let ahead = message.id.wrapping_sub(expected) as usize;
+        if ahead == 0 {
+            self.expected = Some(expected.wrapping_add(1));
+            if !self.ready.is_empty() {
+                // released messages from before a resync go first
+                self.ready.push_back(message);
+                self.release();
+                return Verdict::Held { ack: None };
+            }
+            self.release();
+            return Verdict::Deliver(message);
+        }
+
+        if self.mode == ArqMode::GoBackN {
+            return Verdict::Dropped {
+                message,
+                ack: self.last_acked,
+            };
+        }
+
+        if ahead < self.window {
+            let ack = (!message.is_fragment()).then_some(message.id);
+            if !self.held.iter().any(|held| held.id == message.id) {
+                self.held.push(message);
+            }
+            return Verdict::Held { ack };
+        }
+
+        // behind us: we had it already and the ack got lost
+        let behind = expected.wrapping_sub(message.id) as usize;
+        let ack = (behind <= self.window && completes_payload(&message))
+            .then(|| crate::fragment::payload_id(&message).unwrap_or(message.id));
+        Verdict::Dropped { message, ack }
+    }
+
+    // Next message released from behind a gap that just got filled
+    pub fn pop_ready(&mut self) -> Option<Message> {
+        self.ready.pop_front()
+    }
+
+    // Next id we'd take, `None` before the first `SYNC`
+    pub fn expected(&self) -> Option<u16> {
+        self.expected
+    }
+
+    // Messages held ahead of a gap
+    pub fn held(&self) -> usize {
+        self.held.len()
+    }
+
+    // Messages released and waiting for `pop_ready`
+    pub fn ready(&self) -> usize {
+        self.ready.len()
+    }
+
+    // `id` was delivered and acked, it's what Go-Back-N acks again on a message out of order
+    pub fn acked(&mut self, id: u16) {
+        self.last_acked = Some(id);
+    }
+
+    pub fn last_acked(&self) -> Option<u16> {
+        self.last_acked
+    }
+
+    // Sender moved on to `id`, whatever we were holding goes out in order, gaps and all
+    fn resync(&mut self, id: u16) {
+        if let Some(expected) = self.expected {
+            self.held.sort_by_key(|held| held.id.wrapping_sub(expected));
+            self.ready.extend(self.held.drain(..));
+        }
+        self.expected = Some(id);
+    }
+
+    // Move held messages that are now next in line over to `ready`
+    fn release(&mut self) {
+        while let Some(expected) = self.expected
+            && let Some(position) = self.held.iter().position(|held| held.id == expected)
+        {
+            self.ready.push_back(self.held.swap_remove(position));
+            self.expected = Some(expected.wrapping_add(1));
+        }
+    }
+}
+
+// Acking this message means the sender can forget its whole window entry: a message on its
+// own, or the last fragment of a payload (which only counts once all of it is in)
+fn completes_payload(message: &Message) -> bool {
+    !message.is_fragment() || message.flags & flags::LAST_FRAGMENT != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    fn policy(mode: ArqMode, window: usize) -> RetransmitPolicy {
+        RetransmitPolicy {
+            timeout: TIMEOUT,
+            max_retries: 1,
+            window,
+            mode,
+            adaptive: false,
+            ..RetransmitPolicy::default()
+        }
+    }
+
+    fn message(id: u16) -> Message {
+        Message::new(id, vec![id as u8])
+    }
+
+    fn synced(id: u16) -> Message {
+        let mut message = message(id);
+        message.flags |= flags::SYNC;
+        message
+    }
+
+    fn push_all(window: &mut SendWindow, ids: core::ops::RangeInclusive<u16>) {
+        for id in ids {
+            window.push(id, vec![message(id)], Duration::ZERO).unwrap();
+        }
+    }
+
+    fn ids(messages: &[Message]) -> Vec<u16> {
+        messages.iter().map(|message| message.id).collect()
+    }
+
+    #[test]
+    fn go_back_n_acks_cumulatively() {
+        let mut window = SendWindow::new(policy(ArqMode::GoBackN, 4));
+        assert!(window.needs_sync());
+        push_all(&mut window, 1..=4);
+        assert!(window.is_full());
+        assert_eq!(
+            window.push(5, vec![message(5)], Duration::ZERO),
+            Err(ProtocolError::BufferFull)
+        );
+
+        assert_eq!(window.acked(3, Duration::ZERO), [1, 2, 3]);
+        assert_eq!(window.oldest(), Some(4));
+        assert!(!window.is_full());
+    }
+
+    #[test]
+    fn go_back_n_resends_the_whole_window_in_order() {
+        let mut window = SendWindow::new(policy(ArqMode::GoBackN, 8));
+        push_all(&mut window, 1..=3);
+        assert!(window.poll(TIMEOUT - Duration::from_millis(1)).is_empty());
+        assert_eq!(ids(&window.poll(TIMEOUT)), [1, 2, 3]);
+
+        // a NACK for any of them goes back to the oldest
+        window.acked(1, TIMEOUT);
+        assert!(window.nacked(3, TIMEOUT));
+        assert_eq!(ids(&window.poll(TIMEOUT)), [2, 3]);
+    }
+
+    #[test]
+    fn go_back_n_gives_up_on_the_whole_window() {
+        let mut window = SendWindow::new(policy(ArqMode::GoBackN, 8));
+        push_all(&mut window, 1..=3);
+        assert_eq!(window.poll(TIMEOUT).len(), 3);
+        assert!(window.poll(2 * TIMEOUT).is_empty());
+
+        let failed: Vec<u16> = core::iter::from_fn(|| window.take_failed()).collect();
+        assert_eq!(failed, [1, 2, 3]);
+        assert!(window.is_empty());
+        assert!(window.needs_sync());
+    }
+
+    #[test]
+    fn go_back_n_receiver_takes_only_the_next_id() {
+        let mut receiver = OrderedReceiver::new(ArqMode::GoBackN, 8);
+        // nothing before the first SYNC
+        assert!(matches!(
+            receiver.accept(message(5)),
+            Verdict::Dropped { ack: None, .. }
+        ));
+
+        assert!(matches!(receiver.accept(synced(1)), Verdict::Deliver(m) if m.id == 1));
+        receiver.acked(1);
+        // out of order, thrown away and answered with the last ack again
+        assert!(matches!(
+            receiver.accept(message(3)),
+            Verdict::Dropped { ack: Some(1), .. }
+        ));
+        assert!(matches!(receiver.accept(message(2)), Verdict::Deliver(m) if m.id == 2));
+        assert_eq!(receiver.expected(), Some(3));
+    }
+
+    #[test]
+    fn selective_repeat_resends_only_what_is_missing() {
+        let mut window = SendWindow::new(policy(ArqMode::SelectiveRepeat, 8));
+        push_all(&mut window, 1..=3);
+        assert_eq!(window.acked(1, Duration::ZERO), [1]);
+        assert_eq!(window.acked(3, Duration::ZERO), [3]);
+        assert_eq!(ids(&window.poll(TIMEOUT)), [2]);
+    }
+
+    #[test]
+    fn selective_repeat_window_is_a_range_of_ids() {
+        let mut window = SendWindow::new(policy(ArqMode::SelectiveRepeat, 4));
+        push_all(&mut window, 1..=4);
+        for id in 2..=4 {
+            window.acked(id, Duration::ZERO);
+        }
+        // only 1 is unacked, but the receiver can't hold anything past 4 until it has it
+        assert_eq!(window.len(), 1);
+        assert!(window.is_full());
+        window.acked(1, Duration::ZERO);
+        assert!(!window.is_full());
+    }
+
+    #[test]
+    fn selective_repeat_gives_up_on_the_window_when_one_fails() {
+        let mut window = SendWindow::new(policy(ArqMode::SelectiveRepeat, 8));
+        push_all(&mut window, 1..=3);
+        window.poll(TIMEOUT);
+        window.acked(2, TIMEOUT);
+        window.poll(2 * TIMEOUT);
+
+        let failed: Vec<u16> = core::iter::from_fn(|| window.take_failed()).collect();
+        assert_eq!(failed.len(), 2);
+        assert!(failed.contains(&1) && failed.contains(&3));
+        assert!(window.needs_sync());
+    }
+
+    #[test]
+    fn selective_repeat_receiver_holds_what_arrives_ahead_of_a_gap() {
+        let mut receiver = OrderedReceiver::new(ArqMode::SelectiveRepeat, 4);
+        assert!(matches!(receiver.accept(synced(1)), Verdict::Deliver(_)));
+        assert!(matches!(
+            receiver.accept(message(3)),
+            Verdict::Held { ack: Some(3) }
+        ));
+        assert!(matches!(
+            receiver.accept(message(4)),
+            Verdict::Held { ack: Some(4) }
+        ));
+        // too far ahead to hold
+        assert!(matches!(
+            receiver.accept(message(9)),
+            Verdict::Dropped { ack: None, .. }
+        ));
+        assert_eq!(receiver.held(), 2);
+
+        assert!(matches!(receiver.accept(message(2)), Verdict::Deliver(m) if m.id == 2));
+        let released: Vec<u16> = core::iter::from_fn(|| receiver.pop_ready())
+            .map(|message| message.id)
+            .collect();
+        assert_eq!(released, [3, 4]);
+        assert_eq!(receiver.expected(), Some(5));
+
+        // a copy of one already delivered gets its ack again
+        assert!(matches!(
+            receiver.accept(message(2)),
+            Verdict::Dropped { ack: Some(2), .. }
+        ));
+    }
+}
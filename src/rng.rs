@@ -0,0 +1,40 @@
+// Small seeded xorshift generator for the simulation transports and the ids a `Connection`
+// starts at. Not for anything that needs real randomness, the point is that the same seed
+// gives the same run every time.
+
+#[derive(Debug, Clone)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) const DEFAULT_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+
+    pub(crate) fn new(seed: u64) -> Self {
+        // xorshift gets stuck on 0
+        Rng(if seed == 0 { Self::DEFAULT_SEED } else { seed })
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    // uniform in 0..bound, 0 if `bound` is 0
+    pub(crate) fn below(&mut self, bound: usize) -> usize {
+        if bound == 0 {
+            return 0;
+        }
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    // true with probability `p` (0.0 never, 1.0 always)
+    pub(crate) fn chance(&mut self, p: f32) -> bool {
+        if p <= 0.0 {
+            return false;
+        }
+        ((self.next_u64() >> 40) as f32) < p * (1u64 << 24) as f32
+    }
+}
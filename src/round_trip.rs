@@ -0,0 +1,67 @@
+use core::time::Duration;
+
+// Round trip time estimate for the retransmit timeout, the way TCP does it (RFC 6298): each
+// ack that comes back gives a sample, from the message going out to its ack arriving, and
+// feeds a smoothed RTT and how much it varies,
+//
+//   srtt   = 7/8 srtt + 1/8 sample
+//   rttvar = 3/4 rttvar + 1/4 |srtt - sample|
+//   rto    = srtt + 4 rttvar
+//
+// The first sample sets srtt to itself and rttvar to half of it. Messages that went out more
+// than once aren't sampled, there's no telling which copy got acked (Karn's algorithm).
+//
+// A `SendWindow` keeps one and waits `rto` for an ack instead of the policy's fixed
+// `timeout`, which only counts until the first sample, see `RetransmitPolicy::adaptive`.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RttEstimator {
+    // `None` until the first sample
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    samples: u64,
+}
+
+impl RttEstimator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // An ack came back `rtt` after its message went out
+    pub fn sample(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                self.rttvar = (self.rttvar * 3 + srtt.abs_diff(rtt)) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+        self.samples += 1;
+    }
+
+    // Smoothed RTT, `None` before the first sample
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    pub fn rttvar(&self) -> Duration {
+        self.rttvar
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    // How long to wait for an ack, `initial` before the first sample
+    pub fn rto(&self, initial: Duration) -> Duration {
+        self.srtt
+            .map_or(initial, |srtt| srtt.saturating_add(self.rttvar * 4))
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
@@ -0,0 +1,287 @@
+// Gateway between links, so a node in the middle (MCU2 between MCU1 on a UART and MCU3 on
+// CAN, say) can pass messages on to nodes it's not the final stop for:
+//
+//   let mut router = Router::new(2);
+//   let uart = router.add_port(Link::new(uart, Cobs::default()).with_codec(v3.clone()));
+//   let can = router.add_port(Link::new(can, Cobs::default()).with_codec(v3));
+//   router.add_route(1, uart);
+//   router.add_route(3, can);
+//   loop {
+//       router.poll()?;
+//       while let Some(message) = router.receive() { /* for MCU2 itself */ }
+//   }
+//
+// Every message read off a port is looked at for its destination (`FormatVersion::V3`, see
+// `address`). Messages for this node, or unaddressed ones, are kept for `receive`; anything
+// else goes out on the port the route table names, resealed with that port's codec so the
+// links don't have to agree on a checksum. Fragments are passed on one by one, reassembly is
+// left to the node at the end. Nothing is sent back out the port it came in on, and without a
+// route (or default route) the message is dropped and counted in `Stats::unroutable`.
+//
+// Broadcasts and multicasts go out on every port but the one they came in on (and are kept
+// here too if this node is in the group). That's fine for a chain or a tree of gateways;
+// with a loop in the topology they'd go round it forever.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use crate::address::{self, Groups, NodeId};
+use crate::codec::Codec;
+use crate::error::ProtocolError;
+use crate::framing::Framing;
+use crate::link::Link;
+use crate::message::{Message, Payload, Priority};
+use crate::stats::Stats;
+use crate::transport::Transport;
+
+// Index of a port in its `Router`
+pub type PortId = usize;
+
+// What the router needs from a link, implemented for `Link` over any transport and framing
+pub trait Port {
+    fn codec(&self) -> &Codec;
+    // Frame and send a message that's already sealed
+    fn send_message(&mut self, message: &Message) -> Result<(), ProtocolError>;
+    fn receive(&mut self) -> Option<Result<Message, ProtocolError>>;
+    fn poll_write(&mut self) -> Result<(), ProtocolError>;
+}
+
+impl<T: Transport, F: Framing> Port for Link<T, F> {
+    fn codec(&self) -> &Codec {
+        Link::codec(self)
+    }
+
+    fn send_message(&mut self, message: &Message) -> Result<(), ProtocolError> {
+        Link::send_message(self, message)
+    }
+
+    fn receive(&mut self) -> Option<Result<Message, ProtocolError>> {
+        Link::receive(self)
+    }
+
+    fn poll_write(&mut self) -> Result<(), ProtocolError> {
+        Link::poll_write(self)
+    }
+}
+
+pub struct Router {
+    address: NodeId,
+    groups: Groups,
+    ports: Vec<Box<dyn Port>>,
+    routes: Vec<(NodeId, PortId)>,
+    default_route: Option<PortId>,
+    // for this node, waiting for `receive`
+    local: VecDeque<Message>,
+    next_message: u16,
+    stats: Stats,
+}
+
+impl Router {
+    pub fn new(address: NodeId) -> Self {
+        Router {
+            address,
+            groups: Groups::new(),
+            ports: Vec::new(),
+            routes: Vec::new(),
+            default_route: None,
+            local: VecDeque::new(),
+            next_message: 1,
+            stats: Stats::new(),
+        }
+    }
+
+    pub fn address(&self) -> NodeId {
+        self.address
+    }
+
+    // Keep messages for multicast group `group` for `receive` as well as passing them on
+    pub fn join_group(&mut self, group: NodeId) {
+        self.groups.join(group);
+    }
+
+    pub fn leave_group(&mut self, group: NodeId) {
+        self.groups.leave(group);
+    }
+
+    pub fn add_port<P>(&mut self, port: P) -> PortId
+    where
+        P: Port + 'static,
+    {
+        self.ports.push(Box::new(port));
+        self.ports.len() - 1
+    }
+
+    // Messages for `destination` go out on `port`, replacing any route it had
+    pub fn add_route(&mut self, destination: NodeId, port: PortId) {
+        self.remove_route(destination);
+        self.routes.push((destination, port));
+    }
+
+    pub fn remove_route(&mut self, destination: NodeId) {
+        self.routes.retain(|&(node, _)| node != destination);
+    }
+
+    // Where messages for nodes without a route of their own go
+    pub fn set_default_route(&mut self, port: Option<PortId>) {
+        self.default_route = port;
+    }
+
+    // Port messages for `destination` leave on
+    pub fn route(&self, destination: NodeId) -> Option<PortId> {
+        self.routes
+            .iter()
+            .find(|&&(node, _)| node == destination)
+            .map(|&(_, port)| port)
+            .or(self.default_route)
+    }
+
+    pub fn port(&self, port: PortId) -> Option<&dyn Port> {
+        self.ports.get(port).map(|port| port.as_ref())
+    }
+
+    pub fn port_mut(&mut self, port: PortId) -> Option<&mut dyn Port> {
+        match self.ports.get_mut(port) {
+            Some(port) => Some(port.as_mut()),
+            None => None,
+        }
+    }
+
+    // Send from this node, out on whichever port `destination` is routed to. Broadcasts and
+    // multicasts go out on every port.
+    pub fn send_to(
+        &mut self,
+        destination: NodeId,
+        payload: impl Into<Payload>,
+    ) -> Result<u16, ProtocolError> {
+        self.send_to_with_priority(destination, payload, Priority::default())
+    }
+
+    pub fn send_to_with_priority(
+        &mut self,
+        destination: NodeId,
+        payload: impl Into<Payload>,
+        priority: Priority,
+    ) -> Result<u16, ProtocolError> {
+        let outputs: Vec<PortId> =
+            if destination == address::BROADCAST || address::is_group(destination) {
+                (0..self.ports.len()).collect()
+            } else {
+                self.route(destination)
+                    .filter(|&port| port < self.ports.len())
+                    .into_iter()
+                    .collect()
+            };
+        if outputs.is_empty() {
+            return Err(ProtocolError::Unroutable { destination });
+        }
+
+        let id = self.next_message;
+        let mut message = Message {
+            id,
+            flags: 0,
+            priority,
+            source: self.address,
+            destination,
+            channel: 0,
+            payload: payload.into(),
+            checksum: 0,
+        };
+        for output in outputs {
+            let port = &mut self.ports[output];
+            port.codec().reseal(&mut message);
+            port.send_message(&message)?;
+        }
+
+        self.next_message = id.wrapping_add(1);
+        self.stats.messages_sent += 1;
+        self.stats.bytes_sent += message.payload.len() as u64;
+        Ok(id)
+    }
+
+    // Read every port, keep what's for this node and forward the rest. Returns how many
+    // messages were forwarded. Corrupted frames are counted and skipped, transport errors
+    // come out as they are (the other ports are still read on the next call).
+    pub fn poll(&mut self) -> Result<usize, ProtocolError> {
+        let mut forwarded = 0;
+        for input in 0..self.ports.len() {
+            self.ports[input].poll_write()?;
+            while let Some(result) = self.ports[input].receive() {
+                let message = match result {
+                    Ok(message) => message,
+                    Err(error @ (ProtocolError::Transport(_) | ProtocolError::Disconnected)) => {
+                        return Err(error);
+                    }
+                    Err(error) => {
+                        self.stats.count_decode_error(&error);
+                        continue;
+                    }
+                };
+                if self.forward(input, message)? {
+                    forwarded += 1;
+                }
+            }
+        }
+        Ok(forwarded)
+    }
+
+    // Next message addressed to this node
+    pub fn receive(&mut self) -> Option<Message> {
+        self.local.pop_front()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::new();
+    }
+
+    fn forward(&mut self, input: PortId, mut message: Message) -> Result<bool, ProtocolError> {
+        let destination = message.destination;
+        if destination == address::UNADDRESSED || destination == self.address {
+            self.keep(message);
+            return Ok(false);
+        }
+
+        if destination == address::BROADCAST || address::is_group(destination) {
+            let mut sent = false;
+            for output in (0..self.ports.len()).filter(|&port| port != input) {
+                let port = &mut self.ports[output];
+                port.codec().reseal(&mut message);
+                port.send_message(&message)?;
+                self.stats.forwarded += 1;
+                sent = true;
+            }
+            if destination == address::BROADCAST || self.groups.contains(destination) {
+                self.keep(message);
+            }
+            return Ok(sent);
+        }
+
+        let Some(output) = self.route(destination).filter(|&port| port != input) else {
+            log!(
+                "Router: no route for ID {} to node {}",
+                message.id,
+                destination
+            );
+            self.stats.unroutable += 1;
+            return Ok(false);
+        };
+        let Some(port) = self.ports.get_mut(output) else {
+            self.stats.unroutable += 1;
+            return Ok(false);
+        };
+        port.codec().reseal(&mut message);
+        port.send_message(&message)?;
+        self.stats.forwarded += 1;
+        Ok(true)
+    }
+
+    fn keep(&mut self, message: Message) {
+        self.stats.messages_received += 1;
+        self.stats.bytes_received += message.payload.len() as u64;
+        self.local.push_back(message);
+    }
+}
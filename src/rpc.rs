@@ -0,0 +1,189 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::channel::ChannelId;
+use crate::error::ProtocolError;
+use crate::message::Payload;
+
+// Request/response on top of channels: the requester gets a handle for each request, and the
+// response that comes back with the same request id is matched to it, no hand rolled
+// conventions in the payload:
+//
+//   let mut mcu2 = Endpoint::new(uart, Cobs::default(), 8)
+//       .with_channel_config(1, ChannelConfig { rpc: true, ..ChannelConfig::reliable(8) })
+//       .on_request(|_, command| Some(run_command(command)));
+//
+//   let pending = mcu1.request(1, b"get temperature")?;
+//   // ... poll ...
+//   if let Some(reply) = mcu1.response(&pending) { ... }
+//
+// A request can have a deadline (`Endpoint::request_with_timeout`), and one that passes
+// without a response resolves the request with `ProtocolError::Timeout`. A request can also
+// be cancelled, which frees it here and, if asked to, tells the other end with a CANCEL
+// (its `on_cancel` handler gets the id). Responses to requests no longer outstanding are
+// dropped.
+//
+// On a channel set up for RPC every payload starts with
+//
+//   | kind: u8 | request_id: u16 | data |
+//
+// `kind` is `REQUEST`, `RESPONSE` or `CANCEL` (no data), and a response or cancel carries
+// the id of the request it's about.
+//
+// A request handler that can't answer straight away returns `None` and answers later with
+// `Endpoint::respond`. Ids are per endpoint and wrap, so only so many requests can be
+// outstanding at once before an old one's id comes round again.
+
+// Matches a response to its request
+pub type RequestId = u16;
+
+pub const REQUEST: u8 = 0x01;
+pub const RESPONSE: u8 = 0x02;
+pub const CANCEL: u8 = 0x03;
+
+// Bytes in front of the data
+pub const HEADER_LEN: usize = 3;
+
+// Gets the id and data of each request, returns the response or `None` to answer later
+pub type RequestHandler = Box<dyn FnMut(RequestId, &[u8]) -> Option<Vec<u8>> + Send>;
+
+// Gets the id of each request the other end cancelled
+pub type CancelHandler = Box<dyn FnMut(RequestId) + Send>;
+
+// Payload for a request / response
+pub fn encode(kind: u8, id: RequestId, data: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(HEADER_LEN + data.len());
+    payload.push(kind);
+    payload.extend_from_slice(&id.to_le_bytes());
+    payload.extend_from_slice(data);
+    payload
+}
+
+// Kind, id and data of a payload, `None` if it's too short
+pub fn decode(payload: &[u8]) -> Option<(u8, RequestId, &[u8])> {
+    let (&[kind, low, high], data) = payload.split_first_chunk::<HEADER_LEN>()?;
+    Some((kind, RequestId::from_le_bytes([low, high]), data))
+}
+
+// A request on its way, see `Endpoint::request`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PendingRequest {
+    channel: ChannelId,
+    id: RequestId,
+}
+
+impl PendingRequest {
+    pub fn channel(&self) -> ChannelId {
+        self.channel
+    }
+
+    pub fn id(&self) -> RequestId {
+        self.id
+    }
+}
+
+struct Outstanding {
+    request: PendingRequest,
+    // resolves with `Timeout` once this passes
+    deadline: Option<Duration>,
+    // `None` until the response is in (or the deadline passed)
+    response: Option<Result<Payload, ProtocolError>>,
+}
+
+// Requester side: what's outstanding and what's been answered
+pub struct Requests {
+    next_id: RequestId,
+    outstanding: Vec<Outstanding>,
+}
+
+impl Requests {
+    pub fn new() -> Self {
+        Requests {
+            next_id: 0,
+            outstanding: Vec::new(),
+        }
+    }
+
+    // Handle for a new request on `channel`, waiting for its response until `deadline`
+    pub fn start(&mut self, channel: ChannelId, deadline: Option<Duration>) -> PendingRequest {
+        let request = PendingRequest {
+            channel,
+            id: self.next_id,
+        };
+        self.next_id = self.next_id.wrapping_add(1);
+        self.outstanding.push(Outstanding {
+            request,
+            deadline,
+            response: None,
+        });
+        request
+    }
+
+    // Forget `request`, answered or not. Returns whether it was still there.
+    pub fn remove(&mut self, request: &PendingRequest) -> bool {
+        let before = self.outstanding.len();
+        self.outstanding.retain(|entry| entry.request != *request);
+        self.outstanding.len() != before
+    }
+
+    // Resolve what's past its deadline with `Timeout`, returns how many
+    pub fn expire(&mut self, now: Duration) -> usize {
+        let mut expired = 0;
+        for entry in &mut self.outstanding {
+            if entry.response.is_none() && entry.deadline.is_some_and(|deadline| now >= deadline) {
+                log!("Rpc: request {} timed out", entry.request.id);
+                entry.response = Some(Err(ProtocolError::Timeout));
+                expired += 1;
+            }
+        }
+        expired
+    }
+
+    // A response came in. Returns whether it answered an outstanding request.
+    pub fn complete(&mut self, channel: ChannelId, id: RequestId, response: Payload) -> bool {
+        let request = PendingRequest { channel, id };
+        match self
+            .outstanding
+            .iter_mut()
+            .find(|entry| entry.request == request && entry.response.is_none())
+        {
+            Some(entry) => {
+                entry.response = Some(Ok(response));
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The response to `request` (or `Timeout`) if it's in, the request is done with then
+    pub fn take(&mut self, request: &PendingRequest) -> Option<Result<Payload, ProtocolError>> {
+        let index = self
+            .outstanding
+            .iter()
+            .position(|entry| entry.request == *request && entry.response.is_some())?;
+        self.outstanding.swap_remove(index).response
+    }
+
+    // Still waiting for its response
+    pub fn is_pending(&self, request: &PendingRequest) -> bool {
+        self.outstanding
+            .iter()
+            .any(|entry| entry.request == *request && entry.response.is_none())
+    }
+
+    // Requests sent and not taken yet, answered or not
+    pub fn len(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outstanding.is_empty()
+    }
+}
+
+impl Default for Requests {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,210 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+// Receive side continuity check on message ids. The sender numbers every message (fragments
+// one by one) with a wrapping u16 counter, so an id that skips ahead of the one expected
+// means the ones in between never made it, e.g. overwritten in a full DropOldest buffer.
+//
+// Ids more than half the range ahead are taken as late arrivals from before the wrap, not
+// as a jump forward. A message can also just be overtaken by a higher priority one, so ids
+// skipped over can be passed in as still on their way and only count as lost if they never
+// show up before the next reset.
+
+// How many recent ids `DuplicateFilter` remembers by default
+pub const DEFAULT_DUPLICATE_WINDOW: usize = 64;
+
+// How many outstanding gaps are kept for `take_gaps` before the oldest are merged into the
+// count only
+pub const DEFAULT_MAX_GAPS: usize = 32;
+
+// `a` comes after `b`, counting from `b` around the wrap
+pub(crate) fn is_ahead(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000
+}
+
+// `count` ids in a row starting at `first` that never arrived
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gap {
+    pub first: u16,
+    pub count: u16,
+}
+
+impl Gap {
+    pub fn last(&self) -> u16 {
+        self.first.wrapping_add(self.count - 1)
+    }
+}
+
+#[derive(Debug)]
+pub struct SequenceTracker {
+    // next id in sequence, `None` until the first message
+    expected: Option<u16>,
+    // skipped over but still on their way (overtaken, not lost)
+    overtaken: Vec<u16>,
+    gaps: VecDeque<Gap>,
+    max_gaps: usize,
+    lost: u64,
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        SequenceTracker {
+            expected: None,
+            overtaken: Vec::new(),
+            gaps: VecDeque::new(),
+            max_gaps: DEFAULT_MAX_GAPS,
+            lost: 0,
+        }
+    }
+
+    // When the first id is known up front, so losing the very first messages shows too
+    pub fn starting_at(expected: u16) -> Self {
+        SequenceTracker {
+            expected: Some(expected),
+            ..Self::new()
+        }
+    }
+
+    pub fn with_max_gaps(mut self, max_gaps: usize) -> Self {
+        self.max_gaps = max_gaps.max(1);
+        self
+    }
+
+    // Message `id` came in. `queued` says whether a skipped id is still on its way. Returns
+    // how many ids this showed to be lost.
+    pub fn observe(&mut self, id: u16, queued: impl Fn(u16) -> bool) -> u16 {
+        let Some(expected) = self.expected else {
+            self.expected = Some(id.wrapping_add(1));
+            return 0;
+        };
+
+        if id != expected && !is_ahead(id, expected) {
+            // late: an overtaken one catching up, or a duplicate
+            self.overtaken.retain(|&overtaken| overtaken != id);
+            return 0;
+        }
+
+        let mut lost = 0;
+        let mut missing = expected;
+        while missing != id {
+            if queued(missing) {
+                self.overtaken.push(missing);
+            } else {
+                self.record_lost(missing);
+                lost += 1;
+            }
+            missing = missing.wrapping_add(1);
+        }
+        let expected = id.wrapping_add(1);
+        self.expected = Some(expected);
+
+        // a quarter of the range further on an overtaken id isn't coming any more
+        let mut i = 0;
+        while i < self.overtaken.len() {
+            if expected.wrapping_sub(self.overtaken[i]) >= 0x4000 {
+                let id = self.overtaken.swap_remove(i);
+                self.record_lost(id);
+                lost += 1;
+            } else {
+                i += 1;
+            }
+        }
+        lost
+    }
+
+    // Start over from whatever comes next, e.g. after the sender resynced. Overtaken ids that
+    // never showed up are lost.
+    pub fn reset(&mut self) {
+        for id in core::mem::take(&mut self.overtaken) {
+            self.record_lost(id);
+        }
+        self.expected = None;
+    }
+
+    // Gaps found since the last call, oldest first
+    pub fn take_gaps(&mut self) -> Vec<Gap> {
+        self.gaps.drain(..).collect()
+    }
+
+    // Ids lost since the tracker was made
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+
+    pub fn expected(&self) -> Option<u16> {
+        self.expected
+    }
+
+    fn record_lost(&mut self, id: u16) {
+        self.lost += 1;
+        if let Some(last) = self.gaps.back_mut()
+            && last.last().wrapping_add(1) == id
+            && last.count < u16::MAX
+        {
+            last.count += 1;
+            return;
+        }
+        if self.gaps.len() >= self.max_gaps {
+            self.gaps.pop_front();
+        }
+        self.gaps.push_back(Gap {
+            first: id,
+            count: 1,
+        });
+    }
+}
+
+impl Default for SequenceTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Remembers the last `window` ids delivered, so a copy that turns up again (a retransmission
+// whose ack got lost, a transport that repeats frames) can be recognized and dropped. Ids
+// are only repeated after the counter wraps, far outside any sensible window.
+#[derive(Debug)]
+pub struct DuplicateFilter {
+    recent: VecDeque<u16>,
+    window: usize,
+}
+
+impl DuplicateFilter {
+    pub fn new(window: usize) -> Self {
+        DuplicateFilter {
+            recent: VecDeque::new(),
+            window: window.max(1),
+        }
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    // True the first time `id` shows up, false for a copy of one already seen
+    pub fn check(&mut self, id: u16) -> bool {
+        if self.contains(id) {
+            return false;
+        }
+        if self.recent.len() >= self.window {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(id);
+        true
+    }
+
+    // Whether `id` was seen already, without remembering it
+    pub fn contains(&self, id: u16) -> bool {
+        self.recent.contains(&id)
+    }
+
+    pub fn clear(&mut self) {
+        self.recent.clear();
+    }
+}
+
+impl Default for DuplicateFilter {
+    fn default() -> Self {
+        Self::new(DEFAULT_DUPLICATE_WINDOW)
+    }
+}
@@ -0,0 +1,407 @@
+// Deterministic two MCU simulation: two `Link`s joined by simulated lines (latency, jitter,
+// faults) that run on a `VirtualClock`, no threads and no real time. The same setup and the
+// same seeds give the same run, down to the byte, so a protocol change can be checked
+// against a recorded trace.
+//
+//   let mut sim = Sim::with_lines(
+//       LineConfig { latency: Duration::from_millis(5), ..LineConfig::default() },
+//       LineConfig::default(),
+//   );
+//   sim.send(Node::Mcu1, b"ping".to_vec())?;
+//   sim.run_until_idle(1_000);
+//   assert_eq!(sim.take_received(Node::Mcu2)[0].payload, b"ping");
+//   for event in sim.trace() { println!("{event}"); }
+//
+// Each `step` moves the clock forward by one tick, lets both links write whatever is due and
+// then receives everything that has arrived, MCU1 before MCU2. The lines carry COBS frames,
+// which is what the fault and latency wrappers need to find frame boundaries.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+
+use crate::codec::Codec;
+use crate::error::ProtocolError;
+use crate::framing::{Cobs, cobs};
+use crate::link::Link;
+use crate::message::{Message, Payload};
+use crate::time::{Clock, VirtualClock};
+use crate::transport::{Fault, FaultyTransport, LatencyTransport, Loopback};
+
+// how far `step` moves the clock unless told otherwise
+pub const DEFAULT_TICK: Duration = Duration::from_millis(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Node {
+    Mcu1,
+    Mcu2,
+}
+
+impl Node {
+    pub fn peer(self) -> Node {
+        match self {
+            Node::Mcu1 => Node::Mcu2,
+            Node::Mcu2 => Node::Mcu1,
+        }
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Node::Mcu1 => write!(f, "mcu1"),
+            Node::Mcu2 => write!(f, "mcu2"),
+        }
+    }
+}
+
+// One direction of the wire between the two MCUs
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineConfig {
+    pub latency: Duration,
+    pub jitter: Duration,
+    pub reordering: bool,
+    // chance per frame of each fault, see `FaultyTransport`
+    pub faults: Vec<(Fault, f32)>,
+    pub seed: u64,
+}
+
+impl Default for LineConfig {
+    fn default() -> Self {
+        LineConfig {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            reordering: false,
+            faults: Vec::new(),
+            seed: 1,
+        }
+    }
+}
+
+// What the simulated MCUs write into
+pub type SimTransport = LatencyTransport<FaultyTransport<Loopback>, VirtualClock>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceKind {
+    // handed to the link, `len` payload bytes
+    Sent { id: u16, len: usize },
+    // came out of the link verified
+    Received { id: u16, len: usize },
+    // a frame came in but didn't decode or verify
+    Rejected(ProtocolError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub at: Duration,
+    pub node: Node,
+    pub kind: TraceKind,
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = self.at.as_micros() as f64 / 1000.0;
+        write!(f, "{:>10.3}ms {} ", millis, self.node)?;
+        match &self.kind {
+            TraceKind::Sent { id, len } => write!(f, "sent #{} ({} bytes)", id, len),
+            TraceKind::Received { id, len } => write!(f, "received #{} ({} bytes)", id, len),
+            TraceKind::Rejected(error) => write!(f, "rejected frame: {}", error),
+        }
+    }
+}
+
+pub struct Sim {
+    clock: VirtualClock,
+    tick: Duration,
+    links: [Link<SimTransport, Cobs>; 2],
+    received: [Vec<Message>; 2],
+    trace: Vec<TraceEvent>,
+}
+
+impl Sim {
+    // Two MCUs on a perfect line
+    pub fn new() -> Self {
+        Self::with_lines(LineConfig::default(), LineConfig::default())
+    }
+
+    pub fn with_lines(mcu1_to_mcu2: LineConfig, mcu2_to_mcu1: LineConfig) -> Self {
+        let clock = VirtualClock::new();
+        let (mcu1, mcu2) = Loopback::pair();
+        let links = [
+            Link::new(line(mcu1, &mcu1_to_mcu2, &clock), Cobs::default()),
+            Link::new(line(mcu2, &mcu2_to_mcu1, &clock), Cobs::default()),
+        ];
+        Sim {
+            clock,
+            tick: DEFAULT_TICK,
+            links,
+            received: [Vec::new(), Vec::new()],
+            trace: Vec::new(),
+        }
+    }
+
+    // Clock step per `step`
+    pub fn with_tick(mut self, tick: Duration) -> Self {
+        self.tick = tick;
+        self
+    }
+
+    // Codec of each MCU, they have to agree for anything to get through
+    pub fn with_codecs(self, mcu1_codec: Codec, mcu2_codec: Codec) -> Self {
+        let Sim {
+            clock,
+            tick,
+            links: [mcu1, mcu2],
+            received,
+            trace,
+        } = self;
+        Sim {
+            clock,
+            tick,
+            links: [mcu1.with_codec(mcu1_codec), mcu2.with_codec(mcu2_codec)],
+            received,
+            trace,
+        }
+    }
+
+    pub fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+
+    pub fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    pub fn link(&mut self, node: Node) -> &mut Link<SimTransport, Cobs> {
+        &mut self.links[index(node)]
+    }
+
+    pub fn send(&mut self, from: Node, payload: impl Into<Payload>) -> Result<u16, ProtocolError> {
+        let payload = payload.into();
+        let len = payload.len();
+        let id = self.links[index(from)].send(payload)?;
+        self.record(from, TraceKind::Sent { id, len });
+        Ok(id)
+    }
+
+    // Advance one tick, move due frames and collect what arrived
+    pub fn step(&mut self) -> Result<(), ProtocolError> {
+        self.clock.advance(self.tick);
+        for link in &mut self.links {
+            link.poll_write()?;
+        }
+        for node in [Node::Mcu1, Node::Mcu2] {
+            while let Some(received) = self.links[index(node)].receive() {
+                match received {
+                    Ok(message) => {
+                        let (id, len) = (message.id, message.payload.len());
+                        self.record(node, TraceKind::Received { id, len });
+                        self.received[index(node)].push(message);
+                    }
+                    Err(error @ (ProtocolError::Transport(_) | ProtocolError::Disconnected)) => {
+                        return Err(error);
+                    }
+                    Err(error) => self.record(node, TraceKind::Rejected(error)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Step for `duration` of virtual time
+    pub fn run_for(&mut self, duration: Duration) -> Result<(), ProtocolError> {
+        let end = self.now() + duration;
+        while self.now() < end {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    // Step until nothing is on the wire any more, at most `max_steps` times. Returns the steps
+    // taken, `Timeout` if it was still busy after that.
+    pub fn run_until_idle(&mut self, max_steps: usize) -> Result<usize, ProtocolError> {
+        for steps in 0..max_steps {
+            if self.is_idle() {
+                return Ok(steps);
+            }
+            self.step()?;
+        }
+        if self.is_idle() {
+            Ok(max_steps)
+        } else {
+            Err(ProtocolError::Timeout)
+        }
+    }
+
+    // No frame waiting to be written, held back on a line or unread at the far end
+    pub fn is_idle(&self) -> bool {
+        self.links.iter().all(|link| {
+            let line = link.transport();
+            link.pending_bytes() == 0
+                && line.in_flight() == 0
+                && line.inner().inner().pending() == 0
+        })
+    }
+
+    // Messages `node` has received since the last call
+    pub fn take_received(&mut self, node: Node) -> Vec<Message> {
+        core::mem::take(&mut self.received[index(node)])
+    }
+
+    pub fn trace(&self) -> &[TraceEvent] {
+        &self.trace
+    }
+
+    pub fn clear_trace(&mut self) {
+        self.trace.clear();
+    }
+
+    fn record(&mut self, node: Node, kind: TraceKind) {
+        self.trace.push(TraceEvent {
+            at: self.clock.now(),
+            node,
+            kind,
+        });
+    }
+}
+
+impl Default for Sim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn line(end: Loopback, config: &LineConfig, clock: &VirtualClock) -> SimTransport {
+    let mut faulty = FaultyTransport::new(end, cobs::DELIMITER).with_seed(config.seed);
+    for &(fault, probability) in &config.faults {
+        faulty = faulty.with_probability(fault, probability);
+    }
+    LatencyTransport::new(faulty, cobs::DELIMITER, clock.clone())
+        .with_latency(config.latency)
+        .with_jitter(config.jitter)
+        .with_reordering(config.reordering)
+        // a different stream than the faults, so changing one doesn't shift the other
+        .with_seed(config.seed.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+}
+
+fn index(node: Node) -> usize {
+    match node {
+        Node::Mcu1 => 0,
+        Node::Mcu2 => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn faulty_run() -> Sim {
+        let line = LineConfig {
+            latency: Duration::from_millis(2),
+            faults: vec![(Fault::Drop, 0.2), (Fault::FlipBit, 0.2)],
+            seed: 7,
+            ..LineConfig::default()
+        };
+        let mut sim = Sim::with_lines(line, LineConfig::default());
+        for i in 0..40u8 {
+            sim.send(Node::Mcu1, vec![i; 8]).unwrap();
+        }
+        assert_eq!(sim.run_until_idle(100), Ok(2));
+        sim
+    }
+
+    #[test]
+    fn perfect_line_delivers_everything() {
+        let mut sim = Sim::new();
+        sim.send(Node::Mcu1, b"ping".to_vec()).unwrap();
+        sim.send(Node::Mcu2, b"pong".to_vec()).unwrap();
+        sim.run_until_idle(10).unwrap();
+
+        assert_eq!(&sim.take_received(Node::Mcu2)[0].payload[..], b"ping");
+        assert_eq!(&sim.take_received(Node::Mcu1)[0].payload[..], b"pong");
+        let tick = DEFAULT_TICK;
+        assert_eq!(
+            sim.trace(),
+            [
+                TraceEvent {
+                    at: Duration::ZERO,
+                    node: Node::Mcu1,
+                    kind: TraceKind::Sent { id: 1, len: 4 },
+                },
+                TraceEvent {
+                    at: Duration::ZERO,
+                    node: Node::Mcu2,
+                    kind: TraceKind::Sent { id: 1, len: 4 },
+                },
+                TraceEvent {
+                    at: tick,
+                    node: Node::Mcu1,
+                    kind: TraceKind::Received { id: 1, len: 4 },
+                },
+                TraceEvent {
+                    at: tick,
+                    node: Node::Mcu2,
+                    kind: TraceKind::Received { id: 1, len: 4 },
+                },
+            ]
+        );
+    }
+
+    // recorded from a run, a change that moves any of this changed what goes over the wire
+    #[test]
+    fn faulty_line_matches_recorded_trace() {
+        let mut sim = faulty_run();
+
+        let ids: Vec<u16> = sim
+            .take_received(Node::Mcu2)
+            .iter()
+            .map(|message| message.id)
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 19, 20, 22, 24, 25, 28, 31, 34,
+                36, 37, 38, 39
+            ]
+        );
+
+        let rejected: Vec<&ProtocolError> = sim
+            .trace()
+            .iter()
+            .filter_map(|event| match &event.kind {
+                TraceKind::Rejected(error) => Some(error),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            rejected,
+            [
+                &ProtocolError::MalformedFrame,
+                &ProtocolError::ChecksumMismatch {
+                    expected: 0x14,
+                    actual: 0x34
+                },
+                &ProtocolError::ChecksumMismatch {
+                    expected: 0x29,
+                    actual: 0xa9
+                },
+            ]
+        );
+        assert!(
+            sim.trace()
+                .iter()
+                .filter(|event| event.node == Node::Mcu2)
+                .all(|event| event.at == Duration::from_millis(2))
+        );
+
+        let stats = sim.link(Node::Mcu2).stats();
+        assert_eq!(stats.malformed_frames, 1);
+        assert_eq!(stats.checksum_failures, 2);
+    }
+
+    #[test]
+    fn same_seed_same_run() {
+        assert_eq!(faulty_run().trace(), faulty_run().trace());
+    }
+}
@@ -0,0 +1,162 @@
+// Lock-free single producer / single consumer ring, for when MCU1 produces from an interrupt
+// handler. The producer only ever writes `tail` and the consumer only ever writes `head`, so
+// pushing from an ISR while the main loop pops needs no lock and no critical section. Only
+// atomic loads and stores are used, no compare-and-swap, so it works on a Cortex-M0 too.
+//
+// `split` hands out the two ends. `new` is `const`, so the ring itself can be a `static`
+// (split it once at startup, e.g. through `static_cell` or a `static mut` taken in `main`).
+//
+// Unlike `CircularBuffer` it's strictly FIFO, no priorities and no overflow policy: the
+// producer can't evict anything without racing the consumer, a full ring refuses the message.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::ProtocolError;
+
+pub struct SpscRing<M, const N: usize> {
+    slots: [UnsafeCell<MaybeUninit<M>>; N],
+    // Both count modulo 2 * N, so full (tail - head == N) and empty (tail == head) don't
+    // look the same and any N works, not just powers of two
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// Each slot is only touched by one side at a time, handed over through head/tail
+unsafe impl<M: Send, const N: usize> Sync for SpscRing<M, N> {}
+
+impl<M, const N: usize> SpscRing<M, N> {
+    pub const fn new() -> Self {
+        SpscRing {
+            slots: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    // The sending and receiving ends, borrowing the ring mutably makes sure there's only ever
+    // one of each
+    pub fn split(&mut self) -> (Producer<'_, M, N>, Consumer<'_, M, N>) {
+        let ring = &*self;
+        (Producer { ring }, Consumer { ring })
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn length(&self) -> usize {
+        index_distance::<N>(
+            self.head.load(Ordering::Acquire),
+            self.tail.load(Ordering::Acquire),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.length() == N
+    }
+}
+
+impl<M, const N: usize> Default for SpscRing<M, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M, const N: usize> Drop for SpscRing<M, N> {
+    fn drop(&mut self) {
+        let (_, mut consumer) = self.split();
+        while consumer.try_receive().is_some() {}
+    }
+}
+
+// Sending end, give it to the ISR
+pub struct Producer<'a, M, const N: usize> {
+    ring: &'a SpscRing<M, N>,
+}
+
+unsafe impl<M: Send, const N: usize> Send for Producer<'_, M, N> {}
+
+impl<M, const N: usize> Producer<'_, M, N> {
+    // Queue a message, `BufferFull` (and the message is dropped) if the consumer is behind
+    pub fn try_send(&mut self, message: M) -> Result<(), ProtocolError> {
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        let head = self.ring.head.load(Ordering::Acquire);
+        if index_distance::<N>(head, tail) == N {
+            return Err(ProtocolError::BufferFull);
+        }
+
+        // the consumer won't look at this slot until `tail` says it's filled
+        unsafe { (*self.ring.slots[tail % N].get()).write(message) };
+        self.ring.tail.store(advance::<N>(tail), Ordering::Release);
+        Ok(())
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.ring.is_full()
+    }
+
+    pub fn length(&self) -> usize {
+        self.ring.length()
+    }
+}
+
+// Receiving end, stays in the main loop
+pub struct Consumer<'a, M, const N: usize> {
+    ring: &'a SpscRing<M, N>,
+}
+
+unsafe impl<M: Send, const N: usize> Send for Consumer<'_, M, N> {}
+
+impl<M, const N: usize> Consumer<'_, M, N> {
+    pub fn try_receive(&mut self) -> Option<M> {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+
+        // filled by the producer before it published `tail`, and it won't be reused until
+        // we move `head` past it
+        let message = unsafe { (*self.ring.slots[head % N].get()).assume_init_read() };
+        self.ring.head.store(advance::<N>(head), Ordering::Release);
+        Some(message)
+    }
+
+    // Look at the next message without taking it
+    pub fn peek(&self) -> Option<&M> {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        // same reasoning as `try_receive`, and only the consumer can move `head`
+        Some(unsafe { (*self.ring.slots[head % N].get()).assume_init_ref() })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ring.is_empty()
+    }
+
+    pub fn length(&self) -> usize {
+        self.ring.length()
+    }
+}
+
+// next index, modulo 2 * N
+fn advance<const N: usize>(index: usize) -> usize {
+    if index + 1 == 2 * N { 0 } else { index + 1 }
+}
+
+fn index_distance<const N: usize>(head: usize, tail: usize) -> usize {
+    if tail >= head {
+        tail - head
+    } else {
+        tail + 2 * N - head
+    }
+}
@@ -0,0 +1,123 @@
+// Counters kept by `CircularBuffer` and `CommunicationProtocol`. Plain u64s, readers get a
+// copy via `stats()` and can zero them with `reset_stats()`.
+//
+// The `*_per_sec` ones aren't counters but rates over the last second or so, see
+// `throughput`. Only an `Endpoint` measures them, they count every frame that goes onto /
+// comes off the link (protocol traffic and resends included) at its encoded size.
+//
+// `srtt_micros`, `rttvar_micros` and `rto_micros` aren't counters either, they're the round
+// trip time measured on acks so far and the retransmit timeout that follows from it, see
+// `round_trip`. 0 until the first ack has been timed (and without retransmission).
+//
+// At buffer level every message counts, fragments included. At protocol level `messages_*`
+// count whole payloads the application handed in / got out, bytes are payload bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    // messages accepted for sending
+    pub messages_sent: u64,
+    // messages handed out to the receiver
+    pub messages_received: u64,
+    // messages thrown away to make room (DropOldest overflow, shrinking resize)
+    pub dropped_overflow: u64,
+    // sends refused because the buffer was full (RejectNew/Block, or DropOldest with nothing
+    // low enough to evict)
+    pub rejected_full: u64,
+    // messages that failed checksum verification on receive
+    pub checksum_failures: u64,
+    // frames that didn't decode (framing, length, header) before a checksum could be checked
+    pub malformed_frames: u64,
+    // fragments the reassembler couldn't fit into a message
+    pub invalid_fragments: u64,
+    // payload bytes accepted for sending
+    pub bytes_sent: u64,
+    // payload bytes handed out to the receiver
+    pub bytes_received: u64,
+    // deepest the queue has been, use it to size `capacity` from field data
+    pub high_watermark: u64,
+    // sent messages the receiver acked / nacked, see `ack`
+    pub messages_acked: u64,
+    pub messages_nacked: u64,
+    // messages sent again after a timeout or a NACK, see `retransmit`
+    pub retransmissions: u64,
+    // messages given up on after the last retry
+    pub delivery_failures: u64,
+    // ids that never arrived, see `sequence`
+    pub messages_lost: u64,
+    // copies of messages already delivered, dropped on receive
+    pub duplicates: u64,
+    // messages for other nodes on the bus, dropped on receive, see `address`
+    pub addressed_elsewhere: u64,
+    // messages a `Router` passed on to another link / had no route for
+    pub forwarded: u64,
+    pub unroutable: u64,
+    // messages on a channel the endpoint hasn't opened, dropped on receive
+    pub unknown_channel: u64,
+    // messages an endpoint's receive filter turned away
+    pub filtered: u64,
+    pub send_messages_per_sec: u64,
+    pub send_bytes_per_sec: u64,
+    pub receive_messages_per_sec: u64,
+    pub receive_bytes_per_sec: u64,
+    pub srtt_micros: u64,
+    pub rttvar_micros: u64,
+    pub rto_micros: u64,
+}
+
+impl Stats {
+    pub const fn new() -> Self {
+        Stats {
+            messages_sent: 0,
+            messages_received: 0,
+            dropped_overflow: 0,
+            rejected_full: 0,
+            checksum_failures: 0,
+            malformed_frames: 0,
+            invalid_fragments: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            high_watermark: 0,
+            messages_acked: 0,
+            messages_nacked: 0,
+            retransmissions: 0,
+            delivery_failures: 0,
+            messages_lost: 0,
+            duplicates: 0,
+            addressed_elsewhere: 0,
+            forwarded: 0,
+            unroutable: 0,
+            unknown_channel: 0,
+            filtered: 0,
+            send_messages_per_sec: 0,
+            send_bytes_per_sec: 0,
+            receive_messages_per_sec: 0,
+            receive_bytes_per_sec: 0,
+            srtt_micros: 0,
+            rttvar_micros: 0,
+            rto_micros: 0,
+        }
+    }
+
+    // A frame off the wire that came out as an error, checksum failure or malformed frame
+    #[cfg(feature = "alloc")]
+    pub(crate) fn count_decode_error(&mut self, error: &crate::error::ProtocolError) {
+        match error {
+            crate::error::ProtocolError::ChecksumMismatch { .. } => self.checksum_failures += 1,
+            _ => self.malformed_frames += 1,
+        }
+    }
+
+    // Round trip time fields from `rtt`, left at 0 until it has a sample
+    #[cfg(feature = "alloc")]
+    pub(crate) fn set_rtt(
+        &mut self,
+        rtt: crate::round_trip::RttEstimator,
+        rto: core::time::Duration,
+    ) {
+        let Some(srtt) = rtt.srtt() else {
+            return;
+        };
+        self.srtt_micros = srtt.as_micros() as u64;
+        self.rttvar_micros = rtt.rttvar().as_micros() as u64;
+        self.rto_micros = rto.as_micros() as u64;
+    }
+}
@@ -0,0 +1,90 @@
+use core::time::Duration;
+
+// Messages and bytes per second over a sliding window, for telemetry on how busy the link
+// is. `Endpoint` keeps one for each direction and puts them in its `Stats`
+// (`send_bytes_per_sec` ...):
+//
+//   let stats = endpoint.stats();
+//   let load = stats.send_bytes_per_sec * 100 / (115_200 / 10);
+//
+// The window is split into `SLOTS` slots and rolls on a slot at a time, so a rate covers
+// between the last window and the last window less one slot. Nothing before the window
+// counts, a link that went quiet drops to 0 once the window has passed.
+
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(1);
+
+// Slots per window
+pub const SLOTS: usize = 10;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Slot {
+    // which slot since time 0 this counts for
+    index: u64,
+    messages: u64,
+    bytes: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Throughput {
+    window: Duration,
+    slots: [Slot; SLOTS],
+}
+
+impl Throughput {
+    pub fn new(window: Duration) -> Self {
+        Throughput {
+            window: window.max(Duration::from_micros(SLOTS as u64)),
+            slots: [Slot::default(); SLOTS],
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    // A message of `len` bytes went by
+    pub fn record(&mut self, len: usize, now: Duration) {
+        let index = self.slot_index(now);
+        let slot = &mut self.slots[(index % SLOTS as u64) as usize];
+        if slot.index != index {
+            *slot = Slot {
+                index,
+                ..Slot::default()
+            };
+        }
+        slot.messages += 1;
+        slot.bytes += len as u64;
+    }
+
+    // Messages and bytes per second over the window up to `now`
+    pub fn rates(&self, now: Duration) -> (u64, u64) {
+        let index = self.slot_index(now);
+        let (messages, bytes) = self
+            .slots
+            .iter()
+            .filter(|slot| slot.index <= index && index - slot.index < SLOTS as u64)
+            .fold((0, 0), |(messages, bytes), slot| {
+                (messages + slot.messages, bytes + slot.bytes)
+            });
+        let micros = self.window.as_micros() as u64;
+        (
+            messages.saturating_mul(1_000_000) / micros,
+            bytes.saturating_mul(1_000_000) / micros,
+        )
+    }
+
+    pub fn clear(&mut self) {
+        self.slots = [Slot::default(); SLOTS];
+    }
+
+    fn slot_index(&self, now: Duration) -> u64 {
+        let width = self.window.as_micros() as u64 / SLOTS as u64;
+        now.as_micros() as u64 / width
+    }
+}
+
+impl Default for Throughput {
+    fn default() -> Self {
+        Self::new(DEFAULT_WINDOW)
+    }
+}
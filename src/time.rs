@@ -0,0 +1,150 @@
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+use alloc::rc::Rc;
+#[cfg(feature = "alloc")]
+use core::cell::Cell;
+use core::future::{Future, poll_fn};
+use core::pin::pin;
+use core::task::Poll;
+use core::time::Duration;
+
+use crate::error::ProtocolError;
+
+// Monotonic time source for everything with a timeout in it (reassembly, retransmits,
+// heartbeats ...). Time is a `Duration` since some arbitrary starting point, only differences
+// between two readings mean anything.
+pub trait Clock {
+    fn now(&self) -> Duration;
+}
+
+// `std::time::Instant` backed clock, counting from when it was created
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+pub struct StdClock {
+    start: std::time::Instant,
+}
+
+#[cfg(feature = "std")]
+impl StdClock {
+    pub fn new() -> Self {
+        StdClock {
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for StdClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for StdClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+// Clock that never moves, so nothing ever times out. It's the default without `std` where
+// there's no portable time source, hook up a hardware timer with `with_clock` instead.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoClock;
+
+impl Clock for NoClock {
+    fn now(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+// Clock that only moves when told to, for simulations and tests that must not depend on real
+// time. Clones share the same time, hand one to everything that needs a clock and drive it
+// from the test.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock {
+    now: Rc<Cell<Duration>>,
+}
+
+#[cfg(feature = "alloc")]
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+
+    // Jump to `now`, which may not be earlier than the current time
+    pub fn set(&self, now: Duration) {
+        debug_assert!(now >= self.now.get(), "virtual time can't go backwards");
+        self.now.set(now);
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+}
+
+// What a protocol instance uses until it's given a clock
+#[cfg(feature = "std")]
+pub(crate) fn default_clock() -> Box<dyn Clock + Send + Sync> {
+    Box::new(StdClock::new())
+}
+
+#[cfg(all(feature = "alloc", not(feature = "std")))]
+pub(crate) fn default_clock() -> Box<dyn Clock + Send + Sync> {
+    Box::new(NoClock)
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> Duration {
+        (**self).now()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<C: Clock + ?Sized> Clock for Box<C> {
+    fn now(&self) -> Duration {
+        (**self).now()
+    }
+}
+
+// Async sleep, the waiting half of a timeout in async code. No executor comes with the crate,
+// implement it over the one in use: `embassy_time::Timer::after`, `tokio::time::sleep`, ...
+pub trait Delay {
+    fn delay(&self, duration: Duration) -> impl Future<Output = ()>;
+}
+
+impl<D: Delay + ?Sized> Delay for &D {
+    fn delay(&self, duration: Duration) -> impl Future<Output = ()> {
+        (**self).delay(duration)
+    }
+}
+
+// Run `future` for at most `duration`, `Timeout` if the delay runs out first. `future` is
+// dropped at that point, so it has to be fine with being cancelled.
+pub async fn timeout<F: Future>(
+    delay: &impl Delay,
+    duration: Duration,
+    future: F,
+) -> Result<F::Output, ProtocolError> {
+    let mut future = pin!(future);
+    let mut sleep = pin!(delay.delay(duration));
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        sleep
+            .as_mut()
+            .poll(cx)
+            .map(|()| Err(ProtocolError::Timeout))
+    })
+    .await
+}
@@ -0,0 +1,143 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+// Publish/subscribe on top of channels: the sender publishes data under a numeric topic, the
+// receiver subscribes a handler per topic and gets only what it asked for, no matching on
+// payload bytes in the application:
+//
+//   let mut mcu2 = Endpoint::new(uart, Cobs::default(), 8)
+//       .with_channel_config(1, ChannelConfig { topics: true, ..ChannelConfig::best_effort(8) });
+//   mcu2.subscribe(TEMPERATURE, |_, data| update_temperature(data));
+//   mcu2.subscribe(BATTERY, |_, data| update_battery(data));
+//
+//   mcu1.publish(1, TEMPERATURE, &reading.to_le_bytes())?;
+//
+// On a channel set up for topics every payload starts with the topic id:
+//
+//   | topic: u16 | data |
+//
+// A subscription can also cover many topics at once, a range or every topic matching a mask:
+//
+//   mcu2.subscribe(TopicFilter::Range { first: 0x100, last: 0x1FF }, log_sensor);
+//   // 0x0A00, 0x0A01 ... 0x0AFF
+//   mcu2.subscribe(TopicFilter::Masked { topic: 0x0A00, mask: 0xFF00 }, log_actuator);
+//
+// Messages on a topic nobody subscribed to (or too short to carry one) are handed on like
+// any other message, to the receive handler or `receive`.
+
+// Numeric topic, meaning is up to the application
+pub type TopicId = u16;
+
+// Bytes the topic id takes in front of the data
+pub const TOPIC_LEN: usize = 2;
+
+// Which topics a subscription is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopicFilter {
+    Exact(TopicId),
+    // `first` to `last`, both included
+    Range { first: TopicId, last: TopicId },
+    // every topic whose bits under `mask` are the same as `topic`'s
+    Masked { topic: TopicId, mask: TopicId },
+    All,
+}
+
+impl TopicFilter {
+    pub fn matches(&self, topic: TopicId) -> bool {
+        match *self {
+            TopicFilter::Exact(exact) => topic == exact,
+            TopicFilter::Range { first, last } => (first..=last).contains(&topic),
+            TopicFilter::Masked {
+                topic: wanted,
+                mask,
+            } => topic & mask == wanted & mask,
+            TopicFilter::All => true,
+        }
+    }
+}
+
+impl From<TopicId> for TopicFilter {
+    fn from(topic: TopicId) -> Self {
+        TopicFilter::Exact(topic)
+    }
+}
+
+// Gets the topic and the data published under it
+pub type TopicHandler = Box<dyn FnMut(TopicId, &[u8]) + Send>;
+
+// Payload for `data` published under `topic`
+pub fn encode(topic: TopicId, data: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(TOPIC_LEN + data.len());
+    payload.extend_from_slice(&topic.to_le_bytes());
+    payload.extend_from_slice(data);
+    payload
+}
+
+// Topic and data of a payload, `None` if it's too short to have a topic
+pub fn decode(payload: &[u8]) -> Option<(TopicId, &[u8])> {
+    let (topic, data) = payload.split_first_chunk::<TOPIC_LEN>()?;
+    Some((TopicId::from_le_bytes(*topic), data))
+}
+
+// Handlers by topic. A topic can have several (exact or through a filter), they all get
+// called in the order they subscribed.
+pub struct Subscriptions {
+    handlers: Vec<(TopicFilter, TopicHandler)>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Subscriptions {
+            handlers: Vec::new(),
+        }
+    }
+
+    // `filter` is a single topic or a `TopicFilter`
+    pub fn subscribe<H>(&mut self, filter: impl Into<TopicFilter>, handler: H)
+    where
+        H: FnMut(TopicId, &[u8]) + Send + 'static,
+    {
+        self.handlers.push((filter.into(), Box::new(handler)));
+    }
+
+    // Drop every handler subscribed with exactly `filter`. Returns whether there were any.
+    pub fn unsubscribe(&mut self, filter: impl Into<TopicFilter>) -> bool {
+        let filter = filter.into();
+        let before = self.handlers.len();
+        self.handlers
+            .retain(|(subscribed, _)| *subscribed != filter);
+        self.handlers.len() != before
+    }
+
+    // Whether anything published under `topic` would reach a handler
+    pub fn is_subscribed(&self, topic: TopicId) -> bool {
+        self.handlers
+            .iter()
+            .any(|(filter, _)| filter.matches(topic))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    // Hand a payload to the handlers for its topic. Returns whether any took it.
+    pub fn dispatch(&mut self, payload: &[u8]) -> bool {
+        let Some((topic, data)) = decode(payload) else {
+            return false;
+        };
+        let mut handled = false;
+        for (filter, handler) in &mut self.handlers {
+            if filter.matches(topic) {
+                handler(topic, data);
+                handled = true;
+            }
+        }
+        handled
+    }
+}
+
+impl Default for Subscriptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,369 @@
+// CAN through an `embedded_can::nb::Can` controller, the link between the two ECUs in
+// production. CAN frames only carry 8 bytes, so what `Link` writes is cut up ISO-TP style
+// (ISO 15765-2, normal addressing) and put back together on the other side:
+//
+//   single frame       [0x0L]       [L bytes]          whole write of up to 7 bytes
+//   first frame        [0x1H] [LL]  [6 bytes]          12 bit length, then wait for flow control
+//   consecutive frame  [0x2N]       [7 bytes]          N counts 1..15, 0, 1 ...
+//   flow control       [0x3S] [BS] [STmin]             S: 0 go on, 1 wait, 2 overflow
+//
+// Every `write_bytes` call becomes one ISO-TP message of up to 4095 bytes and goes out on
+// `tx_id`, segments from the other ECU are read off `rx_id`, frames with any other id are left
+// alone. Everything is non-blocking and moves forward whenever `read_bytes` or `write_bytes`
+// is called, so keep calling `Link::receive` while a long write is going out: that's what
+// picks up the flow control frames.
+//
+// We send with the block size and separation time asked for in our own flow control frames
+// (`with_flow_control`). The other side's STmin isn't timed, consecutive frames go out as fast
+// as the controller takes them; most controllers are slower than STmin 0 anyway.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use embedded_can::nb::Can;
+use embedded_can::{Error as _, ErrorKind, Frame, Id};
+
+use crate::error::{ProtocolError, TransportError};
+use crate::transport::Transport;
+
+// longest message a 12 bit first frame length can announce
+pub const MAX_MESSAGE_LEN: usize = 4095;
+// what unused bytes of a frame are padded with
+pub const DEFAULT_PADDING: u8 = 0xCC;
+
+const SINGLE: u8 = 0x0;
+const FIRST: u8 = 0x1;
+const CONSECUTIVE: u8 = 0x2;
+const FLOW_CONTROL: u8 = 0x3;
+
+const CONTINUE: u8 = 0x0;
+const WAIT: u8 = 0x1;
+const OVERFLOW: u8 = 0x2;
+
+enum Sending {
+    Idle,
+    // first frame is out, waiting for the receiver to say go
+    WaitingForFlowControl {
+        offset: usize,
+        sequence: u8,
+    },
+    // `remaining` frames left in this block, `None` = no limit
+    Consecutive {
+        offset: usize,
+        sequence: u8,
+        remaining: Option<u8>,
+    },
+}
+
+struct Receiving {
+    len: usize,
+    data: Vec<u8>,
+    sequence: u8,
+    // consecutive frames left before we owe another flow control frame
+    remaining: Option<u8>,
+}
+
+pub struct CanTransport<C: Can> {
+    can: C,
+    tx_id: Id,
+    rx_id: Id,
+    padding: Option<u8>,
+    block_size: u8,
+    separation_time: u8,
+    // frames the controller couldn't take yet (or pushed back out to make room)
+    outbox: VecDeque<C::Frame>,
+    // message being segmented onto the bus
+    sending: Sending,
+    message: Vec<u8>,
+    receiving: Option<Receiving>,
+    // reassembled bytes waiting for `read_bytes`
+    received: Vec<u8>,
+    received_read: usize,
+}
+
+impl<C: Can> CanTransport<C> {
+    pub fn new(can: C, tx_id: impl Into<Id>, rx_id: impl Into<Id>) -> Self {
+        CanTransport {
+            can,
+            tx_id: tx_id.into(),
+            rx_id: rx_id.into(),
+            padding: Some(DEFAULT_PADDING),
+            block_size: 0,
+            separation_time: 0,
+            outbox: VecDeque::new(),
+            sending: Sending::Idle,
+            message: Vec::new(),
+            receiving: None,
+            received: Vec::new(),
+            received_read: 0,
+        }
+    }
+
+    // Pad every frame to 8 bytes with `padding`, or send short frames with `None`
+    pub fn with_padding(mut self, padding: Option<u8>) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    // Block size and STmin (raw ISO-TP encoding) we ask the other ECU for
+    pub fn with_flow_control(mut self, block_size: u8, separation_time: u8) -> Self {
+        self.block_size = block_size;
+        self.separation_time = separation_time;
+        self
+    }
+
+    pub fn can_mut(&mut self) -> &mut C {
+        &mut self.can
+    }
+
+    pub fn into_inner(self) -> C {
+        self.can
+    }
+
+    // A message is still being segmented
+    pub fn is_sending(&self) -> bool {
+        !matches!(self.sending, Sending::Idle) || !self.outbox.is_empty()
+    }
+
+    // Build a frame on `tx_id`, padded if asked to
+    fn frame(&self, data: &[u8]) -> C::Frame {
+        let mut bytes = [self.padding.unwrap_or(0); 8];
+        bytes[..data.len()].copy_from_slice(data);
+        let len = if self.padding.is_some() {
+            8
+        } else {
+            data.len()
+        };
+        // 8 bytes or fewer always make a valid data frame
+        C::Frame::new(self.tx_id, &bytes[..len]).unwrap()
+    }
+
+    // Transmit whatever's in the outbox, false if the controller is still full
+    fn drain_outbox(&mut self) -> Result<bool, ProtocolError> {
+        while let Some(frame) = self.outbox.front() {
+            match self.can.transmit(frame) {
+                Ok(replaced) => {
+                    self.outbox.pop_front();
+                    // a frame still waiting in the controller got bumped, it goes again first
+                    if let Some(replaced) = replaced {
+                        self.outbox.push_front(replaced);
+                    }
+                }
+                Err(nb::Error::WouldBlock) => return Ok(false),
+                Err(nb::Error::Other(error)) => return Err(can_error(error.kind())),
+            }
+        }
+        Ok(true)
+    }
+
+    fn transmit(&mut self, data: &[u8]) -> Result<(), ProtocolError> {
+        let frame = self.frame(data);
+        self.outbox.push_back(frame);
+        self.drain_outbox().map(|_| ())
+    }
+
+    // Push out as many consecutive frames as the controller and the block size allow
+    fn poll_send(&mut self) -> Result<(), ProtocolError> {
+        while self.drain_outbox()? {
+            let Sending::Consecutive {
+                offset,
+                sequence,
+                remaining,
+            } = self.sending
+            else {
+                return Ok(());
+            };
+            let end = (offset + 7).min(self.message.len());
+            let mut data = [0u8; 8];
+            data[0] = CONSECUTIVE << 4 | sequence;
+            data[1..=end - offset].copy_from_slice(&self.message[offset..end]);
+            let len = 1 + end - offset;
+
+            self.sending = if end == self.message.len() {
+                Sending::Idle
+            } else {
+                let sequence = (sequence + 1) & 0x0F;
+                match remaining {
+                    Some(1) => Sending::WaitingForFlowControl {
+                        offset: end,
+                        sequence,
+                    },
+                    _ => Sending::Consecutive {
+                        offset: end,
+                        sequence,
+                        remaining: remaining.map(|left| left - 1),
+                    },
+                }
+            };
+            self.transmit(&data[..len])?;
+        }
+        Ok(())
+    }
+
+    // Read every frame the controller has and act on ours
+    fn poll_receive(&mut self) -> Result<(), ProtocolError> {
+        loop {
+            let frame = match self.can.receive() {
+                Ok(frame) => frame,
+                Err(nb::Error::WouldBlock) => return Ok(()),
+                Err(nb::Error::Other(error)) => return Err(can_error(error.kind())),
+            };
+            if frame.id() != self.rx_id || frame.is_remote_frame() || frame.data().is_empty() {
+                continue;
+            }
+            self.handle(frame.data())?;
+        }
+    }
+
+    fn handle(&mut self, data: &[u8]) -> Result<(), ProtocolError> {
+        match data[0] >> 4 {
+            SINGLE => {
+                let len = (data[0] & 0x0F) as usize;
+                if len > 0 && len < data.len() {
+                    self.deliver(&data[1..=len]);
+                }
+            }
+            FIRST if data.len() == 8 => {
+                // a new first frame drops whatever was half received
+                let len = ((data[0] & 0x0F) as usize) << 8 | data[1] as usize;
+                let mut received = Vec::with_capacity(len);
+                received.extend_from_slice(&data[2..8.min(2 + len)]);
+                self.receiving = Some(Receiving {
+                    len,
+                    data: received,
+                    sequence: 1,
+                    remaining: None,
+                });
+                self.send_flow_control()?;
+            }
+            CONSECUTIVE => {
+                let Some(receiving) = self.receiving.as_mut() else {
+                    return Ok(());
+                };
+                if data[0] & 0x0F != receiving.sequence {
+                    // a frame went missing, the message can't be completed
+                    self.receiving = None;
+                    return Ok(());
+                }
+                let take = (receiving.len - receiving.data.len()).min(data.len() - 1);
+                receiving.data.extend_from_slice(&data[1..=take]);
+                receiving.sequence = (receiving.sequence + 1) & 0x0F;
+                receiving.remaining = receiving.remaining.map(|left| left - 1);
+
+                if receiving.data.len() == receiving.len {
+                    let receiving = self.receiving.take().unwrap();
+                    self.deliver(&receiving.data);
+                } else if receiving.remaining == Some(0) {
+                    self.send_flow_control()?;
+                }
+            }
+            FLOW_CONTROL if data.len() >= 3 => {
+                let Sending::WaitingForFlowControl { offset, sequence } = self.sending else {
+                    return Ok(());
+                };
+                match data[0] & 0x0F {
+                    CONTINUE => {
+                        self.sending = Sending::Consecutive {
+                            offset,
+                            sequence,
+                            remaining: (data[1] > 0).then_some(data[1]),
+                        };
+                    }
+                    WAIT => {}
+                    OVERFLOW => {
+                        self.sending = Sending::Idle;
+                        return Err(ProtocolError::BufferFull);
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn send_flow_control(&mut self) -> Result<(), ProtocolError> {
+        if let Some(receiving) = self.receiving.as_mut() {
+            receiving.remaining = (self.block_size > 0).then_some(self.block_size);
+        }
+        let data = [
+            FLOW_CONTROL << 4 | CONTINUE,
+            self.block_size,
+            self.separation_time,
+        ];
+        // flow control goes ahead of anything of ours still waiting, the other side is
+        // stuck until it gets it
+        let frame = self.frame(&data);
+        self.outbox.push_front(frame);
+        self.drain_outbox().map(|_| ())
+    }
+
+    fn deliver(&mut self, data: &[u8]) {
+        if self.received_read == self.received.len() {
+            self.received.clear();
+            self.received_read = 0;
+        }
+        self.received.extend_from_slice(data);
+    }
+}
+
+impl<C: Can> Transport for CanTransport<C> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+        self.poll_receive()?;
+        self.poll_send()?;
+        if bytes.is_empty() || self.is_sending() {
+            return Ok(0);
+        }
+
+        let len = bytes.len().min(MAX_MESSAGE_LEN);
+        if len <= 7 {
+            let mut data = [0u8; 8];
+            data[0] = SINGLE << 4 | len as u8;
+            data[1..=len].copy_from_slice(&bytes[..len]);
+            self.transmit(&data[..=len])?;
+        } else {
+            let mut data = [0u8; 8];
+            data[0] = FIRST << 4 | (len >> 8) as u8;
+            data[1] = len as u8;
+            data[2..].copy_from_slice(&bytes[..6]);
+            self.message.clear();
+            self.message.extend_from_slice(&bytes[..len]);
+            self.sending = Sending::WaitingForFlowControl {
+                offset: 6,
+                sequence: 1,
+            };
+            self.transmit(&data)?;
+        }
+        Ok(len)
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        self.poll_receive()?;
+        self.poll_send()?;
+        let start = self.received_read;
+        let len = (self.received.len() - start).min(buf.len());
+        buf[..len].copy_from_slice(&self.received[start..start + len]);
+        self.received_read += len;
+        Ok(len)
+    }
+
+    // Spins until the message being segmented is completely on the bus
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        while self.is_sending() {
+            self.poll_receive()?;
+            self.poll_send()?;
+        }
+        Ok(())
+    }
+}
+
+fn can_error(kind: ErrorKind) -> ProtocolError {
+    match kind {
+        ErrorKind::Overrun => TransportError::Overrun.into(),
+        ErrorKind::Bit | ErrorKind::Stuff | ErrorKind::Form | ErrorKind::Acknowledge => {
+            TransportError::Bus.into()
+        }
+        ErrorKind::Crc => ProtocolError::MalformedFrame,
+        _ => TransportError::Other.into(),
+    }
+}
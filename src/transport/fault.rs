@@ -0,0 +1,209 @@
+// Wraps another transport and damages frames on their way out, to see what the checksums,
+// the decoder's resync and retransmission do with a bad line:
+//
+//   let noisy = FaultyTransport::new(uart, cobs::DELIMITER)
+//       .with_probability(Fault::FlipBit, 0.01)
+//       .with_probability(Fault::Drop, 0.005)
+//       .with_fault_at(3, Fault::Truncate);
+//   let mut link = Link::new(noisy, Cobs::default());
+//
+// Frames are found by their end delimiter (`cobs::DELIMITER`, `slip::END`, `hdlc::FLAG`),
+// which is why `SyncWord` framing can't be used here. Lone delimiters (HDLC's opening flag,
+// SLIP's leading END) pass through untouched and don't count as frames. Each frame is
+// numbered from 0 in the order it was written; every fault is rolled independently per
+// frame, plus whatever `with_fault_at` scheduled for that index.
+//
+// Only the write side is touched, wrap the other end's transport to damage the other
+// direction. The random numbers come from a fixed seed (`with_seed` to change it), so a
+// failing run can be replayed exactly.
+
+use alloc::vec::Vec;
+
+use crate::error::ProtocolError;
+use crate::rng::Rng;
+use crate::transport::Transport;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    // flip one random bit somewhere in the frame
+    FlipBit,
+    // frame never arrives
+    Drop,
+    // frame arrives twice
+    Duplicate,
+    // frame is cut short before its delimiter, as if the line dropped out mid frame
+    Truncate,
+}
+
+const FAULTS: [Fault; 4] = [
+    Fault::Drop,
+    Fault::Truncate,
+    Fault::FlipBit,
+    Fault::Duplicate,
+];
+
+// What has been done so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultStats {
+    pub frames: u64,
+    pub flipped: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub truncated: u64,
+}
+
+pub struct FaultyTransport<T> {
+    inner: T,
+    delimiter: u8,
+    // chance per frame, indexed like `FAULTS`
+    probabilities: [f32; 4],
+    scheduled: Vec<(u64, Fault)>,
+    rng: Rng,
+    // frame written so far, waiting for its delimiter
+    frame: Vec<u8>,
+    // damaged bytes the inner transport hasn't taken yet
+    outgoing: Vec<u8>,
+    stats: FaultStats,
+}
+
+impl<T: Transport> FaultyTransport<T> {
+    pub fn new(inner: T, delimiter: u8) -> Self {
+        FaultyTransport {
+            inner,
+            delimiter,
+            probabilities: [0.0; 4],
+            scheduled: Vec::new(),
+            rng: Rng::new(Rng::DEFAULT_SEED),
+            frame: Vec::new(),
+            outgoing: Vec::new(),
+            stats: FaultStats::default(),
+        }
+    }
+
+    // Chance (0.0 ..= 1.0) of `fault` hitting any one frame
+    pub fn with_probability(mut self, fault: Fault, probability: f32) -> Self {
+        self.probabilities[index(fault)] = probability;
+        self
+    }
+
+    // `fault` hits the frame with this index, on top of the random ones
+    pub fn with_fault_at(mut self, frame: u64, fault: Fault) -> Self {
+        self.scheduled.push((frame, fault));
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    pub fn stats(&self) -> FaultStats {
+        self.stats
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    // A frame is complete (delimiter included), damage it and queue what's left of it
+    fn finish_frame(&mut self) {
+        let mut frame = core::mem::take(&mut self.frame);
+        let number = self.stats.frames;
+        self.stats.frames += 1;
+
+        let mut hits = [false; 4];
+        for (i, &fault) in FAULTS.iter().enumerate() {
+            hits[i] = self.rng.chance(self.probabilities[i])
+                || self
+                    .scheduled
+                    .iter()
+                    .any(|&(at, scheduled)| at == number && scheduled == fault);
+        }
+
+        if hits[index(Fault::Drop)] {
+            self.stats.dropped += 1;
+            return;
+        }
+        let body = frame.len() - 1;
+        if hits[index(Fault::Truncate)] && body > 1 {
+            // keep 1..body bytes, the delimiter stays so the receiver sees a short frame
+            let keep = 1 + self.rng.below(body - 1);
+            frame.drain(keep..body);
+            self.stats.truncated += 1;
+        }
+        if hits[index(Fault::FlipBit)] {
+            let byte = self.rng.below(frame.len() - 1);
+            frame[byte] ^= 1 << self.rng.below(8);
+            self.stats.flipped += 1;
+        }
+        if hits[index(Fault::Duplicate)] {
+            self.outgoing.extend_from_slice(&frame);
+            self.stats.duplicated += 1;
+        }
+        self.outgoing.extend_from_slice(&frame);
+    }
+
+    // Hand as much of the damaged backlog to the inner transport as it takes
+    fn poll_write(&mut self) -> Result<(), ProtocolError> {
+        while !self.outgoing.is_empty() {
+            let written = self.inner.write_bytes(&self.outgoing)?;
+            if written == 0 {
+                break;
+            }
+            self.outgoing.drain(..written);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Transport> Transport for FaultyTransport<T> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+        self.poll_write()?;
+        // the inner transport is still full, push back instead of piling up here
+        if !self.outgoing.is_empty() {
+            return Ok(0);
+        }
+
+        for &byte in bytes {
+            if byte == self.delimiter && self.frame.is_empty() {
+                self.outgoing.push(byte);
+                continue;
+            }
+            self.frame.push(byte);
+            if byte == self.delimiter {
+                self.finish_frame();
+            }
+        }
+        self.poll_write()?;
+        Ok(bytes.len())
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        self.poll_write()?;
+        self.inner.read_bytes(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        while !self.outgoing.is_empty() {
+            self.poll_write()?;
+        }
+        self.inner.flush()
+    }
+}
+
+fn index(fault: Fault) -> usize {
+    match fault {
+        Fault::Drop => 0,
+        Fault::Truncate => 1,
+        Fault::FlipBit => 2,
+        Fault::Duplicate => 3,
+    }
+}
@@ -0,0 +1,141 @@
+// `Transport` over a HAL UART, through the nb based serial traits: `HalSerial` for
+// embedded-hal 0.2's `serial::{Read, Write}`, `NbSerial` for `embedded_hal_nb::serial`, where
+// they live since embedded-hal 1.0. Both take the transmit and receive halves separately,
+// most HALs hand them out through `serial.split()`:
+//
+//   let (tx, rx) = serial.split();
+//   let mut link = Link::new(NbSerial::new(tx, rx), Cobs::default());
+//
+// Bytes go one at a time until the peripheral says `WouldBlock`, so a send only pushes what
+// the TX FIFO takes and the rest waits in `Link`. Poll `receive` often enough that the RX
+// FIFO doesn't overrun, or feed it from an interrupt through `SpscRing`.
+
+use crate::error::{ProtocolError, TransportError};
+use crate::transport::Transport;
+
+// embedded-hal 0.2 serial halves
+#[cfg(feature = "embedded-hal-02")]
+pub struct HalSerial<Tx, Rx> {
+    tx: Tx,
+    rx: Rx,
+}
+
+#[cfg(feature = "embedded-hal-02")]
+impl<Tx, Rx> HalSerial<Tx, Rx>
+where
+    Tx: embedded_hal_02::serial::Write<u8>,
+    Rx: embedded_hal_02::serial::Read<u8>,
+{
+    pub fn new(tx: Tx, rx: Rx) -> Self {
+        HalSerial { tx, rx }
+    }
+
+    pub fn into_inner(self) -> (Tx, Rx) {
+        (self.tx, self.rx)
+    }
+}
+
+#[cfg(feature = "embedded-hal-02")]
+impl<Tx, Rx> Transport for HalSerial<Tx, Rx>
+where
+    Tx: embedded_hal_02::serial::Write<u8>,
+    Rx: embedded_hal_02::serial::Read<u8>,
+{
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+        // 0.2 errors are opaque, nothing better to report than `Other`
+        write_each(bytes, |byte| self.tx.write(byte), |_| TransportError::Other)
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        read_each(buf, || self.rx.read(), |_| TransportError::Other)
+    }
+
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        nb::block!(self.tx.flush()).map_err(|_| TransportError::Other.into())
+    }
+}
+
+// embedded-hal-nb serial halves
+#[cfg(feature = "embedded-hal-nb")]
+pub struct NbSerial<Tx, Rx> {
+    tx: Tx,
+    rx: Rx,
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+impl<Tx, Rx> NbSerial<Tx, Rx>
+where
+    Tx: embedded_hal_nb::serial::Write<u8>,
+    Rx: embedded_hal_nb::serial::Read<u8>,
+{
+    pub fn new(tx: Tx, rx: Rx) -> Self {
+        NbSerial { tx, rx }
+    }
+
+    pub fn into_inner(self) -> (Tx, Rx) {
+        (self.tx, self.rx)
+    }
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+impl<Tx, Rx> Transport for NbSerial<Tx, Rx>
+where
+    Tx: embedded_hal_nb::serial::Write<u8>,
+    Rx: embedded_hal_nb::serial::Read<u8>,
+{
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+        write_each(bytes, |byte| self.tx.write(byte), nb_error)
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        read_each(buf, || self.rx.read(), nb_error)
+    }
+
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        nb::block!(self.tx.flush()).map_err(|error| nb_error(error).into())
+    }
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+fn nb_error<E: embedded_hal_nb::serial::Error>(error: E) -> TransportError {
+    use embedded_hal_nb::serial::ErrorKind;
+    match error.kind() {
+        ErrorKind::Overrun => TransportError::Overrun,
+        ErrorKind::FrameFormat => TransportError::FrameFormat,
+        ErrorKind::Parity => TransportError::Parity,
+        ErrorKind::Noise => TransportError::Noise,
+        _ => TransportError::Other,
+    }
+}
+
+// push bytes until the peripheral would block, returns how many went
+fn write_each<E>(
+    bytes: &[u8],
+    mut write: impl FnMut(u8) -> nb::Result<(), E>,
+    map: impl Fn(E) -> TransportError,
+) -> Result<usize, ProtocolError> {
+    for (written, &byte) in bytes.iter().enumerate() {
+        match write(byte) {
+            Ok(()) => {}
+            Err(nb::Error::WouldBlock) => return Ok(written),
+            Err(nb::Error::Other(error)) => return Err(map(error).into()),
+        }
+    }
+    Ok(bytes.len())
+}
+
+// pull bytes until there are none left or `buf` is full
+fn read_each<E>(
+    buf: &mut [u8],
+    mut read: impl FnMut() -> nb::Result<u8, E>,
+    map: impl Fn(E) -> TransportError,
+) -> Result<usize, ProtocolError> {
+    for (index, slot) in buf.iter_mut().enumerate() {
+        match read() {
+            Ok(byte) => *slot = byte,
+            Err(nb::Error::WouldBlock) => return Ok(index),
+            Err(nb::Error::Other(error)) => return Err(map(error).into()),
+        }
+    }
+    Ok(buf.len())
+}
@@ -0,0 +1,131 @@
+// I2C master side over an embedded-hal 1.0 `I2c` bus, for an MCU2 that sits on I2C as a slave
+// at `address`. MCU2 exposes two registers:
+//
+//   data register     write: frame bytes for MCU2, read: frame bytes from MCU2
+//   count register    read: how many bytes MCU2 has queued for us (0..=255)
+//
+// I2C controllers and slaves usually only buffer a handful of bytes, so every transfer moves at
+// most `N` bytes of frame data. A write is `[data register][up to N bytes]`, a read is a one
+// byte read of the count register followed, if anything is waiting, by a read of at most `N`
+// bytes from the data register. Polling an idle slave is just the count read.
+//
+// A slave that can't take a whole write right now NACKs its address, that counts as nothing
+// written and the bytes go again later. A NACK in the middle of the data is an error, we can't
+// tell how much of it made it. Note a slave that's missing altogether looks busy forever.
+
+use embedded_hal::i2c::{Error as _, ErrorKind, I2c, NoAcknowledgeSource, SevenBitAddress};
+
+use crate::error::{ProtocolError, TransportError};
+use crate::transport::Transport;
+
+pub const DEFAULT_DATA_REGISTER: u8 = 0x00;
+pub const DEFAULT_COUNT_REGISTER: u8 = 0x01;
+
+pub struct I2cTransport<I, const N: usize = 16> {
+    bus: I,
+    address: SevenBitAddress,
+    data_register: u8,
+    count_register: u8,
+}
+
+impl<I: I2c, const N: usize> I2cTransport<I, N> {
+    pub fn new(bus: I, address: SevenBitAddress) -> Self {
+        const {
+            assert!(N >= 1 && N <= 255, "I2C transfers must be 1..=255 bytes");
+        }
+        I2cTransport {
+            bus,
+            address,
+            data_register: DEFAULT_DATA_REGISTER,
+            count_register: DEFAULT_COUNT_REGISTER,
+        }
+    }
+
+    // Register map of the slave, if it doesn't use the defaults
+    pub fn with_registers(mut self, data: u8, count: u8) -> Self {
+        self.data_register = data;
+        self.count_register = count;
+        self
+    }
+
+    pub fn address(&self) -> SevenBitAddress {
+        self.address
+    }
+
+    pub fn bus_mut(&mut self) -> &mut I {
+        &mut self.bus
+    }
+
+    pub fn into_inner(self) -> I {
+        self.bus
+    }
+
+    // frame data bytes per transfer
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    // Bytes MCU2 has waiting for us, 0 while it's busy
+    pub fn available(&mut self) -> Result<usize, ProtocolError> {
+        let mut count = [0u8];
+        match self
+            .bus
+            .write_read(self.address, &[self.count_register], &mut count)
+        {
+            Ok(()) => Ok(count[0] as usize),
+            Err(error) if address_nack(error.kind()) => Ok(0),
+            Err(error) => Err(i2c_error(error.kind())),
+        }
+    }
+}
+
+impl<I: I2c, const N: usize> Transport for I2cTransport<I, N> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+        if bytes.is_empty() {
+            return Ok(0);
+        }
+        // the register address goes out in the same transfer as the data
+        let len = bytes.len().min(N);
+        let mut outgoing = [0u8; 256];
+        outgoing[0] = self.data_register;
+        outgoing[1..=len].copy_from_slice(&bytes[..len]);
+
+        match self.bus.write(self.address, &outgoing[..=len]) {
+            Ok(()) => Ok(len),
+            Err(error) if address_nack(error.kind()) => Ok(0),
+            Err(error) => Err(i2c_error(error.kind())),
+        }
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = self.available()?.min(N).min(buf.len());
+        if len == 0 {
+            return Ok(0);
+        }
+        match self
+            .bus
+            .write_read(self.address, &[self.data_register], &mut buf[..len])
+        {
+            Ok(()) => Ok(len),
+            Err(error) if address_nack(error.kind()) => Ok(0),
+            Err(error) => Err(i2c_error(error.kind())),
+        }
+    }
+}
+
+// the slave is busy, not broken
+fn address_nack(kind: ErrorKind) -> bool {
+    matches!(kind, ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))
+}
+
+fn i2c_error(kind: ErrorKind) -> ProtocolError {
+    match kind {
+        ErrorKind::NoAcknowledge(_) => TransportError::Nack.into(),
+        ErrorKind::Bus | ErrorKind::ArbitrationLoss => TransportError::Bus.into(),
+        ErrorKind::Overrun => TransportError::Overrun.into(),
+        _ => TransportError::Other.into(),
+    }
+}
@@ -0,0 +1,188 @@
+// Wraps another transport and holds frames back before passing them on, to try timeouts,
+// retransmit intervals and flow control against a slow or bursty link on the desk:
+//
+//   let slow = LatencyTransport::new(Loopback::new(), cobs::DELIMITER, StdClock::new())
+//       .with_latency(Duration::from_millis(20))
+//       .with_jitter(Duration::from_millis(15))
+//       .with_reordering(true);
+//
+// Every frame written (found by its end delimiter, same as `FaultyTransport`) is due at
+// `now + latency + random(0..=jitter)`. Without reordering a frame is never due before the
+// one written ahead of it, so jitter only bunches frames up; with it a frame that rolled a
+// short delay overtakes the others, like packets on a network. Frames are handed to the
+// inner transport once they're due, whenever this transport is written, read or flushed.
+//
+// Nothing here waits, `flush` only releases what's already due. `next_due` says when the
+// next frame will be, for a loop driving a virtual clock.
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::error::ProtocolError;
+use crate::rng::Rng;
+use crate::time::Clock;
+use crate::transport::Transport;
+
+struct Delayed {
+    due: Duration,
+    // write order, breaks ties between frames due at the same time
+    sequence: u64,
+    bytes: Vec<u8>,
+}
+
+pub struct LatencyTransport<T, C> {
+    inner: T,
+    clock: C,
+    delimiter: u8,
+    latency: Duration,
+    jitter: Duration,
+    reordering: bool,
+    rng: Rng,
+    // bytes of the frame being written, waiting for its delimiter
+    frame: Vec<u8>,
+    // frames not due yet, in no particular order
+    delayed: Vec<Delayed>,
+    next_sequence: u64,
+    // due bytes the inner transport hasn't taken yet
+    outgoing: Vec<u8>,
+}
+
+impl<T: Transport, C: Clock> LatencyTransport<T, C> {
+    pub fn new(inner: T, delimiter: u8, clock: C) -> Self {
+        LatencyTransport {
+            inner,
+            clock,
+            delimiter,
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            reordering: false,
+            rng: Rng::new(Rng::DEFAULT_SEED),
+            frame: Vec::new(),
+            delayed: Vec::new(),
+            next_sequence: 0,
+            outgoing: Vec::new(),
+        }
+    }
+
+    // Delay every frame gets
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    // Up to this much more, picked at random for each frame
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    // Let frames overtake each other when their jitter says so
+    pub fn with_reordering(mut self, reordering: bool) -> Self {
+        self.reordering = reordering;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    // Frames written that haven't been passed on yet
+    pub fn in_flight(&self) -> usize {
+        self.delayed.len()
+    }
+
+    // When the earliest held back frame is due
+    pub fn next_due(&self) -> Option<Duration> {
+        self.delayed.iter().map(|frame| frame.due).min()
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn delay(&mut self) -> Duration {
+        let jitter = self.jitter.as_micros() as usize;
+        let extra = Duration::from_micros(self.rng.below(jitter + 1) as u64);
+        let mut due = self.clock.now() + self.latency + extra;
+        if !self.reordering
+            && let Some(last) = self.delayed.iter().map(|frame| frame.due).max()
+        {
+            due = due.max(last);
+        }
+        due
+    }
+
+    fn hold(&mut self, bytes: Vec<u8>) {
+        let due = self.delay();
+        self.delayed.push(Delayed {
+            due,
+            sequence: self.next_sequence,
+            bytes,
+        });
+        self.next_sequence += 1;
+    }
+
+    // Move every frame that's due into the outgoing bytes, earliest first, and hand those to
+    // the inner transport
+    fn release(&mut self) -> Result<(), ProtocolError> {
+        let now = self.clock.now();
+        while let Some(next) = self
+            .delayed
+            .iter()
+            .enumerate()
+            .filter(|(_, frame)| frame.due <= now)
+            .min_by_key(|(_, frame)| (frame.due, frame.sequence))
+            .map(|(i, _)| i)
+        {
+            let frame = self.delayed.swap_remove(next);
+            self.outgoing.extend_from_slice(&frame.bytes);
+        }
+
+        while !self.outgoing.is_empty() {
+            let written = self.inner.write_bytes(&self.outgoing)?;
+            if written == 0 {
+                break;
+            }
+            self.outgoing.drain(..written);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Transport, C: Clock> Transport for LatencyTransport<T, C> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+        for &byte in bytes {
+            self.frame.push(byte);
+            if byte == self.delimiter {
+                let frame = core::mem::take(&mut self.frame);
+                // lone delimiters ride along with whatever frame they sit in front of
+                if frame.len() > 1 {
+                    self.hold(frame);
+                } else {
+                    self.frame = frame;
+                }
+            }
+        }
+        self.release()?;
+        Ok(bytes.len())
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        self.release()?;
+        self.inner.read_bytes(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        self.release()?;
+        self.inner.flush()
+    }
+}
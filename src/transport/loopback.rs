@@ -0,0 +1,187 @@
+// In-memory transport for tests, no OS or hardware involved. `Loopback::new` reads back what
+// it wrote, `Loopback::pair` gives two ends where each reads what the other wrote:
+//
+//   let (mcu1, mcu2) = Loopback::pair();
+//   let mut mcu1 = Link::new(mcu1, Cobs::default());
+//   let mut mcu2 = Link::new(mcu2, Cobs::default());
+//   mcu1.send(b"ping".to_vec())?;
+//   assert_eq!(mcu2.receive().unwrap()?.payload, b"ping");
+//
+// Bytes arrive right away and in order, so a test runs the same every time. `with_capacity`
+// makes writes stop short once that many bytes are waiting, like a small UART FIFO nobody is
+// reading. The ends share their queues through an `Rc`, they stay on one thread.
+
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use core::cell::RefCell;
+
+use crate::error::ProtocolError;
+use crate::transport::Transport;
+
+type Queue = Rc<RefCell<VecDeque<u8>>>;
+
+pub struct Loopback {
+    incoming: Queue,
+    outgoing: Queue,
+    capacity: Option<usize>,
+}
+
+impl Loopback {
+    // One end that reads back its own writes
+    pub fn new() -> Self {
+        let queue = Queue::default();
+        Loopback {
+            incoming: queue.clone(),
+            outgoing: queue,
+            capacity: None,
+        }
+    }
+
+    // Two connected ends
+    pub fn pair() -> (Self, Self) {
+        let (a, b) = (Queue::default(), Queue::default());
+        (
+            Loopback {
+                incoming: a.clone(),
+                outgoing: b.clone(),
+                capacity: None,
+            },
+            Loopback {
+                incoming: b,
+                outgoing: a,
+                capacity: None,
+            },
+        )
+    }
+
+    // At most `capacity` bytes waiting in our outgoing direction
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    // Bytes written that the reader hasn't taken yet
+    pub fn pending(&self) -> usize {
+        self.outgoing.borrow().len()
+    }
+
+    // Bytes waiting for us
+    pub fn available(&self) -> usize {
+        self.incoming.borrow().len()
+    }
+
+    // Put raw bytes in front of our reader as if they came off the wire, e.g. a corrupted
+    // frame or line noise
+    pub fn inject(&mut self, bytes: &[u8]) {
+        self.incoming.borrow_mut().extend(bytes);
+    }
+
+    // Drop everything in flight in both directions
+    pub fn clear(&mut self) {
+        self.incoming.borrow_mut().clear();
+        self.outgoing.borrow_mut().clear();
+    }
+}
+
+impl Default for Loopback {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for Loopback {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+        let mut outgoing = self.outgoing.borrow_mut();
+        let room = self.capacity.map_or(usize::MAX, |capacity| {
+            capacity.saturating_sub(outgoing.len())
+        });
+        let len = bytes.len().min(room);
+        outgoing.extend(&bytes[..len]);
+        Ok(len)
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        let mut incoming = self.incoming.borrow_mut();
+        let len = buf.len().min(incoming.len());
+        for (slot, byte) in buf.iter_mut().zip(incoming.drain(..len)) {
+            *slot = byte;
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framing::Cobs;
+    use crate::link::Link;
+
+    #[test]
+    fn pair_round_trip() {
+        let (mcu1, mcu2) = Loopback::pair();
+        let mut mcu1 = Link::new(mcu1, Cobs::default());
+        let mut mcu2 = Link::new(mcu2, Cobs::default());
+
+        let id = mcu1.send(b"ping".to_vec()).unwrap();
+        let message = mcu2.receive().unwrap().unwrap();
+        assert_eq!(message.id, id);
+        assert_eq!(&message.payload[..], b"ping");
+        assert!(mcu2.receive().is_none());
+
+        mcu2.send(b"pong".to_vec()).unwrap();
+        assert_eq!(&mcu1.receive().unwrap().unwrap().payload[..], b"pong");
+    }
+
+    #[test]
+    fn new_reads_back_its_own_writes() {
+        let mut end = Loopback::new();
+        assert_eq!(end.write_bytes(b"abc").unwrap(), 3);
+        assert_eq!(end.available(), 3);
+
+        let mut buf = [0u8; 8];
+        assert_eq!(end.read_bytes(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"abc");
+        assert_eq!(end.read_bytes(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn capacity_stops_writes_short() {
+        let (a, mut b) = Loopback::pair();
+        let mut a = a.with_capacity(4);
+        assert_eq!(a.write_bytes(b"abcdef").unwrap(), 4);
+        assert_eq!(a.write_bytes(b"gh").unwrap(), 0);
+        assert_eq!(a.pending(), 4);
+
+        let mut buf = [0u8; 2];
+        assert_eq!(b.read_bytes(&mut buf).unwrap(), 2);
+        assert_eq!(a.write_bytes(b"gh").unwrap(), 2);
+    }
+
+    #[test]
+    fn injected_noise_is_skipped() {
+        let (mcu1, mcu2) = Loopback::pair();
+        let mut mcu1 = Link::new(mcu1, Cobs::default());
+        let mut mcu2 = Link::new(mcu2, Cobs::default());
+
+        mcu2.transport_mut().inject(&[0x13, 0x37, 0x00]);
+        mcu1.send(b"after noise".to_vec()).unwrap();
+
+        let mut delivered = None;
+        while let Some(received) = mcu2.receive() {
+            if let Ok(message) = received {
+                delivered = Some(message);
+            }
+        }
+        assert_eq!(&delivered.unwrap().payload[..], b"after noise");
+    }
+
+    #[test]
+    fn clear_drops_what_is_in_flight() {
+        let (mut a, mut b) = Loopback::pair();
+        a.write_bytes(b"lost").unwrap();
+        b.write_bytes(b"too").unwrap();
+        a.clear();
+        assert_eq!(a.pending(), 0);
+        assert_eq!(b.pending(), 0);
+    }
+}
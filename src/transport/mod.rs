@@ -0,0 +1,127 @@
+// How bytes actually get from one MCU to the other. A `Transport` only moves raw bytes, the
+// framing and checksums on top are `Link`'s job, so the same protocol logic runs over a UART,
+// a socket or whatever else implements these three calls.
+//
+// Both directions are non-blocking: `write_bytes` takes what it can right now and says how
+// much, `read_bytes` hands over whatever has arrived so far. That maps straight onto a UART
+// FIFO or a non-blocking socket and keeps `Link` usable from a superloop.
+
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+
+use crate::error::ProtocolError;
+
+#[cfg(feature = "embedded-can")]
+pub mod can;
+#[cfg(feature = "alloc")]
+pub mod fault;
+#[cfg(any(feature = "embedded-hal-02", feature = "embedded-hal-nb"))]
+pub mod hal;
+#[cfg(feature = "embedded-hal")]
+pub mod i2c;
+#[cfg(feature = "alloc")]
+pub mod latency;
+#[cfg(feature = "alloc")]
+pub mod loopback;
+#[cfg(feature = "rtt-target")]
+pub mod rtt;
+#[cfg(feature = "serialport")]
+pub mod serial;
+#[cfg(target_has_atomic = "32")]
+pub mod shared;
+#[cfg(feature = "embedded-hal")]
+pub mod spi;
+#[cfg(feature = "std")]
+pub mod tcp;
+#[cfg(feature = "std")]
+pub mod udp;
+#[cfg(all(feature = "std", unix))]
+pub mod unix;
+#[cfg(feature = "usbd-serial")]
+pub mod usb;
+
+#[cfg(feature = "embedded-can")]
+pub use can::CanTransport;
+#[cfg(feature = "alloc")]
+pub use fault::{Fault, FaultyTransport};
+#[cfg(feature = "embedded-hal-02")]
+pub use hal::HalSerial;
+#[cfg(feature = "embedded-hal-nb")]
+pub use hal::NbSerial;
+#[cfg(feature = "embedded-hal")]
+pub use i2c::I2cTransport;
+#[cfg(feature = "alloc")]
+pub use latency::LatencyTransport;
+#[cfg(feature = "alloc")]
+pub use loopback::Loopback;
+#[cfg(feature = "rtt-target")]
+pub use rtt::RttTransport;
+#[cfg(feature = "serialport")]
+pub use serial::SerialTransport;
+#[cfg(feature = "shared-memory")]
+pub use shared::ShmTransport;
+#[cfg(target_has_atomic = "32")]
+pub use shared::{SharedRegion, Side};
+#[cfg(feature = "embedded-hal")]
+pub use spi::SpiTransport;
+#[cfg(feature = "std")]
+pub use tcp::TcpTransport;
+#[cfg(feature = "std")]
+pub use udp::UdpTransport;
+#[cfg(all(feature = "std", unix))]
+pub use unix::UnixTransport;
+#[cfg(feature = "usbd-serial")]
+pub use usb::UsbSerialTransport;
+
+pub trait Transport {
+    // Send as much of `bytes` as possible without waiting, returns how many were taken
+    // (0 = nothing fits right now, try again later)
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError>;
+
+    // Copy whatever has arrived into `buf` without waiting, 0 if nothing has
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError>;
+
+    // Push out anything the transport itself still buffers
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        Ok(())
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for &mut T {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+        (**self).write_bytes(bytes)
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        (**self).read_bytes(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        (**self).flush()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+        (**self).write_bytes(bytes)
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        (**self).read_bytes(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        (**self).flush()
+    }
+}
+
+// an OS error that just means "nothing right now", not a broken transport
+#[cfg(feature = "std")]
+pub(crate) fn would_block(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(
+        error.kind(),
+        ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted
+    )
+}
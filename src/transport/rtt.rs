@@ -0,0 +1,50 @@
+// SEGGER RTT through `rtt-target`, for bring-up when the debug probe is the only connection
+// to the board. Give the protocol its own pair of channels next to the log terminal:
+//
+//   let channels = rtt_init! {
+//       up: { 0: { size: 512, name: "Terminal" } 1: { size: 1024, name: "canopy" } }
+//       down: { 0: { size: 16, name: "Terminal" } 1: { size: 1024, name: "canopy" } }
+//   };
+//   let mut link = Link::new(RttTransport::new(channels.up.1, channels.down.1), Cobs::default());
+//
+// On the host, OpenOCD can expose the channel as a TCP port (`rtt server start 9091 1`) and
+// `TcpTransport` connects to that.
+//
+// The up channel is switched to `NoBlockTrim`, so a full buffer takes what fits and the rest
+// is retried by `Link` instead of being skipped or hanging the MCU while no probe is reading.
+
+use rtt_target::{ChannelMode, DownChannel, UpChannel};
+
+use crate::error::ProtocolError;
+use crate::transport::Transport;
+
+pub struct RttTransport {
+    up: UpChannel,
+    down: DownChannel,
+}
+
+impl RttTransport {
+    pub fn new(mut up: UpChannel, down: DownChannel) -> Self {
+        up.set_mode(ChannelMode::NoBlockTrim);
+        RttTransport { up, down }
+    }
+
+    // Everything written has been picked up by the probe
+    pub fn is_drained(&self) -> bool {
+        self.up.is_empty()
+    }
+
+    pub fn into_inner(self) -> (UpChannel, DownChannel) {
+        (self.up, self.down)
+    }
+}
+
+impl Transport for RttTransport {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+        Ok(self.up.write(bytes))
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        Ok(self.down.read(buf))
+    }
+}
@@ -0,0 +1,90 @@
+// Host side serial port through the `serialport` crate, for tools talking to a real MCU over
+// /dev/ttyUSB0, COM3 and the like:
+//
+//   let mut link = SerialTransport::open("/dev/ttyUSB0", 115_200)?.link(Cobs::default());
+//   link.send(b"ping".to_vec())?;
+//
+// Reads only take what the OS already has buffered, so `Link::receive` never waits on the
+// port. Writes go straight to the driver.
+
+use alloc::boxed::Box;
+use core::time::Duration;
+use std::io::{self, Read, Write};
+
+use serialport::SerialPort;
+
+use crate::error::{ProtocolError, TransportError};
+use crate::framing::Framing;
+use crate::link::Link;
+use crate::transport::{Transport, would_block};
+
+// how long a write may sit waiting on the driver before it counts as nothing written
+const WRITE_TIMEOUT: Duration = Duration::from_millis(10);
+
+pub struct SerialTransport {
+    port: Box<dyn SerialPort>,
+}
+
+impl SerialTransport {
+    // 8N1 without flow control at `baud_rate`, what nearly every MCU UART defaults to
+    pub fn open(path: &str, baud_rate: u32) -> Result<Self, ProtocolError> {
+        let port = serialport::new(path, baud_rate)
+            .timeout(WRITE_TIMEOUT)
+            .open()
+            .map_err(serial_error)?;
+        Ok(SerialTransport { port })
+    }
+
+    // Any other setup (parity, flow control ...) through the `serialport` builder
+    pub fn from_port(port: Box<dyn SerialPort>) -> Self {
+        SerialTransport { port }
+    }
+
+    pub fn port(&self) -> &dyn SerialPort {
+        &*self.port
+    }
+
+    pub fn port_mut(&mut self) -> &mut dyn SerialPort {
+        &mut *self.port
+    }
+
+    // Wrap it in a `Link` with the given framing
+    pub fn link<F: Framing>(self, framing: F) -> Link<Self, F> {
+        Link::new(self, framing)
+    }
+}
+
+impl Transport for SerialTransport {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+        match self.port.write(bytes) {
+            Ok(written) => Ok(written),
+            Err(error) if would_block(&error) => Ok(0),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        let available = self.port.bytes_to_read().map_err(serial_error)? as usize;
+        if available == 0 {
+            return Ok(0);
+        }
+        let len = available.min(buf.len());
+        match self.port.read(&mut buf[..len]) {
+            Ok(read) => Ok(read),
+            Err(error) if would_block(&error) => Ok(0),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        self.port.flush().map_err(ProtocolError::from)
+    }
+}
+
+fn serial_error(error: serialport::Error) -> ProtocolError {
+    match error.kind() {
+        serialport::ErrorKind::NoDevice => ProtocolError::Disconnected,
+        serialport::ErrorKind::Io(kind) => io::Error::from(kind).into(),
+        _ => ProtocolError::Transport(TransportError::Io),
+    }
+}
@@ -0,0 +1,293 @@
+// Two byte rings in a block of memory both sides can see: the dual-port RAM between the two
+// MCUs on the real board, or a memory mapped file when both ends are processes on a host. The
+// layout is plain `u32`s and bytes so both sides can be built separately (or not even in
+// Rust), only the offsets have to match:
+//
+//   0   magic    `MAGIC` once the region is formatted
+//   4   capacity data bytes per ring
+//   8   ring 1   head, tail (MCU1 -> MCU2)
+//   16  ring 2   head, tail (MCU2 -> MCU1)
+//   32  data     ring 1 then ring 2, `capacity` bytes each
+//
+// Each ring has one writer and one reader. The writer only moves `tail` and the reader only
+// moves `head`, both count bytes modulo `2 * capacity`, so like `SpscRing` no lock and no
+// compare-and-swap is needed, just atomic loads and stores with release/acquire ordering
+// around the data. A full ring takes nothing, `write_bytes` reports 0 and `Link` retries.
+//
+// One side formats the region before the other attaches to it. `ShmTransport` (with the
+// `shared-memory` feature) does all of that on top of a file:
+//
+//   let mcu1 = ShmTransport::create("/dev/shm/canopy", 4096)?;   // first process
+//   let mcu2 = ShmTransport::open("/dev/shm/canopy")?;           // second process
+
+use core::ptr;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::error::ProtocolError;
+use crate::transport::Transport;
+
+// "CNPY"
+pub const MAGIC: u32 = 0x4350_4E59;
+// bytes in front of the ring data
+pub const HEADER_LEN: usize = 32;
+
+const MAGIC_OFFSET: usize = 0;
+const CAPACITY_OFFSET: usize = 4;
+const RING_OFFSETS: [usize; 2] = [8, 16];
+
+// Which end of the region we are, decides which ring we write and which we read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Mcu1,
+    Mcu2,
+}
+
+// Region size needed for rings of `capacity` bytes
+pub const fn region_len(capacity: usize) -> usize {
+    HEADER_LEN + 2 * capacity
+}
+
+pub struct SharedRegion {
+    base: *mut u8,
+    capacity: usize,
+    side: Side,
+}
+
+// Only our own ring ends are ever written, the other side's go through atomics
+unsafe impl Send for SharedRegion {}
+
+impl SharedRegion {
+    /// Lay out empty rings over `len` bytes at `base`, as big as fit.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be valid for `len` bytes, 4 byte aligned, and nobody may be attached to the
+    /// region while it's formatted.
+    pub unsafe fn format(base: *mut u8, len: usize) -> Result<(), ProtocolError> {
+        if base.align_offset(4) != 0 || len < region_len(1) {
+            return Err(ProtocolError::InvalidHeader);
+        }
+        let capacity = ((len - HEADER_LEN) / 2).min(u32::MAX as usize / 4);
+        unsafe {
+            ptr::write_bytes(base, 0, HEADER_LEN);
+            word(base, CAPACITY_OFFSET).store(capacity as u32, Ordering::Relaxed);
+            // published last, whoever sees the magic sees the rest
+            word(base, MAGIC_OFFSET).store(MAGIC, Ordering::Release);
+        }
+        Ok(())
+    }
+
+    /// Attach to a formatted region of `len` bytes at `base`. `InvalidHeader` if it isn't
+    /// formatted (yet) or claims to be bigger than `len`.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be valid for `len` bytes and stay that way while the `SharedRegion` lives,
+    /// be 4 byte aligned, and only one `SharedRegion` per side may use it at a time.
+    pub unsafe fn attach(base: *mut u8, len: usize, side: Side) -> Result<Self, ProtocolError> {
+        if base.align_offset(4) != 0 || len < HEADER_LEN {
+            return Err(ProtocolError::InvalidHeader);
+        }
+        let (magic, capacity) = unsafe {
+            (
+                word(base, MAGIC_OFFSET).load(Ordering::Acquire),
+                word(base, CAPACITY_OFFSET).load(Ordering::Relaxed) as usize,
+            )
+        };
+        if magic != MAGIC
+            || capacity == 0
+            || capacity > u32::MAX as usize / 4
+            || region_len(capacity) > len
+        {
+            return Err(ProtocolError::InvalidHeader);
+        }
+        Ok(SharedRegion {
+            base,
+            capacity,
+            side,
+        })
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    // data bytes per ring
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Bytes we've written the other side hasn't read yet
+    pub fn unread_outgoing(&self) -> usize {
+        self.used(self.outgoing())
+    }
+
+    // Bytes waiting for us
+    pub fn available(&self) -> usize {
+        self.used(self.incoming())
+    }
+
+    fn used(&self, ring: usize) -> usize {
+        let (head, tail) = self.ring(ring);
+        self.distance(head.load(Ordering::Acquire), tail.load(Ordering::Acquire))
+    }
+
+    // bytes from `head` to `tail`, both counting modulo 2 * capacity
+    fn distance(&self, head: u32, tail: u32) -> usize {
+        let wrap = 2 * self.capacity;
+        (tail as usize + wrap - head as usize) % wrap
+    }
+
+    fn advance(&self, count: u32, amount: usize) -> u32 {
+        ((count as usize + amount) % (2 * self.capacity)) as u32
+    }
+
+    fn outgoing(&self) -> usize {
+        match self.side {
+            Side::Mcu1 => 0,
+            Side::Mcu2 => 1,
+        }
+    }
+
+    fn incoming(&self) -> usize {
+        1 - self.outgoing()
+    }
+
+    fn ring(&self, ring: usize) -> (&AtomicU32, &AtomicU32) {
+        let offset = RING_OFFSETS[ring];
+        unsafe { (word(self.base, offset), word(self.base, offset + 4)) }
+    }
+
+    fn data(&self, ring: usize) -> *mut u8 {
+        unsafe { self.base.add(HEADER_LEN + ring * self.capacity) }
+    }
+}
+
+impl Transport for SharedRegion {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+        let ring = self.outgoing();
+        let (head, tail) = self.ring(ring);
+        let tail_count = tail.load(Ordering::Relaxed);
+        let used = self.distance(head.load(Ordering::Acquire), tail_count);
+        let len = bytes.len().min(self.capacity - used);
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let start = tail_count as usize % self.capacity;
+        let first = len.min(self.capacity - start);
+        let data = self.data(ring);
+        // the reader won't look past `tail`, so these bytes are ours until it moves
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), data.add(start), first);
+            ptr::copy_nonoverlapping(bytes[first..].as_ptr(), data, len - first);
+        }
+        tail.store(self.advance(tail_count, len), Ordering::Release);
+        Ok(len)
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        let ring = self.incoming();
+        let (head, tail) = self.ring(ring);
+        let head_count = head.load(Ordering::Relaxed);
+        let available = self.distance(head_count, tail.load(Ordering::Acquire));
+        let len = buf.len().min(available);
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let start = head_count as usize % self.capacity;
+        let first = len.min(self.capacity - start);
+        let data = self.data(ring);
+        unsafe {
+            ptr::copy_nonoverlapping(data.add(start), buf.as_mut_ptr(), first);
+            ptr::copy_nonoverlapping(data, buf[first..].as_mut_ptr(), len - first);
+        }
+        head.store(self.advance(head_count, len), Ordering::Release);
+        Ok(len)
+    }
+}
+
+// the `u32` at `offset`, always 4 byte aligned since `base` is
+unsafe fn word<'a>(base: *mut u8, offset: usize) -> &'a AtomicU32 {
+    unsafe { AtomicU32::from_ptr(base.add(offset).cast()) }
+}
+
+#[cfg(feature = "shared-memory")]
+pub use mapped::ShmTransport;
+
+#[cfg(feature = "shared-memory")]
+mod mapped {
+    use std::fs::{File, OpenOptions};
+    use std::path::Path;
+
+    use memmap2::MmapMut;
+
+    use super::{SharedRegion, Side, region_len};
+    use crate::error::ProtocolError;
+    use crate::framing::Framing;
+    use crate::link::Link;
+    use crate::transport::Transport;
+
+    // `SharedRegion` in a memory mapped file, e.g. under /dev/shm
+    pub struct ShmTransport {
+        region: SharedRegion,
+        // keeps the mapping `region` points into alive
+        _map: MmapMut,
+    }
+
+    impl ShmTransport {
+        // Create (or truncate) the file at `path`, format it with rings of `capacity` bytes and
+        // attach as MCU1
+        pub fn create(path: impl AsRef<Path>, capacity: usize) -> Result<Self, ProtocolError> {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?;
+            file.set_len(region_len(capacity) as u64)?;
+            let mut map = map(&file)?;
+            unsafe { SharedRegion::format(map.as_mut_ptr(), map.len())? };
+            Self::attach(map, Side::Mcu1)
+        }
+
+        // Attach to a file `create` has set up, as MCU2. `InvalidHeader` if it isn't ready.
+        pub fn open(path: impl AsRef<Path>) -> Result<Self, ProtocolError> {
+            let file = OpenOptions::new().read(true).write(true).open(path)?;
+            Self::attach(map(&file)?, Side::Mcu2)
+        }
+
+        // Attach to any formatted mapping as `side`
+        pub fn attach(mut map: MmapMut, side: Side) -> Result<Self, ProtocolError> {
+            // the mapping is page aligned and lives as long as we do
+            let region = unsafe { SharedRegion::attach(map.as_mut_ptr(), map.len(), side)? };
+            Ok(ShmTransport { region, _map: map })
+        }
+
+        pub fn region(&self) -> &SharedRegion {
+            &self.region
+        }
+
+        // Wrap it in a `Link` with the given framing
+        pub fn link<F: Framing>(self, framing: F) -> Link<Self, F> {
+            Link::new(self, framing)
+        }
+    }
+
+    impl Transport for ShmTransport {
+        fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+            self.region.write_bytes(bytes)
+        }
+
+        fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+            self.region.read_bytes(buf)
+        }
+    }
+
+    fn map(file: &File) -> Result<MmapMut, ProtocolError> {
+        // another process changing the file under us is the whole point, the region only
+        // goes through atomics and raw copies
+        Ok(unsafe { MmapMut::map_mut(file)? })
+    }
+}
@@ -0,0 +1,128 @@
+// SPI master side over an embedded-hal 1.0 `SpiDevice`, for an MCU2 that only hangs off SPI.
+//
+// SPI has no idle line and the slave can only talk while the master clocks, so bytes move in
+// fixed size transactions of `N` bytes. The first byte says how many real bytes follow, the
+// rest is padded with a fill byte:
+//
+//   [len][len bytes of frame data][fill ...]    (len <= N - 1)
+//
+// Both directions use the same layout in the same transaction, MCU2 answers with whatever it
+// has queued while we shift ours out. A `len` above `N - 1` means the slave wasn't ready
+// (MISO floating high reads 0xFF), that transaction carries nothing either way and our bytes
+// go again in the next one. `read_bytes` with
+// nothing buffered clocks an empty transaction to poll the slave, so calling `Link::receive`
+// in a loop is what polls MCU2.
+
+use embedded_hal::spi::{Error as _, ErrorKind, SpiDevice};
+
+use crate::error::{ProtocolError, TransportError};
+use crate::transport::Transport;
+
+// padding after the data, and what a poll sends
+pub const DEFAULT_FILL: u8 = 0xFF;
+
+pub struct SpiTransport<D, const N: usize = 32> {
+    device: D,
+    fill: u8,
+    // data from the last transaction the reader hasn't taken yet
+    received: [u8; N],
+    received_len: usize,
+    received_read: usize,
+}
+
+impl<D: SpiDevice, const N: usize> SpiTransport<D, N> {
+    pub fn new(device: D) -> Self {
+        const {
+            assert!(N >= 2 && N <= 256, "SPI transactions must be 2..=256 bytes");
+        }
+        SpiTransport {
+            device,
+            fill: DEFAULT_FILL,
+            received: [0; N],
+            received_len: 0,
+            received_read: 0,
+        }
+    }
+
+    // Padding byte, has to match what MCU2 expects
+    pub fn with_fill(mut self, fill: u8) -> Self {
+        self.fill = fill;
+        self
+    }
+
+    pub fn device_mut(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    // frame data bytes per transaction
+    pub const fn capacity(&self) -> usize {
+        N - 1
+    }
+
+    fn unread(&self) -> usize {
+        self.received_len - self.received_read
+    }
+
+    // Shift `data` (at most N - 1 bytes) out and keep what comes back. False if the slave
+    // wasn't there to take it.
+    fn exchange(&mut self, data: &[u8]) -> Result<bool, ProtocolError> {
+        let mut outgoing = [self.fill; N];
+        outgoing[0] = data.len() as u8;
+        outgoing[1..=data.len()].copy_from_slice(data);
+
+        let mut incoming = [0u8; N];
+        self.device
+            .transfer(&mut incoming, &outgoing)
+            .map_err(|error| spi_error(error.kind()))?;
+
+        let len = incoming[0] as usize;
+        let ready = len < N;
+        self.received_len = if ready { len } else { 0 };
+        self.received[..self.received_len].copy_from_slice(&incoming[1..=self.received_len]);
+        self.received_read = 0;
+        Ok(ready)
+    }
+}
+
+impl<D: SpiDevice, const N: usize> Transport for SpiTransport<D, N> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+        // every transaction brings something back, don't clobber what hasn't been read yet
+        if bytes.is_empty() || self.unread() > 0 {
+            return Ok(0);
+        }
+        let len = bytes.len().min(N - 1);
+        if self.exchange(&bytes[..len])? {
+            Ok(len)
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.unread() == 0 {
+            self.exchange(&[])?;
+        }
+        let start = self.received_read;
+        let len = self.unread().min(buf.len());
+        buf[..len].copy_from_slice(&self.received[start..start + len]);
+        self.received_read += len;
+        Ok(len)
+    }
+}
+
+fn spi_error(kind: ErrorKind) -> ProtocolError {
+    match kind {
+        ErrorKind::Overrun => TransportError::Overrun.into(),
+        ErrorKind::ModeFault | ErrorKind::FrameFormat | ErrorKind::ChipSelectFault => {
+            TransportError::Bus.into()
+        }
+        _ => TransportError::Other.into(),
+    }
+}
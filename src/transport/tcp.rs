@@ -0,0 +1,88 @@
+// TCP stream, mostly for reaching an MCU's UART through ser2net or socat in the lab:
+//
+//   // socat TCP-LISTEN:4000,reuseaddr FILE:/dev/ttyUSB0,b115200,raw
+//   let mut link = TcpTransport::connect("lab-pi:4000")?.link(Cobs::default());
+//
+// TCP is a byte stream just like the UART behind it, so the framing on top is the same one the
+// MCU uses and frames may arrive split across reads. The stream is non-blocking with Nagle
+// turned off, small frames go out right away. The other end closing the connection comes out
+// as `ProtocolError::Disconnected`.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::error::ProtocolError;
+use crate::framing::Framing;
+use crate::link::Link;
+use crate::transport::{Transport, would_block};
+
+pub struct TcpTransport {
+    stream: TcpStream,
+}
+
+impl TcpTransport {
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self, ProtocolError> {
+        Self::from_stream(TcpStream::connect(addr)?)
+    }
+
+    // Wait for the next connection on `listener`, for the end that plays the MCU
+    pub fn accept(listener: &TcpListener) -> Result<Self, ProtocolError> {
+        let (stream, _) = listener.accept()?;
+        Self::from_stream(stream)
+    }
+
+    // Any connected stream, it's switched to non-blocking
+    pub fn from_stream(stream: TcpStream) -> Result<Self, ProtocolError> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(TcpTransport { stream })
+    }
+
+    pub fn peer_addr(&self) -> Result<SocketAddr, ProtocolError> {
+        Ok(self.stream.peer_addr()?)
+    }
+
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    pub fn into_inner(self) -> TcpStream {
+        self.stream
+    }
+
+    // Wrap it in a `Link` with the given framing
+    pub fn link<F: Framing>(self, framing: F) -> Link<Self, F> {
+        Link::new(self, framing)
+    }
+}
+
+impl Transport for TcpTransport {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+        if bytes.is_empty() {
+            return Ok(0);
+        }
+        match self.stream.write(bytes) {
+            Ok(0) => Err(ProtocolError::Disconnected),
+            Ok(written) => Ok(written),
+            Err(error) if would_block(&error) => Ok(0),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.stream.read(buf) {
+            // end of stream, the peer hung up
+            Ok(0) => Err(ProtocolError::Disconnected),
+            Ok(read) => Ok(read),
+            Err(error) if would_block(&error) => Ok(0),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        self.stream.flush().map_err(ProtocolError::from)
+    }
+}
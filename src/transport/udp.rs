@@ -0,0 +1,120 @@
+// UDP socket for trying the protocol between two processes on one machine before there's any
+// hardware, each side binds its own port and talks to the other's:
+//
+//   // process A
+//   let mut link = UdpTransport::open("127.0.0.1:4000", "127.0.0.1:4001")?.link(Cobs::default());
+//   // process B
+//   let mut link = UdpTransport::open("127.0.0.1:4001", "127.0.0.1:4000")?.link(Cobs::default());
+//
+// The socket is non-blocking and connected to the peer, datagrams from anywhere else are
+// dropped by the OS. Every `write_bytes` goes out as one datagram, `read_bytes` hands a datagram
+// out in pieces if the caller's buffer is smaller. Frame boundaries don't have to line up with
+// datagrams, the framing takes care of that. Like a real UART this can lose bytes: a peer that
+// isn't running yet just means the datagram is gone.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use crate::error::ProtocolError;
+use crate::framing::Framing;
+use crate::link::Link;
+use crate::transport::Transport;
+
+// the most a UDP datagram can carry over IPv4
+pub const MAX_DATAGRAM: usize = 65_507;
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+    // the datagram being handed out and how far into it we are
+    received: Vec<u8>,
+    received_len: usize,
+    received_read: usize,
+}
+
+impl UdpTransport {
+    // Bind `local` and send everything to `peer`
+    pub fn open(
+        local: impl ToSocketAddrs,
+        peer: impl ToSocketAddrs,
+    ) -> Result<Self, ProtocolError> {
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(peer)?;
+        Self::from_socket(socket)
+    }
+
+    // A socket that's already connected, it's switched to non-blocking
+    pub fn from_socket(socket: UdpSocket) -> Result<Self, ProtocolError> {
+        socket.set_nonblocking(true)?;
+        Ok(UdpTransport {
+            socket,
+            received: vec![0; MAX_DATAGRAM],
+            received_len: 0,
+            received_read: 0,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, ProtocolError> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    pub fn peer_addr(&self) -> Result<SocketAddr, ProtocolError> {
+        Ok(self.socket.peer_addr()?)
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    // Wrap it in a `Link` with the given framing
+    pub fn link<F: Framing>(self, framing: F) -> Link<Self, F> {
+        Link::new(self, framing)
+    }
+
+    fn unread(&self) -> usize {
+        self.received_len - self.received_read
+    }
+}
+
+impl Transport for UdpTransport {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+        if bytes.is_empty() {
+            return Ok(0);
+        }
+        let len = bytes.len().min(MAX_DATAGRAM);
+        match self.socket.send(&bytes[..len]) {
+            Ok(sent) => Ok(sent),
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            // nobody listening on the other port, the datagram is lost
+            Err(error) if error.kind() == io::ErrorKind::ConnectionRefused => Ok(len),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.unread() == 0 {
+            self.received_len = 0;
+            self.received_read = 0;
+            match self.socket.recv(&mut self.received) {
+                Ok(read) => self.received_len = read,
+                Err(error) => {
+                    return match error.kind() {
+                        io::ErrorKind::WouldBlock
+                        | io::ErrorKind::Interrupted
+                        | io::ErrorKind::ConnectionRefused => Ok(0),
+                        _ => Err(error.into()),
+                    };
+                }
+            }
+        }
+        let start = self.received_read;
+        let len = self.unread().min(buf.len());
+        buf[..len].copy_from_slice(&self.received[start..start + len]);
+        self.received_read += len;
+        Ok(len)
+    }
+}
@@ -0,0 +1,102 @@
+// Unix domain socket, for running MCU1 and MCU2 as two local processes, e.g. in a CI job that
+// starts a simulated MCU2 next to the code under test:
+//
+//   // MCU2
+//   let listener = UnixTransport::listen("/tmp/mcu.sock")?;
+//   let mut link = UnixTransport::accept(&listener)?.link(Cobs::default());
+//   // MCU1
+//   let mut link = UnixTransport::connect("/tmp/mcu.sock")?.link(Cobs::default());
+//
+// Same behaviour as `TcpTransport` without the network stack: a non-blocking byte stream, frames
+// may arrive split across reads, the other process going away is `ProtocolError::Disconnected`.
+// `pair` gives two connected ends inside one process.
+
+use std::io::{Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+use crate::error::ProtocolError;
+use crate::framing::Framing;
+use crate::link::Link;
+use crate::transport::{Transport, would_block};
+
+pub struct UnixTransport {
+    stream: UnixStream,
+}
+
+impl UnixTransport {
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self, ProtocolError> {
+        Self::from_stream(UnixStream::connect(path)?)
+    }
+
+    // Bind a listener at `path`, replacing a socket file a previous run left behind
+    pub fn listen(path: impl AsRef<Path>) -> Result<UnixListener, ProtocolError> {
+        let path = path.as_ref();
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(UnixListener::bind(path)?)
+    }
+
+    // Wait for the next connection on `listener`
+    pub fn accept(listener: &UnixListener) -> Result<Self, ProtocolError> {
+        let (stream, _) = listener.accept()?;
+        Self::from_stream(stream)
+    }
+
+    // Two connected ends, no socket file involved
+    pub fn pair() -> Result<(Self, Self), ProtocolError> {
+        let (a, b) = UnixStream::pair()?;
+        Ok((Self::from_stream(a)?, Self::from_stream(b)?))
+    }
+
+    // Any connected stream, it's switched to non-blocking
+    pub fn from_stream(stream: UnixStream) -> Result<Self, ProtocolError> {
+        stream.set_nonblocking(true)?;
+        Ok(UnixTransport { stream })
+    }
+
+    pub fn stream(&self) -> &UnixStream {
+        &self.stream
+    }
+
+    pub fn into_inner(self) -> UnixStream {
+        self.stream
+    }
+
+    // Wrap it in a `Link` with the given framing
+    pub fn link<F: Framing>(self, framing: F) -> Link<Self, F> {
+        Link::new(self, framing)
+    }
+}
+
+impl Transport for UnixTransport {
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+        if bytes.is_empty() {
+            return Ok(0);
+        }
+        match self.stream.write(bytes) {
+            Ok(0) => Err(ProtocolError::Disconnected),
+            Ok(written) => Ok(written),
+            Err(error) if would_block(&error) => Ok(0),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.stream.read(buf) {
+            // end of stream, the peer hung up
+            Ok(0) => Err(ProtocolError::Disconnected),
+            Ok(read) => Ok(read),
+            Err(error) if would_block(&error) => Ok(0),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        self.stream.flush().map_err(ProtocolError::from)
+    }
+}
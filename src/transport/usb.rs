@@ -0,0 +1,111 @@
+// Device side of a USB CDC-ACM port through `usbd-serial`, so the protocol also runs over the
+// debug USB connector. The host just sees a serial port and uses `SerialTransport` as usual.
+//
+//   let mut usb = UsbSerialTransport::new(SerialPort::new(&bus_allocator));
+//   let mut device = UsbDeviceBuilder::new(&bus_allocator, UsbVidPid(0x16c0, 0x27dd)).build();
+//   loop {
+//       device.poll(&mut [usb.port_mut()]);
+//       while let Some(received) = link.receive() { ... }
+//   }
+//
+// USB moves data in bulk packets of 64 bytes (full speed), and the host only hands a transfer
+// to the application once it ends in a short packet. `SerialPort` buffers our bytes and cuts
+// them into packets, but only sends one packet per call and adds the closing zero length
+// packet when the data ends exactly on a packet boundary. So `read_bytes` and `write_bytes`
+// both push the next buffered packet out, otherwise the tail of a frame could sit in the
+// buffer until the next write.
+
+use core::borrow::BorrowMut;
+
+use usb_device::bus::UsbBus;
+use usbd_serial::{DefaultBufferStore, SerialPort, UsbError};
+
+use crate::error::{ProtocolError, TransportError};
+use crate::transport::Transport;
+
+pub struct UsbSerialTransport<'a, B, RS = DefaultBufferStore, WS = DefaultBufferStore>
+where
+    B: UsbBus,
+    RS: BorrowMut<[u8]>,
+    WS: BorrowMut<[u8]>,
+{
+    port: SerialPort<'a, B, RS, WS>,
+}
+
+impl<'a, B, RS, WS> UsbSerialTransport<'a, B, RS, WS>
+where
+    B: UsbBus,
+    RS: BorrowMut<[u8]>,
+    WS: BorrowMut<[u8]>,
+{
+    pub fn new(port: SerialPort<'a, B, RS, WS>) -> Self {
+        UsbSerialTransport { port }
+    }
+
+    // For `UsbDevice::poll`, which has to see the port to service the endpoints
+    pub fn port_mut(&mut self) -> &mut SerialPort<'a, B, RS, WS> {
+        &mut self.port
+    }
+
+    pub fn into_inner(self) -> SerialPort<'a, B, RS, WS> {
+        self.port
+    }
+
+    // The host has the port open (DTR set). Bytes written before that just fill the buffer.
+    pub fn is_connected(&self) -> bool {
+        self.port.dtr()
+    }
+
+    // Hand the next buffered packet (or the closing zero length one) to the endpoint
+    fn push_packet(&mut self) -> Result<(), ProtocolError> {
+        match self.port.flush() {
+            Ok(()) | Err(UsbError::WouldBlock) => Ok(()),
+            Err(error) => Err(usb_error(error)),
+        }
+    }
+}
+
+impl<B, RS, WS> Transport for UsbSerialTransport<'_, B, RS, WS>
+where
+    B: UsbBus,
+    RS: BorrowMut<[u8]>,
+    WS: BorrowMut<[u8]>,
+{
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<usize, ProtocolError> {
+        if bytes.is_empty() {
+            return self.push_packet().map(|_| 0);
+        }
+        // `write` buffers what fits and sends the first packet
+        match self.port.write(bytes) {
+            Ok(written) => Ok(written),
+            Err(UsbError::WouldBlock) => Ok(0),
+            Err(error) => Err(usb_error(error)),
+        }
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        self.push_packet()?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.port.read(buf) {
+            Ok(read) => Ok(read),
+            Err(UsbError::WouldBlock) => Ok(0),
+            Err(error) => Err(usb_error(error)),
+        }
+    }
+
+    // Doesn't wait: the endpoint only empties when `UsbDevice::poll` runs, which in a
+    // superloop is the same loop that would be waiting here
+    fn flush(&mut self) -> Result<(), ProtocolError> {
+        self.push_packet()
+    }
+}
+
+fn usb_error(error: UsbError) -> ProtocolError {
+    match error {
+        UsbError::BufferOverflow => TransportError::Overrun.into(),
+        UsbError::InvalidState => ProtocolError::Disconnected,
+        _ => TransportError::Other.into(),
+    }
+}